@@ -0,0 +1,67 @@
+"""Bundle a Python script and the modules it imports into one file, from Python.
+
+The bundling is done by the python-inliner library, loaded with ctypes, so build scripts
+(setup.py, hatch hooks) don't need the python-inliner binary::
+
+    import python_inliner
+
+    python_inliner.inline("main.py", "dist/main.py", modules=["mylib"], release=True)
+"""
+
+import ctypes
+import json
+import os
+import sys
+from pathlib import Path
+
+__all__ = ["InlinerError", "inline"]
+
+
+class InlinerError(Exception):
+    """A bundle couldn't be made, for the reason the command line would give."""
+
+
+def _library_path():
+    if "PYTHON_INLINER_LIBRARY" in os.environ:
+        return os.environ["PYTHON_INLINER_LIBRARY"]
+    names = {"win32": "python_inliner.dll", "darwin": "libpython_inliner.dylib"}
+    return str(Path(__file__).with_name(names.get(sys.platform, "libpython_inliner.so")))
+
+
+_library = None
+
+
+def _call(export, request):
+    """Send a JSON request to an export of the library, returning its JSON response."""
+    global _library
+    if _library is None:
+        _library = ctypes.CDLL(_library_path())
+        _library.inliner_alloc.restype = ctypes.c_void_p
+        _library.inliner_alloc.argtypes = [ctypes.c_size_t]
+        _library.inliner_output.restype = ctypes.c_void_p
+        for name in ("inliner_run", "inliner_inline"):
+            getattr(_library, name).restype = ctypes.c_size_t
+            getattr(_library, name).argtypes = [ctypes.c_void_p, ctypes.c_size_t]
+    data = json.dumps(request).encode()
+    pointer = _library.inliner_alloc(len(data))
+    ctypes.memmove(pointer, data, len(data))
+    length = getattr(_library, export)(pointer, len(data))
+    response = json.loads(ctypes.string_at(_library.inliner_output(), length))
+    if "error" in response:
+        raise InlinerError(response["error"])
+    return response
+
+
+def inline(input_path, output_path, modules=(), release=False, args=()):
+    """Bundle input_path into output_path, inlining the given modules.
+
+    release strips comments and docstrings, as --release does. args are any other command
+    line options, e.g. ["--tree-shake"]. Raises InlinerError if the bundle can't be made.
+    """
+    options = (["--release"] if release else []) + [str(arg) for arg in args]
+    _call("inliner_run", {
+        "input": os.fspath(input_path),
+        "output": os.fspath(output_path),
+        "modules": ",".join(modules),
+        "args": options,
+    })
@@ -0,0 +1,176 @@
+//! End-to-end timings of bundling synthetic projects, for checking performance work against a
+//! baseline. The benchmarks are ignored tests, only built with the `bench` feature so that the
+//! counting allocator stays out of other test builds, and run with `make bench`:
+//!
+//! ```bash
+//! make bench BENCH_RESULTS=target/bench-baseline.json   # before the change
+//! make bench BENCH_BASELINE=target/bench-baseline.json  # after: fails on a regression
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use serde_json::{json, Value};
+use super::*;
+use crate::modules::virtual_filesystem::VirtualFileSystem;
+
+/// How many times each project is bundled; the fastest run counts
+const RUNS: usize = 3;
+
+/// How much slower or larger than the baseline a benchmark may get before it fails
+const TOLERANCE: f64 = 1.25;
+
+/// The system allocator, keeping track of the most memory in use at once
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// A generated package `app` of `modules` modules of about `functions` functions each, imported
+/// by the entry script. Module i imports up to `fan_out` of the modules after it, half of them
+/// inside a function, so imports are indented and modules imported again.
+struct SyntheticProject {
+    name: &'static str,
+    modules: usize,
+    functions: usize,
+    fan_out: usize,
+}
+
+/// The projects timed: many modules each imported once, a chain of modules each imported from
+/// inside the one before, and a web of modules imported from several others
+const PROJECTS: &[SyntheticProject] = &[
+    SyntheticProject { name: "wide", modules: 400, functions: 20, fan_out: 0 },
+    SyntheticProject { name: "deep", modules: 60, functions: 200, fan_out: 1 },
+    SyntheticProject { name: "web", modules: 200, functions: 40, fan_out: 4 },
+];
+
+impl SyntheticProject {
+    /// Write the project to `fs`, returning its entry script
+    fn generate(&self, fs: &VirtualFileSystem) -> PathBuf {
+        fs.mkdir_p(Path::new("/bench/app")).unwrap();
+        let mut init = String::new();
+        for index in 0..self.modules {
+            let mut source = format!("\"\"\"Module {}.\"\"\"\nimport os\n\n", index);
+            for imported in (index + 1..self.modules).take(self.fan_out).step_by(2) {
+                source.push_str(&format!("from .m{} import f{}_0\n", imported, imported));
+            }
+            for function in 0..self.functions {
+                source.push_str(&format!("\n\ndef f{}_{}(value):\n    \"\"\"Add {}.\"\"\"\n    text = '''{}\n    {}'''\n    return value + len(text) + {}\n", index, function, function, index, function, function));
+            }
+            for imported in (index + 2..self.modules).take(self.fan_out).step_by(2).chain((self.fan_out == 1).then_some(index + 1).filter(|next| *next < self.modules)) {
+                source.push_str(&format!("\n\ndef lazy_{}():\n    from .m{} import f{}_0\n    return f{}_0(1)\n", imported, imported, imported, imported));
+            }
+            fs.write(Path::new(&format!("/bench/app/m{}.py", index)), source.as_bytes()).unwrap();
+        }
+        // A chain is entered at its first module, anything else through all of them
+        let imported = if self.fan_out == 1 { 1 } else { self.modules };
+        let init_imports: String = (0..imported).map(|index| format!("from .m{} import f{}_0\n", index, index)).collect();
+        init.push_str(&init_imports);
+        fs.write(Path::new("/bench/app/__init__.py"), init.as_bytes()).unwrap();
+        fs.write(Path::new("/bench/main.py"), b"import sys\nfrom app import f0_0\n\nif __name__ == \"__main__\":\n    print(f0_0(1))\n").unwrap();
+        PathBuf::from("/bench/main.py")
+    }
+
+    /// Bundle the project once, returning the bundle
+    fn bundle(&self, fs: &VirtualFileSystem, entry: &Path) -> String {
+        let opt = Opt {
+            input_file: Some(entry.to_path_buf()),
+            output_file: Some(PathBuf::from("/bench/bundle.py")),
+            module_names: "app".to_string(),
+            ..Opt::default()
+        };
+        run_with_options(opt, fs, &[PathBuf::from("/bench")]).unwrap();
+        fs.read_to_string(Path::new("/bench/bundle.py")).unwrap()
+    }
+
+    /// The fastest of RUNS bundlings, and the most memory in use during it beyond what was in
+    /// use before
+    fn measure(&self) -> Value {
+        let fs = VirtualFileSystem::new();
+        let entry = self.generate(&fs);
+        let mut fastest = Duration::MAX;
+        let mut peak_bytes = 0;
+        let mut bundle_bytes = 0;
+        for _ in 0..RUNS {
+            let in_use = ALLOCATED.load(Ordering::Relaxed);
+            PEAK.store(in_use, Ordering::Relaxed);
+            let started = Instant::now();
+            let bundle = self.bundle(&fs, &entry);
+            fastest = fastest.min(started.elapsed());
+            peak_bytes = peak_bytes.max(PEAK.load(Ordering::Relaxed) - in_use);
+            bundle_bytes = bundle.len();
+        }
+        json!({ "name": self.name, "modules": self.modules, "elapsed_ms": fastest.as_secs_f64() * 1000.0, "peak_bytes": peak_bytes, "bundle_bytes": bundle_bytes })
+    }
+}
+
+/// What got worse than `baseline` by more than TOLERANCE, one line each
+fn regressions(results: &[Value], baseline: &Value) -> Vec<String> {
+    let mut regressions = Vec::new();
+    for result in results {
+        let Some(before) = baseline.as_array().and_then(|baseline| baseline.iter().find(|before| before["name"] == result["name"])) else {
+            continue;
+        };
+        for metric in ["elapsed_ms", "peak_bytes"] {
+            let (now, then) = (result[metric].as_f64().unwrap_or(0.0), before[metric].as_f64().unwrap_or(0.0));
+            if then > 0.0 && now > then * TOLERANCE {
+                regressions.push(format!("{} {}: {:.1} against {:.1} before", result["name"].as_str().unwrap_or_default(), metric, now, then));
+            }
+        }
+    }
+    regressions
+}
+
+#[test]
+fn test_synthetic_projects() {
+    // Every module ends up in the bundle once, however it's imported
+    for project in [
+        SyntheticProject { name: "wide", modules: 5, functions: 2, fan_out: 0 },
+        SyntheticProject { name: "deep", modules: 5, functions: 2, fan_out: 1 },
+        SyntheticProject { name: "web", modules: 6, functions: 2, fan_out: 4 },
+    ] {
+        let fs = VirtualFileSystem::new();
+        let entry = project.generate(&fs);
+        let bundle = project.bundle(&fs, &entry);
+        for index in 0..project.modules {
+            assert_eq!(bundle.matches(&format!("def f{}_0(", index)).count(), 1, "{} m{}", project.name, index);
+        }
+    }
+
+    let results = [json!({ "name": "wide", "elapsed_ms": 13.0, "peak_bytes": 100 })];
+    let baseline = json!([{ "name": "wide", "elapsed_ms": 10.0, "peak_bytes": 100 }, { "name": "gone", "elapsed_ms": 1.0 }]);
+    assert_eq!(regressions(&results, &baseline), vec!["wide elapsed_ms: 13.0 against 10.0 before"]);
+}
+
+#[test]
+#[ignore] // Timings, run with `make bench`
+fn bench_inlining() {
+    let results: Vec<Value> = PROJECTS.iter().map(SyntheticProject::measure).collect();
+    for result in &results {
+        println!("{:<6} {:>4} modules  {:>9.1} ms  {:>7} KiB peak  {:>7} KiB bundle", result["name"].as_str().unwrap_or_default(), result["modules"].as_u64().unwrap_or_default(),
+            result["elapsed_ms"].as_f64().unwrap_or_default(), result["peak_bytes"].as_u64().unwrap_or_default() / 1024, result["bundle_bytes"].as_u64().unwrap_or_default() / 1024);
+    }
+    if let Some(path) = std::env::var_os("BENCH_RESULTS") {
+        fs::write(&path, serde_json::to_string_pretty(&results).unwrap() + "\n").unwrap();
+    }
+    if let Some(path) = std::env::var_os("BENCH_BASELINE") {
+        let baseline: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let regressions = regressions(&results, &baseline);
+        assert!(regressions.is_empty(), "slower or larger than {:?}:\n{}", path, regressions.join("\n"));
+    }
+}
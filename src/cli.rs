@@ -0,0 +1,331 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use structopt::StructOpt;
+use structopt::clap::{AppSettings, Shell};
+use crate::modules::build_config::{parse_build_config, BuildConfig};
+use crate::modules::bundle_diff::{module_sections, render_report};
+use crate::modules::errors::InlinerError;
+use crate::modules::file_system::{FileSystem, RealFileSystem};
+use crate::modules::import_chain::{explain_inclusion, render_import_graph};
+use crate::modules::man_page::render_man_page;
+use crate::modules::module_cache::clear_cache;
+use crate::modules::options::{EnvironmentArgs, EnvironmentQuery, Opt};
+use crate::{run_bundles, templated_entries};
+
+/// python-inliner's command line. Without a subcommand, the arguments are those of `inline`.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "python-inliner", about = "Python File Inliner - https://github.com/shock/python-inliner", author = env!("CARGO_PKG_AUTHORS"), after_help = "Without a subcommand, python-inliner takes the options and arguments of `inline`: python-inliner [FLAGS] [OPTIONS] <input-file> <output-file> [module-names]", setting = AppSettings::VersionlessSubcommands, setting = AppSettings::ArgRequiredElseHelp)]
+struct Cli {
+    #[structopt(long, short = "V", help = "Print version information and exit", takes_value = false)]
+    version: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+enum Command {
+    /// Bundle the entry script, which is what python-inliner does without a subcommand
+    Inline(Opt),
+    /// Like `inline --check`: exit with an error if the bundle would change, without writing it
+    Check(Opt),
+    /// Bundle the entry script, then again whenever it or a file inlined into it changes
+    #[cfg(feature = "watch")]
+    Watch(Opt),
+    /// Manage environment snapshots
+    Env(EnvCommand),
+    /// Report which modules were added, removed or changed between two non-release bundles
+    Compare {
+        #[structopt(parse(from_os_str))]
+        old_bundle: PathBuf,
+        #[structopt(parse(from_os_str))]
+        new_bundle: PathBuf,
+    },
+    /// Print the imports between the files of the bundles of a --report file, as a Graphviz digraph
+    Graph {
+        #[structopt(parse(from_os_str))]
+        report: PathBuf,
+    },
+    /// Show how a module name resolves: every search directory tried, in order, the file found
+    /// and why the others lost. With --report, print the shortest chain of imports that put the
+    /// module into the bundles of a --report file instead.
+    Explain {
+        /// A dotted module name, or with --report the end of the module's path
+        module: String,
+        #[structopt(long, parse(from_os_str))]
+        report: Option<PathBuf>,
+        #[structopt(flatten)]
+        environment: EnvironmentArgs,
+    },
+    /// Like `explain --report <report> <module>`
+    Why {
+        #[structopt(parse(from_os_str))]
+        report: PathBuf,
+        module: String,
+    },
+    /// Print the completion script for bash, zsh, fish, powershell or elvish
+    Completions {
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
+    /// Print the man page, in roff
+    Man,
+    /// Remove the processed modules kept in a --cache-dir
+    Clean {
+        #[structopt(parse(from_os_str))]
+        cache_dir: PathBuf,
+    },
+    /// Bundle each entry point of the [tool.python-inliner] table of pyproject.toml into its
+    /// output directory, dist/ by default
+    Build {
+        #[structopt(parse(from_os_str), default_value = "pyproject.toml")]
+        pyproject: PathBuf,
+        /// Like `check`: exit with an error if a bundle would change, without writing any
+        #[structopt(long)]
+        check: bool,
+    },
+}
+
+#[derive(StructOpt, Debug, Clone)]
+enum EnvCommand {
+    /// Save the interpreter path, version, resolved sys.path and editable installs to a JSON file
+    Export {
+        #[structopt(parse(from_os_str))]
+        snapshot_file: PathBuf,
+        #[structopt(flatten)]
+        environment: EnvironmentArgs,
+    },
+}
+
+fn get_current_year() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / (60 * 60 * 24 * 365) + 1970)
+        .unwrap_or(2025)
+}
+
+/// Print an error that stopped a run, with its diagnostic if it has one. Printed with Display
+/// rather than the Debug a returned error would get, which buries the message of an io::Error
+/// in its representation.
+pub fn print_error(e: &(dyn Error + 'static)) {
+    match e.downcast_ref::<InlinerError>() {
+        Some(error) => eprint!("{}", error.render()),
+        None => eprintln!("Error: {}", e),
+    }
+}
+
+/// The command line's arguments, with `inline` put in front of those of a run without a
+/// subcommand
+fn with_subcommand(args: Vec<OsString>) -> Vec<OsString> {
+    let app = Cli::clap();
+    let first = args.get(1).and_then(|arg| arg.to_str());
+    let names_subcommand = first.is_none_or(|first| {
+        ["help", "-h", "--help", "-V", "--version"].contains(&first) || app.p.subcommands.iter().any(|subcommand| subcommand.get_name() == first)
+    });
+    if names_subcommand {
+        return args;
+    }
+    let mut args = args;
+    args.insert(1, OsString::from("inline"));
+    args
+}
+
+pub fn run_cli() -> Result<(), Box<dyn Error>> {
+    let matches = Cli::clap().get_matches_from(with_subcommand(std::env::args_os().collect()));
+    let cli = Cli::from_clap(&matches);
+    if cli.version {
+        let current_year = get_current_year();
+        println!("python-inliner v{}", env!("CARGO_PKG_VERSION"));
+        println!("Author: {}", env!("CARGO_PKG_AUTHORS"));
+        println!("Copyright (c) {}", current_year);
+        return Ok(());
+    }
+    let current_dir = fs::canonicalize(".")?;
+    let fs = RealFileSystem::new(current_dir);
+
+    match cli.command {
+        // ArgRequiredElseHelp prints the help of a run without arguments
+        None => Ok(()),
+        Some(Command::Inline(opt)) => Ok(run_bundles(opt)?),
+        Some(Command::Check(opt)) => Ok(run_bundles(Opt { check: true, ..opt })?),
+        #[cfg(feature = "watch")]
+        Some(Command::Watch(opt)) => crate::watch::watch(opt, matches.subcommand_matches("watch").unwrap()),
+        Some(Command::Compare { old_bundle, new_bundle }) => {
+            let mut sections = Vec::new();
+            for bundle in [&old_bundle, &new_bundle] {
+                let content = fs.read_to_string(bundle)?;
+                let bundle_sections = module_sections(&content)
+                    .ok_or_else(|| format!("{:?} has no inlined module markers (bundles built with --release can't be compared)", bundle))?;
+                sections.push(bundle_sections);
+            }
+            print!("{}", render_report(&sections[0], &sections[1]));
+            Ok(())
+        }
+        Some(Command::Graph { report }) => {
+            print!("{}", render_import_graph(&fs.read_to_string(&report)?)?);
+            Ok(())
+        }
+        Some(Command::Explain { report: Some(report), module, .. }) | Some(Command::Why { report, module }) => {
+            print!("{}", explain_inclusion(&fs.read_to_string(&report)?, &module)?);
+            Ok(())
+        }
+        Some(Command::Completions { shell }) => {
+            Cli::clap().gen_completions_to(env!("CARGO_PKG_NAME"), shell, &mut io::stdout());
+            Ok(())
+        }
+        Some(Command::Man) => {
+            print!("{}", render_man_page(&man_page_help()?));
+            Ok(())
+        }
+        Some(Command::Clean { cache_dir }) => {
+            let removed = clear_cache(&fs, &cache_dir)?;
+            println!("Removed {} cached modules from {:?}", removed, cache_dir);
+            Ok(())
+        }
+        Some(Command::Build { pyproject, check }) => build(&pyproject, &parse_build_config(&fs.read_to_string(&pyproject)?)?, check),
+        // `env export` and `explain <module>` resolve the environment like a bundle would
+        Some(Command::Env(EnvCommand::Export { snapshot_file, environment })) => {
+            Ok(run_bundles(Opt { environment, query: Some(EnvironmentQuery::Export(snapshot_file)), ..Opt::default() })?)
+        }
+        Some(Command::Explain { module, report: None, environment }) => {
+            Ok(run_bundles(Opt { environment, query: Some(EnvironmentQuery::Explain(module)), ..Opt::default() })?)
+        }
+    }
+}
+
+/// The help the man page is rendered from: that of a run without a subcommand, followed by the
+/// list of subcommands. Unwrapped, as the man page reader wraps the text to its own width.
+fn man_page_help() -> Result<String, Box<dyn Error>> {
+    let mut help = Vec::new();
+    Opt::clap()
+        .name(env!("CARGO_PKG_NAME"))
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Python File Inliner - https://github.com/shock/python-inliner")
+        .set_term_width(0)
+        .write_long_help(&mut help)?;
+    let mut help = String::from_utf8_lossy(&help).into_owned();
+    let mut commands = Vec::new();
+    Cli::clap().set_term_width(0).write_long_help(&mut commands)?;
+    let commands = String::from_utf8_lossy(&commands).into_owned();
+    if let Some(start) = commands.find("SUBCOMMANDS:") {
+        let section = &commands[start..];
+        let end = section.find("\n\n").unwrap_or(section.len());
+        help = format!("{}\n\n{}\n", help.trim_end(), &section[..end]);
+    }
+    Ok(help)
+}
+
+/// Bundle the entry points of `config`, read from `pyproject`. The bundles are made from
+/// pyproject.toml's directory, which the paths of the config and of its options are relative to.
+fn build(pyproject: &Path, config: &BuildConfig, check: bool) -> Result<(), Box<dyn Error>> {
+    // Every entry's options are checked before any is bundled
+    let options = build_options(pyproject, config)?;
+    if let Some(root) = pyproject.parent().filter(|root| !root.as_os_str().is_empty()) {
+        std::env::set_current_dir(root).map_err(|e| format!("couldn't change to {:?}: {}", root, e))?;
+    }
+    for opt in options {
+        run_bundles(Opt { check, ..opt })?;
+    }
+    Ok(())
+}
+
+/// The options of each entry point of `config`, as the command line would parse them with the
+/// entry's modules and args, bundling into the config's output directory
+fn build_options(pyproject: &Path, config: &BuildConfig) -> Result<Vec<Opt>, Box<dyn Error>> {
+    let mut options = Vec::new();
+    for entry in &config.entries {
+        let mut args = vec![OsString::from(env!("CARGO_PKG_NAME")), entry.script.clone().into_os_string()];
+        if !entry.modules.is_empty() {
+            args.push(entry.modules.join(",").into());
+        }
+        args.extend(["--out-dir".into(), config.out_dir.clone().into_os_string()]);
+        if let Some(output) = &entry.output {
+            args.extend(["--name-template".into(), output.into()]);
+        }
+        args.extend(entry.args.iter().map(OsString::from));
+        let opt = Opt::from_iter_safe(args).map_err(|e| format!("{:?}: bad args for {:?}: {}", pyproject, entry.script, e))?;
+        if !templated_entries(&opt).is_ok_and(|(entries, _)| entries.len() == 1) {
+            return Err(format!("{:?}: the args of {:?} must be options, not subcommands or more scripts", pyproject, entry.script).into());
+        }
+        options.push(opt);
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completions_and_man_page() {
+        let cli = Cli::from_iter(["python-inliner", "completions", "PowerShell"]);
+        assert!(matches!(cli.command, Some(Command::Completions { shell: Shell::PowerShell })));
+        assert!(Cli::from_iter_safe(["python-inliner", "completions", "tcsh"]).is_err());
+        // An entry script named like a subcommand is still an entry script
+        let args = with_subcommand(["python-inliner", "main.py", "man.py", "mylib"].map(OsString::from).to_vec());
+        let Some(Command::Inline(opt)) = Cli::from_iter_safe(args).unwrap().command else { panic!() };
+        assert_eq!((opt.input_file, opt.output_file), (Some(PathBuf::from("main.py")), Some(PathBuf::from("man.py"))));
+
+        let mut completions = Vec::new();
+        Cli::clap().gen_completions_to("python-inliner", Shell::Fish, &mut completions);
+        let completions = String::from_utf8(completions).unwrap();
+        assert!(completions.contains("__fish_seen_subcommand_from inline\" -l no-color"), "{}", completions);
+
+        let page = render_man_page(&man_page_help().unwrap());
+        assert!(page.starts_with(".TH PYTHON\\-INLINER 1"), "{}", page);
+        assert!(page.contains(".TP\n\\fB\\-\\-no\\-color\\fR\nDon't color the output"), "{}", page);
+        assert!(page.contains(".SH SUBCOMMANDS\n"), "{}", page);
+    }
+
+    #[test]
+    fn test_subcommands() {
+        let cli = Cli::from_iter(["python-inliner", "inline", "main.py", "out.py", "mylib", "--release", "-v"]);
+        let Some(Command::Inline(bundle)) = cli.command else { panic!("{:?}", cli.command) };
+        assert_eq!((bundle.input_file, bundle.module_names, bundle.release, bundle.verbose), (Some(PathBuf::from("main.py")), "mylib".to_string(), true, true));
+        #[cfg(feature = "watch")]
+        assert!(Cli::from_iter_safe(["python-inliner", "watch", "main.py", "out.py", "mylib"]).is_ok_and(|cli| matches!(cli.command, Some(Command::Watch(_)))));
+        let Some(Command::Check(bundle)) = Cli::from_iter(["python-inliner", "check", "compare", "a.py", "b.py"]).command else { panic!() };
+        assert_eq!((bundle.input_file, bundle.output_file), (Some(PathBuf::from("compare")), Some(PathBuf::from("a.py"))));
+        // `why` is the name `explain --report` had before the subcommands were reorganized
+        assert!(matches!(Cli::from_iter(["python-inliner", "why", "report.json", "mylib"]).command, Some(Command::Why { .. })));
+        assert!(matches!(Cli::from_iter(["python-inliner", "explain", "--report", "report.json", "mylib"]).command, Some(Command::Explain { report: Some(_), .. })));
+        let Some(Command::Explain { environment, .. }) = Cli::from_iter(["python-inliner", "explain", "--offline", "mylib"]).command else { panic!() };
+        assert!(environment.offline);
+        let Some(Command::Build { pyproject, check }) = Cli::from_iter(["python-inliner", "build", "--check"]).command else { panic!() };
+        assert_eq!((pyproject, check), (PathBuf::from("pyproject.toml"), true));
+        // Each subcommand takes only its own options
+        for args in [&["compare", "--release", "a.py", "b.py"][..], &["graph", "--offline", "report.json"], &["clean", "-v", ".cache"], &["explain", "--release", "mylib"]] {
+            assert!(Cli::from_iter_safe(std::iter::once("python-inliner").chain(args.iter().copied())).is_err(), "{:?}", args);
+        }
+        assert_eq!(with_subcommand(["python-inliner", "--release", "main.py"].map(OsString::from).to_vec())[1], "inline");
+        assert_eq!(with_subcommand(["python-inliner", "--help"].map(OsString::from).to_vec()).len(), 2);
+    }
+
+    #[test]
+    fn test_build_options() {
+        let config = parse_build_config(concat!(
+            "[tool.python-inliner]\nmodules = ['mylib', 'shared']\nargs = ['--release']\n",
+            "[[tool.python-inliner.entry]]\nscript = 'src/cli.py'\noutput = 'tool.py'\nargs = ['--tree-shake']\n",
+            "[[tool.python-inliner.entry]]\nscript = 'admin.py'\nmodules = []\n",
+        )).unwrap();
+        let options = build_options(Path::new("pyproject.toml"), &config).unwrap();
+        let (entries, module_names) = templated_entries(&options[0]).unwrap();
+        assert_eq!(entries, vec![(PathBuf::from("src/cli.py"), PathBuf::from("dist/tool.py"))]);
+        assert_eq!((module_names.as_str(), options[0].release, options[0].tree_shake), ("mylib,shared", true, true));
+        let (entries, module_names) = templated_entries(&options[1]).unwrap();
+        assert_eq!((entries, module_names.as_str()), (vec![(PathBuf::from("admin.py"), PathBuf::from("dist/admin.py"))], ""));
+
+        // An entry's args are options, which can't add scripts or outputs of their own
+        let mut config = config;
+        for args in [vec!["more.py"], vec!["compare", "a.py", "b.py"], vec!["--no-such-option"]] {
+            config.entries[1].args = args.iter().map(|arg| arg.to_string()).collect();
+            let error = build_options(Path::new("pyproject.toml"), &config).unwrap_err().to_string();
+            assert!(error.contains(r#""admin.py""#), "{}", error);
+        }
+    }
+}
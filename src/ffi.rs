@@ -0,0 +1,141 @@
+//! The library for other languages, built as a cdylib: inline_from_sources() for JavaScript from
+//! wasm32-unknown-unknown with the `wasm` feature, and inline_files() for Python's ctypes with
+//! the `python` feature. The exports pass JSON through memory:
+//!
+//! 1. `inliner_alloc(len)` reserves `len` bytes for the request, which the caller fills in
+//! 2. `inliner_inline(ptr, len)` or `inliner_run(ptr, len)` handles the request, frees it and
+//!    returns the length of the response at `inliner_output()`, which is `{"error": "..."}`
+//!    when it fails
+//!
+//! `inliner_inline` takes `{"sources": {"main.py": "...", ...}, "entry": "main.py", "modules":
+//! "mylib", "args": [...]}` and responds with `{"code": "...", "warnings": "..."}`.
+//! `inliner_run` takes `{"input": "main.py", "output": "out.py", "modules": "mylib", "args":
+//! [...]}`, bundles on the file system and responds with `{}`.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use serde_json::{json, Value};
+use crate::inline_from_sources;
+#[cfg(feature = "python")]
+use crate::inline_files;
+
+thread_local! {
+    /// The response to the last request, kept until the next one
+    static OUTPUT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The options after the positional arguments a request gives in `args`
+fn args(request: &Value) -> Vec<&str> {
+    request["args"].as_array().map(|args| args.iter().filter_map(Value::as_str).collect()).unwrap_or_default()
+}
+
+/// The response to a request, or to the error it made
+fn respond(request: &[u8], handle: fn(&Value) -> Result<Value, Box<dyn Error>>) -> Vec<u8> {
+    let response = serde_json::from_slice(request).map_err(Box::from).and_then(|request| handle(&request));
+    response.unwrap_or_else(|e| json!({ "error": e.to_string() })).to_string().into_bytes()
+}
+
+/// The bundle of the sources in the request
+fn inline(request: &Value) -> Result<Value, Box<dyn Error>> {
+    let sources = request["sources"].as_object().ok_or("the request has no \"sources\" object")?;
+    let sources: BTreeMap<PathBuf, String> = sources.iter()
+        .map(|(path, source)| Ok((PathBuf::from(path), source.as_str().ok_or_else(|| format!("the source of {} isn't a string", path))?.to_string())))
+        .collect::<Result<_, String>>()?;
+    let entry = request["entry"].as_str().unwrap_or("main.py");
+    let bundle = inline_from_sources(&sources, Path::new(entry), request["modules"].as_str().unwrap_or_default(), &args(request))?;
+    Ok(json!({ "code": bundle.code, "warnings": bundle.warnings }))
+}
+
+/// Bundle the files the request names
+#[cfg(feature = "python")]
+fn run(request: &Value) -> Result<Value, Box<dyn Error>> {
+    let input = request["input"].as_str().ok_or("the request has no \"input\" path")?;
+    let output = request["output"].as_str().ok_or("the request has no \"output\" path")?;
+    inline_files(Path::new(input), Path::new(output), request["modules"].as_str().unwrap_or_default(), &args(request))?;
+    Ok(json!({}))
+}
+
+/// Keep a response for inliner_output(), returning its length
+fn keep(response: Vec<u8>) -> usize {
+    OUTPUT.with(|output| {
+        *output.borrow_mut() = response;
+        output.borrow().len()
+    })
+}
+
+/// Reserve `len` bytes for a request
+#[no_mangle]
+pub extern "C" fn inliner_alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+/// Bundle the sources in the request at `ptr`, returning the length of the response at
+/// inliner_output()
+///
+/// # Safety
+///
+/// `ptr` and `len` have to be what inliner_alloc() returned and was given, and the request is
+/// freed: it can't be used again.
+#[no_mangle]
+pub unsafe extern "C" fn inliner_inline(ptr: *mut u8, len: usize) -> usize {
+    let request = Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len));
+    keep(respond(&request, inline))
+}
+
+/// Bundle the files named in the request at `ptr`, returning the length of the response at
+/// inliner_output()
+///
+/// # Safety
+///
+/// As for inliner_inline()
+#[cfg(feature = "python")]
+#[no_mangle]
+pub unsafe extern "C" fn inliner_run(ptr: *mut u8, len: usize) -> usize {
+    let request = Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len));
+    keep(respond(&request, run))
+}
+
+/// Where the response to the last request is, valid until the next one
+#[no_mangle]
+pub extern "C" fn inliner_output() -> *const u8 {
+    OUTPUT.with(|output| output.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(export: unsafe extern "C" fn(*mut u8, usize) -> usize, request: &str) -> Value {
+        let ptr = inliner_alloc(request.len());
+        unsafe {
+            std::ptr::copy_nonoverlapping(request.as_ptr(), ptr, request.len());
+            let len = export(ptr, request.len());
+            serde_json::from_slice(std::slice::from_raw_parts(inliner_output(), len)).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_exports() {
+        let response = call(inliner_inline, r#"{"sources": {"main.py": "from mylib import X\nprint(X)\n", "mylib/__init__.py": "X = 1\n"}, "entry": "main.py", "modules": "mylib", "args": ["--release"]}"#);
+        assert_eq!(response, json!({ "code": "X = 1\nprint(X)\n", "warnings": "" }));
+        assert_eq!(call(inliner_inline, r#"{"sources": {"main.py": 1}}"#), json!({ "error": "the source of main.py isn't a string" }));
+        assert!(call(inliner_inline, "not json")["error"].is_string());
+    }
+
+    #[test]
+    #[cfg(feature = "python")]
+    fn test_run() {
+        let dir = std::env::temp_dir().join(format!("python-inliner-{}-ffi", std::process::id()));
+        std::fs::create_dir_all(dir.join("mylib")).unwrap();
+        std::fs::write(dir.join("main.py"), "from mylib import X\nprint(X)\n").unwrap();
+        std::fs::write(dir.join("mylib/__init__.py"), "X = 1\n").unwrap();
+        let request = json!({ "input": dir.join("main.py"), "output": dir.join("out.py"), "modules": "mylib", "args": ["--release", "--offline", "--pythonpath", dir] });
+        assert_eq!(call(inliner_run, &request.to_string()), json!({}));
+        assert_eq!(std::fs::read_to_string(dir.join("out.py")).unwrap(), "X = 1\nprint(X)\n");
+        let request = json!({ "input": dir.join("missing.py"), "output": dir.join("out.py"), "args": ["--offline", "--pythonpath", dir] });
+        assert!(call(inliner_run, &request.to_string())["error"].as_str().unwrap().contains("missing.py"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,3912 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs as fs;
+use std::path::{Path, PathBuf};
+use std::ffi::OsStr;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use regex::Regex;
+use serde_json::Value;
+use structopt::StructOpt;
+mod modules {
+    pub mod annotations;
+    #[cfg(feature = "archive")]
+    pub mod archive_filesystem;
+    #[cfg(feature = "audit")]
+    pub mod audit;
+    pub mod build_config;
+    pub mod bundle_diff;
+    pub mod collisions;
+    pub mod debug_code;
+    pub mod defines;
+    pub mod directives;
+    pub mod embedded_modules;
+    pub mod encoding;
+    pub mod entry_point;
+    pub mod environment;
+    pub mod errors;
+    pub mod file_system;
+    pub mod import_chain;
+    pub mod import_profiling;
+    pub mod imports;
+    pub mod indentation;
+    pub mod inlining;
+    pub mod licenses;
+    pub mod line_endings;
+    pub mod man_page;
+    pub mod module_cache;
+    pub mod options;
+    pub mod output;
+    pub mod output_writer;
+    pub mod overlay_filesystem;
+    pub mod postprocess;
+    pub mod pth_files;
+    pub mod rebundle;
+    pub mod release;
+    pub mod renaming;
+    pub mod reexports;
+    #[cfg(feature = "remote-cache")]
+    pub mod remote_cache;
+    pub mod report;
+    pub mod requirements;
+    pub mod resolution;
+    pub mod resolver;
+    pub mod rope;
+    pub mod sbom;
+    pub mod script_metadata;
+    pub mod self_check;
+    pub mod size_report;
+    pub mod source_analysis;
+    pub mod stamp;
+    pub mod stubs;
+    pub mod template;
+    pub mod terminal;
+    pub mod text_diff;
+    pub mod tree_shake;
+    pub mod virtual_filesystem;
+    pub mod virtualenv;
+    pub mod warnings;
+    #[cfg(feature = "zipapp")]
+    pub mod zipapp;
+}
+mod utils {
+    pub mod file_url;
+    #[cfg(feature = "archive")]
+    pub mod inflate;
+    pub mod python;
+    pub mod sha1;
+    pub mod sha256;
+    pub mod stdlib;
+    #[cfg(any(feature = "zipapp", all(test, feature = "archive")))]
+    pub mod zip;
+}
+mod cli;
+#[cfg(feature = "watch")]
+mod watch;
+#[cfg(any(feature = "wasm", feature = "python"))]
+mod ffi;
+
+pub use cli::{print_error, run_cli};
+
+#[cfg(feature = "archive")]
+use modules::archive_filesystem::ArchiveFileSystem;
+#[cfg(feature = "audit")]
+use modules::audit::audit_inlined_distributions;
+use modules::file_system::RealFileSystem;
+use modules::embedded_modules::{collect_embedded_modules, embed_modules_bundle};
+use modules::environment::{path_list, user_base, user_site_packages, EnvironmentSnapshot};
+use modules::errors::{InlinerError, SourceSite};
+use modules::file_system::FileSystem;
+use modules::import_profiling::{render_import_profiler, ProfileSink};
+use modules::encoding::{decode_source, source_encoding};
+use modules::inlining::{eager_patterns, pattern_root, render_bundle, warn_at};
+use modules::licenses::{inlined_licenses, license_comment, render_notices, DistributionLicense};
+use modules::line_endings::{apply_line_ending, normalize_line_endings};
+use modules::options::{EnvironmentQuery, Opt};
+use modules::output::{check_size, check_vendor_options, output_format, write_output};
+use modules::renaming::bundle_renames;
+use modules::release::{release_passes, strip_blank_lines, strip_comments, strip_docstrings, strip_trailing_whitespace};
+use modules::output_writer::{OutputFormat, VENDOR_PACKAGE};
+use modules::overlay_filesystem::OverlayFileSystem;
+use modules::rebundle::restore_imports;
+use modules::report::{render_run_report, BundleReport, InlinedFile};
+use modules::postprocess::format_source;
+use modules::pth_files::{finder_distribution, finder_mapping};
+use modules::requirements::{external_imports, installed_distributions, render_requirements};
+use modules::resolution::{ResolutionChoices, CONFIG_FILE_NAME};
+use modules::resolver::ModuleResolver;
+use modules::sbom::{inlined_components, render_sbom, SbomFormat};
+use modules::self_check::misplaced_future_imports;
+use modules::size_report::{ByteSize, ModuleSize};
+use modules::script_metadata::{ScriptMetadata, distribution_import_name, emit_script_metadata, hoist_script_metadata, parse_script_metadata, requirement_name};
+use modules::stamp::{build_timestamp, insert_stamp, BuildStamp};
+use modules::stubs::render_stub;
+use modules::template::fill_template;
+use modules::terminal::Terminal;
+use modules::source_analysis::{insert_preamble, mask_strings_and_comments};
+use modules::warnings::WarningCategory;
+use modules::virtualenv::{active_environment, inspect_environment};
+use modules::virtual_filesystem::VirtualFileSystem;
+use modules::directives::{include_pattern_regex, included_modules};
+use modules::tree_shake::tree_shake_bundle;
+use utils::file_url::file_url_to_path;
+use utils::sha256::sha256_hex;
+use utils::python::{get_python_executable, get_python_sys_path, get_python_version, py_compile, Interpreter};
+
+/// The file system bundles are made from: the working tree, and with the `archive` feature the
+/// zip files on sys.path served as directories
+#[cfg(feature = "archive")]
+type SourceFileSystem = ArchiveFileSystem<RealFileSystem>;
+#[cfg(not(feature = "archive"))]
+type SourceFileSystem = RealFileSystem;
+
+#[cfg(feature = "archive")]
+fn source_file_system(current_dir: PathBuf) -> SourceFileSystem {
+    ArchiveFileSystem::new(RealFileSystem::new(current_dir))
+}
+
+#[cfg(not(feature = "archive"))]
+fn source_file_system(current_dir: PathBuf) -> SourceFileSystem {
+    RealFileSystem::new(current_dir)
+}
+
+/// Bundle each entry script of the options, or export the environment they resolve against
+fn run_bundles(mut opt: Opt) -> Result<(), InlinerError> {
+    // --interactive asks its questions on the terminal the progress line would be drawn on
+    opt.terminal = Terminal::detect(opt.no_color, opt.verbose || opt.interactive);
+    #[cfg(feature = "remote-cache")]
+    if let Some(location) = std::env::var("PYTHON_INLINER_REMOTE_CACHE").ok().filter(|location| opt.remote_cache.is_none() && !location.is_empty()) {
+        opt.remote_cache = Some(location.parse().map_err(InlinerError::usage)?);
+    }
+
+    // get current working directory
+    let current_dir = fs::canonicalize(".").map_err(|e| InlinerError::io(Path::new("."), e))?;
+    let fs = source_file_system(current_dir.clone());
+
+    // Don't query Python if the entry script has nothing we'd inline
+    let mut nothing_to_inline = false;
+    if opt.query.is_none() && !names_outputs(&opt) {
+        if let Some(input_file) = &opt.input_file {
+            if let Ok(content) = read_decoded(&fs, input_file) {
+                // A bundle's inlined regions stand for imports that will be inlined again
+                let content = restore_imports(&normalize_line_endings(&content)).ok().flatten().unwrap_or(content);
+                let (module_names, external_modules) = resolve_module_names(&opt, parse_script_metadata(&content).as_ref());
+                nothing_to_inline = !has_inline_candidates(&content, &file_module_names(&content, &module_names), &external_modules);
+                if nothing_to_inline && opt.expect_inlines {
+                    return report_nothing_to_inline(input_file, &opt.module_names, true);
+                }
+            }
+        }
+    }
+
+    // Directories to search before the interpreter's own: --pythonpath, then PYTHONPATH, which
+    // a snapshot doesn't know about and the interpreter only saw when it was queried
+    let mut leading_path = Vec::new();
+    if let Some(list) = &opt.environment.pythonpath {
+        leading_path.extend(path_list(list, &current_dir));
+    }
+    if let Some(list) = std::env::var_os("PYTHONPATH").filter(|_| !opt.environment.deterministic) {
+        leading_path.extend(path_list(&list, &current_dir));
+    }
+    let has_explicit_path = !leading_path.is_empty() || !opt.environment.extra_path.is_empty();
+
+    let report_skipped = opt.verbose && !opt.allow.contains(&WarningCategory::SkippedSearchPath);
+    let mut environment = match (&opt.environment.env, &opt.environment.venv) {
+        (Some(_), Some(_)) => return Err(InlinerError::usage("--env and --venv can't be combined")),
+        _ if nothing_to_inline => EnvironmentSnapshot::default(),
+        (Some(snapshot_file), None) => {
+            let snapshot = EnvironmentSnapshot::from_json(&fs.read_to_string(snapshot_file).map_err(|e| InlinerError::io(snapshot_file, e))?).map_err(|e| InlinerError::parse(snapshot_file, e))?;
+            add_sys_path_archives(&fs, &snapshot.sys_path, report_skipped);
+            snapshot
+        }
+        (None, Some(venv)) => complete_environment(&fs, inspect_environment(&fs, &fs.canonicalize(venv).map_err(|e| InlinerError::io(venv, e))?).map_err(InlinerError::usage)?, report_skipped)?,
+        (None, None) if opt.environment.deterministic => {
+            if !has_explicit_path {
+                return Err(InlinerError::usage("--deterministic doesn't ask Python for its search path or read PYTHONPATH, so give the directories to search with --pythonpath or --extra-path, or an environment with --env or --venv"));
+            }
+            EnvironmentSnapshot::default()
+        }
+        (None, None) if opt.environment.offline => {
+            if !has_explicit_path {
+                return Err(InlinerError::usage("--offline doesn't ask Python for its search path, so give the directories to search with --pythonpath, PYTHONPATH or --extra-path"));
+            }
+            EnvironmentSnapshot::default()
+        }
+        (None, None) => {
+            let python = interpreter(&opt)?;
+            let snapshot = match query_environment(&python) {
+                Ok(snapshot) => snapshot,
+                // Without a working interpreter, an activated environment can still be read from
+                // disk, and failing that the directories given explicitly are searched on their own
+                Err(e) => match active_environment() {
+                    Some(prefix) => {
+                        eprintln!("{}; reading the environment at {} instead", e, prefix.display());
+                        inspect_environment(&fs, &prefix).map_err(InlinerError::usage)?
+                    }
+                    None if has_explicit_path => {
+                        eprintln!("{}; searching only the --pythonpath, PYTHONPATH and --extra-path directories", e);
+                        EnvironmentSnapshot::default()
+                    }
+                    None => return Err(InlinerError::usage(format!("{}. Pick a working interpreter with --python, read an environment from disk with --venv or --env, or search only given directories with --offline --pythonpath <dirs>", e))),
+                },
+            };
+            complete_environment(&fs, snapshot, report_skipped)?
+        }
+    };
+    // What the interpreter would add at startup when it isn't there to ask
+    if opt.environment.user_site && opt.environment.env.is_some() {
+        let version = environment.version.clone().ok_or_else(|| InlinerError::usage("--user-site needs the snapshot to record the interpreter version"))?;
+        if let Some(user_base) = user_base() {
+            let user_site = user_site_packages(&user_base, &version);
+            if fs.is_dir(&user_site).map_err(|e| InlinerError::io(&user_site, e))? {
+                environment.add_user_site(user_site);
+            }
+        }
+    }
+    environment.add_sys_path(&opt.environment.extra_path);
+    environment.prepend_sys_path(&leading_path);
+
+    if let Some(EnvironmentQuery::Export(snapshot_file)) = &opt.query {
+        fs.write(snapshot_file, environment.to_json().as_bytes()).map_err(|e| InlinerError::io(snapshot_file, e))?;
+        println!("Environment snapshot written to {:?}", snapshot_file);
+        return Ok(());
+    }
+    if let Some(EnvironmentQuery::Explain(module)) = &opt.query {
+        // The current directory stands in for the entry script's, which is searched first
+        let mut search_dirs = vec![current_dir.clone()];
+        search_dirs.extend(environment.search_path().into_iter().filter(|dir| *dir != current_dir));
+        let config_path = current_dir.join(CONFIG_FILE_NAME);
+        let choices = match fs.exists(&config_path).map_err(|e| InlinerError::io(&config_path, e))? {
+            true => ResolutionChoices::from_json(&fs.read_to_string(&config_path).map_err(|e| InlinerError::io(&config_path, e))?).map_err(|e| InlinerError::parse(&config_path, e))?,
+            false => ResolutionChoices::default(),
+        };
+        print!("{}", ModuleResolver::new(&fs, &search_dirs).explain(module, &choices));
+        return Ok(());
+    }
+
+    let entries = if names_outputs(&opt) {
+        let (entries, module_names) = templated_entries(&opt)?;
+        opt.module_names = module_names;
+        if let Some(out_dir) = &opt.out_dir {
+            fs.mkdir_p(out_dir).map_err(|e| InlinerError::io(out_dir, e))?;
+        }
+        entries
+    } else {
+        // Check if required arguments are provided
+        vec![(
+            opt.input_file.clone().ok_or_else(|| InlinerError::usage("Input file is required"))?,
+            opt.output_file.clone().ok_or_else(|| InlinerError::usage("Output file is required"))?,
+        )]
+    };
+
+    let python_sys_path = environment.search_path();
+    // if the environment flag is set, print the PYTHONPATH and exit
+    if opt.verbose {
+        println!("PYTHONPATH: {:?}\n", python_sys_path);
+    }
+    let mut fs = OverlayFileSystem::new(fs);
+    if !opt.shim.is_empty() {
+        // Each bundle searches its entry script's directory first
+        let mut search_dirs = Vec::new();
+        for (input_file, _) in &entries {
+            let entry_dir = fs.canonicalize(input_file).map_err(|e| InlinerError::io(input_file, e))?.parent().map(Path::to_path_buf);
+            search_dirs.extend(entry_dir.filter(|dir| !search_dirs.contains(dir)));
+        }
+        search_dirs.extend(python_sys_path.iter().cloned());
+        apply_shims(&mut fs, &search_dirs, &opt.shim, opt.verbose)?;
+    }
+    run_entries(opt, &fs, &python_sys_path, &entries, environment.version.as_deref())
+}
+
+/// An entry script and the file its bundle is written to
+type Entry = (PathBuf, PathBuf);
+
+/// Whether output paths are derived from the entry scripts (--out-dir, --name-template) instead
+/// of given as the second positional argument
+fn names_outputs(opt: &Opt) -> bool {
+    opt.out_dir.is_some() || opt.name_template.is_some()
+}
+
+/// With --out-dir or --name-template, read the positional arguments as `<entry>... [module-names]`:
+/// the entry scripts are the `.py` files. Returns each entry with the path of its bundle, and the
+/// module names.
+fn templated_entries(opt: &Opt) -> Result<(Vec<Entry>, String), InlinerError> {
+    let mut arguments: Vec<PathBuf> = opt.input_file.iter().chain(&opt.output_file).cloned().collect();
+    if !opt.module_names.is_empty() {
+        arguments.push(PathBuf::from(&opt.module_names));
+    }
+    arguments.extend(opt.more_input_files.iter().cloned());
+
+    let name_template = opt.name_template.clone().unwrap_or_else(|| if output_format(opt) == OutputFormat::Zipapp { "{stem}.pyz" } else { "{stem}.py" }.to_string());
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut module_names = String::new();
+    for (index, argument) in arguments.iter().enumerate() {
+        if argument.extension().is_some_and(|extension| extension == "py") {
+            let stem = argument.file_stem().unwrap().to_string_lossy();
+            let out_dir = opt.out_dir.as_deref().unwrap_or_else(|| argument.parent().unwrap());
+            let output_file = out_dir.join(name_template.replace("{stem}", &stem));
+            if let Some((other, _)) = entries.iter().find(|(_, existing)| *existing == output_file) {
+                return Err(InlinerError::usage(format!("{} and {} would both be bundled to {} (use --name-template to tell them apart)", other.display(), argument.display(), output_file.display())));
+            }
+            entries.push((argument.clone(), output_file));
+        } else if index == arguments.len() - 1 && index > 0 {
+            module_names = argument.to_string_lossy().to_string();
+        } else {
+            return Err(InlinerError::usage(format!("Expected an entry script (.py), got {:?}", argument)));
+        }
+    }
+    if entries.is_empty() {
+        return Err(InlinerError::usage("Input file is required"));
+    }
+    Ok((entries, module_names))
+}
+
+/// Bundle each (entry script, output file), sharing the resolved search path and remembered
+/// resolutions between them
+fn run_entries(opt: Opt, fs: &dyn FileSystem, python_sys_path: &[PathBuf], entries: &[Entry], interpreter_version: Option<&str>) -> Result<(), InlinerError> {
+    let started = Stopwatch::start();
+    let canonical = |path: &Path| fs.canonicalize(path).map_err(|e| InlinerError::io(path, e));
+    for (input_file, output_file) in entries {
+        let is_bundle = read_decoded(fs, input_file).is_ok_and(|content| matches!(restore_imports(&normalize_line_endings(&content)), Ok(Some(_))));
+        if !is_bundle && fs.exists(output_file).map_err(|e| InlinerError::io(output_file, e))? && canonical(output_file)? == canonical(input_file)? {
+            return Err(InlinerError::usage(format!("Refusing to overwrite the entry script {}", input_file.display())));
+        }
+        let mut entry_opt = Opt {
+            input_file: Some(input_file.clone()),
+            output_file: Some(output_file.clone()),
+            warnings: if entries.len() > 1 { Arc::default() } else { opt.warnings.clone() },
+            ..opt.clone()
+        };
+        if opt.emit_script_metadata && opt.requires_python.is_none() {
+            // Fall back to the interpreter's version unless the entry script declares one
+            let entry_declares_python = read_decoded(fs, input_file).ok()
+                .and_then(|content| parse_script_metadata(&content))
+                .is_some_and(|metadata| metadata.requires_python.is_some());
+            if !entry_declares_python {
+                entry_opt.requires_python = interpreter_version.map(|version| format!(">={}", version));
+            }
+        }
+        if entries.len() > 1 {
+            println!("Bundling {} into {}", input_file.display(), output_file.display());
+        }
+        run_with_options(entry_opt, fs, python_sys_path)?;
+    }
+    if opt.check {
+        let stale: Vec<String> = opt.stale_outputs.borrow().iter().map(|file| file.display().to_string()).collect();
+        if !stale.is_empty() {
+            let count = if stale.len() == 1 { "1 bundle".to_string() } else { format!("{} bundles", stale.len()) };
+            return Err(InlinerError::check(format!("{} would change, run without --check to update: {}", count, stale.join(", "))));
+        }
+        return Ok(());
+    }
+    if let Some(report_file) = &opt.report {
+        fs.write(report_file, render_run_report(python_sys_path, &opt.bundle_reports.borrow(), report_time(&opt, started)).as_bytes()).map_err(|e| InlinerError::io(report_file, e))?;
+        println!("Report written to {:?}", report_file);
+    }
+    if let Some(requirements_file) = &opt.emit_requirements {
+        write_requirements(fs, python_sys_path, requirements_file, &opt)?;
+    }
+    if let Some(notices_file) = &opt.licenses {
+        let bundle_licenses = opt.inlined_licenses.borrow();
+        let bundles: Vec<&Path> = bundle_licenses.iter().map(|(bundle, _)| bundle.as_path()).collect();
+        let mut licenses: Vec<DistributionLicense> = bundle_licenses.iter().flat_map(|(_, licenses)| licenses.iter().cloned()).collect();
+        licenses.sort_by_key(|license| (license.name.to_lowercase(), license.version.clone()));
+        licenses.dedup();
+        fs.write(notices_file, render_notices(&bundles, &licenses).as_bytes()).map_err(|e| InlinerError::io(notices_file, e))?;
+        println!("Third-party notices written to {:?}", notices_file);
+    }
+    if let Some(sbom_file) = &opt.sbom {
+        let format = opt.sbom_format.unwrap_or_else(|| SbomFormat::for_file(sbom_file));
+        fs.write(sbom_file, render_sbom(format, &opt.sbom_components.borrow(), document_time(&opt)?).as_bytes()).map_err(|e| InlinerError::io(sbom_file, e))?;
+        println!("Software bill of materials written to {:?}", sbom_file);
+    }
+    Ok(())
+}
+
+/// Write --emit-requirements for the external imports of every bundle of the run
+fn write_requirements(fs: &dyn FileSystem, python_sys_path: &[PathBuf], requirements_file: &Path, opt: &Opt) -> Result<(), InlinerError> {
+    let remaining = opt.remaining_imports.borrow();
+    let modules: Vec<String> = remaining.iter()
+        .flat_map(|(_, modules)| modules.iter().cloned())
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect();
+    let bundles: Vec<&Path> = remaining.iter().map(|(bundle, _)| bundle.as_path()).collect();
+    let (content, missing) = render_requirements(&modules, &installed_distributions(fs, python_sys_path), &bundles);
+    for module in missing {
+        println!("No installed distribution provides {:?}, which is left out of {:?}", module, requirements_file);
+    }
+    fs.write(requirements_file, content.as_bytes()).map_err(|e| InlinerError::io(requirements_file, e))?;
+    println!("Requirements written to {:?}", requirements_file);
+    Ok(())
+}
+
+/// Work out the regex matching module names to inline (see module_match_pattern()) and the
+/// top-level modules that must stay external
+fn resolve_module_names(opt: &Opt, script_metadata: Option<&ScriptMetadata>) -> (String, Vec<String>) {
+    // split the module names into a vector and filter out empty strings
+    let mut module_names: Vec<String> = opt.module_names.split(",").filter(|s| !s.is_empty()).map(|s| s.trim().to_string()).collect::<Vec<String>>();
+
+    // PEP 723: dependencies declared in the entry script are external by default,
+    // unless the user asked for them to be resolved from the environment and inlined
+    let mut external_modules = opt.external_modules.clone();
+    if let Some(metadata) = script_metadata {
+        let dependency_modules = metadata.dependencies.iter()
+            .filter_map(|dependency| requirement_name(dependency))
+            .map(|name| distribution_import_name(&name));
+        if opt.inline_script_deps {
+            module_names.extend(dependency_modules);
+        } else {
+            external_modules.extend(dependency_modules);
+        }
+    }
+    // The imports eagerly inlined packages make of each other are inlined like the entry's
+    module_names.extend(eager_patterns(opt).iter().map(|(_, _, pattern)| pattern_root(pattern)).filter(|root| !root.is_empty()));
+    (module_match_pattern(&module_names), external_modules)
+}
+
+/// Build the regex alternation matching the module part of `from X import ...` for imports that
+/// should be inlined: any relative import, plus each requested module and its submodules.
+/// Names only match on dotted boundaries, so `util` matches `util` and `util.x` but not `utilities`.
+fn module_match_pattern(module_names: &[String]) -> String {
+    let mut alternatives = vec![r"\.+[\w.]*".to_string()];
+    if !module_names.is_empty() {
+        let names: Vec<String> = module_names.iter().map(|name| regex::escape(name)).collect();
+        alternatives.push(format!(r"(?:{})(?:\.[\w.]*)?", names.join("|")));
+    }
+    alternatives.join("|")
+}
+
+/// The module names a file's imports are matched against: the run's, plus the modules named
+/// by the `# inliner: include(...)` comments at the top of the file
+fn file_module_names(source: &str, module_names: &str) -> String {
+    let patterns: Vec<String> = included_modules(source).iter().map(|pattern| include_pattern_regex(pattern)).collect();
+    if patterns.is_empty() {
+        return module_names.to_string();
+    }
+    format!(r"{}|(?:{})(?:\.[\w.]*)?", module_names, patterns.join("|"))
+}
+
+/// Whether the content has any import that inline_imports would try to resolve
+fn has_inline_candidates(content: &str, module_names: &str, external_modules: &[String]) -> bool {
+    let import_regex = Regex::new(&format!(r"(?m)^[ \t]*(?:from\s+({0})\s+import\s|import\s+({0})(?:\s+as\s+\w+)?[ \t]*\r?$)", module_names)).unwrap();
+    let masked_content = mask_strings_and_comments(content);
+    let has_candidates = import_regex.captures_iter(&masked_content).any(|cap| {
+        let submodule = cap.get(1).or_else(|| cap.get(2)).unwrap().as_str();
+        let top_level_module = submodule.split('.').next().unwrap_or(submodule);
+        submodule.starts_with('.') || !external_modules.iter().any(|m| m == top_level_module)
+    });
+    has_candidates
+}
+
+/// Tell the user nothing was inlined, failing if --expect-inlines was given. Otherwise the
+/// output is still written, so a bundle of an earlier version of the script isn't left there.
+fn report_nothing_to_inline(input_file: &Path, requested_modules: &str, expect_inlines: bool) -> Result<(), InlinerError> {
+    let modules = if requested_modules.is_empty() { "(none, relative imports only)" } else { requested_modules };
+    let message = format!("Nothing to inline: no imports in {:?} resolved to the requested modules {}", input_file, modules);
+    if expect_inlines {
+        return Err(InlinerError::check(message));
+    }
+    eprintln!("{}. The output is the script with nothing inlined.", message);
+    Ok(())
+}
+
+/// Zip files on sys.path (zipped stdlib, .egg files) are served as directories by the archive
+/// file system, so they resolve like any other search path entry
+#[cfg(feature = "archive")]
+fn add_sys_path_archives(fs: &SourceFileSystem, python_sys_path: &[PathBuf], verbose: bool) {
+    for path in python_sys_path {
+        if matches!(fs.is_file(path), Ok(true)) {
+            if let Err(e) = fs.add_archive(path) {
+                if verbose {
+                    println!("{}: skipping sys.path entry {}: {}", WarningCategory::SkippedSearchPath.heading(), path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// Without the `archive` feature zip files on sys.path can't be read, so they're left out of
+/// the search path like any other entry that isn't a directory
+#[cfg(not(feature = "archive"))]
+fn add_sys_path_archives(fs: &SourceFileSystem, python_sys_path: &[PathBuf], verbose: bool) {
+    for path in python_sys_path.iter().filter(|path| matches!(fs.is_file(path), Ok(true))) {
+        if verbose {
+            println!("{}: skipping sys.path entry {}: python-inliner was built without the `archive` feature", WarningCategory::SkippedSearchPath.heading(), path.display());
+        }
+    }
+}
+
+/// Serve each --shim stub in place of every file its module resolves to in the search
+/// directories, so the stub is inlined whichever of them wins
+fn apply_shims<FS: FileSystem>(fs: &mut OverlayFileSystem<FS>, search_dirs: &[PathBuf], shims: &[(String, PathBuf)], verbose: bool) -> Result<(), InlinerError> {
+    for (module, stub) in shims {
+        let candidates = ModuleResolver::new(fs, search_dirs).candidates(module);
+        if candidates.is_empty() {
+            return Err(InlinerError::usage(format!("Can't shim {}: the module isn't on the search path", module)));
+        }
+        let contents = fs.read(stub).map_err(|e| InlinerError::io(stub, e))?;
+        for candidate in candidates {
+            if verbose {
+                println!("Shimming {} with {}", candidate.display(), stub.display());
+            }
+            fs.add_override(candidate, contents.clone());
+        }
+    }
+    Ok(())
+}
+
+/// The interpreter --python or PYTHON_INLINER_PYTHON selects
+fn interpreter(opt: &Opt) -> Result<Interpreter, InlinerError> {
+    Interpreter::select(opt.environment.python.as_deref(), std::env::var("PYTHON_INLINER_PYTHON").ok().as_deref()).map_err(InlinerError::usage)
+}
+
+/// Query the local interpreter for everything module resolution depends on
+#[allow(clippy::redundant_closure)]
+fn query_environment(python: &Interpreter) -> Result<EnvironmentSnapshot, InlinerError> {
+    let python_sys_path = get_python_sys_path(python)?;
+    Ok(EnvironmentSnapshot {
+        interpreter: get_python_executable(python).ok(),
+        version: get_python_version(python).ok().map(|(major, minor)| format!("{}.{}", major, minor)),
+        // map the python_sys_path to a vector of Path objects
+        sys_path: python_sys_path.into_iter().map(|p| PathBuf::from(p)).collect(),
+        editable_installs: Vec::new(),
+    })
+}
+
+/// Make a live environment's search path resolvable: mount the archives on it, drop the
+/// entries that aren't there and add the editable installs of its site-packages
+#[allow(clippy::match_like_matches_macro)]
+fn complete_environment(fs: &SourceFileSystem, mut snapshot: EnvironmentSnapshot, report_skipped: bool) -> Result<EnvironmentSnapshot, InlinerError> {
+    add_sys_path_archives(fs, &snapshot.sys_path, report_skipped);
+
+    // filter out the non-directories from python_sys_path using the fs.is_dir() method
+    snapshot.sys_path.retain(|p| {
+        match fs.is_dir(p) {
+            Ok(true) => true,
+            _ => false
+        }
+    });
+    snapshot.editable_installs = handle_editable_installs(fs, &mut snapshot.sys_path)?;
+    Ok(snapshot)
+}
+
+/// Convenience wrapper over run_with_options() for the common positional options
+#[cfg(test)]
+fn run(input_file: PathBuf, output_file: PathBuf, module_names: String, release: bool, verbose: bool, fs: &dyn FileSystem, python_sys_path: &[PathBuf]) -> Result<(), InlinerError> {
+    let opt = Opt {
+        input_file: Some(input_file),
+        output_file: Some(output_file),
+        module_names,
+        release,
+        verbose,
+        ..Opt::default()
+    };
+    run_with_options(opt, fs, python_sys_path)
+}
+
+/// Bundle `input_file` into `output_file` as the command line does, `args` being the options
+/// after the positional arguments, e.g. `["--release"]`. For callers such as the Python package,
+/// which a bad option shouldn't end the process of, as it does the command line's.
+pub fn inline_files(input_file: &Path, output_file: &Path, module_names: &str, args: &[&str]) -> Result<(), InlinerError> {
+    let positional = [input_file.as_os_str(), output_file.as_os_str(), OsStr::new(module_names)];
+    let opt = Opt::from_iter_safe(std::iter::once(OsStr::new(env!("CARGO_PKG_NAME"))).chain(positional).chain(args.iter().map(OsStr::new)))
+        .map_err(|e| InlinerError::usage(e.to_string()))?;
+    if names_outputs(&opt) {
+        return Err(InlinerError::usage("inline_files() makes one bundle, without --out-dir or --name-template"));
+    }
+    run_bundles(opt)
+}
+
+/// How long a run has taken so far. wasm32-unknown-unknown has no clock to ask, and there a run
+/// takes no time.
+#[derive(Debug, Clone, Copy)]
+struct Stopwatch(Option<Instant>);
+
+impl Stopwatch {
+    fn start() -> Stopwatch {
+        Stopwatch(if cfg!(all(target_arch = "wasm32", target_os = "unknown")) { None } else { Some(Instant::now()) })
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.0.map_or(Duration::ZERO, |started| started.elapsed())
+    }
+}
+
+/// The time a --report gives for what `started` has timed: none with --deterministic, whose
+/// report is the same on every run
+fn report_time(opt: &Opt, started: Stopwatch) -> Duration {
+    if opt.environment.deterministic { Duration::ZERO } else { started.elapsed() }
+}
+
+/// Where inline_from_sources() writes the bundle in its file system
+const IN_MEMORY_BUNDLE: &str = "/.python-inliner/bundle.py";
+
+/// A bundle made by inline_from_sources()
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlinedBundle {
+    pub code: String,
+    /// The warnings raised, as the command line would print them
+    pub warnings: String,
+}
+
+/// Bundle `entry` from `sources`, a map of paths to the source of each file, without touching
+/// the file system or running Python. Relative paths are relative to `/`. `args` are command line
+/// options, e.g. `["--release"]` or `["--pythonpath", "src"]`; modules are searched for in the
+/// entry script's directory and the --pythonpath and --extra-path directories. Nothing is
+/// printed: the warnings come back with the bundle.
+pub fn inline_from_sources(sources: &BTreeMap<PathBuf, String>, entry: &Path, module_names: &str, args: &[&str]) -> Result<InlinedBundle, InlinerError> {
+    let fs = VirtualFileSystem::new();
+    for (path, source) in sources {
+        if let Some(dir) = Path::new("/").join(path).parent() {
+            fs.mkdir_p(dir).map_err(|e| InlinerError::io(dir, e))?;
+        }
+        fs.write(path, source.as_bytes()).map_err(|e| InlinerError::io(path, e))?;
+    }
+    let positional = [entry.as_os_str(), OsStr::new(IN_MEMORY_BUNDLE), OsStr::new(module_names)];
+    let mut opt = Opt::from_iter_safe(std::iter::once(OsStr::new(env!("CARGO_PKG_NAME"))).chain(positional).chain(args.iter().map(OsStr::new)))
+        .map_err(|e| InlinerError::usage(e.to_string()))?;
+    if names_outputs(&opt) {
+        return Err(InlinerError::usage("inline_from_sources() makes one bundle, without --out-dir or --name-template"));
+    }
+    opt.environment.offline = true;
+    opt.quiet = true;
+    let mut search_path: Vec<PathBuf> = opt.environment.pythonpath.iter().flat_map(|list| path_list(list, Path::new("/"))).collect();
+    search_path.extend(opt.environment.extra_path.iter().map(|dir| Path::new("/").join(dir)));
+    fs.mkdir_p(Path::new(IN_MEMORY_BUNDLE).parent().unwrap()).map_err(|e| InlinerError::io(Path::new(IN_MEMORY_BUNDLE).parent().unwrap(), e))?;
+    let warnings = opt.warnings.clone();
+    let hidden = hidden_warnings(&opt);
+    run_with_options(opt, &fs, &search_path)?;
+    Ok(InlinedBundle { code: fs.read_to_string(Path::new(IN_MEMORY_BUNDLE)).map_err(|e| InlinerError::io(Path::new(IN_MEMORY_BUNDLE), e))?, warnings: warnings.render(&hidden) })
+}
+
+fn run_with_options(opt: Opt, fs: &dyn FileSystem, python_sys_path: &[PathBuf]) -> Result<(), InlinerError> {
+    let started = Stopwatch::start();
+    opt.terminal.progress.borrow_mut().reset();
+    let input_file = opt.input_file.clone().ok_or_else(|| InlinerError::usage("Input file is required"))?;
+    let output_file = opt.output_file.clone().ok_or_else(|| InlinerError::usage("Output file is required"))?;
+    let release = opt.release;
+    let profile_sink = opt.profile_sink.as_deref().map(ProfileSink::parse)
+        .or(if opt.profile_imports { Some(ProfileSink::Stderr) } else { None });
+    if opt.embed_modules && (opt.tree_shake || opt.remove_unused_imports || opt.emit_script_metadata || opt.emit_requirements.is_some() || profile_sink.is_some()) {
+        return Err(InlinerError::usage("--embed-modules can't be combined with --tree-shake, --remove-unused-imports, --emit-script-metadata, --emit-requirements or --profile-imports"));
+    }
+    if opt.embed_modules && opt.topological {
+        return Err(InlinerError::usage("--topological can't be combined with --embed-modules"));
+    }
+    if opt.embed_modules && !eager_patterns(&opt).is_empty() {
+        return Err(InlinerError::usage("--inline-package and --dynamic-map can't be combined with --embed-modules"));
+    }
+    if opt.environment.offline && opt.check_syntax {
+        return Err(InlinerError::usage("--check-syntax compiles the bundle with Python, which --offline doesn't run"));
+    }
+    if opt.environment.deterministic {
+        // What the bundle would depend on besides its sources and options
+        let environmental = [
+            (opt.check_syntax, "--check-syntax"),
+            (opt.format || opt.formatter.is_some(), "--format"),
+            (opt.audit || opt.audit_warn_only, "--audit"),
+            (opt.environment.user_site, "--user-site"),
+        ];
+        let used: Vec<&str> = environmental.iter().filter(|(used, _)| *used).map(|(_, option)| *option).collect();
+        if !used.is_empty() {
+            return Err(InlinerError::usage(format!("--deterministic reads nothing but the sources and options given, so it can't be combined with {}, which run tools or read the home directory", used.join(", "))));
+        }
+    }
+    check_features(&opt).map_err(InlinerError::usage)?;
+    check_vendor_options(&opt).map_err(InlinerError::usage)?;
+
+    // get the input_file as a fully qualified path
+    let input_file = fs.canonicalize(&input_file).map_err(|e| InlinerError::io(&input_file, e))?;
+
+    // get the working directory from the input file path
+    let working_dir = input_file.parent().unwrap();
+    let mut python_sys_path = python_sys_path.to_vec();
+    python_sys_path.insert(0, working_dir.to_path_buf());
+
+    // The entry script as written, for --line-ending keep
+    let entry_source = read_decoded(fs, &input_file)?;
+    // A bundle given as the entry script is bundled again from the imports its regions stand
+    // for; files are still written to the file system as it is
+    let output_fs = fs;
+    let rebundle_fs;
+    let entry_content = normalize_line_endings(&entry_source);
+    let restored = restore_imports(&entry_content).map_err(|(line, reason)| InlinerError::Parse {
+        file: input_file.clone(),
+        site: entry_content.lines().nth(line - 1).map(|text| SourceSite::new(&input_file, line, text, "")),
+        reason,
+    })?;
+    let fs: &dyn FileSystem = match restored {
+        Some(restored) => {
+            println!("{} is a bundle, inlining its modules again from their current source", input_file.display());
+            let mut overlay = OverlayFileSystem::new(output_fs);
+            overlay.add_override(input_file.clone(), restored.into_bytes());
+            rebundle_fs = overlay;
+            &rebundle_fs
+        }
+        None => output_fs,
+    };
+    let script_metadata = parse_script_metadata(&normalize_line_endings(&entry_source));
+    if opt.verbose {
+        if let Some(metadata) = &script_metadata {
+            println!("PEP 723 script dependencies: {:?}", metadata.dependencies);
+        }
+    }
+    let (module_names, external_modules) = resolve_module_names(&opt, script_metadata.as_ref());
+    let requested_modules = opt.module_names.clone();
+
+    let config_path = working_dir.join(CONFIG_FILE_NAME);
+    let resolution_choices = if fs.exists(&config_path).map_err(|e| InlinerError::io(&config_path, e))? {
+        ResolutionChoices::from_json(&fs.read_to_string(&config_path).map_err(|e| InlinerError::io(&config_path, e))?).map_err(|e| InlinerError::parse(&config_path, e))?
+    } else {
+        ResolutionChoices::default()
+    };
+
+    let opt = Opt {
+        input_file: Some(input_file.clone()),
+        output_file: Some(output_file.clone()),
+        module_names: module_names.clone(),
+        external_modules,
+        resolution_choices: Rc::new(RefCell::new(resolution_choices)),
+        profile_imports: profile_sink.is_some(),
+        indented_inlines: Rc::default(),
+        imports: Rc::default(),
+        ..opt
+    };
+    opt.module_sizes.borrow_mut().clear();
+
+    // Top-level names of the requested modules, i.e. the first-party import section
+    let first_party: Vec<String> = requested_modules.split(',')
+        .map(|name| name.trim().split('.').next().unwrap_or("").to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    let mut processed = HashSet::new();
+    // Vendored output copies the modules --embed-modules would embed, and writes the entry script
+    let vendoring = output_format(&opt).vendors();
+    let mut vendored = BTreeMap::new();
+    let mut content = if opt.embed_modules {
+        embed_modules_bundle(fs, &python_sys_path, &input_file, &module_names, &mut processed, &opt)?
+    } else if vendoring {
+        collect_embedded_modules(fs, &python_sys_path, &input_file, None, &module_names, &mut vendored, &mut processed, &opt)?;
+        read_source(fs, &input_file)?
+    } else {
+        render_bundle(fs, &python_sys_path, &input_file, &module_names, &mut processed, &opt)?
+    };
+    opt.inlined_files.borrow_mut().extend(processed.iter().cloned());
+    if opt.resolution_choices.borrow().changed {
+        fs.write(&config_path, opt.resolution_choices.borrow().to_json().as_bytes()).map_err(|e| InlinerError::io(&config_path, e))?;
+        println!("Resolution choices saved to {:?}", config_path);
+    }
+    if processed.is_empty() {
+        report_nothing_to_inline(&input_file, &requested_modules, opt.expect_inlines)?;
+    }
+    #[cfg(feature = "audit")]
+    if opt.audit || opt.audit_warn_only {
+        audit_inlined_distributions(fs, &python_sys_path, &processed, &opt)?;
+    }
+    // Embedded modules were already stripped one by one, and their imports have to stay put
+    if !opt.embed_modules && !vendoring {
+        // The second pass would only repeat the first pass's warnings and imports
+        let renaming_opt = Opt {
+            renamed_definitions: bundle_renames(fs, &python_sys_path, &input_file, &module_names, &processed, &opt)?,
+            warnings: Arc::default(),
+            imports: Rc::default(),
+            indented_inlines: Rc::default(),
+            ..opt.clone()
+        };
+        if !renaming_opt.renamed_definitions.is_empty() {
+            content = render_bundle(fs, &python_sys_path, &input_file, &module_names, &mut HashSet::new(), &renaming_opt)?;
+        }
+        if opt.tree_shake {
+            content = tree_shake_bundle(fs, &python_sys_path, &input_file, &module_names, &content, &processed, &renaming_opt)?;
+        }
+        if let Some(metadata) = &script_metadata {
+            content = hoist_script_metadata(&content, metadata);
+        }
+        content = release_passes(&content, &first_party, &opt);
+    }
+    if let Some(sink) = &profile_sink {
+        // Added last so the release passes leave the helpers alone
+        content = insert_preamble(&content, &render_import_profiler(sink, release));
+    }
+    if let Some(template) = &opt.template {
+        content = fill_template(&read_source(fs, template)?, &content).map_err(|e| InlinerError::usage(format!("--template {}: {}", template.display(), e)))?;
+    }
+    if opt.format || opt.formatter.is_some() {
+        content = format_source(opt.formatter.as_deref(), &content)?;
+    }
+    if opt.emit_script_metadata {
+        let inlined: BTreeSet<String> = processed.iter()
+            .filter(|path| Some(path.as_path()) != opt.input_file.as_deref())
+            .map(|path| module_name(path, &python_sys_path).split('.').next().unwrap_or_default().to_string())
+            .collect();
+        let inlined: Vec<String> = inlined.into_iter().collect();
+        content = emit_script_metadata(&content, script_metadata.as_ref(), opt.requires_python.as_deref(), &installed_distributions(fs, &python_sys_path), &inlined);
+    }
+    if opt.licenses.is_some() || opt.embed_licenses {
+        let licenses = inlined_licenses(fs, &python_sys_path, &processed);
+        for license in licenses.iter().filter(|license| license.license.is_none() && license.files.is_empty()) {
+            println!("No license found for the inlined distribution {} {}", license.name, license.version);
+        }
+        if opt.embed_licenses {
+            content = insert_stamp(&content, &license_comment(&render_notices(&[&output_file], &licenses)));
+        }
+        opt.inlined_licenses.borrow_mut().push((output_file.clone(), licenses));
+    }
+    if opt.sbom.is_some() {
+        opt.sbom_components.borrow_mut().push((output_file.clone(), inlined_components(fs, &python_sys_path, &processed)));
+    }
+    if opt.stamp {
+        content = insert_stamp(&content, &build_stamp(fs, &python_sys_path, &input_file, &processed, &opt)?.render());
+    }
+    if !opt.no_self_check {
+        for offset in misplaced_future_imports(&content) {
+            let site = format!("{}:{}", output_file.display(), line_number(&content, offset));
+            warn_at(&opt, WarningCategory::MisplacedFutureImport, &output_file.display().to_string(), &content, offset, site);
+        }
+    }
+    print_warnings(&opt);
+    let content = apply_line_ending(&content, opt.line_ending, &entry_source);
+    if opt.size_report || opt.max_size.is_some() {
+        check_size(&opt, &python_sys_path, &output_file, &content)?;
+    }
+    let Some(files) = write_output(output_fs, &output_file, &content, vendored, &entry_source, &opt)? else {
+        return Ok(());
+    };
+    if opt.terminal.color && !opt.quiet {
+        print!("{}", opt.terminal.summary_table(&[
+            ("Entry script", input_file.display().to_string()),
+            ("Output", format!("{} ({})", output_file.display(), ByteSize(content.len() as u64))),
+            ("Modules inlined", processed.len().to_string()),
+            ("Warnings", opt.warnings.records(&hidden_warnings(&opt)).len().to_string()),
+            ("Time", format!("{:.2}s", started.elapsed().as_secs_f64())),
+        ]));
+    }
+    if opt.check_syntax {
+        #[cfg(feature = "zipapp")]
+        if output_format(&opt) == OutputFormat::Zipapp {
+            modules::zipapp::check_zipapp_syntax(&interpreter(&opt)?, &content)?;
+        }
+        for file in files.iter().filter(|file| file.text) {
+            py_compile(&interpreter(&opt)?, &output_fs.canonicalize(&file.path).map_err(|e| InlinerError::io(&file.path, e))?)?;
+        }
+    }
+    if opt.emit_requirements.is_some() {
+        let mut remaining = external_imports(&content);
+        if vendoring {
+            // The vendored modules keep their imports, pointed into the vendor package when they
+            // import each other
+            let sources: Vec<String> = files.iter().map(|file| String::from_utf8_lossy(&file.contents).into_owned()).collect();
+            remaining = external_imports(&sources.join("\n"));
+            remaining.retain(|module| module != VENDOR_PACKAGE);
+        }
+        opt.remaining_imports.borrow_mut().push((output_file.clone(), remaining));
+    }
+    if opt.stub {
+        let stub_file = output_file.with_extension("pyi");
+        output_fs.write(&stub_file, apply_line_ending(&render_stub(&content), opt.line_ending, &entry_source).as_bytes()).map_err(|e| InlinerError::io(&stub_file, e))?;
+        println!("Stub written to {:?}", stub_file);
+    }
+    if opt.report.is_some() {
+        let report = bundle_report(fs, &opt, &input_file, &output_file, &requested_modules, &processed, report_time(&opt, started))?;
+        opt.bundle_reports.borrow_mut().push(report);
+    }
+    Ok(())
+}
+
+/// Record what an inlined file adds to the bundle for --size-report: the size of its inlined
+/// code, as the whole-bundle release and minify passes will leave it, less that of the files
+/// inlined into it
+fn note_size<'a>(opt: &Opt, path: &Path, code: &impl fmt::Display, nested: impl Iterator<Item = &'a PathBuf>) {
+    if !opt.size_report && opt.max_size.is_none() {
+        return;
+    }
+    let mut code = code.to_string();
+    if opt.release && !opt.embed_modules {
+        code = strip_blank_lines(&strip_comments(&strip_docstrings(&code)));
+    }
+    if opt.minify && !opt.embed_modules {
+        if !opt.keep_docstrings {
+            code = strip_docstrings(&code);
+        }
+        code = strip_trailing_whitespace(&strip_blank_lines(&strip_comments(&code)));
+    }
+    // The bundle ends each inlined file with a newline, however many the file ends with
+    let code = match code.trim_end_matches('\n') {
+        "" => String::new(),
+        trimmed => format!("{}\n", trimmed),
+    };
+    let mut sizes = opt.module_sizes.borrow_mut();
+    let (nested_bytes, nested_lines) = nested.filter_map(|file| sizes.get(file)).fold((0, 0), |(bytes, lines), size| (bytes + size.bytes, lines + size.lines));
+    let size = ModuleSize { bytes: code.len().saturating_sub(nested_bytes), lines: code.lines().count().saturating_sub(nested_lines) };
+    sizes.insert(path.to_path_buf(), size);
+}
+
+/// Hash the entry script and every inlined file for --stamp
+fn build_stamp(fs: &dyn FileSystem, python_sys_path: &[PathBuf], input_file: &Path, processed: &HashSet<PathBuf>, opt: &Opt) -> Result<BuildStamp, InlinerError> {
+    let input = (search_path_relative(input_file, python_sys_path), sha256_hex(&fs.read(input_file).map_err(|e| InlinerError::io(input_file, e))?));
+    let mut inlined = Vec::new();
+    for path in processed {
+        inlined.push((search_path_relative(path, python_sys_path), sha256_hex(&fs.read(path).map_err(|e| InlinerError::io(path, e))?)));
+    }
+    inlined.sort();
+    let template = match &opt.template {
+        Some(template) => Some((search_path_relative(template, python_sys_path), sha256_hex(&fs.read(template).map_err(|e| InlinerError::io(template, e))?))),
+        None => None,
+    };
+    let timestamp = if opt.stamp_time { Some(document_time(opt)?) } else { None };
+    Ok(BuildStamp { input, template, inlined, timestamp })
+}
+
+/// The time to date what a run writes with: SOURCE_DATE_EPOCH if set, else now, and the epoch
+/// with --deterministic
+fn document_time(opt: &Opt) -> Result<u64, InlinerError> {
+    if opt.environment.deterministic {
+        return Ok(0);
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| InlinerError::usage(format!("The system clock is set before 1970: {}", e)))?.as_secs();
+    build_timestamp(std::env::var("SOURCE_DATE_EPOCH").ok().as_deref(), now).map_err(InlinerError::usage)
+}
+
+/// The path relative to the search path entry it's under (the deepest, when they nest), with
+/// forward slashes
+fn search_path_relative(path: &Path, python_sys_path: &[PathBuf]) -> String {
+    let relative = python_sys_path.iter()
+        .filter_map(|dir| path.strip_prefix(dir).ok())
+        .min_by_key(|relative| relative.components().count())
+        .unwrap_or(path);
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+/// The dotted name of the module a file is, from its path under the search path
+fn module_name(path: &Path, python_sys_path: &[PathBuf]) -> String {
+    let relative = search_path_relative(path, python_sys_path);
+    let relative = relative.strip_suffix(".py").unwrap_or(&relative);
+    relative.strip_suffix("/__init__").unwrap_or(relative).replace('/', ".")
+}
+
+/// The warning categories not printed: those --allow hides and, without --verbose, all but the
+/// self-check's hazards
+fn hidden_warnings(opt: &Opt) -> Vec<WarningCategory> {
+    WarningCategory::ALL.iter()
+        .filter(|category| opt.allow.contains(category) || !(opt.verbose || category.is_hazard()))
+        .copied()
+        .collect()
+}
+
+/// Print the warnings raised for the bundle, except the hidden ones
+fn print_warnings(opt: &Opt) {
+    opt.terminal.progress.borrow_mut().finish();
+    if opt.quiet {
+        return;
+    }
+    print!("{}", opt.terminal.paint_warnings(&opt.warnings.render(&hidden_warnings(opt))));
+}
+
+/// Record what went into a bundle for --report, hashing each inlined file
+#[allow(clippy::too_many_arguments)]
+fn bundle_report(fs: &dyn FileSystem, opt: &Opt, input_file: &Path, output_file: &Path, requested_modules: &str, processed: &HashSet<PathBuf>, elapsed: Duration) -> Result<BundleReport, InlinerError> {
+    let mut paths: Vec<&PathBuf> = processed.iter().collect();
+    paths.sort();
+    let mut inlined = Vec::new();
+    for path in paths {
+        let contents = fs.read(path).map_err(|e| InlinerError::io(path, e))?;
+        let encoding = source_encoding(&contents).unwrap_or("unknown");
+        let source = read_decoded(fs, path)?;
+        let mut transformations = opt.transformations.borrow().get(path).cloned().unwrap_or_default();
+        if encoding != "utf-8" {
+            transformations.insert("decoded");
+        }
+        if source.contains('\r') {
+            transformations.insert("line_endings");
+        }
+        // Whole-bundle passes, which inlined files go through like the rest
+        if !opt.embed_modules && opt.release {
+            transformations.insert("release");
+        }
+        if !opt.embed_modules && opt.minify {
+            transformations.insert("minify");
+        }
+        if opt.format || opt.formatter.is_some() {
+            transformations.insert("format");
+        }
+        inlined.push(InlinedFile {
+            path: path.clone(),
+            sha256: sha256_hex(&contents),
+            bytes: contents.len(),
+            encoding: encoding.to_string(),
+            lines: normalize_line_endings(&source).lines().count(),
+            transformations: transformations.into_iter().map(String::from).collect(),
+        });
+    }
+    Ok(BundleReport {
+        input_file: input_file.to_path_buf(),
+        output_file: output_file.to_path_buf(),
+        module_names: requested_modules.to_string(),
+        inlined,
+        imports: opt.imports.borrow().iter().cloned().collect(),
+        warnings: opt.warnings.records(&opt.allow),
+        elapsed,
+    })
+}
+
+/// Refuse options whose subsystem was left out of the build (see `[features]` in Cargo.toml)
+#[cfg_attr(all(feature = "zipapp", feature = "audit"), allow(unused_variables))]
+fn check_features(opt: &Opt) -> Result<(), String> {
+    let left_out: &[(bool, &str, &str)] = &[
+        #[cfg(not(feature = "zipapp"))]
+        (output_format(opt) == OutputFormat::Zipapp, "--zipapp", "zipapp"),
+        #[cfg(not(feature = "audit"))]
+        (opt.audit || opt.audit_warn_only, "--audit", "audit"),
+    ];
+    for &(used, option, feature) in left_out {
+        if used {
+            return Err(format!("{} isn't available, python-inliner was built without the `{}` feature", option, feature));
+        }
+    }
+    Ok(())
+}
+
+/// Add the source directories of editable installs to the search path, returning the
+/// (distribution, source directory) pairs that were found
+fn handle_editable_installs(fs: &dyn FileSystem, python_sys_path: &mut Vec<PathBuf>) -> Result<Vec<(String, PathBuf)>, InlinerError> {
+    let mut editable_installs = Vec::new();
+    let site_packages_paths: Vec<PathBuf> = python_sys_path
+        .iter()
+        .filter(|path| path.to_string_lossy().contains("site-packages"))
+        .cloned()
+        .collect();
+
+    for path in site_packages_paths {
+        // println!("path: {:?}", path);
+        let pattern = path.join("*.dist-info").join("direct_url.json");
+        for direct_url_path in fs.glob(&pattern).map_err(|e| InlinerError::io(&pattern, e))? {
+            let entry_path = direct_url_path.parent().unwrap().to_path_buf();
+            let content = fs.read_to_string(&direct_url_path).map_err(|e| InlinerError::io(&direct_url_path, e))?;
+            let json: Value = serde_json::from_str(&content).map_err(|e| InlinerError::parse(&direct_url_path, e.to_string()))?;
+
+            if let Some(url) = json.get("url").and_then(Value::as_str) {
+                if let Some(dir_info) = json.get("dir_info") {
+                    if let Some(true) = dir_info.get("editable").and_then(Value::as_bool) {
+                        if let Some(package_path) = file_url_to_path(url) {
+                            if fs.is_dir(&package_path).map_err(|e| InlinerError::io(&package_path, e))? && !python_sys_path.contains(&package_path) {
+                                python_sys_path.push(package_path.clone());
+                            }
+                            let dist_info = entry_path.file_name().unwrap().to_string_lossy();
+                            let distribution = dist_info.trim_end_matches(".dist-info").split('-').next().unwrap_or("").to_string();
+                            editable_installs.push((distribution, package_path));
+                        }
+                    }
+                }
+            }
+        }
+        // setuptools >= 64 installs an import hook instead of a path entry; its MAPPING names the
+        // directory of each package, which is searched through the directory containing it
+        let pattern = path.join("__editable___*_finder.py");
+        for finder_path in fs.glob(&pattern).map_err(|e| InlinerError::io(&pattern, e))? {
+            let Some(distribution) = finder_distribution(&finder_path) else { continue };
+            for package_dir in finder_mapping(&fs.read_to_string(&finder_path).map_err(|e| InlinerError::io(&finder_path, e))?) {
+                let Some(source_dir) = package_dir.parent().map(Path::to_path_buf) else { continue };
+                if fs.is_dir(&source_dir).map_err(|e| InlinerError::io(&source_dir, e))? && !python_sys_path.contains(&source_dir) {
+                    python_sys_path.push(source_dir.clone());
+                }
+                let install = (distribution.clone(), source_dir);
+                if !editable_installs.contains(&install) {
+                    editable_installs.push(install);
+                }
+            }
+        }
+    }
+    Ok(editable_installs)
+}
+
+/// Read a Python source file decoded from its declared encoding
+fn read_decoded(fs: &dyn FileSystem, path: &Path) -> Result<String, InlinerError> {
+    let bytes = fs.read(path).map_err(|e| InlinerError::io(path, e))?;
+    decode_source(&bytes).map_err(|reason| InlinerError::Parse { file: path.to_path_buf(), reason, site: None })
+}
+
+/// Read a Python source file decoded from its declared encoding, with its line endings
+/// normalized to `\n`
+fn read_source(fs: &dyn FileSystem, path: &Path) -> Result<String, InlinerError> {
+    Ok(normalize_line_endings(&read_decoded(fs, path)?))
+}
+
+/// 1-based line number of a byte offset
+fn line_number(content: &str, offset: usize) -> usize {
+    content[..offset].matches('\n').count() + 1
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benchmarks;
+
+#[cfg(test)]
+#[allow(clippy::vec_init_then_push)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+    use crate::modules::import_chain::explain_inclusion;
+    use crate::modules::indentation::Reindent;
+    use crate::modules::inlining::{indent_code, resolve_from_import};
+    use crate::modules::module_cache::{ModuleArtifact, ModuleTransformation};
+    #[cfg(feature = "remote-cache")]
+    use crate::modules::remote_cache::RemoteCache;
+    use crate::modules::line_endings::LineEnding;
+    use crate::modules::options::EnvironmentArgs;
+    use crate::modules::output::show_output_changes;
+    use crate::modules::output_writer::{OutputWriter, SingleFileWriter};
+    use crate::modules::release::post_process_imports;
+    use crate::modules::warnings::WarningAggregator;
+
+    const MAIN_PY_CONTENT: &str = r#"#!/usr/bin/env python3
+from modules.module1 import func1
+
+def main():
+    from modules.module1 import func2
+    print('Hello')
+
+if __name__ == '__main__':
+    main()
+"#;
+
+    const MODULE1_PY_CONTENT: &str = r#"def func1():
+    print('Function 1')
+"#;
+
+    const INLINED_CONTENT: &str = r#"#!/usr/bin/env python3
+# ↓↓↓ inlined submodule: modules.module1
+def func1():
+    print('Function 1')
+
+# ↑↑↑ inlined submodule: modules.module1
+
+def main():
+    # →→ modules.module1 ←← module already inlined
+    print('Hello')
+
+if __name__ == '__main__':
+    main()
+"#;
+
+    #[test]
+    fn test_inline_imports_simple() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/modules")).unwrap();
+        mock_fs.write(Path::new("/test/main.py"), MAIN_PY_CONTENT.as_bytes()).unwrap();
+        mock_fs.write(Path::new("/test/modules/module1.py"), MODULE1_PY_CONTENT.as_bytes()).unwrap();
+
+        let input_file = PathBuf::from("/test/main.py");
+        let output_file = PathBuf::from("/test/main_inlined.py");
+        let module_names = "modules".to_string();
+        let release = false;
+        let verbose = false;
+
+        let mut python_sys_path = Vec::new();
+        python_sys_path.push(PathBuf::from("/test/modules"));
+        run(
+            input_file,
+            output_file,
+            module_names,
+            release,
+            verbose,
+            &mock_fs,
+            &python_sys_path,
+        ).unwrap();
+
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(result, INLINED_CONTENT);
+    }
+
+    #[test]
+    fn test_post_process_imports() {
+        let input = r#"#!/usr/bin/env python3
+import sys
+from os import path
+
+def main():
+    print('Hello')
+
+import re
+
+if __name__ == '__main__':
+    main()
+"#;
+
+        let expected = r#"#!/usr/bin/env python3
+
+import re
+import sys
+from os import path
+
+def main():
+    print('Hello')
+
+
+if __name__ == '__main__':
+    main()
+"#;
+
+        assert_eq!(post_process_imports(input, &[]), expected);
+    }
+
+    #[test]
+    fn test_javascript_import_filtering() {
+        // This test verifies that JavaScript-style imports embedded in Python code
+        // are not mistakenly detected as Python imports
+        let input = r#"#!/usr/bin/env python3
+import os
+from sys import path
+
+def generate_html(is_markdown):
+    mermaid_script = ""
+    if is_markdown:
+        mermaid_script = """
+    <script type="module">
+        import mermaid from 'https://cdn.jsdelivr.net/npm/mermaid@10/dist/mermaid.esm.min.mjs';
+        mermaid.initialize({ startOnLoad: true, theme: 'dark' });
+    </script>"""
+    return f"<html>{mermaid_script}</html>"
+
+def main():
+    import re
+
+if __name__ == '__main__':
+    main()
+"#;
+
+        let expected = r#"#!/usr/bin/env python3
+
+import os
+from sys import path
+
+def generate_html(is_markdown):
+    mermaid_script = ""
+    if is_markdown:
+        mermaid_script = """
+    <script type="module">
+        import mermaid from 'https://cdn.jsdelivr.net/npm/mermaid@10/dist/mermaid.esm.min.mjs';
+        mermaid.initialize({ startOnLoad: true, theme: 'dark' });
+    </script>"""
+    return f"<html>{mermaid_script}</html>"
+
+def main():
+    import re
+
+if __name__ == '__main__':
+    main()
+"#;
+
+        assert_eq!(post_process_imports(input, &[]), expected);
+    }
+
+    #[test]
+    fn test_module_level_indentation_preservation() {
+        // This test verifies that function-scoped imports correctly indent
+        // the inlined content to match the import statement's indentation level
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+
+        // Module with module-level constants at indentation 0
+        let environment_py = r#"import os
+
+API_KEY = os.getenv("API_KEY") or "default-key"
+ANOTHER_CONSTANT = "value"
+
+def helper_function():
+    return API_KEY
+"#;
+        mock_fs.write(Path::new("/test/mylib/environment.py"), environment_py.as_bytes()).unwrap();
+
+        // Main file that imports from an indented context (inside a function)
+        let main_py = r#"def my_function():
+    from mylib.environment import API_KEY
+    return API_KEY
+
+if __name__ == '__main__':
+    print(my_function())
+"#;
+        mock_fs.write(Path::new("/test/main.py"), main_py.as_bytes()).unwrap();
+
+        let input_file = PathBuf::from("/test/main.py");
+        let output_file = PathBuf::from("/test/main_inlined.py");
+        let module_names = "mylib".to_string();
+        let release = false;
+        let verbose = false;
+
+        let mut python_sys_path = Vec::new();
+        python_sys_path.push(PathBuf::from("/test"));
+
+        run(
+            input_file,
+            output_file,
+            module_names,
+            release,
+            verbose,
+            &mock_fs,
+            &python_sys_path,
+        ).unwrap();
+
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+
+        // The expected output should have inlined content indented to match
+        // the import statement's indentation level (4 spaces in this case)
+        let expected = r#"def my_function():
+    # ↓↓↓ inlined submodule: mylib.environment
+    import os
+
+    API_KEY = os.getenv("API_KEY") or "default-key"
+    ANOTHER_CONSTANT = "value"
+
+    def helper_function():
+        return API_KEY
+
+    # ↑↑↑ inlined submodule: mylib.environment
+    return API_KEY
+
+if __name__ == '__main__':
+    print(my_function())
+"#;
+
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+    }
+
+    #[test]
+    fn test_multiline_import_removal() {
+        // This test reproduces the bug where multi-line import statements
+        // are not completely removed, leaving dangling import names
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+
+        // Module with some constants
+        let environment_py = r#"import os
+
+API_KEY = os.getenv("API_KEY") or "default-key"
+ANOTHER_KEY = os.getenv("ANOTHER") or "other"
+THIRD_KEY = "third"
+"#;
+        mock_fs.write(Path::new("/test/mylib/environment.py"), environment_py.as_bytes()).unwrap();
+
+        // Main file with multi-line import statement
+        let main_py = r#"from mylib.environment import (
+    API_KEY,
+    ANOTHER_KEY,
+    THIRD_KEY,
+)
+
+def my_function():
+    return API_KEY
+
+if __name__ == '__main__':
+    print(my_function())
+"#;
+        mock_fs.write(Path::new("/test/main.py"), main_py.as_bytes()).unwrap();
+
+        let input_file = PathBuf::from("/test/main.py");
+        let output_file = PathBuf::from("/test/main_inlined.py");
+        let module_names = "mylib".to_string();
+        let release = false;
+        let verbose = false;
+
+        let mut python_sys_path = Vec::new();
+        python_sys_path.push(PathBuf::from("/test"));
+
+        run(
+            input_file,
+            output_file,
+            module_names,
+            release,
+            verbose,
+            &mock_fs,
+            &python_sys_path,
+        ).unwrap();
+
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+
+        // The expected output should have the entire multi-line import replaced,
+        // with NO dangling import names or parentheses
+        let expected = r#"# ↓↓↓ inlined submodule: mylib.environment
+import os
+
+API_KEY = os.getenv("API_KEY") or "default-key"
+ANOTHER_KEY = os.getenv("ANOTHER") or "other"
+THIRD_KEY = "third"
+
+# ↑↑↑ inlined submodule: mylib.environment
+
+def my_function():
+    return API_KEY
+
+if __name__ == '__main__':
+    print(my_function())
+"#;
+
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+    }
+
+    #[test]
+    fn test_function_scoped_import_indentation() {
+        // This test reproduces the bug where imports inside function bodies
+        // cause inlined content to be at wrong indentation level (0 instead of function indent)
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+
+        // Module with module-level code (indentation 0 in source file)
+        let llm_response_py = r#"from dataclasses import dataclass
+
+@dataclass
+class LLMResponse:
+    """Response from LLM API."""
+    content: str
+    model: str
+
+    def from_api_response(self, api_data):
+        return LLMResponse(
+            content=api_data.get("content", ""),
+            model=api_data.get("model", "unknown")
+        )
+"#;
+        mock_fs.write(Path::new("/test/mylib/llm_response.py"), llm_response_py.as_bytes()).unwrap();
+
+        // Main file with function-scoped imports (indented inside function body)
+        let main_py = r#"def call_llm_light(prompt: str, temperature: float = 0.0):
+    """Call LLM using light provider config."""
+    from mylib.llm_response import LLMResponse
+
+    payload = {
+        "model": "test-model",
+        "messages": [{"role": "user", "content": prompt}]
+    }
+
+    # Simulated API response
+    api_data = {"content": "Hello, world!", "model": "test-model"}
+    return LLMResponse.from_api_response(api_data)
+
+if __name__ == '__main__':
+    result = call_llm_light("Hello!")
+    print(result)
+"#;
+        mock_fs.write(Path::new("/test/main.py"), main_py.as_bytes()).unwrap();
+
+        let input_file = PathBuf::from("/test/main.py");
+        let output_file = PathBuf::from("/test/main_inlined.py");
+        let module_names = "mylib".to_string();
+        let release = false;
+        let verbose = false;
+
+        let mut python_sys_path = Vec::new();
+        python_sys_path.push(PathBuf::from("/test"));
+
+        run(
+            input_file,
+            output_file,
+            module_names,
+            release,
+            verbose,
+            &mock_fs,
+            &python_sys_path,
+        ).unwrap();
+
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+
+        // The expected output should have inlined content indented at the same level
+        // as the import statement (4 spaces), NOT at module level (0 spaces)
+        let expected = r#"def call_llm_light(prompt: str, temperature: float = 0.0):
+    """Call LLM using light provider config."""
+    # ↓↓↓ inlined submodule: mylib.llm_response
+    from dataclasses import dataclass
+
+    @dataclass
+    class LLMResponse:
+        """Response from LLM API."""
+        content: str
+        model: str
+
+        def from_api_response(self, api_data):
+            return LLMResponse(
+                content=api_data.get("content", ""),
+                model=api_data.get("model", "unknown")
+            )
+
+    # ↑↑↑ inlined submodule: mylib.llm_response
+
+    payload = {
+        "model": "test-model",
+        "messages": [{"role": "user", "content": prompt}]
+    }
+
+    # Simulated API response
+    api_data = {"content": "Hello, world!", "model": "test-model"}
+    return LLMResponse.from_api_response(api_data)
+
+if __name__ == '__main__':
+    result = call_llm_light("Hello!")
+    print(result)
+"#;
+
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+    }
+
+    #[test]
+    #[ignore] // TODO: Implement __all__ statement filtering for inlined content
+    #[allow(non_snake_case)]
+    fn test___all___statement_removal() {
+        // This test reproduces the bug where __all__ statements from modules/packages
+        // are inlined into functions, causing invalid Python syntax
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+
+        // Package __init__.py with __all__ statement
+        let init_py = r#"""My library package."""
+
+from .utils import helper_function
+
+__all__ = ["helper_function"]
+"#;
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), init_py.as_bytes()).unwrap();
+
+        // Utils module
+        let utils_py = r#"def helper_function():
+    """Helper function."""
+    return "Hello, world!"
+"#;
+        mock_fs.write(Path::new("/test/mylib/utils.py"), utils_py.as_bytes()).unwrap();
+
+        // Main file with function-scoped import
+        let main_py = r#"def process_data():
+    """Process data using mylib."""
+    from mylib import helper_function
+
+    result = helper_function()
+    return result.upper()
+
+if __name__ == '__main__':
+    print(process_data())
+"#;
+        mock_fs.write(Path::new("/test/main.py"), main_py.as_bytes()).unwrap();
+
+        let input_file = PathBuf::from("/test/main.py");
+        let output_file = PathBuf::from("/test/main_inlined.py");
+        let module_names = "mylib".to_string();
+        let release = false;
+        let verbose = false;
+
+        let mut python_sys_path = Vec::new();
+        python_sys_path.push(PathBuf::from("/test"));
+
+        run(
+            input_file,
+            output_file,
+            module_names,
+            release,
+            verbose,
+            &mock_fs,
+            &python_sys_path,
+        ).unwrap();
+
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+
+        // The expected output should NOT include the __all__ statement
+        // from mylib/__init__.py, as it's only meaningful at module level
+        let expected = r#"def process_data():
+    """Process data using mylib."""
+    # ↓↓↓ inlined package: mylib
+    """My library package."""
+
+    # ↓↓↓ inlined submodule: .utils
+    def helper_function():
+        """Helper function."""
+        return "Hello, world!"
+
+    # ↑↑↑ inlined submodule: .utils
+
+    # ↑↑↑ inlined package: mylib
+
+    result = helper_function()
+    return result.upper()
+
+if __name__ == '__main__':
+    print(process_data())
+"#;
+
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+    }
+
+    #[test]
+    fn test_strip_docstrings_simple() {
+        // Test basic function and class docstrings
+        let input = r##""""Module docstring."""
+
+def func():
+    """Function docstring."""
+    pass
+
+class MyClass:
+    """Class docstring."""
+    pass
+"##;
+
+        // Note: strip_docstrings leaves blank lines (including indented ones) behind - that's OK!
+        // strip_blank_lines() will clean them up in the full release mode flow
+        let expected = "\n\ndef func():\n    \n    pass\n\nclass MyClass:\n    \n    pass\n";
+
+        assert_eq!(strip_docstrings(input), expected);
+    }
+
+    #[test]
+    fn test_strip_docstrings_preserves_variable_assignment() {
+        // Test that variable assignments with triple quotes are preserved
+        let input = r##""""Module docstring."""
+
+MY_VAR = """This is assigned to a variable and should be preserved."""
+
+def func():
+    """Function docstring."""
+    pass
+"##;
+
+        let expected = "\n\nMY_VAR = \"\"\"This is assigned to a variable and should be preserved.\"\"\"\n\ndef func():\n    \n    pass\n";
+
+        assert_eq!(strip_docstrings(input), expected);
+    }
+
+    #[test]
+    fn test_strip_docstrings_f_string_preserved() {
+        // Test that f-strings with triple quotes are preserved
+        let input = r##""""Module docstring."""
+
+def func():
+    """Function docstring."""
+    some_var = f"""long
+string {self.name} with interpolation
+"""
+    pass
+"##;
+
+        let expected = "\n\ndef func():\n    \n    some_var = f\"\"\"long\nstring {self.name} with interpolation\n\"\"\"\n    pass\n";
+
+        assert_eq!(strip_docstrings(input), expected);
+    }
+
+    #[test]
+    fn test_strip_docstrings_single_quotes() {
+        // Test that single triple quotes are also removed as docstrings
+        let input = r##""""Module docstring."""
+
+def func():
+    '''Function docstring with single quotes.'''
+    pass
+
+class MyClass:
+    '''Class docstring with single quotes.'''
+    pass
+"##;
+
+        let expected = "\n\ndef func():\n    \n    pass\n\nclass MyClass:\n    \n    pass\n";
+
+        assert_eq!(strip_docstrings(input), expected);
+    }
+
+    #[test]
+    fn test_strip_docstrings_preserves_attribute_assignment() {
+        // Test that attribute assignments (self.attr, obj.attr) with triple quotes are preserved
+        let input = r##""""Module docstring."""
+
+class MyClass:
+    def __init__(self):
+        """Init docstring."""
+        self.template = """
+        This should be preserved.
+        """
+        pass
+"##;
+
+        let expected = "\n\nclass MyClass:\n    def __init__(self):\n        \n        self.template = \"\"\"\n        This should be preserved.\n        \"\"\"\n        pass\n";
+
+        assert_eq!(strip_docstrings(input), expected);
+    }
+
+    #[test]
+    fn test_strip_docstrings_no_docstrings() {
+        // Test code without docstrings
+        let input = r#"def func():
+    pass
+
+class MyClass:
+    pass
+"#;
+
+        assert_eq!(strip_docstrings(input), input);
+    }
+
+    #[test]
+    fn test_strip_comments_whole_line() {
+        // Test removing whole-line comments
+        let input = r#"#!/usr/bin/env python3
+# This is a comment
+import sys
+
+# Another comment
+def main():
+    pass
+"#;
+
+        let expected = r#"#!/usr/bin/env python3
+import sys
+def main():
+    pass
+"#;
+
+        assert_eq!(strip_comments(input), expected);
+    }
+
+    #[test]
+    fn test_strip_comments_inline() {
+        // Test removing inline comments
+        let input = r#"#!/usr/bin/env python3
+import sys  # This is an inline comment
+
+def main():
+    pass  # Another inline comment
+"#;
+
+        let expected = r#"#!/usr/bin/env python3
+import sys
+def main():
+    pass
+"#;
+
+        assert_eq!(strip_comments(input), expected);
+    }
+
+    #[test]
+    fn test_strip_comments_preserves_strings_with_hash() {
+        // Test that comments inside strings are preserved
+        let input = r#"def func():
+    s = "This # is not a comment"
+    s2 = 'This # is also not a comment'
+    pass
+"#;
+
+        let expected = r#"def func():
+    s = "This # is not a comment"
+    s2 = 'This # is also not a comment'
+    pass
+"#;
+
+        assert_eq!(strip_comments(input), expected);
+    }
+
+    #[test]
+    fn test_strip_comments_preserves_triple_quoted_strings() {
+        // Test that triple-quoted strings with # are preserved
+        let input = r#"MY_VAR = """
+This string contains # symbols that are not comments.
+They should be preserved.
+"""
+"#;
+
+        // # symbols inside triple-quoted strings should be preserved
+        let expected = r#"MY_VAR = """
+This string contains # symbols that are not comments.
+They should be preserved.
+"""
+"#;
+
+        assert_eq!(strip_comments(input), expected);
+    }
+
+    #[test]
+    fn test_strip_comments_no_comments() {
+        // Test code without comments
+        let input = r#"#!/usr/bin/env python3
+import sys
+
+def main():
+    pass
+"#;
+
+        let expected = r#"#!/usr/bin/env python3
+import sys
+def main():
+    pass
+"#;
+
+        assert_eq!(strip_comments(input), expected);
+    }
+
+    #[test]
+    fn test_strip_comments_preserves_pep723_block() {
+        // Test that PEP 723 inline script metadata blocks are preserved
+        let input = r#"#!/usr/bin/env python3
+# /// script
+# requires-python = ">=3.12"
+# dependencies = [
+#     "prompt-toolkit>=3.0.47",
+#     "pydantic>=2.9.1",
+# ]
+# ///
+# This comment should be removed
+import sys
+
+def main():
+    pass  # This comment should also be removed
+"#;
+
+        let expected = r#"#!/usr/bin/env python3
+# /// script
+# requires-python = ">=3.12"
+# dependencies = [
+#     "prompt-toolkit>=3.0.47",
+#     "pydantic>=2.9.1",
+# ]
+# ///
+import sys
+def main():
+    pass
+"#;
+
+        assert_eq!(strip_comments(input), expected);
+    }
+
+    #[test]
+    fn test_strip_blank_lines_single() {
+        // Test removing single blank lines
+        let input = r#"#!/usr/bin/env python3
+
+import sys
+
+def main():
+    pass
+"#;
+
+        let expected = r#"#!/usr/bin/env python3
+import sys
+def main():
+    pass
+"#;
+
+        assert_eq!(strip_blank_lines(input), expected);
+    }
+
+    #[test]
+    fn test_strip_blank_lines_multiple() {
+        // Test removing multiple consecutive blank lines
+        let input = r#"#!/usr/bin/env python3
+
+
+import sys
+
+
+def main():
+
+
+    pass
+"#;
+
+        let expected = r#"#!/usr/bin/env python3
+import sys
+def main():
+    pass
+"#;
+
+        assert_eq!(strip_blank_lines(input), expected);
+    }
+
+    #[test]
+    fn test_strip_blank_lines_no_blank_lines() {
+        // Test code without blank lines
+        let input = r#"#!/usr/bin/env python3
+import sys
+def main():
+    pass
+"#;
+
+        assert_eq!(strip_blank_lines(input), input);
+    }
+
+    #[test]
+    fn test_strip_blank_lines_whitespace_only() {
+        // Test that lines with only whitespace are removed
+        let input = r#"#!/usr/bin/env python3
+
+import sys
+
+    def main():
+    	pass
+"#;
+
+        let expected = r#"#!/usr/bin/env python3
+import sys
+    def main():
+    	pass
+"#;
+
+        assert_eq!(strip_blank_lines(input), expected);
+    }
+
+    #[test]
+    fn test_release_mode_complete_flow() {
+        // Integration test for complete release mode flow with docstrings, comments, and blank lines
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+
+        // Module with docstrings, comments, and blank lines
+        let mylib_py = r##""""My library module."""
+
+# This is a module-level comment
+import sys
+
+
+MY_VAR = """This should be preserved."""
+
+
+class MyClass:
+    """This is a class docstring - should be removed."""
+
+    # This is a comment about __init__
+    def __init__(self):
+        """Initialize the class."""
+        self.name = "MyClass"
+
+
+def my_func():
+    """This is a function docstring - should be removed."""
+    # Inline comment
+    return "Hello"
+
+
+# Another module-level comment
+"##;
+        mock_fs.write(Path::new("/test/mylib/mylib.py"), mylib_py.as_bytes()).unwrap();
+
+        // Main file with various comments and docstrings
+        let main_py = r##"#!/usr/bin/env python3
+"""Main script for testing."""
+
+# Import statement
+from mylib.mylib import MyClass
+
+
+def main():
+    """Main entry point."""
+    # Create instance
+    obj = MyClass()
+    print(obj.name)
+
+
+if __name__ == '__main__':
+    # Run main
+    main()
+"##;
+        mock_fs.write(Path::new("/test/main.py"), main_py.as_bytes()).unwrap();
+
+        let input_file = PathBuf::from("/test/main.py");
+        let output_file = PathBuf::from("/test/main_inlined.py");
+        let module_names = "mylib".to_string();
+        let release = true;
+        let verbose = false;
+
+        let mut python_sys_path = Vec::new();
+        python_sys_path.push(PathBuf::from("/test"));
+
+        run(
+            input_file,
+            output_file,
+            module_names,
+            release,
+            verbose,
+            &mock_fs,
+            &python_sys_path,
+        ).unwrap();
+
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+
+        // Expected: shebang preserved, all docstrings removed, all comments removed,
+        // all blank lines removed, imports consolidated and sorted, mylib inlined
+        let expected = r#"#!/usr/bin/env python3
+import sys
+MY_VAR = """This should be preserved."""
+class MyClass:
+    def __init__(self):
+        self.name = "MyClass"
+def my_func():
+    return "Hello"
+def main():
+    obj = MyClass()
+    print(obj.name)
+if __name__ == '__main__':
+    main()
+"#;
+
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+    }
+
+    #[test]
+    fn test_release_mode_preserves_pep723_block() {
+        // Integration test for release mode with PEP 723 inline script metadata block
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+
+        // Simple module
+        let mylib_py = r#"def helper():
+    return "Hello"
+"#;
+        mock_fs.write(Path::new("/test/mylib/helper.py"), mylib_py.as_bytes()).unwrap();
+
+        // Main file with PEP 723 block
+        let main_py = r#"#!/usr/bin/env python
+# /// script
+# requires-python = ">=3.12"
+# dependencies = [
+#     "prompt-toolkit>=3.0.47",
+#     "pydantic>=2.9.1",
+# ]
+# ///
+"""Main script."""
+
+from mylib.helper import helper
+
+
+def main():
+    # This comment should be removed
+    result = helper()
+    print(result)
+
+
+if __name__ == '__main__':
+    # Run main
+    main()
+"#;
+        mock_fs.write(Path::new("/test/main.py"), main_py.as_bytes()).unwrap();
+
+        let input_file = PathBuf::from("/test/main.py");
+        let output_file = PathBuf::from("/test/main_inlined.py");
+        let module_names = "mylib".to_string();
+        let release = true;
+        let verbose = false;
+
+        let mut python_sys_path = Vec::new();
+        python_sys_path.push(PathBuf::from("/test"));
+
+        run(
+            input_file,
+            output_file,
+            module_names,
+            release,
+            verbose,
+            &mock_fs,
+            &python_sys_path,
+        ).unwrap();
+
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+
+        // Expected: PEP 723 block preserved, shebang preserved, docstrings removed,
+        // other comments removed, blank lines removed, mylib inlined
+        let expected = r#"#!/usr/bin/env python
+# /// script
+# requires-python = ">=3.12"
+# dependencies = [
+#     "prompt-toolkit>=3.0.47",
+#     "pydantic>=2.9.1",
+# ]
+# ///
+def helper():
+    return "Hello"
+def main():
+    result = helper()
+    print(result)
+if __name__ == '__main__':
+    main()
+"#;
+
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+    }
+
+    #[test]
+    fn test_pep723_dependencies_are_external() {
+        // Dependencies declared in the entry script's PEP 723 block are left as imports,
+        // even when they match the requested module names
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.mkdir_p(Path::new("/test/requests")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/helper.py"), b"def helper():\n    return 1\n").unwrap();
+        mock_fs.write(Path::new("/test/requests/__init__.py"), b"def get():\n    pass\n").unwrap();
+
+        let main_py = r#"# /// script
+# dependencies = ["requests>=2"]
+# ///
+from mylib.helper import helper
+from requests import get
+"#;
+        mock_fs.write(Path::new("/test/main.py"), main_py.as_bytes()).unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib,requests".to_string(),
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &python_sys_path).unwrap();
+
+        let expected = r#"# /// script
+# dependencies = ["requests>=2"]
+# ///
+# ↓↓↓ inlined submodule: mylib.helper
+def helper():
+    return 1
+
+# ↑↑↑ inlined submodule: mylib.helper
+from requests import get
+"#;
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+
+        // With --inline-script-deps the declared dependency is resolved and inlined
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            inline_script_deps: true,
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &python_sys_path).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(result.contains("# ↓↓↓ inlined package: requests"), "Got:\n{}", result);
+    }
+
+    #[test]
+    fn test_tree_shake_removes_unused_definitions() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        let utils_py = r#"import os
+
+LIMIT = 10
+UNUSED_CONSTANT = "unused"
+
+
+def used_helper():
+    return _format(LIMIT)
+
+
+def _format(value):
+    return str(value)
+
+
+class UnusedClass:
+    def method(self):
+        return os.getcwd()
+"#;
+        mock_fs.write(Path::new("/test/mylib/utils.py"), utils_py.as_bytes()).unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib.utils import used_helper\n\nprint(used_helper())\n").unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            tree_shake: true,
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &python_sys_path).unwrap();
+
+        let expected = r#"# ↓↓↓ inlined submodule: mylib.utils
+import os
+
+LIMIT = 10
+
+
+def used_helper():
+    return _format(LIMIT)
+
+
+def _format(value):
+    return str(value)
+
+
+
+# ↑↑↑ inlined submodule: mylib.utils
+
+print(used_helper())
+"#;
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+    }
+
+    #[test]
+    fn test_emit_requirements() {
+        let mock_fs = VirtualFileSystem::new();
+        let site_packages = Path::new("/venv/lib/python3.12/site-packages");
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.mkdir_p(&site_packages.join("requests-2.31.0.dist-info")).unwrap();
+        mock_fs.mkdir_p(&site_packages.join("PyYAML-6.0.1.dist-info")).unwrap();
+        mock_fs.write(&site_packages.join("requests-2.31.0.dist-info/RECORD"), b"requests/__init__.py,,\n").unwrap();
+        mock_fs.write(&site_packages.join("PyYAML-6.0.1.dist-info/METADATA"), b"Name: PyYAML\nVersion: 6.0.1\n").unwrap();
+        mock_fs.write(&site_packages.join("PyYAML-6.0.1.dist-info/top_level.txt"), b"yaml\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"import requests\nimport json\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"import mylib\nimport yaml\n").unwrap();
+        mock_fs.write(Path::new("/test/tool.py"), b"import mylib\nimport rich\n").unwrap();
+
+        let entries = vec![
+            (PathBuf::from("/test/main.py"), PathBuf::from("/test/dist/main.py")),
+            (PathBuf::from("/test/tool.py"), PathBuf::from("/test/dist/tool.py")),
+        ];
+        let opt = Opt { module_names: "mylib".to_string(), emit_requirements: Some(PathBuf::from("/test/requirements.txt")), ..Opt::default() };
+        mock_fs.mkdir_p(Path::new("/test/dist")).unwrap();
+        run_entries(opt, &mock_fs, &[PathBuf::from("/test"), site_packages.to_path_buf()], &entries, None).unwrap();
+        assert_eq!(
+            mock_fs.read_to_string(Path::new("/test/requirements.txt")).unwrap(),
+            "# Pinned requirements of the external imports of /test/dist/main.py, /test/dist/tool.py, generated by python-inliner\nPyYAML==6.0.1\nrequests==2.31.0\n# rich: no installed distribution provides it\n"
+        );
+    }
+
+    #[test]
+    fn test_licenses() {
+        let mock_fs = VirtualFileSystem::new();
+        let site_packages = Path::new("/venv/lib/python3.12/site-packages");
+        mock_fs.mkdir_p(&site_packages.join("extlib")).unwrap();
+        mock_fs.mkdir_p(&site_packages.join("extlib-1.2.dist-info")).unwrap();
+        mock_fs.write(&site_packages.join("extlib/__init__.py"), b"Y = 2\n").unwrap();
+        mock_fs.write(&site_packages.join("extlib-1.2.dist-info/RECORD"), b"extlib/__init__.py,,\nextlib-1.2.dist-info/LICENSE,,\n").unwrap();
+        mock_fs.write(&site_packages.join("extlib-1.2.dist-info/METADATA"), b"Name: extlib\nVersion: 1.2\nLicense: MIT\n").unwrap();
+        mock_fs.write(&site_packages.join("extlib-1.2.dist-info/LICENSE"), b"Copyright Ext\n").unwrap();
+        mock_fs.mkdir_p(Path::new("/test")).unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"#!/usr/bin/env python3\nfrom extlib import Y\nprint(Y)\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "extlib".to_string(),
+            licenses: Some(PathBuf::from("/test/THIRD_PARTY_NOTICES.txt")),
+            embed_licenses: true,
+            release: true,
+            ..Opt::default()
+        };
+        let entries = vec![(PathBuf::from("/test/main.py"), PathBuf::from("/test/main_inlined.py"))];
+        run_entries(opt, &mock_fs, &[site_packages.to_path_buf()], &entries, None).unwrap();
+        let notices = mock_fs.read_to_string(Path::new("/test/THIRD_PARTY_NOTICES.txt")).unwrap();
+        let rule = "=".repeat(78);
+        assert_eq!(notices, format!("Third-party notices of /test/main_inlined.py, generated by python-inliner\n\nCode from the following distributions is inlined:\n\n  extlib 1.2\n\n{rule}\nextlib 1.2\nLicense: MIT\n{rule}\n\n--- extlib-1.2.dist-info/LICENSE ---\n\nCopyright Ext\n"));
+        // Embedded after the release passes, which would strip the comment
+        let output = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(output.starts_with(&format!("#!/usr/bin/env python3\n{}", license_comment(&notices))), "{}", output);
+    }
+
+    #[test]
+    fn test_sbom() {
+        let mock_fs = VirtualFileSystem::new();
+        let site_packages = Path::new("/venv/lib/python3.12/site-packages");
+        mock_fs.mkdir_p(&site_packages.join("extlib")).unwrap();
+        mock_fs.mkdir_p(&site_packages.join("extlib-1.2.dist-info")).unwrap();
+        mock_fs.write(&site_packages.join("extlib/__init__.py"), b"Y = 2\n").unwrap();
+        mock_fs.write(&site_packages.join("extlib-1.2.dist-info/RECORD"), b"extlib/__init__.py,,\n").unwrap();
+        mock_fs.write(&site_packages.join("extlib-1.2.dist-info/METADATA"), b"Name: extlib\nVersion: 1.2\nLicense: MIT\n").unwrap();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"X = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from extlib import Y\nfrom mylib import X\nprint(X, Y)\n").unwrap();
+
+        let entries = vec![(PathBuf::from("/test/main.py"), PathBuf::from("/test/main_inlined.py"))];
+        let opt = Opt {
+            module_names: "extlib,mylib".to_string(),
+            sbom: Some(PathBuf::from("/test/bom.cdx.json")),
+            environment: EnvironmentArgs { deterministic: true, ..EnvironmentArgs::default() },
+            ..Opt::default()
+        };
+        run_entries(opt.clone(), &mock_fs, &[site_packages.to_path_buf()], &entries, None).unwrap();
+        let sbom: serde_json::Value = serde_json::from_str(&mock_fs.read_to_string(Path::new("/test/bom.cdx.json")).unwrap()).unwrap();
+        // The project's own modules aren't components
+        let components = sbom["components"].as_array().unwrap();
+        assert_eq!(components.len(), 2, "{}", sbom);
+        assert_eq!((&components[1]["purl"], &components[1]["licenses"][0]["expression"]), (&serde_json::json!("pkg:pypi/extlib@1.2"), &serde_json::json!("MIT")));
+        assert_eq!(components[1]["components"][0]["hashes"][0]["content"], sha256_hex(b"Y = 2\n"));
+        assert_eq!(sbom["metadata"]["timestamp"], "1970-01-01T00:00:00Z");
+
+        let opt = Opt { sbom_format: Some(SbomFormat::Spdx), sbom_components: Rc::default(), ..opt };
+        run_entries(opt, &mock_fs, &[site_packages.to_path_buf()], &entries, None).unwrap();
+        let sbom: serde_json::Value = serde_json::from_str(&mock_fs.read_to_string(Path::new("/test/bom.cdx.json")).unwrap()).unwrap();
+        assert_eq!(sbom["spdxVersion"], "SPDX-2.3");
+        assert_eq!(sbom["packages"][1]["versionInfo"], "1.2");
+    }
+
+    #[test]
+    fn test_emit_script_metadata() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/client.py"), b"import requests\nimport json\n\ndef fetch():\n    return requests.get('x')\n").unwrap();
+
+        let main_py = r#"#!/usr/bin/env python3
+# /// script
+# requires-python = ">=3.11"
+# dependencies = ["rich>=13", "plugin-loaded-dependency", "mylib>=1"]
+# ///
+import rich
+from mylib.client import fetch
+
+rich.print(fetch())
+"#;
+        mock_fs.write(Path::new("/test/main.py"), main_py.as_bytes()).unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            release: true,
+            emit_script_metadata: true,
+            inline_script_deps: true,
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &python_sys_path).unwrap();
+
+        // Declared dependencies stay, imported or not, unless the bundle inlines them
+        let expected = r#"#!/usr/bin/env python3
+# /// script
+# requires-python = ">=3.11"
+# dependencies = [
+#     "rich>=13",
+#     "plugin-loaded-dependency",
+#     "requests",
+# ]
+# ///
+import json
+
+import requests
+import rich
+def fetch():
+    return requests.get('x')
+rich.print(fetch())
+"#;
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+    }
+
+    #[test]
+    fn test_emit_script_metadata_names_distributions() {
+        // Dependencies are named by the distribution installing the module, which its import name needn't match
+        let mock_fs = VirtualFileSystem::new();
+        let site_packages = Path::new("/venv/site-packages");
+        mock_fs.mkdir_p(&site_packages.join("PyYAML-6.0.1.dist-info")).unwrap();
+        mock_fs.mkdir_p(&site_packages.join("pillow-10.2.0.dist-info")).unwrap();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/images.py"), b"import yaml\nimport _yaml\nfrom PIL import Image\n").unwrap();
+        mock_fs.write(&site_packages.join("PyYAML-6.0.1.dist-info/METADATA"), b"Metadata-Version: 2.1\nName: PyYAML\nVersion: 6.0.1\n").unwrap();
+        mock_fs.write(&site_packages.join("PyYAML-6.0.1.dist-info/top_level.txt"), b"_yaml\nyaml\n").unwrap();
+        mock_fs.write(&site_packages.join("pillow-10.2.0.dist-info/METADATA"), b"Metadata-Version: 2.1\nName: pillow\nVersion: 10.2.0\n").unwrap();
+        mock_fs.write(&site_packages.join("pillow-10.2.0.dist-info/RECORD"), b"PIL/__init__.py,,\nPIL/Image.py,,\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"# /// script\n# dependencies = [\"Pillow>=10\"]\n# ///\nfrom mylib.images import Image\nimport sklearn\n").unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test"), site_packages.to_path_buf()];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            emit_script_metadata: true,
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &python_sys_path).unwrap();
+
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(result.starts_with("# /// script\n# dependencies = [\n#     \"Pillow>=10\",\n#     \"PyYAML\",\n#     \"sklearn\",\n# ]\n# ///\n"), "Got:\n{}", result);
+    }
+
+    #[test]
+    #[cfg(feature = "archive")]
+    fn test_inline_from_zip_archive_on_sys_path() {
+        use crate::modules::archive_filesystem::ArchiveFileSystem;
+        use crate::utils::zip::{build_zip, ZipMember};
+
+        let inner_fs = VirtualFileSystem::new();
+        inner_fs.mkdir_p(Path::new("/test/lib")).unwrap();
+        inner_fs.write(Path::new("/test/main.py"), b"from mylib.helpers import greet\ngreet()\n").unwrap();
+        let zip = build_zip(&[
+            ZipMember::stored("mylib/__init__.py", b""),
+            ZipMember::stored("mylib/helpers.py", b"def greet():\n    print('hi')\n"),
+        ]);
+        inner_fs.write(Path::new("/test/lib/bundle.zip"), &zip).unwrap();
+
+        let mock_fs = ArchiveFileSystem::new(inner_fs);
+        mock_fs.add_archive(Path::new("/test/lib/bundle.zip")).unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test/lib/bundle.zip")];
+        run(
+            PathBuf::from("/test/main.py"),
+            PathBuf::from("/test/main_inlined.py"),
+            "mylib".to_string(),
+            true,
+            false,
+            &mock_fs,
+            &python_sys_path,
+        ).unwrap();
+
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(result, "def greet():\n    print('hi')\ngreet()\n");
+    }
+
+    #[test]
+    fn test_imports_inside_strings_are_ignored() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/modules")).unwrap();
+        mock_fs.write(Path::new("/test/modules/helper.py"), b"def help():\n    pass\n").unwrap();
+        let main_py = r#"import sys
+USAGE = """
+from modules.helper import help
+import os
+"""
+from modules.helper import help  # the real one
+s = "from modules.helper import help"
+"#;
+        mock_fs.write(Path::new("/test/main.py"), main_py.as_bytes()).unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "modules".to_string(),
+            release: true,
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &python_sys_path).unwrap();
+
+        let expected = r#"import sys
+USAGE = """
+from modules.helper import help
+import os
+"""
+def help():
+    pass
+s = "from modules.helper import help"
+"#;
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+    }
+
+    #[test]
+    fn test_nothing_to_inline() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test")).unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"import os\nfrom json import dumps\ns = 'from mylib import x'\n").unwrap();
+
+        let (module_names, external_modules) = resolve_module_names(&Opt { module_names: "mylib".to_string(), ..Opt::default() }, None);
+        assert!(!has_inline_candidates(&mock_fs.read_to_string(Path::new("/test/main.py")).unwrap(), &module_names, &external_modules));
+        assert!(has_inline_candidates("from mylib.core import x\n", &module_names, &external_modules));
+
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            ..Opt::default()
+        };
+        // A bundle of an earlier version of the script doesn't stay at the output path
+        mock_fs.write(Path::new("/test/main_inlined.py"), b"# stale bundle\n").unwrap();
+        for _ in 0..2 {
+            run_with_options(opt.clone(), &mock_fs, &python_sys_path).unwrap();
+            assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap(), mock_fs.read_to_string(Path::new("/test/main.py")).unwrap());
+        }
+
+        let opt = Opt { expect_inlines: true, ..opt };
+        let error = run_with_options(opt, &mock_fs, &python_sys_path).unwrap_err();
+        assert!(error.to_string().starts_with("Nothing to inline"));
+    }
+
+    #[test]
+    fn test_minify() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"def helper():   \n    \"\"\"Helper docstring.\"\"\"\n\n    # comment\n    return 1  # trailing\n").unwrap();
+        let main_py = "#!/usr/bin/env python3\n\"\"\"Entry docstring.\"\"\"\nfrom mylib import helper\n\n\nTEMPLATE = \"\"\"line with spaces   \nend\"\"\"\nprint(helper(), TEMPLATE)\n";
+        mock_fs.write(Path::new("/test/main.py"), main_py.as_bytes()).unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            minify: true,
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &python_sys_path).unwrap();
+        let expected = "#!/usr/bin/env python3\ndef helper():\n    return 1\nTEMPLATE = \"\"\"line with spaces   \nend\"\"\"\nprint(helper(), TEMPLATE)\n";
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+
+        run_with_options(Opt { keep_docstrings: true, ..opt }, &mock_fs, &python_sys_path).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(result.starts_with("#!/usr/bin/env python3\n\"\"\"Entry docstring.\"\"\"\ndef helper():\n    \"\"\"Helper docstring.\"\"\"\n"), "{}", result);
+    }
+
+    #[test]
+    fn test_strip_debug_code() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"import logging\nlog = logging.getLogger(__name__)\n\ndef helper(x):\n    assert x, 'x is required'\n    log.debug('helper(%r)', x)\n    return x\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import helper\nif __debug__:\n    print('debug build')\nprint(helper(1))\n").unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            strip_asserts: true,
+            strip_pattern: vec!["log.debug".to_string()],
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &python_sys_path).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(result.contains("def helper(x):\n    return x\n") && !result.contains("__debug__"), "{}", result);
+
+        // Embedded modules are stripped the same way
+        run_with_options(Opt { embed_modules: true, ..opt }, &mock_fs, &python_sys_path).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(!result.contains("assert x") && !result.contains("log.debug") && !result.contains("__debug__"), "{}", result);
+    }
+
+    #[test]
+    fn test_defines() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/config.py"), b"import os\nDEBUG = os.environ.get('DEBUG') == '1'\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"from mylib.config import DEBUG\n\ndef run():\n    if DEBUG:\n        return 'debug'\n    else:\n        return 'release'\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import run\nprint(run())\n").unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            define: vec![("DEBUG".to_string(), "False".to_string())],
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &python_sys_path).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(result.contains("DEBUG = False\n") && result.contains("    if DEBUG:\n"), "{}", result);
+
+        run_with_options(Opt { prune_defines: true, ..opt }, &mock_fs, &python_sys_path).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(result.contains("def run():\n    return 'release'\n") && !result.contains("'debug'"), "{}", result);
+
+    }
+
+    #[test]
+    fn test_entry_function() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"def main():\n    print('running')\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import main\nmain()\nVERSION = 1\n").unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            entry_function: Some("main".to_string()),
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &python_sys_path).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(result.ends_with("VERSION = 1\n\n\nif __name__ == \"__main__\":\n    main()\n") && result.matches("main()").count() == 2, "{}", result);
+
+        let error = run_with_options(Opt { entry_function: Some("run".to_string()), ..opt }, &mock_fs, &python_sys_path).unwrap_err();
+        assert!(error.to_string().contains("neither defines nor imports `run`"), "{}", error);
+    }
+
+    #[test]
+    fn test_template() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"# helper comment\nVALUE = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"#!/usr/bin/env python3\nfrom mylib import VALUE\nprint(VALUE)\n").unwrap();
+        mock_fs.write(Path::new("/test/wrapper.py"), b"# License header\ntry:\n    # {{INLINED_CODE}}\nfinally:\n    done()\n").unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            template: Some(PathBuf::from("/test/wrapper.py")),
+            release: true,
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &python_sys_path).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(result, "#!/usr/bin/env python3\n# License header\ntry:\n    VALUE = 1\n    print(VALUE)\nfinally:\n    done()\n");
+
+        let error = run_with_options(Opt { template: Some(PathBuf::from("/test/main.py")), ..opt }, &mock_fs, &python_sys_path).unwrap_err();
+        assert!(error.to_string().starts_with("--template /test/main.py: there's no"), "{}", error);
+    }
+
+    #[test]
+    fn test_module_names_match_on_dotted_boundaries() {
+        let pattern = module_match_pattern(&["util".to_string(), "my.pkg".to_string()]);
+        let matcher = Regex::new(&format!(r"^from\s+({})\s+import\s", pattern)).unwrap();
+        for matching in ["from util import x", "from util.helpers import x", "from my.pkg.sub import x", "from . import x", "from ..base import x"] {
+            assert!(matcher.is_match(matching), "{} should match", matching);
+        }
+        for not_matching in ["from utilities.helpers import x", "from utils import x", "from my.pkgs import x", "from my import pkg", "from xutil import x"] {
+            assert!(!matcher.is_match(not_matching), "{} should not match", not_matching);
+        }
+
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/util")).unwrap();
+        mock_fs.mkdir_p(Path::new("/test/utilities")).unwrap();
+        mock_fs.write(Path::new("/test/util/__init__.py"), b"A = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/utilities/__init__.py"), b"B = 2\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from util import A\nfrom utilities import B\nprint(A, B)\n").unwrap();
+        let python_sys_path = vec![PathBuf::from("/test")];
+        run(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"), "util".to_string(), true, false, &mock_fs, &python_sys_path).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/out.py")).unwrap(), "from utilities import B\nA = 1\nprint(A, B)\n");
+    }
+
+    #[test]
+    fn test_remembered_resolution_choices() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/first/mylib")).unwrap();
+        mock_fs.mkdir_p(Path::new("/test/second")).unwrap();
+        mock_fs.write(Path::new("/test/first/mylib/__init__.py"), b"SOURCE = 'first'\n").unwrap();
+        mock_fs.write(Path::new("/test/second/mylib.py"), b"SOURCE = 'second'\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import SOURCE\nfrom mylib.extra import thing\nprint(SOURCE)\n").unwrap();
+        let python_sys_path = vec![PathBuf::from("/test/first"), PathBuf::from("/test/second")];
+
+        // Without a remembered choice the first match on the search path wins
+        run(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"), "mylib".to_string(), true, false, &mock_fs, &python_sys_path).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/out.py")).unwrap(), "from mylib.extra import thing\nSOURCE = 'first'\nprint(SOURCE)\n");
+
+        mock_fs.write(Path::new("/test/.python-inliner.json"), br#"{"resolutions": {"mylib": "/test/second/mylib.py", "mylib.extra": "skip"}}"#).unwrap();
+        run(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"), "mylib".to_string(), true, false, &mock_fs, &python_sys_path).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/out.py")).unwrap(), "from mylib.extra import thing\nSOURCE = 'second'\nprint(SOURCE)\n");
+    }
+
+    #[test]
+    fn test_remembered_distribution_choices() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/venv/site-packages/extlib")).unwrap();
+        mock_fs.mkdir_p(Path::new("/venv/site-packages/ext_lib-1.0.dist-info")).unwrap();
+        mock_fs.mkdir_p(Path::new("/test")).unwrap();
+        mock_fs.write(Path::new("/venv/site-packages/extlib/__init__.py"), b"X = 1\n").unwrap();
+        mock_fs.write(Path::new("/venv/site-packages/ext_lib-1.0.dist-info/top_level.txt"), b"extlib\n").unwrap();
+        mock_fs.write(Path::new("/venv/site-packages/other.py"), b"Z = 3\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib.py"), b"Y = 2\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from extlib import X\nfrom other import Z\nfrom mylib import Y\n").unwrap();
+        mock_fs.write(Path::new("/test/.python-inliner.json"), br#"{"distributions": {"ext_lib": "skip"}}"#).unwrap();
+        let python_sys_path = vec![PathBuf::from("/test"), PathBuf::from("/venv/site-packages")];
+
+        // Distributions without an answer are inlined unless --interactive asks about them
+        run(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"), "extlib,other,mylib".to_string(), true, false, &mock_fs, &python_sys_path).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/out.py")).unwrap(), "from extlib import X\nZ = 3\nY = 2\n\n");
+    }
+
+    #[test]
+    fn test_package_init_imports_own_submodules() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/src/pkg/sub")).unwrap();
+        mock_fs.mkdir_p(Path::new("/test/app")).unwrap();
+        mock_fs.write(Path::new("/test/src/pkg/__init__.py"), b"from .core import *\nfrom . import helpers\n").unwrap();
+        mock_fs.write(Path::new("/test/src/pkg/core.py"), b"from .sub.deep import D\n\ndef run():\n    return D\n").unwrap();
+        mock_fs.write(Path::new("/test/src/pkg/helpers.py"), b"H = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/src/pkg/sub/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/test/src/pkg/sub/deep.py"), b"from ..helpers import H\nfrom .. import helpers\nD = H + helpers.H\n").unwrap();
+        let python_sys_path = vec![PathBuf::from("/test/src")];
+
+        // Each dot past the first goes up from the file's own directory, not the entry's
+        let opt = Opt::default();
+        let deep = Path::new("/test/src/pkg/sub/deep.py");
+        assert_eq!(resolve_from_import(&mock_fs, &python_sys_path, deep, "..helpers", &opt).unwrap(), Some(PathBuf::from("/test/src/pkg/helpers.py")));
+        assert_eq!(resolve_from_import(&mock_fs, &python_sys_path, deep, "..", &opt).unwrap(), Some(PathBuf::from("/test/src/pkg/__init__.py")));
+        assert_eq!(resolve_from_import(&mock_fs, &python_sys_path, deep, ".", &opt).unwrap(), Some(PathBuf::from("/test/src/pkg/sub/__init__.py")));
+
+        for entry in ["from pkg import run, helpers\nprint(run(), helpers.H)\n", "import sys\nif sys:\n    from pkg import run, helpers\nprint(run(), helpers.H)\n"] {
+            mock_fs.write(Path::new("/test/app/main.py"), entry.as_bytes()).unwrap();
+            run(PathBuf::from("/test/app/main.py"), PathBuf::from("/test/app/out.py"), "pkg".to_string(), true, false, &mock_fs, &python_sys_path).unwrap();
+            let output = mock_fs.read_to_string(Path::new("/test/app/out.py")).unwrap();
+            assert!(!output.contains("import H") && !output.contains("from ."), "{}", output);
+            assert_eq!(output.matches("H = 1\n").count(), 1, "{}", output);
+            assert!(output.contains("D = H + helpers.H\n"), "{}", output);
+        }
+    }
+
+    #[test]
+    fn test_from_dot_import_without_package_init() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/app")).unwrap();
+        mock_fs.write(Path::new("/test/app/helper.py"), b"X = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/app/other.py"), b"Y = 2\n").unwrap();
+        mock_fs.write(Path::new("/test/app/main.py"), b"from . import helper, other as o\nfrom . import missing\nprint(helper.X, o.Y)\n").unwrap();
+
+        let warnings = Arc::new(WarningAggregator::default());
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/app/main.py")),
+            output_file: Some(PathBuf::from("/test/app/out.py")),
+            release: true,
+            warnings: warnings.clone(),
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &[]).unwrap();
+        // The directory has no __init__.py, so the names can only be its modules
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/app/out.py")).unwrap(), "X = 1\nhelper = __import__(\"sys\").modules[__name__]\nY = 2\no = __import__(\"sys\").modules[__name__]\nfrom . import missing\nprint(helper.X, o.Y)\n");
+        assert_eq!(warnings.render(&[]), "WARNING[PI0001]: could not find module \".\"\n    at /test/app/main.py:2\n");
+    }
+
+    #[test]
+    fn test_symlinked_module_is_inlined_once() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/shared/pkg")).unwrap();
+        mock_fs.mkdir_p(Path::new("/test")).unwrap();
+        mock_fs.write(Path::new("/shared/pkg/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/shared/pkg/helpers.py"), b"def helper():\n    return 1\n").unwrap();
+        mock_fs.symlink(Path::new("../shared"), Path::new("/test/vendor")).unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from pkg.helpers import helper\nfrom .vendor.pkg.helpers import helper\nprint(helper())\n").unwrap();
+
+        run(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"), "pkg".to_string(), true, false, &mock_fs, &[PathBuf::from("/shared")]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/out.py")).unwrap().matches("def helper").count(), 1);
+    }
+
+    #[test]
+    fn test_case_insensitive_imports_are_inlined_once() {
+        let mock_fs = VirtualFileSystem::case_insensitive();
+        mock_fs.mkdir_p(Path::new("/test")).unwrap();
+        mock_fs.write(Path::new("/test/utils.py"), b"def helper():\n    return 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from .Utils import helper\nfrom .utils import helper\nprint(helper())\n").unwrap();
+
+        run(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"), "".to_string(), true, false, &mock_fs, &[]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/out.py")).unwrap().matches("def helper").count(), 1);
+    }
+
+    #[test]
+    fn test_submodules_imported_from_package() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mypkg")).unwrap();
+        // helper is only imported lazily; run is re-exported from a submodule of the same name
+        mock_fs.write(Path::new("/test/mypkg/__init__.py"), b"from .run import run\n\ndef load_helper():\n    from . import helper\n    return helper\n").unwrap();
+        mock_fs.write(Path::new("/test/mypkg/run.py"), b"def run():\n    return 1\n").unwrap();
+        mock_fs.write(Path::new("/test/mypkg/helper.py"), b"def assist():\n    return 2\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mypkg import helper as h, run\nprint(h.assist(), run())\n").unwrap();
+
+        run(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"), "mypkg".to_string(), false, false, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/out.py")).unwrap();
+        assert_eq!(result.matches("def run").count(), 1, "{}", result);
+        assert!(result.contains("# ↓↓↓ inlined submodule: mypkg.helper\ndef assist():\n    return 2\n\n# ↑↑↑ inlined submodule: mypkg.helper\nh = __import__(\"sys\").modules[__name__]\nprint(h.assist(), run())\n"), "{}", result);
+    }
+
+    #[test]
+    fn test_dotted_plain_import() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/pkg/sub")).unwrap();
+        mock_fs.write(Path::new("/test/pkg/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/test/pkg/sub/__init__.py"), b"LEVEL = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/pkg/sub/mod.py"), b"def func():\n    return 3\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"import pkg.sub.mod\nimport pkg.sub.mod as m\nimport pkg.missing\nprint(pkg.sub.mod.func(), m.func(), pkg.sub.LEVEL)\n").unwrap();
+
+        run(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"), "pkg".to_string(), true, false, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/out.py")).unwrap(), r#"import pkg.missing
+LEVEL = 1
+def func():
+    return 3
+pkg = vars().get("pkg") or __import__("types").SimpleNamespace()
+pkg.sub = getattr(pkg, "sub", None) or __import__("types").SimpleNamespace()
+vars(pkg.sub).update(LEVEL=LEVEL)
+pkg.sub.mod = getattr(pkg.sub, "mod", None) or __import__("types").SimpleNamespace()
+vars(pkg.sub.mod).update(func=func)
+m = __import__("types").SimpleNamespace(func=func)
+print(pkg.sub.mod.func(), m.func(), pkg.sub.LEVEL)
+"#);
+    }
+
+    #[test]
+    fn test_inline_package() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/plugins/formats")).unwrap();
+        mock_fs.write(Path::new("/test/plugins/__init__.py"), b"import importlib\n\ndef load(name):\n    return importlib.import_module(\"plugins.formats.\" + name)\n").unwrap();
+        mock_fs.write(Path::new("/test/plugins/registry.py"), b"REGISTRY = {}\n").unwrap();
+        mock_fs.write(Path::new("/test/plugins/formats/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/test/plugins/formats/csv_fmt.py"), b"from plugins.registry import REGISTRY\nREGISTRY[\"csv\"] = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/plugins/formats/not-a-module.py"), b"raise SystemExit\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"\"\"\"Entry.\"\"\"\nfrom plugins import load\nprint(load(\"csv_fmt\").REGISTRY)\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/out.py")),
+            release: true,
+            inline_package: vec!["plugins".to_string()],
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        // Each module after what it imports, then all of them registered for importlib
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/out.py")).unwrap(), r#"import importlib
+def load(name):
+    return importlib.import_module("plugins.formats." + name)
+REGISTRY = {}
+REGISTRY["csv"] = 1
+__import__("sys").modules.setdefault("plugins", __import__("sys").modules[__name__])
+__import__("sys").modules.setdefault("plugins.formats", __import__("sys").modules[__name__])
+__import__("sys").modules.setdefault("plugins.formats.csv_fmt", __import__("sys").modules[__name__])
+__import__("sys").modules.setdefault("plugins.registry", __import__("sys").modules[__name__])
+print(load("csv_fmt").REGISTRY)
+"#);
+
+        let error = run_with_options(Opt { inline_package: vec!["plugins.registry".to_string()], ..opt.clone() }, &mock_fs, &[PathBuf::from("/test")]).unwrap_err();
+        assert!(error.to_string().contains("is a module, not a package"), "{}", error);
+        assert!(run_with_options(Opt { inline_package: vec!["missing".to_string()], ..opt }, &mock_fs, &[PathBuf::from("/test")]).is_err());
+    }
+
+    #[test]
+    fn test_dynamic_map() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/app/plugins")).unwrap();
+        mock_fs.write(Path::new("/test/app/__init__.py"), b"import importlib\nimport json\n\ndef load(name):\n    return importlib.import_module(\"app.plugins.\" + name)\n\nCODEC = __import__(\"json\")\n").unwrap();
+        mock_fs.write(Path::new("/test/app/plugins/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/test/app/plugins/csv_fmt.py"), b"KIND = 'csv'\n").unwrap();
+        mock_fs.write(Path::new("/test/app/plugins/tsv_fmt.py"), b"KIND = 'tsv'\n").unwrap();
+        mock_fs.write(Path::new("/test/app/cli.py"), b"X = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from app import load\n").unwrap();
+
+        let bundle = |opt: Opt| -> (String, Vec<Value>) {
+            let entries = vec![(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"))];
+            let opt = Opt { module_names: "app".to_string(), release: true, report: Some(PathBuf::from("/test/report.json")), ..opt };
+            run_entries(opt, &mock_fs, &[PathBuf::from("/test")], &entries, None).unwrap();
+            let report: Value = serde_json::from_str(&mock_fs.read_to_string(Path::new("/test/report.json")).unwrap()).unwrap();
+            let sites = report["bundles"][0]["warnings"].as_array().unwrap().iter()
+                .filter(|warning| warning["code"] == "PI0106")
+                .map(|warning| warning["sites"][0].clone())
+                .collect();
+            (mock_fs.read_to_string(Path::new("/test/out.py")).unwrap(), sites)
+        };
+        // Only the import by a computed name is reported; json isn't inlined either way
+        let (_, sites) = bundle(Opt::default());
+        assert_eq!(sites, vec![Value::from("/test/app/__init__.py:5")]);
+
+        let (result, sites) = bundle(Opt { dynamic_map: vec!["app.plugins.*_fmt".to_string()], ..Opt::default() });
+        assert!(sites.is_empty(), "{:?}", sites);
+        assert!(!result.contains("X = 1"), "{}", result);
+        assert!(result.starts_with("import importlib\nimport json\n"), "{}", result);
+        assert!(result.contains("KIND = 'csv'\nKIND = 'tsv'\n"), "{}", result);
+        for name in ["app", "app.plugins", "app.plugins.csv_fmt", "app.plugins.tsv_fmt"] {
+            assert!(result.contains(&format!("__import__(\"sys\").modules.setdefault(\"{}\", __import__(\"sys\").modules[__name__])\n", name)), "{}", result);
+        }
+    }
+
+    #[test]
+    fn test_topological() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/lib")).unwrap();
+        mock_fs.write(Path::new("/test/lib/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/test/lib/base.py"), b"def greet():\n    return 'hi'\n").unwrap();
+        mock_fs.write(Path::new("/test/lib/app.py"), b"from .base import greet as hello\nMESSAGE = hello()\n").unwrap();
+        // Textually, base would be inlined inside the function, leaving the later alias unbound
+        mock_fs.write(Path::new("/test/main.py"), b"def later():\n    from lib.base import greet\n    return greet()\nfrom lib.base import greet as g\nfrom lib import app\nprint(g(), app.MESSAGE, later())\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/out.py")),
+            module_names: "lib".to_string(),
+            release: true,
+            topological: true,
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/out.py")).unwrap(), r#"def greet():
+    return 'hi'
+hello = greet
+MESSAGE = hello()
+def later():
+    return greet()
+g = greet
+app = __import__("sys").modules[__name__]
+print(g(), app.MESSAGE, later())
+"#);
+
+        run_with_options(Opt { release: false, ..opt }, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/out.py")).unwrap();
+        assert!(result.starts_with("# ↓↓↓ inlined submodule: lib.base\n"), "{}", result);
+        assert!(result.contains("# ↓↓↓ inlined package: lib\n\n# ↑↑↑ inlined package: lib\n# ↓↓↓ inlined submodule: lib.app\n# →→ .base ←← module inlined above\nhello = greet\n"), "{}", result);
+        assert!(result.contains("    # →→ lib.base ←← module inlined above\n    return greet()\n"), "{}", result);
+    }
+
+    #[test]
+    fn test_rename_collisions() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/lib")).unwrap();
+        mock_fs.write(Path::new("/test/lib/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/test/lib/a.py"), b"def helper():\n    return 'a'\n").unwrap();
+        mock_fs.write(Path::new("/test/lib/b.py"), b"def helper():\n    return 'b'\n\ndef run():\n    return helper()\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from lib.a import helper\nfrom lib.b import run, helper as b_helper\nprint(helper(), run(), b_helper())\n").unwrap();
+
+        let bundle = |opt: Opt| -> (String, Vec<(String, Value)>) {
+            let entries = vec![(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"))];
+            let opt = Opt { module_names: "lib".to_string(), release: true, report: Some(PathBuf::from("/test/report.json")), ..opt };
+            run_entries(opt, &mock_fs, &[PathBuf::from("/test")], &entries, None).unwrap();
+            let report: Value = serde_json::from_str(&mock_fs.read_to_string(Path::new("/test/report.json")).unwrap()).unwrap();
+            let warnings = report["bundles"][0]["warnings"].as_array().unwrap().iter()
+                .map(|warning| (warning["subject"].as_str().unwrap().to_string(), warning["sites"].clone()))
+                .collect();
+            (mock_fs.read_to_string(Path::new("/test/out.py")).unwrap(), warnings)
+        };
+        let (_, warnings) = bundle(Opt::default());
+        assert_eq!(warnings, vec![(
+            "helper (lib/a.py, lib/b.py)".to_string(),
+            serde_json::json!(["/test/lib/a.py:1", "/test/lib/b.py:1"]),
+        )]);
+
+        let (result, _) = bundle(Opt { rename_collisions: true, ..Opt::default() });
+        assert_eq!(result, "def _lib_a__helper():\n    return 'a'\nhelper = _lib_a__helper\ndef _lib_b__helper():\n    return 'b'\ndef run():\n    return _lib_b__helper()\nb_helper = _lib_b__helper\nprint(helper(), run(), b_helper())\n");
+    }
+
+    #[test]
+    fn test_rewrite_annotations() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mypkg")).unwrap();
+        mock_fs.write(Path::new("/test/mypkg/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/test/mypkg/models.py"), b"class Thing:\n    pass\n").unwrap();
+        mock_fs.write(Path::new("/test/mypkg/service.py"), b"from typing import TYPE_CHECKING, Optional\nif TYPE_CHECKING:\n    from collections.abc import Sequence\n    from mypkg.models import Thing as T\nimport mypkg.models\n\ndef make(x: 'mypkg.models.Thing', y: Optional['T'] = None) -> \"Sequence[T]\":\n    return [x, 'mypkg.models.Thing']\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mypkg.service import make\nmake(None)\n").unwrap();
+
+        let bundle = |opt: Opt| -> String {
+            let entries = vec![(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"))];
+            let opt = Opt { module_names: "mypkg".to_string(), release: true, rewrite_annotations: true, ..opt };
+            run_entries(opt, &mock_fs, &[PathBuf::from("/test")], &entries, None).unwrap();
+            mock_fs.read_to_string(Path::new("/test/out.py")).unwrap()
+        };
+        let result = bundle(Opt::default());
+        assert!(result.contains("if TYPE_CHECKING:\n    from collections.abc import Sequence\n    T = Thing\n"), "{}", result);
+        assert!(result.contains("def make(x: 'Thing', y: Optional['Thing'] = None) -> \"Sequence[Thing]\":\n    return [x, 'mypkg.models.Thing']\n"), "{}", result);
+
+        let result = bundle(Opt { mangle_names: true, ..Opt::default() });
+        assert!(result.contains("    T = _m_mypkg_models__Thing\n"), "{}", result);
+        assert!(result.contains("def _m_mypkg_service__make(x: '_m_mypkg_models__Thing', y: Optional['_m_mypkg_models__Thing'] = None)"), "{}", result);
+    }
+
+    #[test]
+    fn test_mangle_names() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/lib")).unwrap();
+        mock_fs.write(Path::new("/test/lib/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/test/lib/a.py"), b"__all__ = ['helper']\nLIMIT = 3\n\ndef helper(n):\n    return min(n, LIMIT)\n").unwrap();
+        mock_fs.write(Path::new("/test/lib/b.py"), b"import os\nfrom . import a\nfrom .a import helper as limit\n\ndef run():\n    return limit(os.sep.count('/') + a.LIMIT)\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from lib.b import run\nprint(run())\n").unwrap();
+
+        let entries = vec![(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"))];
+        let opt = Opt { module_names: "lib".to_string(), release: true, mangle_names: true, ..Opt::default() };
+        run_entries(opt, &mock_fs, &[PathBuf::from("/test")], &entries, None).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/out.py")).unwrap(), "import os\n__all__ = ['helper']\n_m_lib_a__LIMIT = 3\ndef _m_lib_a__helper(n):\n    return min(n, _m_lib_a__LIMIT)\n_m_lib_b__a = __import__(\"types\").SimpleNamespace(LIMIT=_m_lib_a__LIMIT, __all__=__all__, helper=_m_lib_a__helper)\n_m_lib_b__limit = _m_lib_a__helper\ndef _m_lib_b__run():\n    return _m_lib_b__limit(os.sep.count('/') + _m_lib_b__a.LIMIT)\nrun = _m_lib_b__run\nprint(run())\n");
+    }
+
+    #[test]
+    fn test_star_import_respects_all() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/lib")).unwrap();
+        mock_fs.mkdir_p(Path::new("/test/lib2")).unwrap();
+        mock_fs.write(Path::new("/test/lib/__init__.py"), b"from .core import *\n").unwrap();
+        mock_fs.write(Path::new("/test/lib/core.py"), b"__all__ = ['run']\nLIMIT = 3\n\ndef _scale(n):\n    return n * LIMIT\n\ndef run(n):\n    return _scale(n)\n").unwrap();
+        mock_fs.write(Path::new("/test/lib2/__init__.py"), b"__all__ = ['tools', 'VERSION']\nVERSION = '1'\n").unwrap();
+        mock_fs.write(Path::new("/test/lib2/tools.py"), b"X = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from lib import *\nfrom lib2 import *\nLIMIT = 1\nprint(run(2), LIMIT, tools.X, VERSION)\n").unwrap();
+
+        let warnings = Arc::new(WarningAggregator::default());
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/out.py")),
+            module_names: "lib,lib2".to_string(),
+            release: true,
+            warnings: warnings.clone(),
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        // The names left out of __all__ are renamed out of the way, so LIMIT doesn't collide
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/out.py")).unwrap(), "__all__ = ['run']\n_lib_core__LIMIT = 3\ndef _lib_core___scale(n):\n    return n * _lib_core__LIMIT\ndef run(n):\n    return _lib_core___scale(n)\n__all__ = ['tools', 'VERSION']\nVERSION = '1'\nX = 1\ntools = __import__(\"sys\").modules[__name__]\nLIMIT = 1\nprint(run(2), LIMIT, tools.X, VERSION)\n");
+        assert_eq!(warnings.render(&[]), "");
+
+        run_with_options(Opt { mangle_names: true, ..opt }, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        let mangled = mock_fs.read_to_string(Path::new("/test/out.py")).unwrap();
+        assert!(mangled.contains("\nrun = _m_lib_core__run\n") && mangled.contains("\nVERSION = _m_lib2__VERSION\n"), "{}", mangled);
+    }
+
+    #[test]
+    fn test_shim() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/site/mylib")).unwrap();
+        mock_fs.mkdir_p(Path::new("/test/stubs")).unwrap();
+        mock_fs.write(Path::new("/site/mylib/__init__.py"), b"from .native import speedup\n").unwrap();
+        mock_fs.write(Path::new("/site/mylib/native.py"), b"import _mylib_native\nspeedup = _mylib_native.speedup\n").unwrap();
+        mock_fs.write(Path::new("/test/stubs/native.py"), b"def speedup():\n    pass\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import speedup\nspeedup()\n").unwrap();
+        let python_sys_path = vec![PathBuf::from("/site")];
+
+        let mut fs = OverlayFileSystem::new(mock_fs);
+        apply_shims(&mut fs, &python_sys_path, &[("mylib.native".to_string(), PathBuf::from("/test/stubs/native.py"))], false).unwrap();
+        run(PathBuf::from("/test/main.py"), PathBuf::from("/test/out.py"), "mylib".to_string(), true, false, &fs, &python_sys_path).unwrap();
+        assert_eq!(fs.read_to_string(Path::new("/test/out.py")).unwrap(), "def speedup():\n    pass\nspeedup()\n");
+
+        assert!(apply_shims(&mut fs, &python_sys_path, &[("mylib.gone".to_string(), PathBuf::from("/test/stubs/native.py"))], false).is_err());
+    }
+
+    #[test]
+    fn test_handle_editable_installs() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/venv/site-packages/mylib-1.0.dist-info")).unwrap();
+        mock_fs.mkdir_p(Path::new("/venv/site-packages/gone-2.0.dist-info")).unwrap();
+        mock_fs.mkdir_p(Path::new("/venv/site-packages/other-3.0.dist-info")).unwrap();
+        mock_fs.mkdir_p(Path::new("/src/mylib")).unwrap();
+        mock_fs.mkdir_p(Path::new("/src/other lib")).unwrap();
+        mock_fs.write(Path::new("/venv/site-packages/mylib-1.0.dist-info/direct_url.json"), br#"{"url": "file:///src/mylib", "dir_info": {"editable": true}}"#).unwrap();
+        mock_fs.write(Path::new("/venv/site-packages/other-3.0.dist-info/direct_url.json"), br#"{"url": "file://localhost/src/other%20lib", "dir_info": {"editable": true}}"#).unwrap();
+        // The source checkout of an editable install can go away
+        mock_fs.write(Path::new("/venv/site-packages/gone-2.0.dist-info/direct_url.json"), br#"{"url": "file:///src/gone", "dir_info": {"editable": true}}"#).unwrap();
+
+        let mut python_sys_path = vec![PathBuf::from("/venv/site-packages")];
+        let editable_installs = handle_editable_installs(&mock_fs, &mut python_sys_path).unwrap();
+        assert_eq!(editable_installs, vec![("gone".to_string(), PathBuf::from("/src/gone")), ("mylib".to_string(), PathBuf::from("/src/mylib")), ("other".to_string(), PathBuf::from("/src/other lib"))]);
+        assert_eq!(python_sys_path, vec![PathBuf::from("/venv/site-packages"), PathBuf::from("/src/mylib"), PathBuf::from("/src/other lib")]);
+
+        // A setuptools finder points into the project's src layout
+        mock_fs.mkdir_p(Path::new("/src/mylib/src/mylib")).unwrap();
+        mock_fs.mkdir_p(Path::new("/src/mylib/src/mylib_extras")).unwrap();
+        mock_fs.write(Path::new("/venv/site-packages/__editable___mylib_1_0_finder.py"), b"MAPPING: dict[str, str] = {'mylib': '/src/mylib/src/mylib', 'mylib_extras': '/src/mylib/src/mylib_extras'}\n").unwrap();
+        let mut python_sys_path = vec![PathBuf::from("/venv/site-packages")];
+        let editable_installs = handle_editable_installs(&mock_fs, &mut python_sys_path).unwrap();
+        assert_eq!(editable_installs.last(), Some(&("mylib".to_string(), PathBuf::from("/src/mylib/src"))));
+        assert_eq!(editable_installs.len(), 4);
+        assert_eq!(python_sys_path.last(), Some(&PathBuf::from("/src/mylib/src")));
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_handle_editable_installs_on_windows() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new(r"C:\venv\Lib\site-packages\mylib-1.0.dist-info")).unwrap();
+        mock_fs.mkdir_p(Path::new(r"C:\Users\me\src\mylib")).unwrap();
+        mock_fs.write(Path::new(r"C:\venv\Lib\site-packages\mylib-1.0.dist-info\direct_url.json"), br#"{"url": "file:///C:/Users/me/src/mylib", "dir_info": {"editable": true}}"#).unwrap();
+
+        let mut python_sys_path = vec![PathBuf::from(r"C:\venv\Lib\site-packages")];
+        let editable_installs = handle_editable_installs(&mock_fs, &mut python_sys_path).unwrap();
+        assert_eq!(editable_installs, vec![("mylib".to_string(), PathBuf::from(r"C:\Users\me\src\mylib"))]);
+        assert_eq!(python_sys_path.len(), 2);
+    }
+
+    #[test]
+    fn test_check_features() {
+        let result = check_features(&Opt { zipapp: true, audit: true, ..Opt::default() });
+        #[cfg(all(feature = "zipapp", feature = "audit"))]
+        assert!(result.is_ok());
+        #[cfg(not(feature = "zipapp"))]
+        assert_eq!(result.unwrap_err(), "--zipapp isn't available, python-inliner was built without the `zipapp` feature");
+        #[cfg(all(feature = "zipapp", not(feature = "audit")))]
+        assert_eq!(result.unwrap_err(), "--audit isn't available, python-inliner was built without the `audit` feature");
+        assert!(check_features(&Opt::default()).is_ok());
+    }
+
+    #[test]
+    #[cfg(all(feature = "zipapp", feature = "archive"))]
+    fn test_zipapp_output() {
+        use crate::modules::archive_filesystem::ArchiveFileSystem;
+
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"def greet():\n    print('hi')\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"#!/usr/bin/python3.12\nfrom mylib import greet\ngreet()\n").unwrap();
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/app.pyz")),
+            module_names: "mylib".to_string(),
+            release: true,
+            zipapp: true,
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &python_sys_path).unwrap();
+
+        assert!(mock_fs.read(Path::new("/test/app.pyz")).unwrap().starts_with(b"#!/usr/bin/python3.12\nPK\x03\x04"));
+        let archive_fs = ArchiveFileSystem::new(mock_fs);
+        archive_fs.add_archive(Path::new("/test/app.pyz")).unwrap();
+        assert_eq!(archive_fs.read_dir(Path::new("/test/app.pyz")).unwrap(), vec![PathBuf::from("/test/app.pyz/__main__.py")]);
+        assert_eq!(archive_fs.read_to_string(Path::new("/test/app.pyz/__main__.py")).unwrap(), "#!/usr/bin/python3.12\ndef greet():\n    print('hi')\ngreet()\n");
+    }
+
+    #[test]
+    fn test_vendor_output() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib/utils")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"import requests\nfrom .core import greet\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/core.py"), b"import mylib.utils.text\n\ndef greet():\n    print(mylib.utils.text.HELLO)\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/utils/text.py"), b"HELLO = 'hi'\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import greet\ngreet()\n").unwrap();
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/dist/app.py")),
+            module_names: "mylib".to_string(),
+            output_format: OutputFormat::Vendor,
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &python_sys_path).unwrap();
+
+        let read = |path: &str| mock_fs.read_to_string(Path::new(path)).unwrap();
+        assert_eq!(read("/test/dist/app.py"), "from _vendor.mylib import greet\ngreet()\n");
+        assert_eq!(read("/test/dist/_vendor/__init__.py"), "");
+        assert_eq!(read("/test/dist/_vendor/mylib/__init__.py"), "import requests\nfrom .core import greet\n");
+        assert_eq!(read("/test/dist/_vendor/mylib/core.py"), "import _vendor.mylib.utils.text, _vendor.mylib as mylib\n\ndef greet():\n    print(mylib.utils.text.HELLO)\n");
+        // A directory without an __init__.py is a namespace package, vendored as a regular one
+        assert_eq!(read("/test/dist/_vendor/mylib/utils/__init__.py"), "");
+        assert_eq!(read("/test/dist/_vendor/mylib/utils/text.py"), "HELLO = 'hi'\n");
+
+        let package_opt = Opt { output_file: Some(PathBuf::from("/test/pkg/app/cli.py")), output_format: OutputFormat::Package, ..opt.clone() };
+        run_with_options(package_opt, &mock_fs, &python_sys_path).unwrap();
+        assert_eq!(read("/test/pkg/app/cli.py"), "from ._vendor.mylib import greet\ngreet()\n");
+        assert_eq!(read("/test/pkg/app/__init__.py"), "");
+        assert_eq!(read("/test/pkg/app/_vendor/mylib/core.py"), "mylib = __import__(\"mylib.utils.text\", globals(), level=2)\n\ndef greet():\n    print(mylib.utils.text.HELLO)\n");
+
+        let error = run_with_options(Opt { tree_shake: true, ..opt.clone() }, &mock_fs, &python_sys_path).unwrap_err();
+        assert!(matches!(error, InlinerError::Usage { .. }));
+        assert_eq!(error.to_string(), "--output-format vendor copies the modules as they are, so it can't be combined with --tree-shake");
+        assert!(run_with_options(Opt { zipapp: true, ..opt }, &mock_fs, &python_sys_path).is_err());
+    }
+
+    #[test]
+    fn test_stub() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"from pathlib import Path\nimport os\n\nROOT = os.getcwd()\n\ndef load(path: Path, strict: bool = False) -> str:\n    return path.read_text()\n\nclass Loader:\n    def __init__(self, root: Path):\n        self._root = root\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import load\n\ndef _main():\n    load(None)\n").unwrap();
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/app.py")),
+            module_names: "mylib".to_string(),
+            release: true,
+            stub: true,
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/app.pyi")).unwrap(), "from pathlib import Path\nfrom typing import Any\n\nROOT: Any\ndef load(path: Path, strict: bool = ...) -> str: ...\nclass Loader:\n    def __init__(self, root: Path): ...\n");
+    }
+
+    #[test]
+    fn test_embed_modules() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib/ns")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"from .utils import helper\nfrom . import extra\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/utils.py"), b"def helper():\n    return __name__\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/extra.py"), b"from .ns.deep import VALUE\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/ns/deep.py"), b"VALUE = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/local.py"), b"X = 2\n").unwrap();
+        let main_py = "from __future__ import annotations\nfrom mylib import helper\nfrom .local import X\nprint(helper(), X)\n";
+        mock_fs.write(Path::new("/test/main.py"), main_py.as_bytes()).unwrap();
+
+        let python_sys_path = vec![PathBuf::from("/test")];
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            embed_modules: true,
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &python_sys_path).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(result.starts_with("from __future__ import annotations\n# ↓↓↓ embedded modules\n"), "{}", result);
+        assert!(result.ends_with("# ↑↑↑ embedded modules\nfrom mylib import helper\nfrom local import X\nprint(helper(), X)\n"), "{}", result);
+        for entry in [
+            "\"local\": (False, \"local.py\", \"X = 2\\n\")",
+            "\"mylib\": (True, \"mylib/__init__.py\", \"from .utils import helper\\nfrom . import extra\\n\")",
+            "\"mylib.extra\": (False, \"mylib/extra.py\", \"from .ns.deep import VALUE\\n\")",
+            // No __init__.py, so an empty namespace package
+            "\"mylib.ns\": (True, \"mylib/ns/__init__.py\", \"\")",
+            "\"mylib.ns.deep\": (False, \"mylib/ns/deep.py\", \"VALUE = 1\\n\")",
+            "\"mylib.utils\": (False, \"mylib/utils.py\", \"def helper():\\n    return __name__\\n\")",
+        ] {
+            assert!(result.contains(entry), "missing {}\n{}", entry, result);
+        }
+
+        assert!(run_with_options(Opt { tree_shake: true, ..opt }, &mock_fs, &python_sys_path).is_err());
+    }
+
+    #[test]
+    fn test_offline() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/vendor/mylib")).unwrap();
+        mock_fs.write(Path::new("/vendor/mylib/__init__.py"), b"X = 1\n").unwrap();
+        mock_fs.mkdir_p(Path::new("/test")).unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import X\nprint(X)\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            environment: EnvironmentArgs { offline: true, ..EnvironmentArgs::default() },
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[PathBuf::from("/vendor")]).unwrap();
+        assert!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap().contains("X = 1\n"));
+        // Nothing can compile the bundle without Python
+        let err = run_with_options(Opt { check_syntax: true, ..opt }, &mock_fs, &[PathBuf::from("/vendor")]).unwrap_err();
+        assert!(err.to_string().contains("--offline"), "{}", err);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        // The same project in two places bundles to the same bytes, time stamp and all
+        let mock_fs = VirtualFileSystem::new();
+        let mut bundles = Vec::new();
+        for root in ["/a", "/b/checkout"] {
+            let root = Path::new(root);
+            mock_fs.mkdir_p(&root.join("src/mylib")).unwrap();
+            mock_fs.write(&root.join("src/mylib/__init__.py"), b"from .b import B\nfrom .a import A\n").unwrap();
+            mock_fs.write(&root.join("src/mylib/a.py"), b"A = 1\n").unwrap();
+            mock_fs.write(&root.join("src/mylib/b.py"), b"B = 2\n").unwrap();
+            mock_fs.write(&root.join("main.py"), b"from mylib import A, B\nprint(A, B)\n").unwrap();
+            let opt = Opt {
+                input_file: Some(root.join("main.py")),
+                output_file: Some(root.join("main_inlined.py")),
+                module_names: "mylib".to_string(),
+                stamp: true,
+                stamp_time: true,
+                report: Some(root.join("report.json")),
+                environment: EnvironmentArgs { deterministic: true, ..EnvironmentArgs::default() },
+                ..Opt::default()
+            };
+            run_with_options(opt.clone(), &mock_fs, &[root.join("src")]).unwrap();
+            bundles.push(mock_fs.read_to_string(&root.join("main_inlined.py")).unwrap());
+            assert_eq!(opt.bundle_reports.borrow()[0].elapsed, Duration::ZERO);
+
+            let err = run_with_options(Opt { format: true, environment: EnvironmentArgs { user_site: true, ..opt.environment.clone() }, ..opt }, &mock_fs, &[root.join("src")]).unwrap_err();
+            assert!(err.to_string().contains("combined with --format, --user-site"), "{}", err);
+        }
+        assert!(bundles[0].contains("# Built at: 1970-01-01T00:00:00Z\n"), "{}", bundles[0]);
+        assert_eq!(bundles[0], bundles[1]);
+    }
+
+    #[test]
+    fn test_inline_from_sources() {
+        let sources = BTreeMap::from([
+            (PathBuf::from("app/main.py"), "from util import Y\nprint(Y)\n".to_string()),
+            (PathBuf::from("app/util.py"), "\"\"\"Docs.\"\"\"\nfrom mylib import X\nY = X + 1\n".to_string()),
+            (PathBuf::from("/vendor/mylib/__init__.py"), "X = 1\n".to_string()),
+        ]);
+        let bundle = inline_from_sources(&sources, Path::new("app/main.py"), "mylib,util", &["--pythonpath", "/vendor", "--release"]).unwrap();
+        assert_eq!(bundle, InlinedBundle { code: "X = 1\nY = X + 1\nprint(Y)\n".to_string(), warnings: String::new() });
+
+        // Warnings come back rather than being printed
+        let sources = BTreeMap::from([
+            (PathBuf::from("main.py"), "def f():\n    from mylib import X\n    return X\nfrom mylib import X\n".to_string()),
+            (PathBuf::from("mylib/__init__.py"), "X = 1\n".to_string()),
+        ]);
+        let bundle = inline_from_sources(&sources, Path::new("main.py"), "mylib", &[]).unwrap();
+        assert!(bundle.warnings.starts_with("WARNING[PI0102]"), "{}", bundle.warnings);
+
+        let err = inline_from_sources(&sources, Path::new("app/main.py"), "mylib", &["--out-dir", "dist"]).unwrap_err();
+        assert!(err.to_string().contains("one bundle"), "{}", err);
+        assert!(inline_from_sources(&sources, Path::new("app/main.py"), "mylib", &["--no-such-flag"]).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_warnings_are_aggregated() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"from mylib.missing import x\nfrom .a import A\nfrom .b import B\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/a.py"), b"from mylib.missing import x\nA = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/b.py"), b"from mylib.missing import x\nfrom .a import A\nB = 2\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import A, B\nprint(A, B)\n").unwrap();
+
+        let warnings = Arc::new(WarningAggregator::default());
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            warnings: warnings.clone(),
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        assert_eq!(warnings.render(&[]), "WARNING[PI0001]: could not find module \"mylib.missing\" (3 times)
+    at /test/mylib/__init__.py:1
+    at /test/mylib/a.py:1
+    at /test/mylib/b.py:1
+    note: package mylib has no submodule missing
+WARNING[PI0101]: /test/mylib/a.py has already been inlined, skipped
+    at /test/mylib/b.py:2
+");
+    }
+
+    #[test]
+    fn test_suppression_comments() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"# inliner: allow(PI0101)\nfrom .a import A\nfrom .a import A\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/a.py"), b"A = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import A\nfrom mylib.missing import x  # inliner: allow(PI0001)\nfrom mylib.gone import y\n").unwrap();
+
+        let warnings = Arc::new(WarningAggregator::default());
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            warnings: warnings.clone(),
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        assert_eq!(warnings.render(&[]), "WARNING[PI0001]: could not find module \"mylib.gone\"\n    at /test/main.py:3\n    note: package mylib has no submodule gone\n");
+    }
+
+    #[test]
+    fn test_inlining_directives() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/test/mylib/a.py"), b"from helpers import h\nA = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/helpers.py"), b"def h():\n    pass\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"# inliner: include(helpers)\nimport mylib  # inliner: skip\nfrom helpers import h\nfrom mylib.a import A\n").unwrap();
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        let output = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(output.contains("\nimport mylib  # inliner: skip\n"));
+        assert!(output.contains("# ↓↓↓ inlined submodule: helpers\n"));
+        // The include comment only applies to the file it's in
+        assert!(output.contains("\nfrom helpers import h\nA = 1\n"));
+
+        // Included modules count as something to inline even when no module names are given
+        run_with_options(Opt { module_names: String::new(), ..opt }, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        assert!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap().contains("def h():"));
+    }
+
+    #[test]
+    fn test_profile_imports() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"from .utils import helper\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/utils.py"), b"def helper():\n    return 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"def main():\n    from mylib import helper\n    print(helper())\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            release: true,
+            profile_sink: Some("stdout".to_string()),
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        let preamble = render_import_profiler(&ProfileSink::Stdout, true);
+        let expected = format!("{}def main():\n    _inliner_import_start()\n    _inliner_import_start()\n    def helper():\n        return 1\n    _inliner_import_end(\".utils\")\n    _inliner_import_end(\"mylib\")\n    print(helper())\n", preamble);
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+    }
+
+    #[test]
+    fn test_function_local_inline_keeps_multiline_strings() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"TEMPLATE = \"\"\"Dear {name},\n  thanks!\n\"\"\"\ndef render(name):\n    return TEMPLATE.format(name=name)\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"def main():\n    from mylib import render\n    print(render(\"you\"))\n").unwrap();
+
+        run(PathBuf::from("/test/main.py"), PathBuf::from("/test/main_inlined.py"), "mylib".to_string(), true, false, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        let expected = "def main():\n    TEMPLATE = \"\"\"Dear {name},\n  thanks!\n\"\"\"\n    def render(name):\n        return TEMPLATE.format(name=name)\n    print(render(\"you\"))\n";
+        assert_eq!(result, expected, "\n\nExpected:\n{}\n\nGot:\n{}\n", expected, result);
+        assert_eq!(indent_code("a = '''x\n\ny'''\n\nb = 1\n", "  "), "  a = '''x\n\ny'''\n\n  b = 1\n");
+    }
+
+    #[test]
+    fn test_crlf_input() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"from .utils import (\r\n    helper,\r\n)\r\nTEXT = '''a\r\nb'''\r\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/utils.py"), b"def helper():\r\n    return 1\r\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"def main():\r\n    from mylib import helper\r\n    print(helper())\r\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            release: true,
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        let expected = "def main():\n    def helper():\n        return 1\n    TEXT = '''a\nb'''\n    print(helper())\n";
+        assert_eq!(result, expected.replace('\n', "\r\n"));
+
+        run_with_options(Opt { line_ending: LineEnding::Lf, ..opt }, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_import_statement_spans() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test")).unwrap();
+        mock_fs.write(Path::new("/test/lib.py"), b"A = 1\r\nB = 2\r\n").unwrap();
+        // Continued imports, an import that's all there is in its block, and one on the last
+        // line, without a line break
+        mock_fs.write(Path::new("/test/main.py"), b"from lib import (A,\r\n    B)  # both\r\nfrom lib import A, \\\r\n    B\r\nif A:\r\n    from lib import A\r\nprint(A, B)\r\nfrom lib import B").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "lib".to_string(),
+            release: true,
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap(), "A = 1\r\nB = 2\r\nif A:\r\n    pass\r\nprint(A, B)\r\n");
+
+        run_with_options(Opt { release: false, ..opt }, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap().replace("\r\n", "\n"), "\
+# ↓↓↓ inlined submodule: lib
+A = 1
+B = 2
+
+# ↑↑↑ inlined submodule: lib
+# →→ lib ←← module already inlined
+if A:
+    # →→ lib ←← module already inlined
+    pass
+print(A, B)
+# →→ lib ←← module already inlined
+");
+    }
+
+    #[test]
+    fn test_templated_entries() {
+        let opt = Opt {
+            input_file: Some(PathBuf::from("bin/cli.py")),
+            output_file: Some(PathBuf::from("worker.py")),
+            module_names: "admin.py".to_string(),
+            more_input_files: vec![PathBuf::from("mylib,tools")],
+            out_dir: Some(PathBuf::from("dist")),
+            ..Opt::default()
+        };
+        let (entries, module_names) = templated_entries(&opt).unwrap();
+        assert_eq!(entries, vec![
+            (PathBuf::from("bin/cli.py"), PathBuf::from("dist/cli.py")),
+            (PathBuf::from("worker.py"), PathBuf::from("dist/worker.py")),
+            (PathBuf::from("admin.py"), PathBuf::from("dist/admin.py")),
+        ]);
+        assert_eq!(module_names, "mylib,tools");
+
+        let (entries, _) = templated_entries(&Opt { name_template: Some("{stem}-bundle.pyz".to_string()), ..opt.clone() }).unwrap();
+        assert_eq!(entries[0].1, PathBuf::from("dist/cli-bundle.pyz"));
+        // Without --out-dir each bundle goes next to its entry script
+        let (entries, _) = templated_entries(&Opt { name_template: Some("{stem}_bundled.py".to_string()), out_dir: None, ..opt.clone() }).unwrap();
+        assert_eq!(entries[0].1, PathBuf::from("bin/cli_bundled.py"));
+        assert_eq!(entries[1].1, PathBuf::from("worker_bundled.py"));
+        assert!(templated_entries(&Opt { output_file: Some(PathBuf::from("lib/cli.py")), ..opt.clone() }).is_err());
+        assert!(templated_entries(&Opt { output_file: Some(PathBuf::from("mylib")), ..opt }).is_err());
+    }
+
+    #[test]
+    fn test_run_entries() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.mkdir_p(Path::new("/dist")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"X = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/cli.py"), b"from mylib import X\nprint('cli', X)\n").unwrap();
+        mock_fs.write(Path::new("/test/worker.py"), b"from mylib import X\nprint('worker', X)\n").unwrap();
+
+        let opt = Opt { module_names: "mylib".to_string(), release: true, ..Opt::default() };
+        let entries = vec![
+            (PathBuf::from("/test/cli.py"), PathBuf::from("/dist/cli.py")),
+            (PathBuf::from("/test/worker.py"), PathBuf::from("/dist/worker.py")),
+        ];
+        run_entries(opt.clone(), &mock_fs, &[PathBuf::from("/test")], &entries, None).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/dist/cli.py")).unwrap(), "X = 1\nprint('cli', X)\n");
+        assert_eq!(mock_fs.read_to_string(Path::new("/dist/worker.py")).unwrap(), "X = 1\nprint('worker', X)\n");
+
+        let overwriting = vec![(PathBuf::from("/test/cli.py"), PathBuf::from("/test/cli.py"))];
+        assert!(run_entries(opt, &mock_fs, &[PathBuf::from("/test")], &overwriting, None).is_err());
+    }
+
+    #[test]
+    fn test_run_report_transformations() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"# coding: latin-1\r\nfrom .utils import helper\r\nNAME = 'Jos\xE9'\r\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/utils.py"), b"def helper():\n\treturn 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"def main():\n    from mylib import helper\n    print(helper())\n").unwrap();
+
+        let opt = Opt {
+            module_names: "mylib".to_string(),
+            reindent: Reindent::Spaces,
+            release: true,
+            report: Some(PathBuf::from("/test/report.json")),
+            ..Opt::default()
+        };
+        let entries = vec![(PathBuf::from("/test/main.py"), PathBuf::from("/test/main_inlined.py"))];
+        run_entries(opt, &mock_fs, &[PathBuf::from("/test")], &entries, None).unwrap();
+        let report: Value = serde_json::from_str(&mock_fs.read_to_string(Path::new("/test/report.json")).unwrap()).unwrap();
+        let inlined = &report["bundles"][0]["inlined"];
+        assert_eq!(inlined[0]["encoding"], "latin-1");
+        assert_eq!(inlined[0]["lines"], 2);
+        assert_eq!(inlined[0]["transformations"], serde_json::json!(["decoded", "imports_inlined", "indented", "line_endings", "release"]));
+        assert_eq!(inlined[1]["path"], "/test/mylib/utils.py");
+        assert_eq!(inlined[1]["transformations"], serde_json::json!(["indented", "reindent", "release"]));
+    }
+
+    #[test]
+    fn test_cache_dir() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"from .utils import helper\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/utils.py"), b"def helper():\n    return 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import helper\nprint(helper())\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            cache_dir: Some(PathBuf::from("/cache")),
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[]).unwrap();
+        let uncached = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(mock_fs.read_dir(Path::new("/cache")).unwrap().len(), 3);
+
+        // Later runs take unchanged modules from the cache
+        for entry in mock_fs.read_dir(Path::new("/cache")).unwrap() {
+            let artifact = mock_fs.read_to_string(&entry).unwrap();
+            if artifact.contains("return 1") {
+                mock_fs.write(&entry, artifact.replace("return 1", "return 'cached'").as_bytes()).unwrap();
+            }
+        }
+        run_with_options(opt.clone(), &mock_fs, &[]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap(), uncached.replace("return 1", "return 'cached'"));
+
+        // A changed module is processed again
+        mock_fs.write(Path::new("/test/mylib/utils.py"), b"def helper():\n    return 2\n").unwrap();
+        run_with_options(opt, &mock_fs, &[]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap(), uncached.replace("return 1", "return 2"));
+        assert_eq!(mock_fs.read_dir(Path::new("/cache")).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_cache_dir_with_defines() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"CONST = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import CONST\nprint(CONST)\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            cache_dir: Some(PathBuf::from("/cache")),
+            define: vec![("CONST".to_string(), "5".to_string())],
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[]).unwrap();
+        let uncached = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(uncached.contains("CONST = 5\n"));
+
+        // The artifact of the rewritten module loads, so the next run reuses it
+        for entry in mock_fs.read_dir(Path::new("/cache")).unwrap() {
+            let file = Path::new("/test/mylib/__init__.py");
+            let artifact = ModuleArtifact::from_json(&mock_fs.read_to_string(&entry).unwrap(), file).unwrap();
+            if artifact.transformations.contains(&ModuleTransformation::Defines) {
+                mock_fs.write(&entry, artifact.to_json(file).replace("CONST = 5", "CONST = 'cached'").as_bytes()).unwrap();
+            }
+        }
+        run_with_options(opt, &mock_fs, &[]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap(), uncached.replace("CONST = 5", "CONST = 'cached'"));
+    }
+
+    #[test]
+    #[cfg(feature = "remote-cache")]
+    fn test_remote_cache() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"def helper():\n    return 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import helper\nprint(helper())\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            remote_cache: Some(RemoteCache::Directory(PathBuf::from("/shared"))),
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[]).unwrap();
+        let uncached = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        let shared = mock_fs.read_dir(Path::new("/shared")).unwrap();
+        assert_eq!(shared.len(), 2);
+
+        // Another machine's run takes the modules from the remote cache, and keeps them in its
+        // --cache-dir
+        for entry in &shared {
+            let artifact = mock_fs.read_to_string(entry).unwrap();
+            mock_fs.write(entry, artifact.replace("return 1", "return 'shared'").as_bytes()).unwrap();
+        }
+        let local = Opt { cache_dir: Some(PathBuf::from("/cache")), ..opt.clone() };
+        run_with_options(local, &mock_fs, &[]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap(), uncached.replace("return 1", "return 'shared'"));
+        let cached: Vec<String> = mock_fs.read_dir(Path::new("/cache")).unwrap().iter().map(|entry| entry.file_name().unwrap().to_string_lossy().to_string()).collect();
+        assert_eq!(cached, shared.iter().map(|entry| entry.file_name().unwrap().to_string_lossy().to_string()).collect::<Vec<_>>());
+
+        // A checkout at another path shares the entries too
+        mock_fs.mkdir_p(Path::new("/ci/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/ci/test/mylib/__init__.py"), b"def helper():\n    return 1\n").unwrap();
+        mock_fs.write(Path::new("/ci/test/main.py"), b"from mylib import helper\nprint(helper())\n").unwrap();
+        let elsewhere = Opt {
+            input_file: Some(PathBuf::from("/ci/test/main.py")),
+            output_file: Some(PathBuf::from("/ci/test/main_inlined.py")),
+            ..opt.clone()
+        };
+        run_with_options(elsewhere, &mock_fs, &[]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/ci/test/main_inlined.py")).unwrap(), uncached.replace("return 1", "return 'shared'"));
+        assert_eq!(mock_fs.read_dir(Path::new("/shared")).unwrap().len(), 2);
+
+        // A remote cache that can't be used is a warning, not a failed run
+        mock_fs.write(Path::new("/blocked"), b"").unwrap();
+        let blocked = Opt { remote_cache: Some(RemoteCache::Directory(PathBuf::from("/blocked/cache"))), verbose: true, ..opt };
+        run_with_options(blocked.clone(), &mock_fs, &[]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap(), uncached);
+        let records = blocked.warnings.records(&[]);
+        assert_eq!(records.iter().map(|record| (record.category, record.subject.as_str())).collect::<Vec<_>>(), [(WarningCategory::UnreachableRemoteCache, "/blocked/cache")]);
+    }
+
+    #[test]
+    fn test_stamp() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.mkdir_p(Path::new("/venv/site-packages/extlib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"X = 1\n").unwrap();
+        mock_fs.write(Path::new("/venv/site-packages/extlib/__init__.py"), b"Y = 2\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"#!/usr/bin/env python3\nfrom mylib import X\nfrom extlib import Y\nprint(X, Y)\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib,extlib".to_string(),
+            stamp: true,
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[PathBuf::from("/venv/site-packages")]).unwrap();
+        let first = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert!(first.starts_with(&format!(
+            "#!/usr/bin/env python3\n# Built by python-inliner {}\n# Input: main.py sha256:{}\n# Inlined: extlib/__init__.py sha256:{}\n# Inlined: mylib/__init__.py sha256:{}\n",
+            env!("CARGO_PKG_VERSION"),
+            sha256_hex(b"#!/usr/bin/env python3\nfrom mylib import X\nfrom extlib import Y\nprint(X, Y)\n"),
+            sha256_hex(b"Y = 2\n"),
+            sha256_hex(b"X = 1\n"),
+        )), "{}", first);
+        // Stamping without the time is reproducible
+        run_with_options(opt, &mock_fs, &[PathBuf::from("/venv/site-packages")]).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap(), first);
+    }
+
+    #[test]
+    fn test_size_budget() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"from .big import TABLE\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/big.py"), format!("\"\"\"Docs\"\"\"\nTABLE = [\n{}]\n", "    'row',\n".repeat(50)).as_bytes()).unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import TABLE\nprint(len(TABLE))\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            release: true,
+            size_report: true,
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[]).unwrap();
+        let output = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        let module_sizes = opt.module_sizes.borrow().clone();
+        // The package's own code doesn't count the submodule inlined into it
+        assert_eq!(module_sizes[Path::new("/test/mylib/big.py")], ModuleSize { bytes: "TABLE = [\n".len() + 50 * "    'row',\n".len() + "]\n".len(), lines: 52 });
+        assert!(module_sizes[Path::new("/test/mylib/__init__.py")].bytes < 10, "{:?}", module_sizes);
+        assert!(module_sizes.values().map(|size| size.bytes).sum::<usize>() < output.len());
+
+        mock_fs.remove_file(Path::new("/test/main_inlined.py")).unwrap();
+        let over = Opt { max_size: Some(ByteSize(100)), ..opt.clone() };
+        let error = run_with_options(over, &mock_fs, &[]).unwrap_err().to_string();
+        assert_eq!(error, format!("/test/main_inlined.py is {} bytes, over the --max-size of 100 bytes, output not written", output.len()));
+        assert!(!mock_fs.exists(Path::new("/test/main_inlined.py")).unwrap());
+        run_with_options(Opt { max_size: Some("1K".parse().unwrap()), ..opt }, &mock_fs, &[]).unwrap();
+        assert!(mock_fs.exists(Path::new("/test/main_inlined.py")).unwrap());
+    }
+
+    #[test]
+    fn test_rebundle() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"from .utils import helper\nX = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/utils.py"), b"def helper():\n    return 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"import sys\nfrom mylib import X, helper\n\ndef main():\n    from mylib.utils import helper\n    return helper()\n").unwrap();
+        let opt = Opt { module_names: "mylib".to_string(), ..Opt::default() };
+        let bundle = |input: &str, output: &str| {
+            let entries = vec![(PathBuf::from(input), PathBuf::from(output))];
+            run_entries(opt.clone(), &mock_fs, &[], &entries, None).unwrap();
+            mock_fs.read_to_string(Path::new(output)).unwrap()
+        };
+        let first = bundle("/test/main.py", "/test/bundle.py");
+
+        // Bundling the bundle again, in place, gives the same bundle
+        assert_eq!(bundle("/test/bundle.py", "/test/bundle.py"), first);
+
+        // A changed module replaces its region instead of being inlined a second time
+        mock_fs.write(Path::new("/test/mylib/utils.py"), b"def helper():\n    return 2\n").unwrap();
+        let updated = bundle("/test/bundle.py", "/test/bundle.py");
+        assert_eq!(updated, first.replace("return 1", "return 2"));
+        assert_eq!(updated, bundle("/test/main.py", "/test/fresh.py"));
+
+        mock_fs.write(Path::new("/test/broken.py"), "# ↓↓↓ inlined package: mylib\nX = 1\n".as_bytes()).unwrap();
+        let entries = vec![(PathBuf::from("/test/broken.py"), PathBuf::from("/test/out.py"))];
+        let error = run_entries(opt, &mock_fs, &[], &entries, None).unwrap_err();
+        assert_eq!(error.render(), "\
+Error: /test/broken.py:1: the region of mylib is never closed
+  --> /test/broken.py:1:1
+  |
+1 | # ↓↓↓ inlined package: mylib
+  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^
+");
+    }
+
+    #[test]
+    fn test_inlined_files_are_recorded() {
+        // The files of a bundle are recorded for `watch`
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"from .a import A\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/a.py"), b"A = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import A\n").unwrap();
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/out.py")),
+            module_names: "mylib".to_string(),
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        assert_eq!(*opt.inlined_files.borrow(), BTreeSet::from([PathBuf::from("/test/mylib/__init__.py"), PathBuf::from("/test/mylib/a.py")]));
+    }
+
+    #[test]
+    fn test_error_diagnostics() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"from .missing import x\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"import mylib\n").unwrap();
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/out.py")),
+            module_names: "mylib".to_string(),
+            ..Opt::default()
+        };
+        // A file that can't be read is named in the error
+        mock_fs.write(Path::new("/test/mylib/missing.py"), &[0xff, 0xfe, 0x00]).unwrap();
+        let error = run_with_options(opt.clone(), &mock_fs, &[PathBuf::from("/test")]).unwrap_err();
+        assert!(matches!(&error, InlinerError::Parse { file, .. } if file == Path::new("/test/mylib/missing.py")), "{}", error);
+
+        let error = run_with_options(Opt { input_file: Some(PathBuf::from("/test/absent.py")), ..opt }, &mock_fs, &[PathBuf::from("/test")]).unwrap_err();
+        assert!(matches!(&error, InlinerError::Io { path, .. } if path == Path::new("/test/absent.py")), "{}", error);
+    }
+
+    #[test]
+    fn test_check_and_diff() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"X = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import X\nprint(X)\n").unwrap();
+        let entries = vec![(PathBuf::from("/test/main.py"), PathBuf::from("/test/main_inlined.py"))];
+        let opt = Opt { module_names: "mylib".to_string(), ..Opt::default() };
+        let check = Opt { check: true, diff: true, ..opt.clone() };
+
+        let error = run_entries(check.clone(), &mock_fs, &[], &entries, None).unwrap_err();
+        assert!(matches!(error, InlinerError::Check { .. }));
+        assert_eq!(error.to_string(), "1 bundle would change, run without --check to update: /test/main_inlined.py");
+        assert!(!mock_fs.exists(Path::new("/test/main_inlined.py")).unwrap());
+
+        run_entries(opt.clone(), &mock_fs, &[], &entries, None).unwrap();
+        let written = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        run_entries(Opt { stale_outputs: Rc::default(), ..check.clone() }, &mock_fs, &[], &entries, None).unwrap();
+
+        // --check leaves the stale output alone, --diff alone updates it
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"X = 2\n").unwrap();
+        assert!(run_entries(Opt { stale_outputs: Rc::default(), ..check }, &mock_fs, &[], &entries, None).is_err());
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap(), written);
+        let diff_opt = Opt { diff: true, ..opt };
+        assert!(show_output_changes(&mock_fs, &SingleFileWriter.render(Path::new("/test/main_inlined.py"), &written.replace("X = 1", "X = 2")), &diff_opt).unwrap());
+        assert!(!show_output_changes(&mock_fs, &SingleFileWriter.render(Path::new("/test/main_inlined.py"), &written), &diff_opt).unwrap());
+        run_entries(diff_opt, &mock_fs, &[], &entries, None).unwrap();
+        assert_eq!(mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap(), written.replace("X = 1", "X = 2"));
+    }
+
+    #[test]
+    fn test_self_check_hazards() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/test/mylib/paths.py"), b"import os\nHERE = os.path.dirname(__file__)  # not for '__file__'\n").unwrap();
+        mock_fs.write(Path::new("/test/mylib/compat.py"), b"from __future__ import annotations\nVERSION = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"import sys\ndef setup():\n    from mylib.paths import HERE\n    from mylib.compat import VERSION\nfrom mylib.paths import HERE as here\n").unwrap();
+
+        let codes = |opt: Opt, mock_fs: &VirtualFileSystem| -> Vec<(String, Value)> {
+            let entries = vec![(PathBuf::from("/test/main.py"), PathBuf::from("/test/main_inlined.py"))];
+            let opt = Opt { module_names: "mylib".to_string(), report: Some(PathBuf::from("/test/report.json")), ..opt };
+            run_entries(opt, mock_fs, &[PathBuf::from("/test")], &entries, None).unwrap();
+            let report: Value = serde_json::from_str(&mock_fs.read_to_string(Path::new("/test/report.json")).unwrap()).unwrap();
+            report["bundles"][0]["warnings"].as_array().unwrap().iter()
+                .map(|warning| (warning["code"].as_str().unwrap().to_string(), warning["sites"][0].clone()))
+                .collect()
+        };
+        assert_eq!(codes(Opt::default(), &mock_fs), vec![
+            ("PI0101".to_string(), Value::from("/test/main.py:5")),
+            ("PI0102".to_string(), Value::from("/test/main.py:5")),
+            ("PI0104".to_string(), Value::from("/test/mylib/paths.py:2")),
+            ("PI0105".to_string(), Value::from("/test/main_inlined.py:9")),
+        ]);
+        assert_eq!(codes(Opt { no_self_check: true, ..Opt::default() }, &mock_fs), vec![("PI0101".to_string(), Value::from("/test/main.py:5"))]);
+    }
+
+    #[test]
+    fn test_run_report() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"X = 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"from mylib import X\nfrom mylib.gone import y\nprint(X)\n").unwrap();
+
+        let opt = Opt { module_names: "mylib".to_string(), report: Some(PathBuf::from("/test/report.json")), ..Opt::default() };
+        let entries = vec![(PathBuf::from("/test/main.py"), PathBuf::from("/test/main_inlined.py"))];
+        run_entries(opt, &mock_fs, &[PathBuf::from("/test")], &entries, None).unwrap();
+        let report: Value = serde_json::from_str(&mock_fs.read_to_string(Path::new("/test/report.json")).unwrap()).unwrap();
+        let bundle = &report["bundles"][0];
+        assert_eq!(bundle["input"], "/test/main.py");
+        assert_eq!(bundle["modules"], "mylib");
+        assert_eq!(bundle["inlined"], serde_json::json!([{
+            "path": "/test/mylib/__init__.py",
+            "sha256": sha256_hex(b"X = 1\n"),
+            "bytes": 6,
+            "encoding": "utf-8",
+            "lines": 1,
+            "transformations": [],
+        }]));
+        assert_eq!(bundle["unresolved_imports"][0]["module"], "mylib.gone");
+        assert_eq!(bundle["unresolved_imports"][0]["sites"][0], "/test/main.py:2");
+        assert_eq!(bundle["imports"], serde_json::json!([{ "from": "/test/main.py", "to": "/test/mylib/__init__.py", "module": "mylib", "site": "/test/main.py:1" }]));
+        assert!(explain_inclusion(&mock_fs.read_to_string(Path::new("/test/report.json")).unwrap(), "mylib").unwrap().contains("/test/main.py:1: from mylib import ..."));
+    }
+
+    #[test]
+    fn test_declared_encodings() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"# -*- coding: latin-1 -*-\nNAME = 'Jos\xE9'\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"\xEF\xBB\xBFfrom mylib import NAME\nprint(NAME)\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            release: true,
+            ..Opt::default()
+        };
+        run_with_options(opt, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(result, "NAME = 'Jos\u{e9}'\nprint(NAME)\n");
+    }
+
+    #[test]
+    fn test_reindent_tabbed_module() {
+        let mock_fs = VirtualFileSystem::new();
+        mock_fs.mkdir_p(Path::new("/test/mylib")).unwrap();
+        mock_fs.write(Path::new("/test/mylib/__init__.py"), b"def helper():\n\tif True:\n\t\treturn 1\n").unwrap();
+        mock_fs.write(Path::new("/test/main.py"), b"def main():\n    from mylib import helper\n    print(helper())\n").unwrap();
+
+        let opt = Opt {
+            input_file: Some(PathBuf::from("/test/main.py")),
+            output_file: Some(PathBuf::from("/test/main_inlined.py")),
+            module_names: "mylib".to_string(),
+            release: true,
+            reindent: Reindent::Spaces,
+            ..Opt::default()
+        };
+        run_with_options(opt.clone(), &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(result, "def main():\n    def helper():\n        if True:\n            return 1\n    print(helper())\n");
+
+        run_with_options(Opt { reindent: Reindent::Tabs, ..opt }, &mock_fs, &[PathBuf::from("/test")]).unwrap();
+        let result = mock_fs.read_to_string(Path::new("/test/main_inlined.py")).unwrap();
+        assert_eq!(result, "def main():\n\tdef helper():\n\t\tif True:\n\t\t\treturn 1\n\tprint(helper())\n");
+    }
+}
@@ -0,0 +1,164 @@
+use regex::Regex;
+use crate::modules::source_analysis::{mask_strings_and_comments, string_spans};
+
+/// Keywords that end in a colon at the start of a line without starting an annotation
+const BLOCK_KEYWORDS: &[&str] = &["else", "try", "finally", "except", "lambda"];
+
+/// The end of the expression starting at `start` of the masked content: the first `stop` byte
+/// outside brackets, or the end of its line
+fn expression_end(masked: &str, start: usize, stop: &[u8]) -> usize {
+    let mut depth = 0i32;
+    for (index, byte) in masked.bytes().enumerate().skip(start) {
+        match byte {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' if depth == 0 => return index,
+            b')' | b']' | b'}' => depth -= 1,
+            b'\n' if depth == 0 => return index,
+            // `==` compares, it doesn't start a value
+            b'=' if depth == 0 && stop.contains(&b'=') && masked.as_bytes().get(index + 1) == Some(&b'=') => {}
+            _ if depth == 0 && stop.contains(&byte) => return index,
+            _ => {}
+        }
+    }
+    masked.len()
+}
+
+/// The spans of the masked content that are annotations: of the parameters and return value of
+/// each def, and of annotated assignments (`x: int = 1`) outside brackets.
+fn annotation_regions(masked: &str) -> Vec<(usize, usize)> {
+    let def = Regex::new(r"(?m)^[ \t]*(?:async[ \t]+)?def[ \t]+\w+[ \t]*(?:\[[^\]\n]*\])?[ \t]*\(").unwrap();
+    let variable = Regex::new(r"(?m)^[ \t]*([A-Za-z_][\w.]*)[ \t]*:").unwrap();
+    let bytes = masked.as_bytes();
+
+    let mut regions = Vec::new();
+    for parameters in def.find_iter(masked) {
+        // Each parameter is a name, then its annotation after `:`, then its default after `=`
+        let (mut index, mut depth, mut annotation_start, mut in_default) = (parameters.end(), 1, None, false);
+        while index < bytes.len() && depth > 0 {
+            match bytes[index] {
+                b'(' | b'[' | b'{' => depth += 1,
+                b')' | b']' | b'}' => depth -= 1,
+                b':' if depth == 1 && !in_default && annotation_start.is_none() => annotation_start = Some(index + 1),
+                b'=' if depth == 1 => in_default = true,
+                b',' if depth == 1 => in_default = false,
+                _ => {}
+            }
+            if depth == 0 || (depth == 1 && matches!(bytes[index], b',' | b'=')) {
+                if let Some(start) = annotation_start.take() {
+                    regions.push((start, index));
+                }
+            }
+            index += 1;
+        }
+        let rest = &masked[index.min(masked.len())..];
+        if let Some(returns) = rest.trim_start().strip_prefix("->") {
+            let start = masked.len() - returns.len();
+            regions.push((start, expression_end(masked, start, b":")));
+        }
+    }
+
+    // Annotated assignments are statements, so they start a line outside any bracket
+    let mut depth = 0i32;
+    let mut last = 0;
+    for statement in variable.captures_iter(masked) {
+        let line_start = statement.get(0).unwrap().start();
+        depth += masked[last..line_start].bytes()
+            .map(|byte| match byte {
+                b'(' | b'[' | b'{' => 1,
+                b')' | b']' | b'}' => -1,
+                _ => 0,
+            })
+            .sum::<i32>();
+        last = line_start;
+        if depth == 0 && !BLOCK_KEYWORDS.contains(&&statement[1]) {
+            let start = statement.get(0).unwrap().end();
+            regions.push((start, expression_end(masked, start, b"=")));
+        }
+    }
+    regions
+}
+
+/// Whether the masked text of an annotation up to a string has the string inside `Literal[...]`,
+/// whose strings are values rather than names
+fn inside_literal(annotation: &str) -> bool {
+    let mut brackets: Vec<bool> = Vec::new();
+    for (index, c) in annotation.char_indices() {
+        match c {
+            '[' => brackets.push(annotation[..index].trim_end().ends_with("Literal")),
+            '(' | '{' => brackets.push(false),
+            ']' | ')' | '}' => {
+                brackets.pop();
+            }
+            _ => {}
+        }
+    }
+    brackets.contains(&true)
+}
+
+/// The string literals of the content that are annotations or part of one (`"Thing"`,
+/// `Optional["Thing"]`), as their spans including the quotes. Prefixed strings, which can't be
+/// annotations, and the values of `Literal[...]` are left out.
+pub fn annotation_strings(content: &str) -> Vec<(usize, usize)> {
+    let masked = mask_strings_and_comments(content);
+    let regions = annotation_regions(&masked);
+    string_spans(content).into_iter()
+        .filter(|(start, _)| matches!(content.as_bytes()[*start], b'"' | b'\''))
+        .filter(|(start, _)| regions.iter().any(|(region_start, region_end)| {
+            (*region_start..*region_end).contains(start) && !inside_literal(&masked[*region_start..*start])
+        }))
+        .collect()
+}
+
+/// Rewrite the names the annotation strings of the content refer to: each dotted name
+/// (`mypkg.models.Thing`, `Thing`) is replaced by what `rewrite` returns for it, if anything.
+pub fn rewrite_string_annotations(content: &str, rewrite: &dyn Fn(&str) -> Option<String>) -> String {
+    let dotted_name = Regex::new(r"[A-Za-z_]\w*(?:\.[A-Za-z_]\w*)*").unwrap();
+    let mut result = String::with_capacity(content.len());
+    let mut last = 0;
+    for (start, end) in annotation_strings(content) {
+        let quote = if content[start..].starts_with("\"\"\"") || content[start..].starts_with("'''") { 3 } else { 1 };
+        let expression = &content[start + quote..end - quote];
+        result.push_str(&content[last..start + quote]);
+        let mut copied = 0;
+        for name in dotted_name.find_iter(expression) {
+            if expression[..name.start()].ends_with('.') {
+                continue;
+            }
+            if let Some(rewritten) = rewrite(name.as_str()) {
+                result.push_str(&expression[copied..name.start()]);
+                result.push_str(&rewritten);
+                copied = name.end();
+            }
+        }
+        result.push_str(&expression[copied..]);
+        last = end - quote;
+    }
+    result.push_str(&content[last..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotation_strings() {
+        let content = "def f(x: \"A\", y: Optional['B'] = \"not\", *, z=lambda q: 'no', **kw: \"C\") -> \"D\":\n    v: \"E\" = 'value'\n    d = {\n        key: 'F',\n    }\n    else_: Literal[\"G\"] = f\"H\"\n";
+        let strings: Vec<&str> = annotation_strings(content).into_iter().map(|(start, end)| &content[start..end]).collect();
+        assert_eq!(strings, ["\"A\"", "'B'", "\"C\"", "\"D\"", "\"E\""]);
+    }
+
+    #[test]
+    fn test_rewrite_string_annotations() {
+        let content = "def f(x: \"mypkg.models.Thing\", y: \"list[Thing.Inner]\") -> 'Other':\n    return 'mypkg.models.Thing'\n";
+        let rewrite = |name: &str| match name {
+            "mypkg.models.Thing" => Some("Thing".to_string()),
+            "Thing.Inner" => Some("_m_models__Thing.Inner".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            rewrite_string_annotations(content, &rewrite),
+            "def f(x: \"Thing\", y: \"list[_m_models__Thing.Inner]\") -> 'Other':\n    return 'mypkg.models.Thing'\n"
+        );
+    }
+}
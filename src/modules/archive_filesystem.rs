@@ -0,0 +1,323 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::io;
+use std::rc::Rc;
+use crate::modules::file_system::{copy_contents, CopyStrategy, FileSystem};
+use crate::utils::inflate::inflate;
+
+/// A member of a zip archive: compression method and where its data lives in the archive bytes
+#[derive(Debug, Clone)]
+struct ArchiveEntry {
+    method: u16,
+    data_start: usize,
+    compressed_size: usize,
+}
+
+/// A zip archive loaded into memory, indexed by member name (`pkg/module.py`)
+struct Archive {
+    path: PathBuf,
+    data: Vec<u8>,
+    files: HashMap<String, ArchiveEntry>,
+    /// Every directory implied by a member name, whether or not the archive stores it explicitly
+    directories: HashSet<String>,
+}
+
+/// Serves the contents of zip archives (zipped stdlib, `.egg` files, frozen apps) as read-only
+/// directory trees rooted at the archive's own path, so `/usr/lib/python312.zip/json/__init__.py`
+/// reads the `json/__init__.py` member. Everything else is delegated to the wrapped file system.
+pub struct ArchiveFileSystem<FS: FileSystem> {
+    inner: FS,
+    /// In a RefCell, so archives found while resolving imports can be added through a shared reference
+    archives: RefCell<Vec<Rc<Archive>>>,
+}
+
+fn read_u16(data: &[u8], at: usize) -> io::Result<u16> {
+    data.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(|| invalid_archive("truncated record"))
+}
+
+fn read_u32(data: &[u8], at: usize) -> io::Result<u32> {
+    data.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(|| invalid_archive("truncated record"))
+}
+
+fn invalid_archive(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid zip archive: {}", message))
+}
+
+/// Parse the central directory of a zip archive
+fn parse_archive(path: PathBuf, data: Vec<u8>) -> io::Result<Archive> {
+    const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+    const CENTRAL_DIRECTORY_HEADER: u32 = 0x02014b50;
+    const LOCAL_FILE_HEADER: u32 = 0x04034b50;
+
+    // The end record sits at the very end, possibly followed by an archive comment of up to 64KB
+    if data.len() < 22 {
+        return Err(invalid_archive("too short"));
+    }
+    let earliest = data.len().saturating_sub(22 + 0xffff);
+    let mut end_record = None;
+    for position in (earliest..=data.len() - 22).rev() {
+        if read_u32(&data, position)? == END_OF_CENTRAL_DIRECTORY {
+            end_record = Some(position);
+            break;
+        }
+    }
+    let end_record = end_record.ok_or_else(|| invalid_archive("no end of central directory record"))?;
+
+    let entry_count = read_u16(&data, end_record + 10)? as usize;
+    let directory_size = read_u32(&data, end_record + 12)? as usize;
+    let directory_offset = read_u32(&data, end_record + 16)? as usize;
+    if entry_count == 0xffff || directory_offset == 0xffffffff {
+        return Err(invalid_archive("zip64 archives are not supported"));
+    }
+    // Archives appended to another file (e.g. a frozen executable) have offsets relative to the
+    // start of the zip data, not the start of the file
+    let directory_start = end_record.checked_sub(directory_size).ok_or_else(|| invalid_archive("bad central directory size"))?;
+    let prefix_length = directory_start.checked_sub(directory_offset).ok_or_else(|| invalid_archive("bad central directory offset"))?;
+
+    let mut files = HashMap::new();
+    let mut directories = HashSet::new();
+    let mut position = directory_start;
+    for _ in 0..entry_count {
+        if read_u32(&data, position)? != CENTRAL_DIRECTORY_HEADER {
+            return Err(invalid_archive("bad central directory header"));
+        }
+        let method = read_u16(&data, position + 10)?;
+        let compressed_size = read_u32(&data, position + 20)? as usize;
+        let name_length = read_u16(&data, position + 28)? as usize;
+        let extra_length = read_u16(&data, position + 30)? as usize;
+        let comment_length = read_u16(&data, position + 32)? as usize;
+        let local_header = read_u32(&data, position + 42)? as usize + prefix_length;
+        let name_bytes = data.get(position + 46..position + 46 + name_length).ok_or_else(|| invalid_archive("truncated file name"))?;
+        let name = String::from_utf8_lossy(name_bytes).replace('\\', "/");
+        position += 46 + name_length + extra_length + comment_length;
+
+        // Register every parent directory of the member
+        let mut parent = name.trim_end_matches('/');
+        while let Some(separator) = parent.rfind('/') {
+            parent = &parent[..separator];
+            directories.insert(parent.to_string());
+        }
+        if name.ends_with('/') {
+            directories.insert(name.trim_end_matches('/').to_string());
+            continue;
+        }
+
+        if read_u32(&data, local_header)? != LOCAL_FILE_HEADER {
+            return Err(invalid_archive("bad local file header"));
+        }
+        let local_name_length = read_u16(&data, local_header + 26)? as usize;
+        let local_extra_length = read_u16(&data, local_header + 28)? as usize;
+        let data_start = local_header + 30 + local_name_length + local_extra_length;
+        files.insert(name, ArchiveEntry { method, data_start, compressed_size });
+    }
+
+    Ok(Archive { path, data, files, directories })
+}
+
+impl<FS: FileSystem> ArchiveFileSystem<FS> {
+    pub fn new(inner: FS) -> Self {
+        ArchiveFileSystem {
+            inner,
+            archives: RefCell::default(),
+        }
+    }
+
+    /// Load the zip archive at `path` (read through the inner file system) and serve its contents.
+    /// Fails if the file isn't a zip archive we can read.
+    pub fn add_archive(&self, path: &Path) -> io::Result<()> {
+        let data = self.inner.read(path)?;
+        let archive = parse_archive(path.to_path_buf(), data)?;
+        self.archives.borrow_mut().push(Rc::new(archive));
+        Ok(())
+    }
+
+    /// Find the archive holding `path`, along with the member name within it ("" for the archive root)
+    fn locate(&self, path: &Path) -> Option<(Rc<Archive>, String)> {
+        self.archives.borrow().iter().find_map(|archive| {
+            let member = path.strip_prefix(&archive.path).ok()?;
+            let parts: Vec<&str> = member.components().filter_map(|c| c.as_os_str().to_str()).collect();
+            Some((Rc::clone(archive), parts.join("/")))
+        })
+    }
+
+    fn read_only_error(path: &Path) -> io::Error {
+        io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is inside a read-only archive", path.display()))
+    }
+}
+
+impl Archive {
+    fn is_dir(&self, member: &str) -> bool {
+        member.is_empty() || self.directories.contains(member)
+    }
+
+    fn read(&self, member: &str) -> io::Result<Vec<u8>> {
+        let entry = self.files.get(member).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))?;
+        let compressed = self.data
+            .get(entry.data_start..entry.data_start + entry.compressed_size)
+            .ok_or_else(|| invalid_archive("member data out of bounds"))?;
+        match entry.method {
+            0 => Ok(compressed.to_vec()),
+            8 => inflate(compressed),
+            method => Err(io::Error::new(io::ErrorKind::Unsupported, format!("unsupported zip compression method {}", method))),
+        }
+    }
+}
+
+impl<FS: FileSystem> FileSystem for ArchiveFileSystem<FS> {
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.locate(path) {
+            Some(_) => Ok(path.to_path_buf()),
+            None => self.inner.canonicalize(path),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        match self.locate(path) {
+            Some(_) => Err(Self::read_only_error(path)),
+            None => self.inner.write(path, contents),
+        }
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        match self.locate(path) {
+            Some((archive, member)) => String::from_utf8(archive.read(&member)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => self.inner.read_to_string(path),
+        }
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.locate(path) {
+            Some((archive, member)) => archive.read(&member),
+            None => self.inner.read(path),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let Some((archive, member)) = self.locate(path) else {
+            return self.inner.read_dir(path);
+        };
+        if !archive.is_dir(&member) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Directory not found"));
+        }
+        let prefix = if member.is_empty() { String::new() } else { format!("{}/", member) };
+        let mut children: Vec<&str> = archive.files.keys()
+            .chain(archive.directories.iter())
+            .filter_map(|name| name.strip_prefix(&prefix))
+            .filter(|child| !child.is_empty() && !child.contains('/'))
+            .collect();
+        children.sort();
+        children.dedup();
+        Ok(children.into_iter().map(|child| path.join(child)).collect())
+    }
+
+    fn mkdir_p(&self, path: &Path) -> io::Result<()> {
+        match self.locate(path) {
+            Some(_) => Err(Self::read_only_error(path)),
+            None => self.inner.mkdir_p(path),
+        }
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        match self.locate(path) {
+            Some(_) => Err(Self::read_only_error(path)),
+            None => self.inner.remove_file(path),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        match self.locate(path) {
+            Some(_) => Err(Self::read_only_error(path)),
+            None => self.inner.remove_dir(path),
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> io::Result<bool> {
+        match self.locate(path) {
+            Some((archive, member)) => Ok(archive.files.contains_key(&member)),
+            None => self.inner.is_file(path),
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> io::Result<bool> {
+        match self.locate(path) {
+            Some((archive, member)) => Ok(archive.is_dir(&member)),
+            None => self.inner.is_dir(path),
+        }
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        match self.locate(path) {
+            Some((archive, member)) => Ok(archive.is_dir(&member) || archive.files.contains_key(&member)),
+            None => self.inner.exists(path),
+        }
+    }
+
+    fn is_symlink(&self, path: &Path) -> io::Result<bool> {
+        match self.locate(path) {
+            Some(_) => Ok(false),
+            None => self.inner.is_symlink(path),
+        }
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.locate(path) {
+            Some(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is inside an archive, not a symbolic link", path.display()))),
+            None => self.inner.read_link(path),
+        }
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path, strategy: CopyStrategy) -> io::Result<bool> {
+        if self.locate(from).is_none() && self.locate(to).is_none() {
+            return self.inner.copy_file(from, to, strategy);
+        }
+        copy_contents(self, from, to)
+    }
+
+    fn set_executable(&self, path: &Path) -> io::Result<()> {
+        match self.locate(path) {
+            Some(_) => Err(Self::read_only_error(path)),
+            None => self.inner.set_executable(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+    use crate::utils::zip::{build_zip, crc32, ZipMember};
+
+    #[test]
+    fn test_archive_filesystem() {
+        // zlib.compress(b"hello hello hello hello", 9)[2:-4]
+        let deflated = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01];
+        // A stub in front of the zip data, as with archives appended to a frozen executable
+        let mut zip = b"#!frozen app stub\n".to_vec();
+        let text = b"hello hello hello hello";
+        zip.extend(build_zip(&[
+            ZipMember::stored("pkg/__init__.py", b"X = 1\n"),
+            ZipMember { name: "pkg/sub/mod.py", method: 8, data: &deflated, crc: crc32(text), uncompressed_size: text.len() as u32 },
+        ]));
+        let inner = VirtualFileSystem::new();
+        inner.mkdir_p(Path::new("/lib")).unwrap();
+        inner.write(Path::new("/lib/app.zip"), &zip).unwrap();
+        inner.write(Path::new("/lib/plain.py"), b"Y = 2\n").unwrap();
+
+        let fs = ArchiveFileSystem::new(inner);
+        // Not a zip archive
+        assert!(fs.add_archive(Path::new("/lib/plain.py")).is_err());
+        fs.add_archive(Path::new("/lib/app.zip")).unwrap();
+
+        assert!(fs.is_dir(Path::new("/lib/app.zip")).unwrap());
+        assert!(fs.is_dir(Path::new("/lib/app.zip/pkg/sub")).unwrap());
+        assert!(fs.exists(Path::new("/lib/app.zip/pkg/__init__.py")).unwrap());
+        assert!(!fs.exists(Path::new("/lib/app.zip/pkg/missing.py")).unwrap());
+        assert_eq!(fs.read_to_string(Path::new("/lib/app.zip/pkg/__init__.py")).unwrap(), "X = 1\n");
+        assert_eq!(fs.read_to_string(Path::new("/lib/app.zip/pkg/sub/mod.py")).unwrap(), "hello hello hello hello");
+        assert_eq!(fs.read_dir(Path::new("/lib/app.zip/pkg")).unwrap(), vec![PathBuf::from("/lib/app.zip/pkg/__init__.py"), PathBuf::from("/lib/app.zip/pkg/sub")]);
+        assert_eq!(fs.write(Path::new("/lib/app.zip/pkg/new.py"), b"").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        // Paths outside the archive go to the inner file system
+        assert_eq!(fs.read_to_string(Path::new("/lib/plain.py")).unwrap(), "Y = 2\n");
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use serde_json::Value;
+use crate::modules::errors::InlinerError;
+use crate::modules::file_system::FileSystem;
+use crate::modules::options::Opt;
+use crate::modules::requirements::record_paths;
+use crate::modules::warnings::WarningCategory;
+
+/// An installed distribution, as named by its `{name}-{version}.dist-info` directory
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Distribution {
+    pub name: String,
+    pub version: String,
+}
+
+/// A known vulnerability affecting an inlined distribution
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vulnerability {
+    pub distribution: Distribution,
+    pub id: String,
+    pub fix_versions: Vec<String>,
+}
+
+/// Find the installed distributions owning any of the given files, using the RECORD of each
+/// `*.dist-info` directory in the site-packages directories on the search path. Files that
+/// aren't installed from a distribution (the project's own, editable installs) are ignored.
+pub fn inlined_distributions(fs: &dyn FileSystem, python_sys_path: &[PathBuf], files: &HashSet<PathBuf>) -> Result<Vec<Distribution>, InlinerError> {
+    let mut distributions = BTreeSet::new();
+    for site_packages in python_sys_path.iter().filter(|path| path.to_string_lossy().contains("site-packages")) {
+        let relative_files: Vec<String> = files.iter()
+            .filter_map(|file| file.strip_prefix(site_packages).ok())
+            .map(|relative| relative.to_string_lossy().replace('\\', "/"))
+            .collect();
+        if relative_files.is_empty() {
+            continue;
+        }
+
+        let pattern = site_packages.join("*.dist-info").join("RECORD");
+        for record_path in fs.glob(&pattern).map_err(|e| InlinerError::io(&pattern, e))? {
+            let dir_name = record_path.parent().and_then(Path::file_name).unwrap_or_default().to_string_lossy().to_string();
+            let Some((name, version)) = dir_name.strip_suffix(".dist-info").and_then(|stem| stem.split_once('-')) else {
+                continue;
+            };
+            let paths = record_paths(&fs.read_to_string(&record_path).map_err(|e| InlinerError::io(&record_path, e))?);
+            if relative_files.iter().any(|file| paths.contains(file)) {
+                distributions.insert(Distribution { name: name.to_string(), version: version.to_string() });
+            }
+        }
+    }
+    Ok(distributions.into_iter().collect())
+}
+
+/// Parse the `--format json` report of pip-audit
+pub fn parse_pip_audit_report(json: &str) -> Result<Vec<Vulnerability>, String> {
+    let report: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let dependencies = report.get("dependencies").and_then(Value::as_array).ok_or("pip-audit report has no `dependencies`")?;
+    let mut vulnerabilities = Vec::new();
+    for dependency in dependencies {
+        let string_field = |field: &str| dependency.get(field).and_then(Value::as_str).unwrap_or("").to_string();
+        let distribution = Distribution { name: string_field("name"), version: string_field("version") };
+        for vulnerability in dependency.get("vulns").and_then(Value::as_array).into_iter().flatten() {
+            vulnerabilities.push(Vulnerability {
+                distribution: distribution.clone(),
+                id: vulnerability.get("id").and_then(Value::as_str).unwrap_or("unknown").to_string(),
+                fix_versions: vulnerability.get("fix_versions").and_then(Value::as_array).into_iter().flatten()
+                    .filter_map(Value::as_str)
+                    .map(String::from)
+                    .collect(),
+            });
+        }
+    }
+    Ok(vulnerabilities)
+}
+
+/// Look up known vulnerabilities of the exact distribution versions with pip-audit (which
+/// queries the PyPI/OSV advisory databases)
+pub fn run_pip_audit(distributions: &[Distribution]) -> Result<Vec<Vulnerability>, InlinerError> {
+    let requirements_path = std::env::temp_dir().join(format!("python-inliner-{}-audit.txt", std::process::id()));
+    let requirements: String = distributions.iter().map(|d| format!("{}=={}\n", d.name, d.version)).collect();
+    fs::write(&requirements_path, requirements).map_err(|e| InlinerError::io(&requirements_path, e))?;
+    let output = Command::new("pip-audit")
+        .arg("--requirement")
+        .arg(&requirements_path)
+        .args(["--no-deps", "--disable-pip", "--progress-spinner", "off", "--format", "json"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output();
+    fs::remove_file(&requirements_path).map_err(|e| InlinerError::io(&requirements_path, e))?;
+
+    let failed = |reason: String| InlinerError::Tool { tool: "pip-audit".to_string(), reason };
+    let output = match output {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(failed("pip-audit not found, install it (`pip install pip-audit`) to audit inlined distributions".to_string())),
+        result => result.map_err(|e| failed(format!("pip-audit couldn't be run: {}", e)))?,
+    };
+    // pip-audit exits unsuccessfully when it finds vulnerabilities, so go by whether it wrote a report
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    parse_pip_audit_report(&stdout).map_err(|_| {
+        failed(format!("pip-audit failed with {}:\n{}", output.status, String::from_utf8_lossy(&output.stderr).trim_end()))
+    })
+}
+
+/// Check the third-party distributions the bundle inlines code from for known vulnerabilities,
+/// failing unless --audit-warn-only was given
+pub fn audit_inlined_distributions(fs: &dyn FileSystem, python_sys_path: &[PathBuf], processed: &HashSet<PathBuf>, opt: &Opt) -> Result<(), InlinerError> {
+    let distributions = inlined_distributions(fs, python_sys_path, processed)?;
+    if opt.verbose {
+        let names: Vec<String> = distributions.iter().map(|d| format!("{}=={}", d.name, d.version)).collect();
+        println!("Auditing inlined distributions: {:?}", names);
+    }
+    if distributions.is_empty() {
+        return Ok(());
+    }
+    if opt.allow.contains(&WarningCategory::KnownVulnerability) {
+        return Ok(());
+    }
+    let vulnerabilities = run_pip_audit(&distributions)?;
+    print!("{}", render_vulnerabilities(&vulnerabilities));
+    if !vulnerabilities.is_empty() && !opt.audit_warn_only {
+        return Err(InlinerError::check(format!("{} known vulnerabilities in inlined distributions, output not written (use --audit-warn-only to write it anyway)", vulnerabilities.len())));
+    }
+    Ok(())
+}
+
+/// One line per vulnerability
+pub fn render_vulnerabilities(vulnerabilities: &[Vulnerability]) -> String {
+    vulnerabilities.iter()
+        .map(|v| {
+            let fix = if v.fix_versions.is_empty() { "no fix available".to_string() } else { format!("fixed in {}", v.fix_versions.join(", ")) };
+            format!("{}: inlined {} {} has known vulnerability {} ({})\n", WarningCategory::KnownVulnerability.heading(), v.distribution.name, v.distribution.version, v.id, fix)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+
+    #[test]
+    fn test_inlined_distributions() {
+        let fs = VirtualFileSystem::new();
+        let site_packages = PathBuf::from("/venv/lib/python3.12/site-packages");
+        fs.mkdir_p(&site_packages.join("jinja2")).unwrap();
+        fs.mkdir_p(&site_packages.join("jinja2-3.0.0.dist-info")).unwrap();
+        fs.mkdir_p(&site_packages.join("idna-3.7.dist-info")).unwrap();
+        fs.write(&site_packages.join("jinja2-3.0.0.dist-info/RECORD"), b"jinja2/__init__.py,sha256=abc,100\njinja2-3.0.0.dist-info/RECORD,,\n").unwrap();
+        fs.write(&site_packages.join("idna-3.7.dist-info/RECORD"), b"idna/__init__.py,sha256=def,50\n").unwrap();
+
+        let files: HashSet<PathBuf> = [site_packages.join("jinja2/__init__.py"), PathBuf::from("/src/mylib/__init__.py")].into_iter().collect();
+        let distributions = inlined_distributions(&fs, &[PathBuf::from("/src"), site_packages], &files).unwrap();
+        assert_eq!(distributions, vec![Distribution { name: "jinja2".to_string(), version: "3.0.0".to_string() }]);
+    }
+
+    #[test]
+    fn test_parse_pip_audit_report() {
+        let report = r#"{"dependencies": [
+            {"name": "jinja2", "version": "3.0.0", "vulns": [{"id": "GHSA-h5c8-rqwp-cp95", "fix_versions": ["3.1.3"], "aliases": ["CVE-2024-22195"]}]},
+            {"name": "idna", "version": "3.7", "vulns": []}
+        ], "fixes": []}"#;
+        let vulnerabilities = parse_pip_audit_report(report).unwrap();
+        assert_eq!(render_vulnerabilities(&vulnerabilities), "WARNING[PI0301]: inlined jinja2 3.0.0 has known vulnerability GHSA-h5c8-rqwp-cp95 (fixed in 3.1.3)\n");
+        assert!(parse_pip_audit_report("[]").is_err());
+    }
+}
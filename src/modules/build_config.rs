@@ -0,0 +1,295 @@
+use regex::Regex;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// The table of pyproject.toml that `python-inliner build` reads
+pub const BUILD_TABLE: &str = "tool.python-inliner";
+
+/// What `python-inliner build` bundles, from the `[tool.python-inliner]` table of pyproject.toml
+/// and its `[[tool.python-inliner.entry]]` array:
+///
+/// ```toml
+/// [tool.python-inliner]
+/// out-dir = "dist"
+/// modules = ["mylib"]
+/// args = ["--release"]
+///
+/// [[tool.python-inliner.entry]]
+/// script = "src/cli.py"
+/// output = "mytool.py"
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildConfig {
+    /// Where the bundles go, relative to pyproject.toml
+    pub out_dir: PathBuf,
+    pub entries: Vec<BuildEntry>,
+}
+
+/// An entry point of the build
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildEntry {
+    /// The entry script, relative to pyproject.toml
+    pub script: PathBuf,
+    /// The bundle's file name in the output directory, by default the script's
+    pub output: Option<String>,
+    /// The modules to inline, the table's unless the entry has its own
+    pub modules: Vec<String>,
+    /// Options of the command line, the table's followed by the entry's
+    pub args: Vec<String>,
+}
+
+/// A value of the subset of TOML the build tables are written in
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Array(Vec<String>),
+}
+
+impl Value {
+    /// A module list may be a comma-separated string, as on the command line, or an array
+    fn list(self) -> Vec<String> {
+        match self {
+            Value::String(list) => list.split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string).collect(),
+            Value::Array(items) => items,
+        }
+    }
+
+    /// Options are given one to an item, but a string holding a few is split like a shell would
+    /// without quotes
+    fn list_of_args(self) -> Vec<String> {
+        match self {
+            Value::String(args) => args.split_whitespace().map(str::to_string).collect(),
+            Value::Array(items) => items,
+        }
+    }
+}
+
+/// Parse the build tables of a pyproject.toml. The rest of the file is skipped, so only the
+/// build tables have to keep to the strings and arrays of strings this reads.
+pub fn parse_build_config(pyproject: &str) -> Result<BuildConfig, Box<dyn Error>> {
+    let header_regex = Regex::new(r"^(\[\[?)\s*([^\[\]]+?)\s*(\]\]?)$").unwrap();
+    let key_regex = Regex::new(r"^([A-Za-z0-9_-]+)\s*=\s*(.*)$").unwrap();
+    let entry_table = format!("{}.entry", BUILD_TABLE);
+
+    let mut found = false;
+    let mut out_dir = PathBuf::from("dist");
+    let (mut modules, mut args) = (Vec::new(), Vec::new());
+    // The entries, each with its own modules if it has any
+    let mut entries: Vec<(BuildEntry, Option<Vec<String>>)> = Vec::new();
+    // Which of the build tables the lines belong to: None outside them
+    let mut table: Option<&str> = None;
+    let mut lines = pyproject.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let line_number = index + 1;
+        let mut statement = strip_comment(line).trim().to_string();
+        if statement.is_empty() {
+            continue;
+        }
+        if let Some(header) = header_regex.captures(&statement) {
+            let name = header[2].replace('"', "");
+            table = match (&header[1], &header[3], name.as_str()) {
+                ("[", "]", name) if name == BUILD_TABLE => Some(BUILD_TABLE),
+                ("[[", "]]", name) if name == entry_table => {
+                    entries.push((BuildEntry { script: PathBuf::new(), output: None, modules: Vec::new(), args: Vec::new() }, None));
+                    Some("entry")
+                }
+                _ => None,
+            };
+            found |= table.is_some();
+            continue;
+        }
+        let Some(table) = table else {
+            // A multi-line string elsewhere in the file could hold lines that look like headers
+            for quotes in ["\"\"\"", "'''"] {
+                if statement.matches(quotes).count() % 2 == 1 {
+                    for (_, line) in lines.by_ref() {
+                        if line.contains(quotes) {
+                            break;
+                        }
+                    }
+                }
+            }
+            continue;
+        };
+        // An array may go on over several lines
+        while statement.ends_with('[') || (statement.contains('[') && !statement.ends_with(']')) {
+            match lines.next() {
+                Some((_, line)) => {
+                    statement.push(' ');
+                    statement.push_str(strip_comment(line).trim());
+                }
+                None => break,
+            }
+        }
+        let error = |message: String| -> Box<dyn Error> { format!("pyproject.toml line {}: {}", line_number, message).into() };
+        let key_value = key_regex.captures(&statement).ok_or_else(|| error(format!("expected `key = value` in [{}], found `{}`", BUILD_TABLE, statement)))?;
+        let key = key_value[1].to_string();
+        let value = parse_value(&key_value[2]).ok_or_else(|| error(format!("`{}` must be a string or an array of strings", key)))?;
+        let string = |value: Value| match value {
+            Value::String(string) => Ok(string),
+            Value::Array(_) => Err(error(format!("`{}` must be a string", key))),
+        };
+        match (table, key.as_str()) {
+            (BUILD_TABLE, "out-dir") => out_dir = PathBuf::from(string(value)?),
+            (BUILD_TABLE, "modules") => modules = value.list(),
+            (BUILD_TABLE, "args") => args = value.list_of_args(),
+            ("entry", "script") => entries.last_mut().unwrap().0.script = PathBuf::from(string(value)?),
+            ("entry", "output") => entries.last_mut().unwrap().0.output = Some(string(value)?),
+            ("entry", "modules") => entries.last_mut().unwrap().1 = Some(value.list()),
+            ("entry", "args") => entries.last_mut().unwrap().0.args = value.list_of_args(),
+            (BUILD_TABLE, _) => return Err(error(format!("unknown key `{}` in [{}] (expected out-dir, modules or args)", key, BUILD_TABLE))),
+            _ => return Err(error(format!("unknown key `{}` in [[{}]] (expected script, output, modules or args)", key, entry_table))),
+        }
+    }
+
+    if !found {
+        return Err(format!("pyproject.toml has no [{}] table", BUILD_TABLE).into());
+    }
+    if entries.is_empty() {
+        return Err(format!("[{}] has no entry points: add a [[{}]] with a `script`", BUILD_TABLE, entry_table).into());
+    }
+    let entries = entries.into_iter().enumerate().map(|(index, (mut entry, entry_modules))| {
+        if entry.script.as_os_str().is_empty() {
+            return Err(format!("entry point {} of [[{}]] has no `script`", index + 1, entry_table).into());
+        }
+        entry.modules = entry_modules.unwrap_or_else(|| modules.clone());
+        entry.args = args.iter().cloned().chain(entry.args).collect();
+        Ok(entry)
+    }).collect::<Result<Vec<BuildEntry>, Box<dyn Error>>>()?;
+    Ok(BuildConfig { out_dir, entries })
+}
+
+/// The line without its comment: from a `#` outside any string
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(open), c) if c == open && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..index],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// A string, basic or literal, or an array of them
+fn parse_value(value: &str) -> Option<Value> {
+    let value = value.trim();
+    if let Some(items) = value.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+        let mut strings = Vec::new();
+        let mut rest = items.trim();
+        while !rest.is_empty() {
+            let (string, after) = parse_string(rest)?;
+            strings.push(string);
+            rest = after.trim_start();
+            rest = match rest.strip_prefix(',') {
+                Some(after_comma) => after_comma.trim_start(),
+                None if rest.is_empty() => rest,
+                None => return None,
+            };
+        }
+        return Some(Value::Array(strings));
+    }
+    match parse_string(value)? {
+        (string, "") => Some(Value::String(string)),
+        _ => None,
+    }
+}
+
+/// The string at the start of `text`, and the text after it
+fn parse_string(text: &str) -> Option<(String, &str)> {
+    if let Some(rest) = text.strip_prefix('\'') {
+        let end = rest.find('\'')?;
+        return Some((rest[..end].to_string(), &rest[end + 1..]));
+    }
+    let rest = text.strip_prefix('"')?;
+    let mut string = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((string, &rest[index + 1..])),
+            '\\' => string.push(match chars.next()?.1 {
+                'n' => '\n',
+                't' => '\t',
+                c @ ('"' | '\\') => c,
+                _ => return None,
+            }),
+            c => string.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_build_config() {
+        let pyproject = r##"
+[project]
+name = "mytool"
+description = """
+[tool.python-inliner]
+"""
+
+[tool.python-inliner]
+out-dir = "build/single-file"  # not dist
+modules = ["mylib", 'shared']
+args = [
+    "--release",
+    "--tree-shake",
+]
+
+[[tool.python-inliner.entry]]
+script = "src/cli.py"
+output = "mytool.py"
+
+[[ tool."python-inliner".entry ]]
+script = "tools/admin.py"
+modules = "mylib"
+args = ["--header", "# admin \"tool\""]
+
+[tool.other]
+anything = { goes = 1 }
+"##;
+        let config = parse_build_config(pyproject).unwrap();
+        assert_eq!(config.out_dir, PathBuf::from("build/single-file"));
+        assert_eq!(config.entries, vec![
+            BuildEntry {
+                script: PathBuf::from("src/cli.py"),
+                output: Some("mytool.py".to_string()),
+                modules: vec!["mylib".to_string(), "shared".to_string()],
+                args: vec!["--release".to_string(), "--tree-shake".to_string()],
+            },
+            BuildEntry {
+                script: PathBuf::from("tools/admin.py"),
+                output: None,
+                modules: vec!["mylib".to_string()],
+                args: vec!["--release".to_string(), "--tree-shake".to_string(), "--header".to_string(), "# admin \"tool\"".to_string()],
+            },
+        ]);
+
+        let minimal = parse_build_config("[tool.python-inliner]\n\n[[tool.python-inliner.entry]]\nscript = 'main.py'\n").unwrap();
+        assert_eq!(minimal.out_dir, PathBuf::from("dist"));
+        assert!(minimal.entries[0].modules.is_empty() && minimal.entries[0].args.is_empty());
+    }
+
+    #[test]
+    fn test_build_config_errors() {
+        let error = |pyproject: &str| parse_build_config(pyproject).unwrap_err().to_string();
+        assert!(error("[project]\nname = 'x'\n").contains("no [tool.python-inliner] table"));
+        assert!(error("[tool.python-inliner]\nmodules = 'mylib'\n").contains("no entry points"));
+        assert!(error("[[tool.python-inliner.entry]]\noutput = 'x.py'\n").contains("entry point 1"));
+        assert!(error("[tool.python-inliner]\nout_dir = 'dist'\n").contains("line 2: unknown key `out_dir`"));
+        assert!(error("[tool.python-inliner]\nout-dir = ['dist']\n").contains("`out-dir` must be a string"));
+        assert!(error("[tool.python-inliner]\nrelease = true\n").contains("`release` must be a string or an array of strings"));
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use regex::Regex;
+
+/// Split a non-release bundle into the code each inlined module contributed, keyed by module
+/// name. The `# ↓↓↓ inlined ...` / `# ↑↑↑ inlined ...` markers act as the bundle's source map.
+/// A module's code excludes any modules nested inside it; code outside every marker belongs to
+/// the entry script and is keyed as `""`.
+/// Returns None if the bundle has no markers (e.g. one built with --release).
+pub fn module_sections(bundle: &str) -> Option<BTreeMap<String, String>> {
+    let marker_regex = Regex::new(r"^\s*# (↓↓↓|↑↑↑) inlined (package|submodule): (\S+)\s*$").unwrap();
+    let mut sections: BTreeMap<String, String> = BTreeMap::new();
+    // (resolved name, is package) for each section we're inside of
+    let mut stack: Vec<(String, bool)> = Vec::new();
+    let mut saw_marker = false;
+
+    for line in bundle.split_inclusive('\n') {
+        if let Some(cap) = marker_regex.captures(line.trim_end_matches(['\n', '\r'])) {
+            saw_marker = true;
+            if &cap[1] == "↓↓↓" {
+                let name = resolve_relative_name(&cap[3], stack.last());
+                stack.push((name, &cap[2] == "package"));
+            } else {
+                stack.pop();
+            }
+            continue;
+        }
+        let owner = stack.last().map(|(name, _)| name.clone()).unwrap_or_default();
+        sections.entry(owner).or_default().push_str(line);
+    }
+
+    if saw_marker { Some(sections) } else { None }
+}
+
+/// Turn a relative module name from a marker (`.utils`) into an absolute one using the module
+/// that contains it, the way Python resolves relative imports
+pub fn resolve_relative_name(name: &str, parent: Option<&(String, bool)>) -> String {
+    let relative = name.trim_start_matches('.');
+    let level = name.len() - relative.len();
+    let Some((parent_name, parent_is_package)) = parent else {
+        // Relative to the entry script, there's nothing to anchor it to
+        return name.to_string();
+    };
+    if level == 0 {
+        return name.to_string();
+    }
+
+    let mut package: Vec<&str> = parent_name.split('.').collect();
+    if !parent_is_package {
+        package.pop();
+    }
+    for _ in 1..level {
+        package.pop();
+    }
+    if !relative.is_empty() {
+        package.push(relative);
+    }
+    package.join(".")
+}
+
+/// How one module differs between two bundles
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleChange {
+    Added { size: usize },
+    Removed { size: usize },
+    Changed { old_size: usize, new_size: usize },
+}
+
+/// Compare the module sections of two bundles. Unchanged modules are left out.
+pub fn compare_sections(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> BTreeMap<String, ModuleChange> {
+    let mut changes = BTreeMap::new();
+    for (name, old_code) in old {
+        match new.get(name) {
+            None => {
+                changes.insert(name.clone(), ModuleChange::Removed { size: old_code.len() });
+            }
+            Some(new_code) if new_code != old_code => {
+                changes.insert(name.clone(), ModuleChange::Changed { old_size: old_code.len(), new_size: new_code.len() });
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, new_code) in new {
+        if !old.contains_key(name) {
+            changes.insert(name.clone(), ModuleChange::Added { size: new_code.len() });
+        }
+    }
+    changes
+}
+
+fn signed(delta: i64) -> String {
+    if delta > 0 { format!("+{}", delta) } else { delta.to_string() }
+}
+
+/// Render the comparison as a changelog-style report
+pub fn render_report(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> String {
+    let changes = compare_sections(old, new);
+    let display_name = |name: &String| if name.is_empty() { "(entry script)".to_string() } else { name.clone() };
+    let mut report = String::new();
+
+    let mut section = |title: &str, lines: Vec<String>| {
+        if !lines.is_empty() {
+            report.push_str(&format!("{}:\n", title));
+            for line in lines {
+                report.push_str(&format!("  {}\n", line));
+            }
+        }
+    };
+    section("Added", changes.iter().filter_map(|(name, change)| match change {
+        ModuleChange::Added { size } => Some(format!("+ {} ({} bytes)", display_name(name), size)),
+        _ => None,
+    }).collect());
+    section("Removed", changes.iter().filter_map(|(name, change)| match change {
+        ModuleChange::Removed { size } => Some(format!("- {} ({} bytes)", display_name(name), size)),
+        _ => None,
+    }).collect());
+    section("Changed", changes.iter().filter_map(|(name, change)| match change {
+        ModuleChange::Changed { old_size, new_size } => Some(format!("~ {} ({} -> {} bytes, {})", display_name(name), old_size, new_size, signed(*new_size as i64 - *old_size as i64))),
+        _ => None,
+    }).collect());
+
+    let unchanged = old.keys().filter(|name| new.contains_key(*name) && !changes.contains_key(*name)).count();
+    let old_total: usize = old.values().map(String::len).sum();
+    let new_total: usize = new.values().map(String::len).sum();
+    report.push_str(&format!("Unchanged: {} module(s)\n", unchanged));
+    report.push_str(&format!("Total: {} -> {} bytes ({})\n", old_total, new_total, signed(new_total as i64 - old_total as i64)));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "import sys
+# ↓↓↓ inlined package: mylib
+X = 1
+    # ↓↓↓ inlined submodule: .utils
+    def helper():
+        pass
+    # ↑↑↑ inlined submodule: .utils
+# ↑↑↑ inlined package: mylib
+# ↓↓↓ inlined submodule: mylib.legacy
+OLD = True
+# ↑↑↑ inlined submodule: mylib.legacy
+main()
+";
+
+    const NEW: &str = "import sys
+# ↓↓↓ inlined package: mylib
+X = 1
+    # ↓↓↓ inlined submodule: .utils
+    def helper():
+        return 42
+    # ↑↑↑ inlined submodule: .utils
+# ↑↑↑ inlined package: mylib
+# ↓↓↓ inlined submodule: mylib.fresh
+NEW = True
+# ↑↑↑ inlined submodule: mylib.fresh
+main()
+";
+
+    #[test]
+    fn test_module_sections() {
+        let sections = module_sections(OLD).unwrap();
+        assert_eq!(sections.keys().collect::<Vec<_>>(), vec!["", "mylib", "mylib.legacy", "mylib.utils"]);
+        assert_eq!(sections["mylib"], "X = 1\n");
+        assert_eq!(sections["mylib.utils"], "    def helper():\n        pass\n");
+        assert_eq!(sections[""], "import sys\nmain()\n");
+        assert!(module_sections("import sys\n").is_none());
+        assert_eq!(resolve_relative_name("..core", Some(&("a.b.c".to_string(), false))), "a.core");
+    }
+
+    #[test]
+    fn test_render_report() {
+        let report = render_report(&module_sections(OLD).unwrap(), &module_sections(NEW).unwrap());
+        assert_eq!(report, "Added:
+  + mylib.fresh (11 bytes)
+Removed:
+  - mylib.legacy (11 bytes)
+Changed:
+  ~ mylib.utils (31 -> 36 bytes, +5)
+Unchanged: 2 module(s)
+Total: 66 -> 71 bytes (+5)
+");
+    }
+}
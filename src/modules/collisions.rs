@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use regex::Regex;
+use crate::modules::source_analysis::{mask_strings_and_comments, top_level_statements, StatementKind};
+
+/// Where a file defines a name at the top level
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub file: PathBuf,
+    /// The offset of the definition, including its decorators, in the file's content
+    pub offset: usize,
+    pub line: usize,
+}
+
+/// The names that more than one of the files defines at the top level with a def, class or
+/// assignment, with where each file first defines them, in the order of the files. Inlined
+/// files share the bundle's namespace, so the definition that runs last replaces the others.
+/// Dunder names (`__all__`, `__version__`) and imports are left out: every module has its own
+/// of the former, and importing a name twice binds the same object.
+pub fn find_collisions(files: &[(PathBuf, String)]) -> BTreeMap<String, Vec<Definition>> {
+    let mut definitions: BTreeMap<String, Vec<Definition>> = BTreeMap::new();
+    for (file, content) in files {
+        for statement in top_level_statements(content) {
+            let Some(name) = statement.name else { continue };
+            let is_dunder = name.starts_with("__") && name.ends_with("__");
+            if is_dunder || !matches!(statement.kind, StatementKind::Function | StatementKind::Class | StatementKind::Assignment) {
+                continue;
+            }
+            let defined = definitions.entry(name).or_default();
+            if defined.iter().all(|definition| definition.file != *file) {
+                let line = content[..statement.start].matches('\n').count() + 1;
+                defined.push(Definition { file: file.clone(), offset: statement.start, line });
+            }
+        }
+    }
+    definitions.retain(|_, defined| defined.len() > 1);
+    definitions
+}
+
+/// The name --rename-collisions gives the definition of `name` in the module `module`:
+/// `_mylib_utils__helper` for `helper` of `mylib.utils`
+pub fn collision_name(module: &str, name: &str) -> String {
+    format!("_{}__{}", module.replace('.', "_"), name)
+}
+
+/// The name --mangle-names gives `name` of the module `module`: `_m_mypkg_utils__helper` for
+/// `helper` of `mypkg.utils`
+pub fn mangled_name(module: &str, name: &str) -> String {
+    format!("_m_{}__{}", module.replace('.', "_"), name)
+}
+
+/// Rename every use of the names in the code of the content, outside strings and comments.
+/// Attributes (`obj.helper`) and keyword arguments (`f(helper=1)`) are other names and are left
+/// alone, as are names inside f-string replacement fields. Import statements keep the names
+/// they import from the other module; the names they bind are assigned where they're inlined.
+pub fn rename_names(content: &str, renames: &BTreeMap<String, String>) -> String {
+    let masked = mask_strings_and_comments(content);
+    let identifier = Regex::new(r"[A-Za-z_]\w*").unwrap();
+    let keyword_argument = Regex::new(r"^\s*=[^=]").unwrap();
+    let import_statement = Regex::new(r"(?m)^[ \t]*(?:from[ \t]+\S+[ \t]+import[ \t]*(?:\([^)]*\)|[^\n]*)|import[ \t][^\n]*)").unwrap();
+    let imports: Vec<(usize, usize)> = import_statement.find_iter(&masked).map(|m| (m.start(), m.end())).collect();
+
+    let mut result = String::with_capacity(content.len());
+    let (mut last, mut bracket_depth) = (0, 0i32);
+    for m in identifier.find_iter(&masked) {
+        bracket_depth += masked[last..m.start()].chars()
+            .map(|c| match c {
+                '(' | '[' | '{' => 1,
+                ')' | ']' | '}' => -1,
+                _ => 0,
+            })
+            .sum::<i32>();
+        result.push_str(&content[last..m.start()]);
+        let is_attribute = masked[..m.start()].trim_end().ends_with('.');
+        let is_keyword = bracket_depth > 0 && keyword_argument.is_match(&masked[m.end()..]);
+        let in_import = imports.iter().any(|(start, end)| (*start..*end).contains(&m.start()));
+        match renames.get(m.as_str()) {
+            Some(renamed) if !is_attribute && !is_keyword && !in_import => result.push_str(renamed),
+            _ => result.push_str(&content[m.start()..m.end()]),
+        }
+        last = m.end();
+    }
+    result.push_str(&content[last..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_collisions() {
+        let files = vec![
+            (PathBuf::from("/lib/a.py"), "__all__ = ['helper']\nimport os\n\nCONFIG = {}\n\n@cache\ndef helper():\n    return CONFIG\n".to_string()),
+            (PathBuf::from("/lib/b.py"), "import os\nCONFIG = {'b': 1}\nCONFIG = {'b': 2}\nclass Other:\n    def helper(self):\n        pass\n".to_string()),
+            (PathBuf::from("/main.py"), "__all__ = []\ndef helper():\n    pass\n".to_string()),
+        ];
+        let collisions = find_collisions(&files);
+        assert_eq!(collisions.keys().collect::<Vec<_>>(), ["CONFIG", "helper"]);
+        assert_eq!(collisions["CONFIG"], vec![
+            Definition { file: PathBuf::from("/lib/a.py"), offset: 32, line: 4 },
+            Definition { file: PathBuf::from("/lib/b.py"), offset: 10, line: 2 },
+        ]);
+        assert_eq!(collisions["helper"].iter().map(|definition| definition.line).collect::<Vec<_>>(), [6, 2]);
+
+        assert_eq!(collision_name("mylib.utils", "helper"), "_mylib_utils__helper");
+        assert_eq!(mangled_name("mypkg.utils", "helper"), "_m_mypkg_utils__helper");
+    }
+
+    #[test]
+    fn test_rename_names() {
+        let renames = BTreeMap::from([("helper".to_string(), "_a__helper".to_string())]);
+        let content = "from .a import helper\ndef helper(x=helper):\n    # helper\n    return obj.helper(helper=helper, y=helper == 1, s='helper')\nhelpers = [helper]\n";
+        assert_eq!(rename_names(content, &renames), "from .a import helper\ndef _a__helper(x=_a__helper):\n    # helper\n    return obj.helper(helper=_a__helper, y=_a__helper == 1, s='helper')\nhelpers = [_a__helper]\n");
+    }
+}
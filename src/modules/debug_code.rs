@@ -0,0 +1,173 @@
+use crate::modules::source_analysis::{logical_statements, LogicalStatement};
+
+/// Whether the code starts with the word or words, not just with the same letters
+pub fn starts_with_words(code: &str, words: &str) -> bool {
+    code.strip_prefix(words).is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+        && !words.is_empty()
+}
+
+/// A clause continuing the compound statement before it, which can't be removed on its own
+fn continues_statement(code: &str) -> bool {
+    ["elif", "else", "except", "finally"].iter().any(|clause| starts_with_words(code, clause))
+}
+
+/// Remove the statements --strip-asserts and --strip-pattern drop: with `strip_asserts`, every
+/// `assert` and every `if __debug__:` block without an `else`, as python -O skips them; and
+/// every statement starting with one of the patterns, with the block of one that opens a block.
+/// A compound statement followed by an `elif`, `else`, `except` or `finally` is kept whole,
+/// since removing only its first clause would change what the others do. A block left empty
+/// gets a `pass`.
+pub fn strip_debug_code(content: &str, strip_asserts: bool, patterns: &[String]) -> String {
+    let statements = logical_statements(content);
+    let strips = |statement: &LogicalStatement| {
+        (strip_asserts && (starts_with_words(&statement.code, "assert") || is_debug_block(&statement.code)))
+            || patterns.iter().any(|pattern| starts_with_words(&statement.code, pattern.trim()))
+    };
+
+    let mut removed = vec![false; statements.len()];
+    let mut index = 0;
+    while index < statements.len() {
+        let statement = &statements[index];
+        // The statement and its block
+        let mut after = index + 1;
+        if statement.opens_block {
+            while after < statements.len() && statements[after].indent > statement.indent {
+                after += 1;
+            }
+        }
+        let continued = statements.get(after).is_some_and(|next| next.indent == statement.indent && continues_statement(&next.code));
+        if strips(statement) && !continues_statement(&statement.code) && !continued {
+            removed[index..after].fill(true);
+            index = after;
+        } else {
+            index += 1;
+        }
+    }
+    if !removed.contains(&true) {
+        return content.to_string();
+    }
+
+    // Blocks whose every statement is removed keep a `pass` where the first one was
+    let mut passes = Vec::new();
+    for (index, header) in statements.iter().enumerate().filter(|(index, statement)| statement.opens_block && !removed[*index]) {
+        let body = statements[index + 1..].iter().take_while(|statement| statement.indent > header.indent).count();
+        if body > 0 && removed[index + 1..index + 1 + body].iter().all(|removed| *removed) {
+            passes.push(index + 1);
+        }
+    }
+
+    let mut result = String::new();
+    let mut last_end = 0;
+    for (index, statement) in statements.iter().enumerate() {
+        if !removed[index] {
+            continue;
+        }
+        result.push_str(&content[last_end..statement.start]);
+        if passes.contains(&index) {
+            result.push_str(&content[statement.start..statement.start + statement.indent]);
+            result.push_str("pass\n");
+        }
+        last_end = statement.end;
+    }
+    result.push_str(&content[last_end..]);
+    result
+}
+
+/// `if __debug__:`, whose block python -O never runs
+fn is_debug_block(code: &str) -> bool {
+    code.strip_prefix("if").and_then(|rest| rest.trim().strip_prefix("__debug__")).is_some_and(|rest| {
+        let rest = rest.trim_start();
+        rest == ":" || rest.starts_with(":") && rest[1..].trim_start().starts_with('#')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_asserts() {
+        let content = r#"def check(x):
+    assert x > 0, (
+        "x must be positive"
+    )
+    assertion = x  # not an assert
+    return assertion
+
+def only(x):
+    assert(x)
+
+if __debug__:  # debug build
+    print("debug")
+    assert True
+
+if __debug__:
+    tracing = True
+else:
+    tracing = False
+
+MESSAGE = """
+assert inside a string
+"""
+"#;
+        assert_eq!(strip_debug_code(content, true, &[]), r#"def check(x):
+    assertion = x  # not an assert
+    return assertion
+
+def only(x):
+    pass
+
+
+if __debug__:
+    tracing = True
+else:
+    tracing = False
+
+MESSAGE = """
+assert inside a string
+"""
+"#);
+        assert_eq!(strip_debug_code(content, false, &[]), content);
+    }
+
+    #[test]
+    fn test_strip_patterns() {
+        let content = r#"import logging
+logger = logging.getLogger(__name__)
+
+def run(items):
+    logger.debug("running %d items",
+                 len(items))
+    logger.debugging = False
+    for item in items:
+        logger.debug(item)
+    if DEBUG:
+        dump(items)
+        if VERBOSE:
+            dump(items)
+    try:
+        logger.debug("trying")
+    except ValueError:
+        pass
+    return len(items)
+"#;
+        let patterns = ["logger.debug".to_string(), "if DEBUG".to_string()];
+        assert_eq!(strip_debug_code(content, false, &patterns), r#"import logging
+logger = logging.getLogger(__name__)
+
+def run(items):
+    logger.debugging = False
+    for item in items:
+        pass
+    try:
+        pass
+    except ValueError:
+        pass
+    return len(items)
+"#);
+
+        // A clause of an if statement goes only with the whole statement
+        let content = "if DEBUG:\n    level = 10\nelse:\n    level = 20\n";
+        assert_eq!(strip_debug_code(content, false, &patterns), content);
+    }
+}
@@ -0,0 +1,369 @@
+use crate::modules::debug_code::starts_with_words;
+use crate::modules::source_analysis::{logical_statements, mask_strings_and_comments, string_spans, LogicalStatement};
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Set the constants given with --define in a module: each top-level `NAME = ...` (or
+/// `NAME: type = ...`) assignment of a defined name gets the value instead. With `prune`, the
+/// `if NAME:` and `if not NAME:` clauses the values rule out are then removed, along with the
+/// `elif` and `else` clauses that can no longer run, where the module binds the name only
+/// through those assignments or a `from ... import NAME`.
+pub fn apply_defines(content: &str, defines: &[(String, String)], prune: bool) -> String {
+    let mut content = content.to_string();
+    let mut assigned = Vec::new();
+    for (name, value) in defines {
+        if let Some(rewritten) = assign_constant(&content, name, value) {
+            content = rewritten;
+            assigned.push(name);
+        }
+    }
+    if !prune {
+        return content;
+    }
+
+    let known: HashMap<&str, bool> = defines.iter()
+        .filter_map(|(name, value)| Some((name.as_str(), truthiness(value)?)))
+        .filter(|(name, _)| bound_by_define(&content, name, assigned.contains(&&name.to_string())))
+        .collect();
+    if known.is_empty() {
+        return content;
+    }
+    while let Some(pruned) = prune_first_chain(&content, &known) {
+        content = pruned;
+    }
+    content
+}
+
+/// The content with the value in every top-level assignment of the name, or None if there's none.
+/// Chained assignments (`NAME = OTHER = ...`) are left alone, as they bind other names too.
+fn assign_constant(content: &str, name: &str, value: &str) -> Option<String> {
+    let masked = mask_strings_and_comments(content);
+    let spans = string_spans(content);
+    let assignment_regex = Regex::new(&format!(r"^{}[ \t]*(?::[^=]*)?(=)[^=]", regex::escape(name))).unwrap();
+    let mut result = String::new();
+    let mut last_end = 0;
+    for statement in logical_statements(content).iter().filter(|statement| statement.indent == 0) {
+        let code = &masked[statement.start..statement.end];
+        let Some(equals) = assignment_regex.captures(code).map(|captures| captures.get(1).unwrap().end()) else {
+            continue;
+        };
+        if has_assignment(&code[equals..]) {
+            continue;
+        }
+        // Up to a trailing comment, which stays: the masked code ends before it, or before the
+        // last string
+        let string_end = spans.iter().map(|(_, end)| *end).filter(|end| *end <= statement.end).max().unwrap_or(0);
+        let value_end = (statement.start + code.trim_end().len()).max(string_end);
+        result.push_str(&content[last_end..statement.start + equals]);
+        result.push(' ');
+        result.push_str(value.trim());
+        last_end = value_end;
+    }
+    if last_end == 0 {
+        return None;
+    }
+    result.push_str(&content[last_end..]);
+    Some(result)
+}
+
+/// Whether masked code has an `=` outside brackets that isn't part of a comparison
+fn has_assignment(masked: &str) -> bool {
+    let bytes = masked.as_bytes();
+    let mut depth = 0;
+    for (index, byte) in bytes.iter().enumerate() {
+        match byte {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => depth -= 1,
+            b'=' if depth == 0 => {
+                let before = index.checked_sub(1).map(|before| bytes[before]);
+                let after = bytes.get(index + 1);
+                if !matches!(before, Some(b'=' | b'!' | b'<' | b'>')) && after != Some(&b'=') {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Whether a defined value is true or false, for a literal whose truth is known
+fn truthiness(value: &str) -> Option<bool> {
+    let value = value.trim();
+    match value {
+        "True" => return Some(true),
+        "False" | "None" | "()" | "[]" | "{}" => return Some(false),
+        _ => {}
+    }
+    if value.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') {
+        return value.parse::<f64>().ok().map(|number| number != 0.0);
+    }
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let inner = value.strip_prefix(quote)?.strip_suffix(quote)?;
+    (!inner.contains(quote)).then_some(!inner.is_empty())
+}
+
+/// Whether every binding of the name in the module is one the define sets: a top-level
+/// assignment rewritten with its value or a `from ... import NAME`. A parameter, loop variable,
+/// `global`, `as` or other assignment of the name could give it another value.
+fn bound_by_define(content: &str, name: &str, assigned: bool) -> bool {
+    let masked = mask_strings_and_comments(content);
+    let name = regex::escape(name);
+    let assignment_regex = Regex::new(&format!(r"^{}[ \t]*(?::[^=]*)?=[^=]", name)).unwrap();
+    let other_binding_regex = Regex::new(&format!(
+        r"^{0}\s*(?:[-+*/%&|^@]|//|\*\*|<<|>>)=|^(?:global|nonlocal)\s.*\b{0}\b|^(?:async\s+)?def\s+(?:{0}\b|.*\b{0}\b)|^class\s+{0}\b|^(?:async\s+)?for\s+.*\b{0}\b.*\bin\b|\bas\s+{0}\b|\b{0}\s*:=|^import\s.*\b{0}\b",
+        name
+    )).unwrap();
+    let imported_regex = Regex::new(&format!(r"^from\s+\S+\s+import\s.*\b{}\b", name)).unwrap();
+
+    let mut defined = false;
+    for statement in logical_statements(content) {
+        let code = masked[statement.start + statement.indent..statement.end].replace('\n', " ");
+        if other_binding_regex.is_match(&code) {
+            return false;
+        }
+        if assignment_regex.is_match(&code) {
+            // Only the top-level ones have the value
+            if statement.indent > 0 || !assigned {
+                return false;
+            }
+            defined = true;
+        } else if imported_regex.is_match(&code) {
+            defined = true;
+        }
+    }
+    defined
+}
+
+/// What is known about the clauses of an if statement with the defined values
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Clause {
+    /// An `if` or `elif` whose condition is known
+    Known(bool),
+    /// An `if` or `elif` whose condition isn't
+    Unknown,
+    Else,
+}
+
+fn clause(masked_code: &str, known: &HashMap<&str, bool>) -> Option<Clause> {
+    let condition_regex = Regex::new(r"^(?:if|elif)\s+(not\s+)?\(?\s*([A-Za-z_]\w*)\s*\)?\s*:\s*$").unwrap();
+    let code = masked_code.trim_end();
+    if Regex::new(r"^else\s*:$").unwrap().is_match(code) {
+        return Some(Clause::Else);
+    }
+    if !starts_with_words(code, "if") && !starts_with_words(code, "elif") {
+        return None;
+    }
+    Some(match condition_regex.captures(code) {
+        Some(captures) => match known.get(&captures[2]) {
+            Some(value) => Clause::Known(*value != captures.get(1).is_some()),
+            None => Clause::Unknown,
+        },
+        None => Clause::Unknown,
+    })
+}
+
+/// The content with the first if statement that has a clause of known truth rewritten, or None
+/// if there's none left. A statement whose clauses don't all open blocks is left alone.
+fn prune_first_chain(content: &str, known: &HashMap<&str, bool>) -> Option<String> {
+    let masked = mask_strings_and_comments(content);
+    let statements = logical_statements(content);
+    let masked_code = |statement: &LogicalStatement| masked[statement.start + statement.indent..statement.end].to_string();
+
+    for (index, first) in statements.iter().enumerate() {
+        if !starts_with_words(&masked_code(first), "if") || !matches!(clause(&masked_code(first), known), Some(Clause::Known(_) | Clause::Unknown)) {
+            continue;
+        }
+        // Each clause: its header, what's known of it, and the end of its body
+        let mut clauses: Vec<(usize, Clause, usize)> = Vec::new();
+        let mut header = index;
+        let chain_end = loop {
+            let mut body_end = header + 1;
+            while body_end < statements.len() && statements[body_end].indent > first.indent {
+                body_end += 1;
+            }
+            clauses.push((header, clause(&masked_code(&statements[header]), known).unwrap(), body_end));
+            let next = statements.get(body_end).filter(|next| next.indent == first.indent);
+            match next.map(|next| (next, clause(&masked_code(next), known))) {
+                Some((next, Some(next_clause))) if next_clause == Clause::Else || starts_with_words(&masked_code(next), "elif") => header = body_end,
+                _ => break body_end,
+            }
+        };
+        if !clauses.iter().any(|(_, clause, _)| matches!(clause, Clause::Known(_)))
+            || clauses.iter().any(|(header, _, body_end)| !statements[*header].opens_block || *body_end == header + 1) {
+            continue;
+        }
+
+        // The clauses that can still run, up to the first one that's sure to
+        let mut kept: Vec<(usize, bool, usize)> = Vec::new();
+        for (header, clause, body_end) in &clauses {
+            match clause {
+                Clause::Known(false) => {}
+                Clause::Unknown => kept.push((*header, false, *body_end)),
+                Clause::Known(true) | Clause::Else => {
+                    kept.push((*header, true, *body_end));
+                    break;
+                }
+            }
+        }
+
+        let indent = &content[first.start..first.start + first.indent];
+        // Each clause's body runs up to the next clause, taking the comments between them along
+        let clause_end = |body_end: usize| match body_end {
+            body_end if body_end == chain_end => statements[body_end - 1].end,
+            body_end => statements[body_end].start,
+        };
+        let replacement = match kept.first() {
+            None => {
+                let alone = index > 0 && statements[index - 1].opens_block && statements[index - 1].indent < first.indent
+                    && statements.get(chain_end).is_none_or(|next| next.indent < first.indent);
+                if alone { format!("{}pass\n", indent) } else { String::new() }
+            }
+            Some((header, true, body_end)) => {
+                let body = &statements[header + 1];
+                dedent(content, statements[*header].end, clause_end(*body_end), body.indent - first.indent)
+            }
+            Some(_) => {
+                let mut replacement = String::new();
+                for (position, (header, sure, body_end)) in kept.iter().enumerate() {
+                    let header = &statements[*header];
+                    let code = &content[header.start + header.indent..header.end];
+                    match (position, sure) {
+                        (_, true) if !starts_with_words(&masked_code(header), "else") => replacement.push_str(&format!("{}else:\n", indent)),
+                        (0, _) => replacement.push_str(&format!("{}if{}", indent, code.strip_prefix("elif").unwrap_or(&code[2..]))),
+                        _ => replacement.push_str(&content[header.start..header.end]),
+                    }
+                    replacement.push_str(&content[header.end..clause_end(*body_end)]);
+                }
+                replacement
+            }
+        };
+        return Some(format!("{}{}{}", &content[..first.start], replacement, &content[clause_end(chain_end)..]));
+    }
+    None
+}
+
+/// The lines of content[start..end] with `width` bytes of indentation removed, except for lines
+/// that start inside a string
+fn dedent(content: &str, start: usize, end: usize, width: usize) -> String {
+    let spans = string_spans(content);
+    let mut result = String::new();
+    let mut line_start = start;
+    for line in content[start..end].split_inclusive('\n') {
+        let in_string = spans.iter().any(|(span_start, span_end)| *span_start < line_start && line_start < *span_end);
+        let removed = match in_string {
+            true => 0,
+            false => line.bytes().take(width).take_while(|byte| *byte == b' ' || *byte == b'\t').count(),
+        };
+        result.push_str(&line[removed..]);
+        line_start += line.len();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defines(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_assign_defines() {
+        let content = r#"import os
+DEBUG = os.environ.get("DEBUG") == "1"  # set by the environment
+LEVEL: int = (
+    10
+)
+TRACE = VERBOSE = False
+
+def configure(DEBUG=None):
+    DEBUG = True
+"#;
+        let result = apply_defines(content, &defines(&[("DEBUG", "False"), ("LEVEL", "20"), ("TRACE", "True")]), false);
+        assert_eq!(result, r#"import os
+DEBUG = False  # set by the environment
+LEVEL: int = 20
+TRACE = VERBOSE = False
+
+def configure(DEBUG=None):
+    DEBUG = True
+"#);
+    }
+
+    #[test]
+    fn test_prune_defines() {
+        let content = r#"DEBUG = True
+FAST = False
+
+def run():
+    if DEBUG:
+        print("debug")
+        if not FAST:
+            check("""
+  slow
+""")
+    else:
+        quiet()
+    if FAST:
+        go()
+    elif ready():
+        wait()
+    elif DEBUG:
+        step()
+    else:
+        stop()
+    if FAST:
+        go()
+    return 1
+
+class Config:
+    if FAST:
+        speed = 2
+"#;
+        let result = apply_defines(content, &defines(&[("DEBUG", "False"), ("FAST", "'yes'")]), true);
+        assert_eq!(result, r#"DEBUG = False
+FAST = 'yes'
+
+def run():
+    quiet()
+    go()
+    go()
+    return 1
+
+class Config:
+    speed = 2
+"#);
+
+        let result = apply_defines(content, &defines(&[("DEBUG", "True"), ("FAST", "0")]), true);
+        assert_eq!(result, r#"DEBUG = True
+FAST = 0
+
+def run():
+    print("debug")
+    check("""
+  slow
+""")
+    if ready():
+        wait()
+    else:
+        step()
+    return 1
+
+class Config:
+    pass
+"#);
+    }
+
+    #[test]
+    fn test_prune_needs_every_binding() {
+        // A name set elsewhere, or of unknown truth, keeps its blocks
+        let content = "from config import DEBUG\n\nif DEBUG:\n    trace()\n";
+        assert_eq!(apply_defines(content, &defines(&[("DEBUG", "False")]), true), "from config import DEBUG\n\n");
+        let content = "DEBUG = False\n\ndef run(DEBUG):\n    if DEBUG:\n        trace()\n";
+        assert_eq!(apply_defines(content, &defines(&[("DEBUG", "False")]), true), content);
+        let content = "LEVEL = 1\nif LEVEL:\n    trace()\n";
+        assert_eq!(apply_defines(content, &defines(&[("LEVEL", "level()")]), true), "LEVEL = level()\nif LEVEL:\n    trace()\n");
+    }
+}
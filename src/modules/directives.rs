@@ -0,0 +1,71 @@
+use regex::Regex;
+
+/// The comments at the top of a file, before its first statement, whose directives apply to
+/// the whole file
+pub fn header_lines(source: &str) -> impl Iterator<Item = &str> {
+    source.lines().take_while(|line| line.trim().is_empty() || line.trim_start().starts_with('#'))
+}
+
+/// The line containing `offset`, without its line ending
+pub fn line_at(source: &str, offset: usize) -> &str {
+    let line_start = source[..offset].rfind('\n').map_or(0, |position| position + 1);
+    let line_end = source[offset..].find('\n').map_or(source.len(), |position| offset + position);
+    source[line_start..line_end].trim_end_matches('\r')
+}
+
+/// Whether the line containing `offset` has a `# inliner: skip` comment, which leaves the
+/// import on it as it is
+pub fn skips_import(source: &str, offset: usize) -> bool {
+    Regex::new(r"#\s*inliner:\s*skip\b").unwrap().is_match(line_at(source, offset))
+}
+
+/// The module patterns of the `# inliner: include(utils, plugins.*)` comments at the top of a
+/// file, which inline those modules into it as if they had been given with --module-names.
+/// `include=utils.*` is accepted too.
+pub fn included_modules(source: &str) -> Vec<String> {
+    let include = Regex::new(r"#\s*inliner:\s*include(?:\(([^)]*)\)|=(\S+))").unwrap();
+    let mut patterns = Vec::new();
+    for line in header_lines(source) {
+        for captures in include.captures_iter(line) {
+            let list = captures.get(1).or_else(|| captures.get(2)).unwrap().as_str();
+            patterns.extend(list.split(',').map(str::trim).filter(|pattern| !pattern.is_empty()).map(str::to_string));
+        }
+    }
+    patterns
+}
+
+/// The regex for a module pattern of an include directive. Like a name given with
+/// --module-names it matches the module and its submodules, so a trailing `.*` changes
+/// nothing; a `*` elsewhere matches any part of a name, as in `plugins.backend_*`.
+pub fn include_pattern_regex(pattern: &str) -> String {
+    let pattern = pattern.strip_suffix(".*").unwrap_or(pattern);
+    pattern.split('.')
+        .map(|part| part.split('*').map(regex::escape).collect::<Vec<_>>().join(r"\w*"))
+        .collect::<Vec<_>>()
+        .join(r"\.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skips_import() {
+        let source = "import a  # inliner: skip\r\nimport b  # inliner: skipped\nimport c\n";
+        assert!(skips_import(source, 0));
+        assert!(!skips_import(source, source.find("import b").unwrap()));
+        assert!(!skips_import(source, source.find("import c").unwrap()));
+    }
+
+    #[test]
+    fn test_included_modules() {
+        let source = "#!/usr/bin/env python\n# inliner: include(utils.*, plugins)\n\n# inliner: include=helpers\nimport a  # inliner: include(late)\n";
+        assert_eq!(included_modules(source), vec!["utils.*", "plugins", "helpers"]);
+        assert!(included_modules("import a\n").is_empty());
+
+        let regex = Regex::new(&format!("^(?:{})$", include_pattern_regex("plugins.backend_*"))).unwrap();
+        assert!(regex.is_match("plugins.backend_qt"));
+        assert!(!regex.is_match("plugins.frontend"));
+        assert_eq!(include_pattern_regex("utils.*"), "utils");
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use crate::modules::bundle_diff::resolve_relative_name;
+use crate::modules::entry_point::normalize_entry_point;
+use crate::modules::errors::{InlinerError, SourceSite};
+use crate::modules::file_system::FileSystem;
+use crate::modules::inlining::{find_type_checking_blocks, note_unresolved, warn_at};
+use crate::modules::options::Opt;
+use crate::modules::release::strip_source;
+use crate::modules::resolution::choose_resolution;
+use crate::modules::resolver::ModuleResolver;
+use crate::modules::terminal::Style;
+use crate::modules::source_analysis::{insert_preamble, mask_strings_and_comments};
+use crate::modules::warnings::WarningCategory;
+use crate::modules::directives::skips_import;
+use crate::modules::tree_shake::imported_names;
+use crate::{file_module_names, line_number, note_size, read_source};
+
+/// The source of a module embedded in a bundle built with --embed-modules
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddedModule {
+    pub is_package: bool,
+    pub source: String,
+}
+
+/// The file name tracebacks show for an embedded module. It isn't wrapped in `<...>`, so
+/// linecache asks the loader's get_source() for the lines.
+fn origin(name: &str, is_package: bool) -> String {
+    let path = name.replace('.', "/");
+    if is_package { format!("{}/__init__.py", path) } else { format!("{}.py", path) }
+}
+
+/// Render the preamble that holds every embedded module's source, keyed by absolute module name,
+/// and installs a meta path finder/loader serving them. Each module then runs in its own
+/// namespace with the `__name__`, `__package__` and import-once semantics Python would give it.
+pub fn render_module_loader(modules: &BTreeMap<String, EmbeddedModule>, release: bool) -> String {
+    let mut preamble = String::new();
+    if !release {
+        preamble.push_str("# ↓↓↓ embedded modules\n");
+    }
+    preamble.push_str("import importlib.abc as _inliner_abc\n");
+    preamble.push_str("import importlib.util as _inliner_util\n");
+    preamble.push_str("import sys as _inliner_sys\n");
+    preamble.push_str("_INLINED_MODULES = {\n");
+    for (name, module) in modules {
+        // JSON string escapes are all valid in Python string literals
+        preamble.push_str(&format!(
+            "    {}: ({}, {}, {}),\n",
+            serde_json::to_string(name).unwrap(),
+            if module.is_package { "True" } else { "False" },
+            serde_json::to_string(&origin(name, module.is_package)).unwrap(),
+            serde_json::to_string(&module.source).unwrap(),
+        ));
+    }
+    preamble.push_str("}\n");
+    preamble.push_str(r#"class _InlinedModuleLoader(_inliner_abc.MetaPathFinder, _inliner_abc.Loader):
+    def find_spec(self, fullname, path=None, target=None):
+        if fullname not in _INLINED_MODULES:
+            return None
+        is_package, origin, _ = _INLINED_MODULES[fullname]
+        return _inliner_util.spec_from_loader(fullname, self, origin=origin, is_package=is_package)
+    def create_module(self, spec):
+        return None
+    def exec_module(self, module):
+        _, origin, source = _INLINED_MODULES[module.__spec__.name]
+        exec(compile(source, origin, "exec"), module.__dict__)
+    def get_source(self, fullname):
+        return _INLINED_MODULES[fullname][2]
+_inliner_sys.meta_path.insert(0, _InlinedModuleLoader())
+"#);
+    if !release {
+        preamble.push_str("# ↑↑↑ embedded modules\n");
+    }
+    preamble
+}
+
+/// Build a bundle for --embed-modules: the entry script, preceded by a loader serving the source
+/// of every module it would otherwise have inlined
+pub fn embed_modules_bundle(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, input_file: &Path, module_names: &str, processed: &mut HashSet<PathBuf>, opt: &Opt) -> Result<String, InlinerError> {
+    let mut modules = BTreeMap::new();
+    collect_embedded_modules(fs, python_sys_path, input_file, None, module_names, &mut modules, processed, opt)?;
+
+    // The entry script runs as __main__, outside any package, so its relative imports (which
+    // resolve against its directory) are made absolute
+    let entry = read_source(fs, input_file)?;
+    let masked_entry = mask_strings_and_comments(&entry);
+    let relative_import_regex = Regex::new(r"(?m)^[ \t]*from\s+(\.+)([\w.]+)\s+import\s").unwrap();
+    let mut rewritten = String::new();
+    let mut last_end = 0;
+    for cap in relative_import_regex.captures_iter(&masked_entry) {
+        if modules.contains_key(&cap[2]) {
+            let dots = cap.get(1).unwrap();
+            rewritten.push_str(&entry[last_end..dots.start()]);
+            last_end = dots.end();
+        }
+    }
+    rewritten.push_str(&entry[last_end..]);
+
+    let mut entry = strip_source(&rewritten, opt);
+    if let Some(function) = &opt.entry_function {
+        entry = normalize_entry_point(&entry, function).map_err(InlinerError::usage)?;
+    }
+    Ok(insert_preamble(&entry, &render_module_loader(&modules, opt.release)))
+}
+
+/// Collect the source of every module `file` imports that would otherwise be inlined, keyed by
+/// absolute module name. `module` is the file's own (name, is_package), None for the entry script.
+#[allow(clippy::too_many_arguments)]
+pub fn collect_embedded_modules(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, file: &Path, module: Option<(&str, bool)>, module_names: &str, modules: &mut BTreeMap<String, EmbeddedModule>, processed: &mut HashSet<PathBuf>, opt: &Opt) -> Result<(), InlinerError> {
+    let content = read_source(fs, file)?;
+    let module_names = &file_module_names(&content, module_names);
+    let masked_content = mask_strings_and_comments(&content);
+    // Imports for static type checkers never run, so they don't need embedding
+    let type_checking_blocks = find_type_checking_blocks(&content);
+    let in_type_checking_block = |position: usize| type_checking_blocks.iter().any(|(start, end)| (*start..*end).contains(&position));
+
+    let from_import_regex = Regex::new(&format!(r"(?m)^[ \t]*from\s+({})\s+import\s+(\([^)]*\)|.*)$", module_names)).unwrap();
+    let import_regex = Regex::new(r"(?m)^[ \t]*import\s+(.+)$").unwrap();
+    let module_name_regex = Regex::new(&format!(r"^(?:{})$", module_names)).unwrap();
+
+    // (module as written, names imported from it, offset of the import)
+    let mut imports: Vec<(String, Vec<String>, usize)> = Vec::new();
+    for cap in from_import_regex.captures_iter(&masked_content) {
+        if !in_type_checking_block(cap.get(0).unwrap().start()) && !skips_import(&content, cap.get(0).unwrap().start()) {
+            imports.push((cap[1].to_string(), imported_names(&cap[2]), cap.get(0).unwrap().start()));
+        }
+    }
+    for cap in import_regex.captures_iter(&masked_content) {
+        if in_type_checking_block(cap.get(0).unwrap().start()) || skips_import(&content, cap.get(0).unwrap().start()) {
+            continue;
+        }
+        for item in cap[1].split(',') {
+            if let Some(name) = item.split_whitespace().next() {
+                if module_name_regex.is_match(name) {
+                    imports.push((name.to_string(), Vec::new(), cap.get(0).unwrap().start()));
+                }
+            }
+        }
+    }
+
+    for (submodule, names, start) in imports {
+        let relative = submodule.trim_start_matches('.');
+        let level = submodule.len() - relative.len();
+        let name = match module {
+            Some((own_name, is_package)) => resolve_relative_name(&submodule, Some(&(own_name.to_string(), is_package))),
+            // The entry script's relative imports are made absolute, see embed_modules_bundle()
+            None => relative.to_string(),
+        };
+        if name.is_empty() {
+            continue;
+        }
+        let top_level_module = name.split('.').next().unwrap_or(&name);
+        if level == 0 && opt.external_modules.iter().any(|m| m == top_level_module) {
+            if opt.verbose {
+                println!("{}", opt.terminal.paint(&format!("Leaving external module {:?} as an import", submodule), Style::Skip));
+            }
+            continue;
+        }
+
+        let search_dirs = if level > 0 {
+            let mut base_dir = file.parent().unwrap().to_path_buf();
+            if module.is_some() {
+                for _ in 1..level {
+                    base_dir.pop();
+                }
+            }
+            vec![base_dir]
+        } else {
+            python_sys_path.clone()
+        };
+        let resolver = ModuleResolver::new(fs, &search_dirs).cached(&opt.resolution_cache);
+        let resolved = if level > 0 {
+            resolver.candidates(relative).first().cloned()
+        } else {
+            choose_resolution(fs, &name, &resolver.candidates(&name), opt).map_err(|e| InlinerError::located(e, || SourceSite::at(file, &content, start, &submodule)))?
+        };
+        let Some(path) = resolved else {
+            warn_at(opt, WarningCategory::UnresolvedModule, &name, &content, start, format!("{}:{}", file.display(), line_number(&content, start)));
+            note_unresolved(opt, &resolver, &name, relative);
+            continue;
+        };
+
+        embed_module(fs, python_sys_path, &name, &path, module_names, modules, processed, opt)?;
+        // `from package import name` imports the submodule `name` when there is one
+        if path.file_name().is_some_and(|file_name| file_name == "__init__.py") {
+            let package_dir = [path.parent().unwrap().to_path_buf()];
+            for imported in names {
+                if let Some(submodule_path) = ModuleResolver::new(fs, &package_dir).cached(&opt.resolution_cache).candidates(&imported).first() {
+                    embed_module(fs, python_sys_path, &format!("{}.{}", name, imported), submodule_path, module_names, modules, processed, opt)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Embed one module and the packages containing it, then whatever it imports in turn
+#[allow(clippy::too_many_arguments)]
+fn embed_module(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, name: &str, path: &Path, module_names: &str, modules: &mut BTreeMap<String, EmbeddedModule>, processed: &mut HashSet<PathBuf>, opt: &Opt) -> Result<(), InlinerError> {
+    if modules.contains_key(name) || !processed.insert(path.to_path_buf()) {
+        return Ok(());
+    }
+    opt.terminal.progress.borrow_mut().inline(path);
+    if opt.verbose {
+        println!("Embedding module {} from {}", name, path.display());
+    }
+    let is_package = path.file_name().is_some_and(|file_name| file_name == "__init__.py");
+    let source = strip_source(&read_source(fs, path)?, opt);
+    note_size(opt, path, &source, std::iter::empty());
+    modules.insert(name.to_string(), EmbeddedModule { is_package, source });
+
+    // Python imports the enclosing packages first, so they're embedded too. A directory
+    // without an __init__.py is a namespace package and gets an empty one.
+    let mut package_dir = path.parent().unwrap().to_path_buf();
+    if is_package {
+        package_dir.pop();
+    }
+    let mut package_name = name;
+    while let Some((parent, _)) = package_name.rsplit_once('.') {
+        let init_path = package_dir.join("__init__.py");
+        if fs.exists(&init_path).map_err(|e| InlinerError::io(&init_path, e))? {
+            embed_module(fs, python_sys_path, parent, &init_path, module_names, modules, processed, opt)?;
+        } else {
+            modules.entry(parent.to_string()).or_insert(EmbeddedModule { is_package: true, source: String::new() });
+        }
+        package_name = parent;
+        package_dir.pop();
+    }
+
+    collect_embedded_modules(fs, python_sys_path, path, Some((name, is_package)), module_names, modules, processed, opt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_module_loader() {
+        let mut modules = BTreeMap::new();
+        modules.insert("mylib".to_string(), EmbeddedModule { is_package: true, source: "from .utils import f\n".to_string() });
+        modules.insert("mylib.utils".to_string(), EmbeddedModule { is_package: false, source: "def f():\n    return \"hi\"\n".to_string() });
+        let preamble = render_module_loader(&modules, true);
+        assert!(preamble.contains("    \"mylib\": (True, \"mylib/__init__.py\", \"from .utils import f\\n\"),\n"));
+        assert!(preamble.contains("    \"mylib.utils\": (False, \"mylib/utils.py\", \"def f():\\n    return \\\"hi\\\"\\n\"),\n"));
+        assert!(!preamble.contains("# ↓↓↓"));
+    }
+}
@@ -0,0 +1,134 @@
+use regex::Regex;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Characters of windows-1252 bytes 0x80 to 0x9F, which latin-1 leaves as C1 control codes.
+/// None for the five bytes the encoding leaves undefined.
+const CP1252_HIGH: [Option<char>; 32] = [
+    Some('\u{20AC}'), None, Some('\u{201A}'), Some('\u{0192}'), Some('\u{201E}'), Some('\u{2026}'), Some('\u{2020}'), Some('\u{2021}'),
+    Some('\u{02C6}'), Some('\u{2030}'), Some('\u{0160}'), Some('\u{2039}'), Some('\u{0152}'), None, Some('\u{017D}'), None,
+    None, Some('\u{2018}'), Some('\u{2019}'), Some('\u{201C}'), Some('\u{201D}'), Some('\u{2022}'), Some('\u{2013}'), Some('\u{2014}'),
+    Some('\u{02DC}'), Some('\u{2122}'), Some('\u{0161}'), Some('\u{203A}'), Some('\u{0153}'), None, Some('\u{017E}'), Some('\u{0178}'),
+];
+
+/// The source encodings that can be decoded, under the names Python normalizes them to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SourceEncoding {
+    Utf8,
+    Latin1,
+    Cp1252,
+    Ascii,
+}
+
+impl SourceEncoding {
+    fn from_name(name: &str) -> Option<SourceEncoding> {
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" | "utf-8-sig" => Some(SourceEncoding::Utf8),
+            "latin-1" | "latin1" | "l1" | "iso-8859-1" | "iso8859-1" | "iso-latin-1" => Some(SourceEncoding::Latin1),
+            "cp1252" | "windows-1252" => Some(SourceEncoding::Cp1252),
+            "ascii" | "us-ascii" => Some(SourceEncoding::Ascii),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SourceEncoding::Utf8 => "utf-8",
+            SourceEncoding::Latin1 => "latin-1",
+            SourceEncoding::Cp1252 => "cp1252",
+            SourceEncoding::Ascii => "ascii",
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<String, String> {
+        match self {
+            SourceEncoding::Utf8 => String::from_utf8(bytes.to_vec()).map_err(|e| format!("invalid UTF-8 at byte {}", e.utf8_error().valid_up_to())),
+            SourceEncoding::Latin1 => Ok(bytes.iter().map(|b| *b as char).collect()),
+            SourceEncoding::Cp1252 => bytes.iter().enumerate()
+                .map(|(position, b)| match b {
+                    0x80..=0x9F => CP1252_HIGH[(b - 0x80) as usize].ok_or_else(|| format!("byte 0x{:X} at {} is undefined in cp1252", b, position)),
+                    _ => Ok(*b as char),
+                })
+                .collect(),
+            SourceEncoding::Ascii => match bytes.iter().position(|b| !b.is_ascii()) {
+                Some(position) => Err(format!("non-ASCII byte 0x{:X} at {}", bytes[position], position)),
+                None => Ok(String::from_utf8(bytes.to_vec()).unwrap()),
+            },
+        }
+    }
+}
+
+/// The span of the PEP 263 encoding declaration line (`# -*- coding: latin-1 -*-`) and the
+/// encoding it names. Only the first line counts, or the second when the first is blank or a
+/// comment.
+fn encoding_cookie(bytes: &[u8]) -> Option<((usize, usize), String)> {
+    let cookie = Regex::new(r"^[ \t\f]*#.*?coding[:=][ \t]*([-\w.]+)").unwrap();
+    let mut line_start = 0;
+    for _ in 0..2 {
+        let line_end = bytes[line_start..].iter().position(|b| *b == b'\n').map_or(bytes.len(), |end| line_start + end + 1);
+        let line = String::from_utf8_lossy(&bytes[line_start..line_end]);
+        if let Some(captures) = cookie.captures(&line) {
+            return Some(((line_start, line_end), captures[1].to_string()));
+        }
+        let trimmed = line.trim_start_matches([' ', '\t', '\x0C']);
+        if !(trimmed.trim_end().is_empty() || trimmed.starts_with('#')) || line_end == bytes.len() {
+            break;
+        }
+        line_start = line_end;
+    }
+    None
+}
+
+/// Decode a Python source file to UTF-8 the way the interpreter would: a UTF-8 BOM or a PEP 263
+/// encoding declaration decides the encoding, UTF-8 otherwise. The BOM is dropped, as is a
+/// declaration of anything but UTF-8, since it would be wrong for the UTF-8 output.
+pub fn decode_source(bytes: &[u8]) -> Result<String, String> {
+    let (bytes, has_bom) = match bytes.strip_prefix(UTF8_BOM) {
+        Some(rest) => (rest, true),
+        None => (bytes, false),
+    };
+    let Some(((cookie_start, cookie_end), name)) = encoding_cookie(bytes) else {
+        return SourceEncoding::Utf8.decode(bytes);
+    };
+    let encoding = SourceEncoding::from_name(&name).ok_or_else(|| format!("unsupported source encoding {:?}", name))?;
+    if encoding == SourceEncoding::Utf8 {
+        return SourceEncoding::Utf8.decode(bytes);
+    }
+    if has_bom {
+        return Err(format!("encoding declaration {:?} conflicts with the UTF-8 byte order mark", name));
+    }
+    let mut without_cookie = bytes[..cookie_start].to_vec();
+    without_cookie.extend_from_slice(&bytes[cookie_end..]);
+    encoding.decode(&without_cookie)
+}
+
+/// The name of the encoding decode_source() reads the file with: `utf-8-sig` when it starts
+/// with a UTF-8 BOM, else the declared encoding, else `utf-8`. None for an unsupported declaration.
+pub fn source_encoding(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(UTF8_BOM) {
+        return Some("utf-8-sig");
+    }
+    match encoding_cookie(bytes) {
+        Some((_, name)) => SourceEncoding::from_name(&name).map(|encoding| encoding.name()),
+        None => Some("utf-8"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_source() {
+        assert_eq!(decode_source(b"\xEF\xBB\xBFx = 1\n").unwrap(), "x = 1\n");
+        assert_eq!(decode_source(b"# -*- coding: utf-8 -*-\nx = '\xC3\xA9'\n").unwrap(), "# -*- coding: utf-8 -*-\nx = '\u{e9}'\n");
+        assert_eq!(decode_source(b"#!/usr/bin/env python\n# vim: set fileencoding=latin-1 :\nx = '\xE9'\n").unwrap(), "#!/usr/bin/env python\nx = '\u{e9}'\n");
+        assert_eq!(decode_source(b"# coding=cp1252\nx = '\x80'\n").unwrap(), "x = '\u{20AC}'\n");
+        // A declaration after the first statement is only a comment
+        assert!(decode_source(b"import os\n# coding: latin-1\nx = '\xE9'\n").is_err());
+        assert!(decode_source(b"# coding: ebcdic\n").unwrap_err().contains("unsupported"));
+        assert!(decode_source(b"\xEF\xBB\xBF# coding: latin-1\n").is_err());
+        assert_eq!(source_encoding(b"# -*- coding: ISO-8859-1 -*-\n"), Some("latin-1"));
+        assert_eq!(source_encoding(b"\xEF\xBB\xBFx = 1\n"), Some("utf-8-sig"));
+    }
+}
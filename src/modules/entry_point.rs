@@ -0,0 +1,129 @@
+use crate::modules::debug_code::starts_with_words;
+use crate::modules::indentation::indent_width;
+use crate::modules::source_analysis::{logical_statements, mask_strings_and_comments, string_spans, top_level_statements, LogicalStatement};
+use regex::Regex;
+
+/// Rewrite an entry script for --entry-function: its top-level calls of the function and its
+/// `if __name__ == "__main__":` blocks are taken out and run from a single such block at the
+/// end, which calls the function itself when none of them did. Everything else stays at module
+/// scope, so exec'ing or importing the bundle defines it all without running the program.
+pub fn normalize_entry_point(content: &str, function: &str) -> Result<String, String> {
+    let name = regex::escape(function);
+    let defined = top_level_statements(content).iter().any(|statement| statement.name.as_deref() == Some(function));
+    let import_regex = Regex::new(&format!(r"^(?:import|from)\s[\s\S]*\b{}\b", name)).unwrap();
+    let masked = mask_strings_and_comments(content);
+    let statements = logical_statements(content);
+    let imported = statements.iter().any(|statement| statement.indent == 0 && import_regex.is_match(&masked[statement.start..statement.end]));
+    if !defined && !imported {
+        return Err(format!("--entry-function {}: the entry script neither defines nor imports `{}`", function, function));
+    }
+
+    let guard_regex = Regex::new(r#"^if\s+\(?\s*(?:__name__\s*==\s*(['"])__main__['"]|(['"])__main__['"]\s*==\s*__name__)\s*\)?\s*:\s*(?:#.*)?$"#).unwrap();
+    let call_regex = Regex::new(&format!(r"^(?:(?:sys\.exit|exit|quit|asyncio\.run|raise\s+SystemExit)\s*\(\s*)?{}\s*\(", name)).unwrap();
+    let spans = string_spans(content);
+    let unit = indent_unit(content, &statements);
+
+    // The statements the footer runs, each with their indentation inside it
+    let mut footer = String::new();
+    let mut result = String::new();
+    let mut last_end = 0;
+    let mut index = 0;
+    while index < statements.len() {
+        let statement = &statements[index];
+        let mut after = index + 1;
+        if statement.indent > 0 {
+            index = after;
+            continue;
+        }
+        if statement.opens_block && guard_regex.is_match(&statement.code) {
+            while after < statements.len() && statements[after].indent > 0 {
+                after += 1;
+            }
+            if statements.get(after).is_some_and(|next| ["elif", "else"].iter().any(|clause| starts_with_words(&next.code, clause))) {
+                let line = content[..statement.start].matches('\n').count() + 1;
+                return Err(format!("--entry-function {}: the `if __name__ == \"__main__\":` block on line {} has an else clause, which runs when the bundle is imported", function, line));
+            }
+            footer.push_str(content[statement.end..statements[after - 1].end].trim_start_matches(['\r', '\n']));
+        } else if call_regex.is_match(&statement.code) {
+            // Indented into the footer, apart from lines continuing a string
+            let mut line_start = statement.start;
+            for line in content[statement.start..statement.end].split_inclusive('\n') {
+                if !spans.iter().any(|(start, end)| *start < line_start && line_start < *end) {
+                    footer.push_str(&unit);
+                }
+                footer.push_str(line);
+                line_start += line.len();
+            }
+        } else {
+            index = after;
+            continue;
+        }
+        result.push_str(&content[last_end..statement.start]);
+        last_end = statements[after - 1].end;
+        index = after;
+    }
+    result.push_str(&content[last_end..]);
+
+    if !footer.is_empty() && !footer.ends_with('\n') {
+        footer.push('\n');
+    }
+    let footer_masked = mask_strings_and_comments(&footer);
+    if !Regex::new(&format!(r"\b{}\s*\(", name)).unwrap().is_match(&footer_masked) {
+        footer.push_str(&format!("{}{}()\n", unit, function));
+    }
+    let body = result.trim_end();
+    let separator = if body.is_empty() { "" } else { "\n\n\n" };
+    Ok(format!("{}{}if __name__ == \"__main__\":\n{}", body, separator, footer))
+}
+
+/// One level of the script's indentation: a tab if its blocks are indented with tabs, otherwise
+/// as many spaces as they use
+fn indent_unit(content: &str, statements: &[LogicalStatement]) -> String {
+    match statements.iter().find(|statement| statement.indent > 0) {
+        Some(statement) if content[statement.start..].starts_with('\t') => "\t".to_string(),
+        _ => " ".repeat(indent_width(content)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_entry_point() {
+        let content = r#"import sys
+from mylib.cli import main
+
+if __name__ == '__main__':  # run as a script
+    configure()
+    sys.exit(main())
+
+def configure():
+    pass
+"#;
+        assert_eq!(normalize_entry_point(content, "main").unwrap(), r#"import sys
+from mylib.cli import main
+
+
+def configure():
+    pass
+
+
+if __name__ == "__main__":
+    configure()
+    sys.exit(main())
+"#);
+
+        // A bare call moves into the footer, and a script that never calls it gets one
+        let content = "def main(argv=None):\n\treturn 0\n\nmain(\n\t[\"--verbose\"],\n)\nprint(\"\"\"done\n\"\"\")\n";
+        assert_eq!(normalize_entry_point(content, "main").unwrap(), "def main(argv=None):\n\treturn 0\n\nprint(\"\"\"done\n\"\"\")\n\n\nif __name__ == \"__main__\":\n\tmain(\n\t\t[\"--verbose\"],\n\t)\n");
+        assert_eq!(normalize_entry_point("def main():\n    pass\n", "main").unwrap(), "def main():\n    pass\n\n\nif __name__ == \"__main__\":\n    main()\n");
+    }
+
+    #[test]
+    fn test_normalize_entry_point_errors() {
+        assert!(normalize_entry_point("def run():\n    pass\n", "main").unwrap_err().contains("neither defines nor imports `main`"));
+        let content = "def main():\n    pass\n\nif __name__ == \"__main__\":\n    main()\nelse:\n    print(\"imported\")\n";
+        assert!(normalize_entry_point(content, "main").unwrap_err().contains("on line 4 has an else clause"));
+    }
+}
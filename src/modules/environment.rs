@@ -0,0 +1,199 @@
+use std::env;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use serde_json::{json, Value};
+
+/// Everything module resolution needs to know about a Python environment, so a run can be
+/// reproduced later (or on another machine) without the original interpreter.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvironmentSnapshot {
+    /// Path of the interpreter the snapshot was taken from
+    pub interpreter: Option<String>,
+    /// Interpreter version as `major.minor`
+    pub version: Option<String>,
+    /// The resolved search path, including editable install locations
+    pub sys_path: Vec<PathBuf>,
+    /// (distribution, source directory) for each editable install found in site-packages
+    pub editable_installs: Vec<(String, PathBuf)>,
+}
+
+impl EnvironmentSnapshot {
+    pub fn to_json(&self) -> String {
+        let value = json!({
+            "interpreter": self.interpreter,
+            "version": self.version,
+            "sys_path": self.sys_path.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>(),
+            "editable_installs": self.editable_installs.iter()
+                .map(|(name, path)| json!({ "name": name, "path": path.to_string_lossy() }))
+                .collect::<Vec<_>>(),
+        });
+        // Serializing a Value can't fail
+        serde_json::to_string_pretty(&value).unwrap() + "\n"
+    }
+
+    pub fn from_json(content: &str) -> Result<EnvironmentSnapshot, String> {
+        let value: Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+        let string_field = |field: &str| value.get(field).and_then(Value::as_str).map(String::from);
+
+        let sys_path = value.get("sys_path")
+            .and_then(Value::as_array)
+            .ok_or("environment snapshot is missing `sys_path`")?
+            .iter()
+            .map(|entry| entry.as_str().map(PathBuf::from).ok_or("`sys_path` entries must be strings"))
+            .collect::<Result<Vec<PathBuf>, _>>()?;
+
+        let mut editable_installs = Vec::new();
+        for install in value.get("editable_installs").and_then(Value::as_array).into_iter().flatten() {
+            let name = install.get("name").and_then(Value::as_str);
+            let path = install.get("path").and_then(Value::as_str);
+            match (name, path) {
+                (Some(name), Some(path)) => editable_installs.push((name.to_string(), PathBuf::from(path))),
+                _ => return Err("`editable_installs` entries need a `name` and a `path`".to_string()),
+            }
+        }
+
+        Ok(EnvironmentSnapshot {
+            interpreter: string_field("interpreter"),
+            version: string_field("version"),
+            sys_path,
+            editable_installs,
+        })
+    }
+
+    /// The search path to resolve against: the recorded sys.path plus any editable install
+    /// locations that aren't already on it
+    pub fn search_path(&self) -> Vec<PathBuf> {
+        let mut search_path = self.sys_path.clone();
+        for (_, path) in &self.editable_installs {
+            if !search_path.contains(path) {
+                search_path.push(path.clone());
+            }
+        }
+        search_path
+    }
+
+    /// Append directories to sys.path, skipping those already on it
+    pub fn add_sys_path(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            if !self.sys_path.contains(path) {
+                self.sys_path.push(path.clone());
+            }
+        }
+    }
+
+    /// Put directories at the front of sys.path in the order given, where PYTHONPATH entries go.
+    /// One that is already on it moves to the front rather than being listed twice.
+    pub fn prepend_sys_path(&mut self, paths: &[PathBuf]) {
+        let mut sys_path: Vec<PathBuf> = Vec::new();
+        for path in paths.iter().chain(&self.sys_path) {
+            if !sys_path.contains(path) {
+                sys_path.push(path.clone());
+            }
+        }
+        self.sys_path = sys_path;
+    }
+
+    /// Add the user site-packages directory where the site module puts it: before the first
+    /// site-packages directory, so user installs shadow system ones
+    pub fn add_user_site(&mut self, user_site: PathBuf) {
+        if self.sys_path.contains(&user_site) {
+            return;
+        }
+        let position = self.sys_path.iter()
+            .position(|path| path.to_string_lossy().contains("site-packages"))
+            .unwrap_or(self.sys_path.len());
+        self.sys_path.insert(position, user_site);
+    }
+}
+
+/// The base directory of user installs (`site.USER_BASE`): PYTHONUSERBASE, else `~/.local`, or
+/// `%APPDATA%\Python` on Windows. None when PYTHONNOUSERSITE disables the user site.
+pub fn user_base() -> Option<PathBuf> {
+    if env::var_os("PYTHONNOUSERSITE").is_some_and(|value| !value.is_empty()) {
+        return None;
+    }
+    if let Some(base) = env::var_os("PYTHONUSERBASE").filter(|value| !value.is_empty()) {
+        return Some(PathBuf::from(base));
+    }
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(|appdata| Path::new(&appdata).join("Python"))
+    } else {
+        env::var_os("HOME").map(|home| Path::new(&home).join(".local"))
+    }
+}
+
+/// The directories of a path list like PYTHONPATH, `:`-separated (`;` on Windows), made
+/// absolute against `current_dir` as the site module does. Empty entries are skipped.
+pub fn path_list(list: &OsStr, current_dir: &Path) -> Vec<PathBuf> {
+    env::split_paths(list)
+        .filter(|path| !path.as_os_str().is_empty())
+        .map(|path| current_dir.join(path))
+        .collect()
+}
+
+/// The user site-packages directory (`site.USER_SITE`) of a `major.minor` interpreter version
+pub fn user_site_packages(user_base: &Path, version: &str) -> PathBuf {
+    if cfg!(windows) {
+        user_base.join(format!("Python{}", version.replace('.', ""))).join("site-packages")
+    } else {
+        user_base.join("lib").join(format!("python{}", version)).join("site-packages")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let snapshot = EnvironmentSnapshot {
+            interpreter: Some("/usr/bin/python3".to_string()),
+            version: Some("3.12".to_string()),
+            sys_path: vec![PathBuf::from("/usr/lib/python312.zip"), PathBuf::from("/venv/lib/python3.12/site-packages")],
+            editable_installs: vec![("mylib".to_string(), PathBuf::from("/src/mylib"))],
+        };
+        assert_eq!(EnvironmentSnapshot::from_json(&snapshot.to_json()).unwrap(), snapshot);
+        assert_eq!(snapshot.search_path().last(), Some(&PathBuf::from("/src/mylib")));
+
+        let minimal = EnvironmentSnapshot::from_json(r#"{"sys_path": ["/lib"]}"#).unwrap();
+        assert_eq!(minimal.sys_path, vec![PathBuf::from("/lib")]);
+        assert!(minimal.interpreter.is_none());
+        assert!(EnvironmentSnapshot::from_json(r#"{"version": "3.12"}"#).is_err());
+    }
+
+    #[test]
+    fn test_user_site() {
+        let mut snapshot = EnvironmentSnapshot {
+            sys_path: vec![PathBuf::from("/usr/lib/python3.12"), PathBuf::from("/usr/lib/python3/site-packages")],
+            ..EnvironmentSnapshot::default()
+        };
+        let user_site = user_site_packages(Path::new("/home/me/.local"), "3.12");
+        if cfg!(not(windows)) {
+            assert_eq!(user_site, PathBuf::from("/home/me/.local/lib/python3.12/site-packages"));
+        }
+        snapshot.add_user_site(user_site.clone());
+        snapshot.add_user_site(user_site.clone());
+        snapshot.add_sys_path(&[PathBuf::from("/opt/plugins"), PathBuf::from("/usr/lib/python3.12")]);
+        assert_eq!(snapshot.sys_path, vec![
+            PathBuf::from("/usr/lib/python3.12"),
+            user_site,
+            PathBuf::from("/usr/lib/python3/site-packages"),
+            PathBuf::from("/opt/plugins"),
+        ]);
+    }
+
+    #[test]
+    fn test_pythonpath() {
+        let separator = if cfg!(windows) { ";" } else { ":" };
+        let list = format!("/opt/plugins{0}{0}src", separator);
+        let entries = path_list(OsStr::new(&list), Path::new("/work"));
+        assert_eq!(entries, vec![PathBuf::from("/opt/plugins"), PathBuf::from("/work/src")]);
+
+        let mut snapshot = EnvironmentSnapshot {
+            sys_path: vec![PathBuf::from("/usr/lib/python3.12"), PathBuf::from("/work/src")],
+            ..EnvironmentSnapshot::default()
+        };
+        snapshot.prepend_sys_path(&entries);
+        assert_eq!(snapshot.sys_path, vec![PathBuf::from("/opt/plugins"), PathBuf::from("/work/src"), PathBuf::from("/usr/lib/python3.12")]);
+    }
+}
@@ -0,0 +1,180 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The line of a source file an error is about, with the part of it to underline
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceSite {
+    pub file: PathBuf,
+    /// 1-based
+    pub line: usize,
+    /// The line as written, without its line ending
+    pub text: String,
+    /// Byte range of the underlined part of `text`
+    pub span: (usize, usize),
+}
+
+impl SourceSite {
+    /// The site of `needle` on line `line` of `file`, whose text is `text`: its first occurrence
+    /// as a whole word, else the whole line without its indentation
+    pub fn new(file: &Path, line: usize, text: &str, needle: &str) -> SourceSite {
+        let text = text.trim_end_matches(['\n', '\r']).to_string();
+        let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+        let found = match needle.is_empty() {
+            true => None,
+            false => text.match_indices(needle).map(|(start, _)| start).find(|&start| {
+                !text[..start].ends_with(is_name_char) && !text[start + needle.len()..].starts_with(is_name_char)
+            }),
+        };
+        let span = match found {
+            Some(start) => (start, start + needle.len()),
+            None => (text.len() - text.trim_start().len(), text.trim_end().len()),
+        };
+        SourceSite { file: file.to_path_buf(), line, text, span }
+    }
+
+    /// The site of `needle` on the line of `content` containing `offset`
+    pub fn at(file: &Path, content: &str, offset: usize, needle: &str) -> SourceSite {
+        let line = content[..offset].matches('\n').count() + 1;
+        SourceSite::new(file, line, crate::modules::directives::line_at(content, offset), needle)
+    }
+}
+
+/// Why python-inliner stopped. Errors about a place in a source file carry its site, for
+/// render() to show.
+#[derive(Debug)]
+pub enum InlinerError {
+    /// An import couldn't be resolved to a file to inline, or the user declined to choose one
+    Resolve { module: String, reason: String, site: Option<SourceSite> },
+    /// A file couldn't be understood, such as a bundle whose markers don't pair up
+    Parse { file: PathBuf, reason: String, site: Option<SourceSite> },
+    /// Reading or writing a file failed
+    Io { path: PathBuf, source: io::Error },
+    /// The Python interpreter couldn't be run or queried
+    PythonDiscovery { interpreter: String, reason: String },
+    /// The options given can't be used, on their own or together
+    Usage { reason: String },
+    /// A check the options asked for failed: the bundle is out of date or too large, inlines
+    /// nothing or has known vulnerabilities
+    Check { reason: String },
+    /// A program run on the bundle, such as its formatter or pip-audit, failed
+    Tool { tool: String, reason: String },
+}
+
+impl InlinerError {
+    pub fn io(path: &Path, source: io::Error) -> InlinerError {
+        InlinerError::Io { path: path.to_path_buf(), source }
+    }
+
+    pub fn usage(reason: impl Into<String>) -> InlinerError {
+        InlinerError::Usage { reason: reason.into() }
+    }
+
+    pub fn check(reason: impl Into<String>) -> InlinerError {
+        InlinerError::Check { reason: reason.into() }
+    }
+
+    /// A file whose content couldn't be understood, without a line to point at
+    pub fn parse(file: &Path, reason: impl Into<String>) -> InlinerError {
+        InlinerError::Parse { file: file.to_path_buf(), reason: reason.into(), site: None }
+    }
+
+    /// `error` pointing at `site` when it's a resolve error that doesn't point anywhere yet, so
+    /// the import being inlined is shown rather than wherever resolution gave up
+    pub fn located(error: InlinerError, site: impl FnOnce() -> SourceSite) -> InlinerError {
+        match error {
+            InlinerError::Resolve { module, reason, site: None } => InlinerError::Resolve { module, reason, site: Some(site()) },
+            error => error,
+        }
+    }
+
+    fn site(&self) -> Option<&SourceSite> {
+        match self {
+            InlinerError::Resolve { site, .. } | InlinerError::Parse { site, .. } => site.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The error as printed on the terminal: its message and, when it's about a place in a file,
+    /// that line with the offending part underlined, much like rustc shows it
+    pub fn render(&self) -> String {
+        let mut rendered = format!("Error: {}\n", self);
+        if let Some(site) = self.site() {
+            let gutter = " ".repeat(site.line.to_string().len());
+            let column = site.text[..site.span.0].chars().count();
+            let width = site.text[site.span.0..site.span.1].chars().count().max(1);
+            rendered.push_str(&format!("{} --> {}:{}:{}\n", gutter, site.file.display(), site.line, column + 1));
+            rendered.push_str(&format!("{} |\n", gutter));
+            rendered.push_str(&format!("{} | {}\n", site.line, site.text));
+            rendered.push_str(&format!("{} | {}{}\n", gutter, " ".repeat(column), "^".repeat(width)));
+        }
+        rendered
+    }
+}
+
+impl fmt::Display for InlinerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InlinerError::Resolve { module, reason, .. } => write!(f, "Can't resolve module {:?}: {}", module, reason),
+            InlinerError::Parse { file, reason, site: Some(site) } => write!(f, "{}:{}: {}", file.display(), site.line, reason),
+            InlinerError::Parse { file, reason, site: None } => write!(f, "{}: {}", file.display(), reason),
+            // RealFileSystem's errors name their path already
+            InlinerError::Io { path, source } if source.to_string().contains(&path.display().to_string()) => write!(f, "{}", source),
+            InlinerError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            InlinerError::PythonDiscovery { interpreter, reason } => write!(f, "Python interpreter {} {}", interpreter, reason),
+            InlinerError::Usage { reason } | InlinerError::Check { reason } | InlinerError::Tool { reason, .. } => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for InlinerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InlinerError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let content = "import os\n    from mylib.sub import helper\n";
+        let error = InlinerError::Resolve {
+            module: "mylib.sub".to_string(),
+            reason: "aborted at the --interactive prompt".to_string(),
+            site: Some(SourceSite::at(Path::new("app.py"), content, content.find("from").unwrap(), "mylib.sub")),
+        };
+        assert_eq!(error.render(), "\
+Error: Can't resolve module \"mylib.sub\": aborted at the --interactive prompt
+  --> app.py:2:10
+  |
+2 |     from mylib.sub import helper
+  |          ^^^^^^^^^
+");
+        // Without the name on the line the whole statement is underlined
+        let site = SourceSite::new(Path::new("app.py"), 12, "  import a as b\r\n", "c");
+        assert_eq!(site.span, (2, 15));
+        // A name is only found as a whole word
+        assert_eq!(SourceSite::new(Path::new("app.py"), 1, "from mylib2 import mylib", "mylib").span, (19, 24));
+
+        let error = InlinerError::io(Path::new("/missing.py"), io::Error::new(io::ErrorKind::NotFound, "not found"));
+        assert_eq!(error.render(), "Error: /missing.py: not found\n");
+        let error = InlinerError::io(Path::new("/missing.py"), io::Error::new(io::ErrorKind::NotFound, "could not read /missing.py: not found"));
+        assert_eq!(error.render(), "Error: could not read /missing.py: not found\n");
+    }
+
+    #[test]
+    fn test_located() {
+        let site = || SourceSite::new(Path::new("app.py"), 3, "import mylib", "mylib");
+        let resolve = InlinerError::Resolve { module: "mylib".to_string(), reason: "aborted".to_string(), site: None };
+        let located = InlinerError::located(resolve, site);
+        assert_eq!(located.site(), Some(&site()));
+        // Other errors pass through unchanged
+        let other = InlinerError::located(InlinerError::usage("--env and --venv can't be combined"), site);
+        assert_eq!((other.site(), other.to_string().as_str()), (None, "--env and --venv can't be combined"));
+    }
+}
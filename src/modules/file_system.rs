@@ -1,38 +1,238 @@
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::io;
 
+/// How `FileSystem::copy_file` puts a copy in place
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CopyStrategy {
+    /// An independent copy keeping the file's mode and modification time. On Linux the kernel
+    /// shares the data (a reflink) where the filesystem supports it, e.g. btrfs and XFS.
+    #[default]
+    Copy,
+    /// A hard link to the original, falling back to a copy across filesystems
+    HardLink,
+}
+
+/// File access for the inliner. Object safe, so callers can take a `&dyn FileSystem`, and
+/// every method takes `&self`: implementations that keep state use interior mutability.
 pub trait FileSystem {
 
     #[allow(unused)]
-    fn canonicalize<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf>;
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    #[allow(unused)]
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    #[allow(unused)]
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+
+    #[allow(unused)]
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    #[allow(unused)]
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
 
     #[allow(unused)]
-    fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&mut self, path: P, contents: C) -> io::Result<()>;
+    fn mkdir_p(&self, path: &Path) -> io::Result<()>;
 
     #[allow(unused)]
-    fn read_to_string<P: AsRef<Path>>(&mut self, path: P) -> io::Result<String>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
 
     #[allow(unused)]
-    fn read_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Vec<PathBuf>>;
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
 
     #[allow(unused)]
-    fn mkdir_p<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()>;
+    fn is_file(&self, path: &Path) -> io::Result<bool>;
 
     #[allow(unused)]
-    fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()>;
+    fn is_dir(&self, path: &Path) -> io::Result<bool>;
 
     #[allow(unused)]
-    fn remove_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()>;
+    fn exists(&self, path: &Path) -> io::Result<bool>;
 
+    /// Whether the path itself is a symlink. Every other method follows symlinks, except
+    /// remove_file(), which removes the link.
     #[allow(unused)]
-    fn is_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<bool>;
+    fn is_symlink(&self, path: &Path) -> io::Result<bool>;
 
+    /// Where a symlink points, as written in the link
     #[allow(unused)]
-    fn is_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<bool>;
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
 
+    /// Mark a file as executable by everyone who can read it (a no-op where that isn't a thing)
     #[allow(unused)]
-    fn exists<P: AsRef<Path>>(&mut self, path: P) -> io::Result<bool>;
+    fn set_executable(&self, path: &Path) -> io::Result<()>;
+
+    /// Copy a file, replacing whatever is at `to`. A copy that is already up to date is left alone:
+    /// returns whether anything was written.
+    #[allow(unused)]
+    fn copy_file(&self, from: &Path, to: &Path, strategy: CopyStrategy) -> io::Result<bool> {
+        // Links and file metadata aren't a thing everywhere, the contents are
+        copy_contents(self, from, to)
+    }
+
+    /// The existing paths matching a glob pattern, sorted. In each path component `*` matches any
+    /// run of characters and `?` any one character; a `**` component matches any number of
+    /// directories, including none.
+    #[allow(unused)]
+    fn glob(&self, pattern: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut current = vec![PathBuf::new()];
+        for component in pattern.components() {
+            let Component::Normal(name) = component else {
+                // Root, prefix, `.` and `..` are taken literally
+                current.iter_mut().for_each(|path| path.push(component));
+                continue;
+            };
+            let name = name.to_string_lossy();
+            let mut next = Vec::new();
+            for path in current {
+                if name == "**" {
+                    collect_directories(self, path, &mut next)?;
+                } else if name.contains(['*', '?']) {
+                    let dir = if path.as_os_str().is_empty() { PathBuf::from(".") } else { path.clone() };
+                    if self.is_dir(&dir)? {
+                        for entry in self.read_dir(&dir)? {
+                            let Some(entry_name) = entry.file_name() else { continue };
+                            if wildcard_match(&name, &entry_name.to_string_lossy()) {
+                                next.push(path.join(entry_name));
+                            }
+                        }
+                    }
+                } else {
+                    let path = path.join(&*name);
+                    if self.exists(&path)? {
+                        next.push(path);
+                    }
+                }
+            }
+            current = next;
+        }
+        current.retain(|path| !path.as_os_str().is_empty());
+        current.sort();
+        current.dedup();
+        Ok(current)
+    }
+}
+
+/// A borrowed file system, so a wrapper like OverlayFileSystem can sit in front of a
+/// `&dyn FileSystem` it doesn't own
+impl<FS: FileSystem + ?Sized> FileSystem for &FS {
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        (**self).canonicalize(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        (**self).write(path, contents)
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        (**self).read_to_string(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        (**self).read(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        (**self).read_dir(path)
+    }
+
+    fn mkdir_p(&self, path: &Path) -> io::Result<()> {
+        (**self).mkdir_p(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        (**self).remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        (**self).remove_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> io::Result<bool> {
+        (**self).is_file(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> io::Result<bool> {
+        (**self).is_dir(path)
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        (**self).exists(path)
+    }
+
+    fn is_symlink(&self, path: &Path) -> io::Result<bool> {
+        (**self).is_symlink(path)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        (**self).read_link(path)
+    }
+
+    fn set_executable(&self, path: &Path) -> io::Result<()> {
+        (**self).set_executable(path)
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path, strategy: CopyStrategy) -> io::Result<bool> {
+        (**self).copy_file(from, to, strategy)
+    }
+
+    fn glob(&self, pattern: &Path) -> io::Result<Vec<PathBuf>> {
+        (**self).glob(pattern)
+    }
+}
+
+/// Copy a file by reading and writing its contents, skipping the write when they're unchanged
+pub fn copy_contents<FS: FileSystem + ?Sized>(fs: &FS, from: &Path, to: &Path) -> io::Result<bool> {
+    let contents = fs.read(from)?;
+    if fs.exists(to)? && fs.is_file(to)? && fs.read(to)? == contents {
+        return Ok(false);
+    }
+    fs.write(to, &contents)?;
+    Ok(true)
+}
+
+/// `dir` and every directory below it, for `**`
+fn collect_directories<FS: FileSystem + ?Sized>(fs: &FS, dir: PathBuf, directories: &mut Vec<PathBuf>) -> io::Result<()> {
+    let listed = if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir.clone() };
+    if !fs.is_dir(&listed)? {
+        return Ok(());
+    }
+    let entries = fs.read_dir(&listed)?;
+    directories.push(dir.clone());
+    for entry in entries {
+        if let Some(name) = entry.file_name() {
+            let child = dir.join(name);
+            collect_directories(fs, child, directories)?;
+        }
+    }
+    Ok(())
+}
+
+/// Match one path component, or a dotted module name, against a pattern with `*` and `?` wildcards
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Classic backtracking over the last `*` seen
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
 pub struct RealFileSystem {
@@ -41,6 +241,7 @@ pub struct RealFileSystem {
 }
 
 impl RealFileSystem {
+    #[allow(clippy::redundant_field_names)]
     pub fn new(current_dir: PathBuf) -> Self {
         RealFileSystem {
             current_dir: current_dir,
@@ -48,78 +249,212 @@ impl RealFileSystem {
     }
 }
 
+/// What went wrong, as an error naming the operation and path, with a hint on what to check.
+/// The error keeps its kind, so callers can still tell e.g. a missing file apart.
+fn path_error(error: io::Error, operation: &str, path: &Path) -> io::Error {
+    let hint = match error.kind() {
+        io::ErrorKind::NotFound if operation == "write" || operation == "create" => Some("check that the parent directory exists"),
+        io::ErrorKind::PermissionDenied => Some("check the permissions of the path and its parent directories"),
+        io::ErrorKind::ReadOnlyFilesystem => Some("it is on a read-only mount"),
+        io::ErrorKind::IsADirectory => Some("a directory is in the way"),
+        io::ErrorKind::NotADirectory => Some("part of the path is a file, not a directory"),
+        io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => Some("free up space or raise the quota"),
+        _ => None,
+    };
+    let message = match hint {
+        Some(hint) => format!("could not {} {}: {} ({})", operation, path.display(), error, hint),
+        None => format!("could not {} {}: {}", operation, path.display(), error),
+    };
+    io::Error::new(error.kind(), message)
+}
+
+/// The file's metadata, or None if there's nothing at the path, including when part of the
+/// path is a file
+fn metadata_if_exists(path: &Path) -> io::Result<Option<fs::Metadata>> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::NotADirectory) => Ok(None),
+        Err(e) => Err(path_error(e, "inspect", path)),
+    }
+}
+
 impl FileSystem for RealFileSystem {
-    fn canonicalize<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
-        fs::canonicalize(path)
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path).map_err(|e| path_error(e, "resolve", path))
     }
 
-    fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&mut self, path: P, contents: C) -> io::Result<()> {
-        fs::write(path, contents)
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents).map_err(|e| path_error(e, "write", path))
     }
 
-    fn read_to_string<P: AsRef<Path>>(&mut self, path: P) -> io::Result<String> {
-        fs::read_to_string(path)
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path).map_err(|e| path_error(e, "read", path))
     }
 
-    fn read_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Vec<PathBuf>> {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path).map_err(|e| path_error(e, "read", path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let scan_error = |e| path_error(e, "scan", path);
         // map the read_dir result to a vector of PathBuf
-        let read_dir = fs::read_dir(path)?;
+        let read_dir = fs::read_dir(path).map_err(scan_error)?;
         let mut paths = Vec::new();
         for entry in read_dir {
-            let entry = entry?;
+            let entry = entry.map_err(scan_error)?;
             paths.push(entry.path());
         }
+        // Directory order is arbitrary, sort for deterministic output
+        paths.sort();
         Ok(paths)
     }
 
-    fn mkdir_p<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        fs::create_dir_all(path)
+    fn mkdir_p(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path).map_err(|e| path_error(e, "create", path))
     }
 
-    fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        fs::remove_file(path)
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path).map_err(|e| path_error(e, "remove", path))
     }
 
-    fn remove_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        fs::remove_dir(path)
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path).map_err(|e| path_error(e, "remove", path))
     }
 
-    fn is_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<bool> {
-        match fs::metadata(path) {
-            Ok(m) => Ok(m.is_file()),
-            Err(e) => {
-                if e.kind() == io::ErrorKind::NotFound {
-                    Ok(false)
-                } else {
-                    Err(e)
-                }
-            },
-        }
+    fn is_file(&self, path: &Path) -> io::Result<bool> {
+        Ok(metadata_if_exists(path)?.is_some_and(|m| m.is_file()))
     }
 
-    fn is_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<bool> {
-        match fs::metadata(path) {
-            Ok(m) => Ok(m.is_dir()),
-            Err(e) => {
-                if e.kind() == io::ErrorKind::NotFound {
-                    Ok(false)
-                } else {
-                    Err(e)
-                }
-            },
+    fn is_dir(&self, path: &Path) -> io::Result<bool> {
+        Ok(metadata_if_exists(path)?.is_some_and(|m| m.is_dir()))
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        Ok(metadata_if_exists(path)?.is_some_and(|m| m.is_file() || m.is_dir()))
+    }
+
+    fn is_symlink(&self, path: &Path) -> io::Result<bool> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) => Ok(metadata.file_type().is_symlink()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(path_error(e, "inspect", path)),
         }
     }
 
-    fn exists<P: AsRef<Path>>(&mut self, path: P) -> io::Result<bool> {
-        match fs::metadata(path) {
-            Ok(m) => Ok(m.is_file() || m.is_dir()),
-            Err(e) => {
-                if e.kind() == io::ErrorKind::NotFound {
-                    Ok(false)
-                } else {
-                    Err(e)
-                }
-            },
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path).map_err(|e| path_error(e, "read the link", path))
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path, strategy: CopyStrategy) -> io::Result<bool> {
+        let read_error = |e| path_error(e, "read", from);
+        let write_error = |e| path_error(e, "write", to);
+        let source = fs::metadata(from).map_err(read_error)?;
+        if let Ok(target) = fs::symlink_metadata(to) {
+            let unchanged = match strategy {
+                CopyStrategy::HardLink => same_file(&source, &target),
+                // The quick check rsync does. A hard link left by an earlier run is replaced, as
+                // writing through it would change the original.
+                CopyStrategy::Copy => !same_file(&source, &target)
+                    && target.is_file()
+                    && target.len() == source.len()
+                    && target.modified().map_err(write_error)? == source.modified().map_err(read_error)?,
+            };
+            if unchanged {
+                return Ok(false);
+            }
+            fs::remove_file(to).map_err(|e| path_error(e, "remove", to))?;
+        }
+
+        if strategy == CopyStrategy::HardLink && fs::hard_link(from, to).is_ok() {
+            return Ok(true);
         }
+        // Copies the permissions along with the contents
+        fs::copy(from, to).map_err(|e| path_error(e, "copy to", to))?;
+        fs::File::options().write(true).open(to)
+            .and_then(|file| file.set_modified(source.modified()?))
+            .map_err(write_error)?;
+        Ok(true)
+    }
+
+    #[cfg(unix)]
+    fn set_executable(&self, path: &Path) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = fs::metadata(path).map_err(|e| path_error(e, "inspect", path))?.permissions();
+        // Add an execute bit wherever there's a read bit
+        permissions.set_mode(permissions.mode() | ((permissions.mode() & 0o444) >> 2));
+        fs::set_permissions(path, permissions).map_err(|e| path_error(e, "make executable", path))
+    }
+
+    #[cfg(not(unix))]
+    fn set_executable(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &fs::Metadata, _b: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_copy_file() {
+        let dir = std::env::temp_dir().join(format!("python-inliner-{}-copy-file", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (source, target) = (dir.join("tool.py"), dir.join("copy.py"));
+        let fs = RealFileSystem::new(dir.clone());
+        fs.write(&source, b"print('hi')\n").unwrap();
+        fs.set_executable(&source).unwrap();
+
+        assert!(fs.copy_file(&source, &target, CopyStrategy::Copy).unwrap());
+        assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o111, 0o111);
+        assert!(!fs.copy_file(&source, &target, CopyStrategy::Copy).unwrap());
+
+        // Switching to hard links replaces the copy, and switching back breaks the link
+        assert!(fs.copy_file(&source, &target, CopyStrategy::HardLink).unwrap());
+        assert!(!fs.copy_file(&source, &target, CopyStrategy::HardLink).unwrap());
+        assert!(fs.copy_file(&source, &target, CopyStrategy::Copy).unwrap());
+        fs.write(&target, b"changed\n").unwrap();
+        assert_eq!(fs.read_to_string(&source).unwrap(), "print('hi')\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_symlinks() {
+        let dir = std::env::temp_dir().join(format!("python-inliner-{}-symlinks", std::process::id()));
+        fs::create_dir_all(dir.join("shared/pkg")).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        std::os::unix::fs::symlink("../shared/pkg", dir.join("src/pkg")).unwrap();
+        let fs = RealFileSystem::new(dir.clone());
+        assert!(fs.is_symlink(&dir.join("src/pkg")).unwrap());
+        assert!(!fs.is_symlink(&dir.join("shared/pkg")).unwrap());
+        assert!(!fs.is_symlink(&dir.join("src/missing")).unwrap());
+        assert_eq!(fs.read_link(&dir.join("src/pkg")).unwrap(), PathBuf::from("../shared/pkg"));
+        assert_eq!(fs.canonicalize(&dir.join("src/pkg")).unwrap(), fs.canonicalize(&dir.join("shared/pkg")).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_errors_name_the_path() {
+        let dir = std::env::temp_dir().join(format!("python-inliner-{}-errors", std::process::id()));
+        let fs = RealFileSystem::new(std::env::temp_dir());
+        let missing = dir.join("missing.py");
+        let error = fs.read(&missing).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().starts_with(&format!("could not read {}: ", missing.display())), "{}", error);
+        let error = fs.write(&dir.join("no/such/dir/out.py"), b"").unwrap_err();
+        assert!(error.to_string().ends_with("(check that the parent directory exists)"), "{}", error);
+        assert!(!fs.exists(&missing).unwrap());
     }
 }
@@ -0,0 +1,168 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use crate::modules::report::ImportEdge;
+
+/// A bundle of a --report file, with the imports between its files
+struct ReportedBundle {
+    input: PathBuf,
+    output: PathBuf,
+    inlined: Vec<PathBuf>,
+    imports: Vec<ImportEdge>,
+}
+
+fn parse_bundle(bundle: &Value) -> Result<ReportedBundle, String> {
+    let path = |value: &Value, field: &str| -> Result<PathBuf, String> {
+        value.get(field).and_then(Value::as_str).map(PathBuf::from).ok_or_else(|| format!("report bundle has no `{}`", field))
+    };
+    let array = |field: &str| -> Result<&Vec<Value>, String> {
+        bundle.get(field).and_then(Value::as_array).ok_or_else(|| format!("report bundle has no `{}` (reports from before `why` existed can't be used)", field))
+    };
+    let mut imports = Vec::new();
+    for edge in array("imports")? {
+        let string = |field: &str| edge.get(field).and_then(Value::as_str).unwrap_or("").to_string();
+        imports.push(ImportEdge { importer: path(edge, "from")?, imported: path(edge, "to")?, module: string("module"), site: string("site") });
+    }
+    Ok(ReportedBundle {
+        input: path(bundle, "input")?,
+        output: path(bundle, "output")?,
+        inlined: array("inlined")?.iter().map(|file| path(file, "path")).collect::<Result<_, _>>()?,
+        imports,
+    })
+}
+
+/// The dotted name a file is imported as from the search path directory it's under, e.g.
+/// `mylib.utils` for `mylib/utils.py` and `mylib` for `mylib/__init__.py`
+fn module_name(path: &Path, search_dirs: &[PathBuf]) -> Option<String> {
+    let relative = search_dirs.iter().filter_map(|dir| path.strip_prefix(dir).ok()).min_by_key(|relative| relative.components().count())?;
+    let mut parts: Vec<String> = relative.with_extension("").components().map(|part| part.as_os_str().to_string_lossy().to_string()).collect();
+    if parts.last().is_some_and(|last| last == "__init__") {
+        parts.pop();
+    }
+    Some(parts.join("."))
+}
+
+/// The shortest chain of imports leading from the entry script to `target`. Imports are tried in
+/// the (sorted) order of the report, so the chain is the same from run to run.
+fn shortest_chain(entry: &Path, imports: &[ImportEdge], target: &Path) -> Option<Vec<ImportEdge>> {
+    let mut reached_by: HashMap<&Path, &ImportEdge> = HashMap::new();
+    let mut queue = VecDeque::from([entry]);
+    while let Some(file) = queue.pop_front() {
+        if file == target {
+            let mut chain = Vec::new();
+            let mut step = target;
+            while let Some(edge) = reached_by.get(step) {
+                chain.push((*edge).clone());
+                step = &edge.importer;
+            }
+            chain.reverse();
+            return Some(chain);
+        }
+        for edge in imports.iter().filter(|edge| edge.importer == file) {
+            if edge.imported != entry && !reached_by.contains_key(edge.imported.as_path()) {
+                reached_by.insert(&edge.imported, edge);
+                queue.push_back(&edge.imported);
+            }
+        }
+    }
+    None
+}
+
+/// Explain why a module ended up in the bundles of a --report file: for each bundle that
+/// inlines it, the shortest chain of imports from the entry script to it. `module` is a dotted
+/// module name, or the end of the file's path.
+pub fn explain_inclusion(report: &str, module: &str) -> Result<String, Box<dyn Error>> {
+    let report: Value = serde_json::from_str(report)?;
+    let sys_path: Vec<PathBuf> = report.get("sys_path").and_then(Value::as_array).into_iter().flatten()
+        .filter_map(Value::as_str)
+        .map(PathBuf::from)
+        .collect();
+    let bundles = report.get("bundles").and_then(Value::as_array).ok_or("not a --report file, it has no `bundles`")?;
+
+    let mut explanation = String::new();
+    for bundle in bundles {
+        let bundle = parse_bundle(bundle)?;
+        // The entry script's directory comes first on the search path of its bundle
+        let search_dirs: Vec<PathBuf> = bundle.input.parent().map(Path::to_path_buf).into_iter().chain(sys_path.iter().cloned()).collect();
+        let matches = |file: &&PathBuf| module_name(file, &search_dirs).is_some_and(|name| name == module) || file.ends_with(module);
+        for file in bundle.inlined.iter().filter(matches) {
+            let Some(chain) = shortest_chain(&bundle.input, &bundle.imports, file) else {
+                continue;
+            };
+            explanation.push_str(&format!("{} includes {}:\n", bundle.output.display(), file.display()));
+            for edge in chain {
+                explanation.push_str(&format!("    {}: from {} import ...\n", edge.site, edge.module));
+            }
+        }
+    }
+    if explanation.is_empty() {
+        return Err(format!("{:?} isn't inlined into any bundle of the report", module).into());
+    }
+    Ok(explanation)
+}
+
+/// The imports between the files of the bundles of a --report file, as a Graphviz digraph with
+/// an edge from each importer to each file it imports, labeled with the module as written
+pub fn render_import_graph(report: &str) -> Result<String, Box<dyn Error>> {
+    let report: Value = serde_json::from_str(report)?;
+    let bundles = report.get("bundles").and_then(Value::as_array).ok_or("not a --report file, it has no `bundles`")?;
+    let mut edges = BTreeSet::new();
+    for bundle in bundles {
+        for edge in parse_bundle(bundle)?.imports {
+            edges.insert((edge.importer, edge.imported, edge.module));
+        }
+    }
+    let quoted = |path: &Path| format!("{:?}", path.display().to_string());
+    let mut graph = String::from("digraph imports {\n");
+    for (importer, imported, module) in edges {
+        graph.push_str(&format!("    {} -> {} [label={:?}];\n", quoted(&importer), quoted(&imported), module));
+    }
+    graph.push_str("}\n");
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain_inclusion() {
+        let report = r#"{"sys_path": ["/venv/site-packages"], "bundles": [{
+            "input": "/src/main.py", "output": "/dist/main.py",
+            "inlined": [{"path": "/src/mylib/__init__.py"}, {"path": "/src/mylib/a.py"}, {"path": "/src/mylib/utils.py"}, {"path": "/venv/site-packages/extlib/core.py"}],
+            "imports": [
+                {"from": "/src/main.py", "to": "/src/mylib/__init__.py", "module": "mylib", "site": "/src/main.py:1"},
+                {"from": "/src/mylib/__init__.py", "to": "/src/mylib/a.py", "module": ".a", "site": "/src/mylib/__init__.py:1"},
+                {"from": "/src/mylib/a.py", "to": "/src/mylib/utils.py", "module": ".utils", "site": "/src/mylib/a.py:2"},
+                {"from": "/src/main.py", "to": "/src/mylib/utils.py", "module": "mylib.utils", "site": "/src/main.py:4"},
+                {"from": "/src/mylib/utils.py", "to": "/venv/site-packages/extlib/core.py", "module": "extlib.core", "site": "/src/mylib/utils.py:1"}
+            ]
+        }]}"#;
+        assert_eq!(explain_inclusion(report, "mylib.utils").unwrap(), "/dist/main.py includes /src/mylib/utils.py:\n    /src/main.py:4: from mylib.utils import ...\n");
+        assert_eq!(explain_inclusion(report, "extlib.core").unwrap(), "/dist/main.py includes /venv/site-packages/extlib/core.py:
+    /src/main.py:4: from mylib.utils import ...
+    /src/mylib/utils.py:1: from extlib.core import ...
+");
+        assert!(explain_inclusion(report, "mylib/a.py").unwrap().contains("/src/mylib/__init__.py:1: from .a import"));
+        assert!(explain_inclusion(report, "mylib.gone").is_err());
+        assert!(explain_inclusion("{}", "mylib").is_err());
+    }
+
+    #[test]
+    fn test_render_import_graph() {
+        let bundle = |output: &str| format!(r#"{{"input": "/src/main.py", "output": "{}", "inlined": [], "imports": [
+            {{"from": "/src/mylib/__init__.py", "to": "/src/mylib/a.py", "module": ".a", "site": "/src/mylib/__init__.py:1"}},
+            {{"from": "/src/main.py", "to": "/src/mylib/__init__.py", "module": "mylib", "site": "/src/main.py:1"}}
+        ]}}"#, output);
+        // Bundles of the same entry script share their edges
+        let report = format!(r#"{{"bundles": [{}, {}]}}"#, bundle("/dist/a.py"), bundle("/dist/b.py"));
+        assert_eq!(render_import_graph(&report).unwrap(), "\
+digraph imports {
+    \"/src/main.py\" -> \"/src/mylib/__init__.py\" [label=\"mylib\"];
+    \"/src/mylib/__init__.py\" -> \"/src/mylib/a.py\" [label=\".a\"];
+}
+");
+        assert!(render_import_graph("{}").is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+/// Where the timings of --profile-imports are written
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProfileSink {
+    Stderr,
+    Stdout,
+    /// Appended to, one line per inlined module
+    File(PathBuf),
+}
+
+impl ProfileSink {
+    /// `stderr`, `stdout`, or anything else as a file path
+    pub fn parse(sink: &str) -> ProfileSink {
+        match sink {
+            "stderr" => ProfileSink::Stderr,
+            "stdout" => ProfileSink::Stdout,
+            path => ProfileSink::File(PathBuf::from(path)),
+        }
+    }
+}
+
+/// The statement placed before an inlined module's body
+pub fn start_timer(indent: &str) -> String {
+    format!("{}_inliner_import_start()\n", indent)
+}
+
+/// The statement placed after an inlined module's body, reporting its timing under `module`
+pub fn stop_timer(indent: &str, module: &str) -> String {
+    // JSON string escapes are all valid in Python string literals
+    format!("{}_inliner_import_end({})\n", indent, serde_json::to_string(module).unwrap())
+}
+
+/// Render the helpers the timer statements call. Timings are reported in the format of
+/// `python -X importtime`: self and cumulative microseconds, nested modules indented, each
+/// module reported when it finishes.
+pub fn render_import_profiler(sink: &ProfileSink, release: bool) -> String {
+    let mut preamble = String::new();
+    if !release {
+        preamble.push_str("# ↓↓↓ import profiling\n");
+    }
+    preamble.push_str("import sys as _inliner_sys\n");
+    preamble.push_str("import time as _inliner_time\n");
+    match sink {
+        ProfileSink::Stderr => preamble.push_str("_inliner_import_sink = _inliner_sys.stderr\n"),
+        ProfileSink::Stdout => preamble.push_str("_inliner_import_sink = _inliner_sys.stdout\n"),
+        ProfileSink::File(path) => preamble.push_str(&format!(
+            "_inliner_import_sink = open({}, \"a\")\n",
+            serde_json::to_string(&path.to_string_lossy()).unwrap()
+        )),
+    }
+    preamble.push_str(r#"_inliner_import_stack = []
+def _inliner_import_report(line):
+    _inliner_import_sink.write(line + "\n")
+    _inliner_import_sink.flush()
+def _inliner_import_start():
+    _inliner_import_stack.append([_inliner_time.perf_counter(), 0.0])
+def _inliner_import_end(module):
+    start, nested = _inliner_import_stack.pop()
+    cumulative = _inliner_time.perf_counter() - start
+    if _inliner_import_stack:
+        _inliner_import_stack[-1][1] += cumulative
+    _inliner_import_report("import time: %9d | %10d | %s%s" % ((cumulative - nested) * 1e6, cumulative * 1e6, "  " * len(_inliner_import_stack), module))
+_inliner_import_report("import time: self [us] | cumulative | inlined module")
+"#);
+    if !release {
+        preamble.push_str("# ↑↑↑ import profiling\n");
+    }
+    preamble
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_import_profiler() {
+        assert_eq!(ProfileSink::parse("stdout"), ProfileSink::Stdout);
+        let preamble = render_import_profiler(&ProfileSink::parse("/tmp/import times.log"), true);
+        assert!(preamble.starts_with("import sys as _inliner_sys\nimport time as _inliner_time\n_inliner_import_sink = open(\"/tmp/import times.log\", \"a\")\n"));
+        assert!(!preamble.contains("# ↓↓↓"));
+        assert_eq!(stop_timer("    ", "mylib.\"odd\""), "    _inliner_import_end(\"mylib.\\\"odd\\\"\")\n");
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, BTreeSet};
+use regex::Regex;
+use crate::modules::source_analysis::mask_strings_and_comments;
+use crate::utils::stdlib::is_stdlib_module;
+
+/// A single-line Python import statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportStatement {
+    /// `import a.b, c as d` - each entry is (dotted module, alias)
+    Import(Vec<(String, Option<String>)>),
+    /// `from module import a, b as c` - each entry is (name, alias)
+    From { module: String, names: Vec<(String, Option<String>)> },
+}
+
+fn parse_aliased_names(list: &str) -> Option<Vec<(String, Option<String>)>> {
+    let alias_regex = Regex::new(r"^([\w.*]+)(?:\s+as\s+(\w+))?$").unwrap();
+    let mut names = Vec::new();
+    for item in list.split(',') {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+        let cap = alias_regex.captures(item)?;
+        names.push((cap[1].to_string(), cap.get(2).map(|m| m.as_str().to_string())));
+    }
+    if names.is_empty() { None } else { Some(names) }
+}
+
+/// Parse a single-line import statement (leading whitespace and trailing comments are ignored).
+/// Returns None for anything else, including multi-line parenthesized imports that aren't closed
+/// on the same line.
+pub fn parse_import_line(line: &str) -> Option<ImportStatement> {
+    let code = line.split('#').next().unwrap_or("").trim();
+    let from_regex = Regex::new(r"^from\s+(\.*[\w.]*)\s+import\s+(.+)$").unwrap();
+    let import_regex = Regex::new(r"^import\s+(.+)$").unwrap();
+
+    if let Some(cap) = from_regex.captures(code) {
+        let mut list = cap[2].trim();
+        if let Some(inner) = list.strip_prefix('(') {
+            list = inner.strip_suffix(')')?;
+        }
+        let names = parse_aliased_names(list)?;
+        return Some(ImportStatement::From { module: cap[1].to_string(), names });
+    }
+    if let Some(cap) = import_regex.captures(code) {
+        let modules = parse_aliased_names(&cap[1])?;
+        if modules.iter().any(|(module, _)| module.contains('*')) {
+            return None;
+        }
+        return Some(ImportStatement::Import(modules));
+    }
+    None
+}
+
+impl ImportStatement {
+    /// Render the statement back to a single line of Python
+    pub fn render(&self) -> String {
+        let render_names = |names: &Vec<(String, Option<String>)>| {
+            names
+                .iter()
+                .map(|(name, alias)| match alias {
+                    Some(alias) => format!("{} as {}", name, alias),
+                    None => name.clone(),
+                })
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        match self {
+            ImportStatement::Import(modules) => format!("import {}", render_names(modules)),
+            ImportStatement::From { module, names } => format!("from {} import {}", module, render_names(names)),
+        }
+    }
+}
+
+/// Merge import lines semantically: `from X import ...` statements for the same module are
+/// combined into one with their names deduplicated and sorted, and repeated `import` statements
+/// collapse to one. `import X` never absorbs `from X import ...` (they bind different names), star
+/// imports are kept on their own, and lines that don't parse are passed through untouched.
+pub fn merge_imports(lines: Vec<String>) -> Vec<String> {
+    let mut from_names: BTreeMap<String, BTreeSet<(String, Option<String>)>> = BTreeMap::new();
+    let mut merged = BTreeSet::new();
+
+    for line in lines {
+        match parse_import_line(&line) {
+            Some(ImportStatement::From { module, names }) if !names.iter().any(|(name, _)| name == "*") => {
+                from_names.entry(module).or_default().extend(names);
+            }
+            Some(statement) => {
+                merged.insert(statement.render());
+            }
+            None => {
+                merged.insert(line);
+            }
+        }
+    }
+    for (module, names) in from_names {
+        merged.insert(ImportStatement::From { module, names: names.into_iter().collect() }.render());
+    }
+
+    merged.into_iter().collect()
+}
+
+/// isort import sections, in output order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ImportSection {
+    Future,
+    Stdlib,
+    ThirdParty,
+    FirstParty,
+    LocalFolder,
+}
+
+/// Which isort section an import statement belongs to, along with its sort key
+/// (`import x` before `from x import y`, then the module name case-insensitively).
+/// Lines that don't parse as imports sort with third-party imports.
+fn import_section(line: &str, first_party: &[String]) -> (ImportSection, bool, String) {
+    let (module, is_from) = match parse_import_line(line) {
+        Some(ImportStatement::Import(modules)) => (modules[0].0.clone(), false),
+        Some(ImportStatement::From { module, .. }) => (module, true),
+        None => (String::new(), false),
+    };
+    let top_level = module.split('.').next().unwrap_or("");
+    let section = if module == "__future__" {
+        ImportSection::Future
+    } else if module.starts_with('.') {
+        ImportSection::LocalFolder
+    } else if first_party.iter().any(|name| name == top_level) {
+        ImportSection::FirstParty
+    } else if is_stdlib_module(&module) {
+        ImportSection::Stdlib
+    } else {
+        ImportSection::ThirdParty
+    };
+    (section, is_from, module.to_lowercase())
+}
+
+/// Group import lines into isort-style sections (`__future__`, standard library, third-party,
+/// first-party, relative) separated by an empty string. Within a section, `import x` statements
+/// come before `from x import y` statements, each sorted case-insensitively by module, as with
+/// isort's "black" profile. `first_party` holds the top-level names of the project's own modules.
+pub fn group_imports(lines: Vec<String>, first_party: &[String]) -> Vec<String> {
+    let mut keyed: Vec<((ImportSection, bool, String), String)> = lines
+        .into_iter()
+        .map(|line| (import_section(&line, first_party), line))
+        .collect();
+    keyed.sort();
+
+    let mut grouped = Vec::new();
+    let mut previous_section = None;
+    for ((section, _, _), line) in keyed {
+        if previous_section.is_some_and(|previous| previous != section) {
+            grouped.push(String::new());
+        }
+        previous_section = Some(section);
+        grouped.push(line);
+    }
+    grouped
+}
+
+/// Put a blank line between adjacent imports from different sections of the leading import
+/// block, after the shebang and comments heading the content, restoring the grouping from
+/// group_imports() after a pass that dropped blank lines. The imports of inlined module bodies
+/// further down are left as they are.
+pub fn separate_import_sections(content: &str, first_party: &[String]) -> String {
+    // Masking keeps import-looking lines inside strings from being treated as imports
+    let masked_content = mask_strings_and_comments(content);
+    let mut result = String::new();
+    let mut previous_section = None;
+    let mut in_leading_block = true;
+    for (line, masked_line) in content.split_inclusive('\n').zip(masked_content.split_inclusive('\n')) {
+        let is_import = (masked_line.starts_with("import ") || masked_line.starts_with("from ")) && parse_import_line(line).is_some();
+        in_leading_block &= is_import || masked_line.trim().is_empty() && (line.trim().is_empty() || line.starts_with('#'));
+        let section = if is_import && in_leading_block { Some(import_section(line, first_party).0) } else { None };
+        if section.is_some() && previous_section.is_some() && section != previous_section {
+            result.push('\n');
+        }
+        previous_section = section;
+        result.push_str(line);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_import_line() {
+        let statement = parse_import_line("    from os.path import join, sep as separator  # comment").unwrap();
+        assert_eq!(statement, ImportStatement::From {
+            module: "os.path".to_string(),
+            names: vec![("join".to_string(), None), ("sep".to_string(), Some("separator".to_string()))],
+        });
+        assert_eq!(statement.render(), "from os.path import join, sep as separator");
+
+        let statement = parse_import_line("import os.path, numpy as np").unwrap();
+        assert_eq!(statement, ImportStatement::Import(vec![("os.path".to_string(), None), ("numpy".to_string(), Some("np".to_string()))]));
+        assert_eq!(parse_import_line("from . import (a, b)").unwrap().render(), "from . import a, b");
+        assert!(parse_import_line("from x import (").is_none());
+        assert!(parse_import_line("x = 1").is_none());
+    }
+
+    #[test]
+    fn test_merge_imports() {
+        let lines = vec![
+            "from os import sep".to_string(),
+            "import os".to_string(),
+            "from os import path, sep  # duplicate".to_string(),
+            "from typing import *".to_string(),
+            "from typing import List".to_string(),
+            "import os".to_string(),
+        ];
+        assert_eq!(merge_imports(lines), vec!["from os import path, sep", "from typing import *", "from typing import List", "import os"]);
+    }
+
+    #[test]
+    fn test_group_imports() {
+        let lines = ["import requests", "from mylib import core", "from os import path", "import Sys2", "import os",
+            "from __future__ import annotations", "from . import sibling", "import attr"];
+        let grouped = group_imports(lines.iter().map(|line| line.to_string()).collect(), &["mylib".to_string()]);
+        assert_eq!(grouped, vec![
+            "from __future__ import annotations", "",
+            "import os", "from os import path", "",
+            "import attr", "import requests", "import Sys2", "",
+            "from mylib import core", "",
+            "from . import sibling",
+        ]);
+    }
+
+    #[test]
+    fn test_separate_import_sections() {
+        let content = "import os\nimport requests\nfrom mylib import core\nx = 1\nimport zlib\n";
+        assert_eq!(separate_import_sections(content, &["mylib".to_string()]), "import os\n\nimport requests\n\nfrom mylib import core\nx = 1\nimport zlib\n");
+
+        // Only the leading block is grouped, not the imports of an inlined module's body
+        let content = "#!/usr/bin/env python3\n# /// script\n# ///\nimport os\nimport requests\ndef helper():\n    pass\nimport json\nimport yaml\n";
+        assert_eq!(separate_import_sections(content, &[]), "#!/usr/bin/env python3\n# /// script\n# ///\nimport os\n\nimport requests\ndef helper():\n    pass\nimport json\nimport yaml\n");
+    }
+}
@@ -0,0 +1,110 @@
+use std::str::FromStr;
+use crate::modules::source_analysis::string_spans;
+
+/// Spaces a tab stands for when converting tab indentation to spaces
+const SPACES_PER_LEVEL: usize = 4;
+
+/// How the indentation of inlined files is normalized
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Reindent {
+    Spaces,
+    Tabs,
+    /// Leave every file as it is
+    #[default]
+    Keep,
+}
+
+impl FromStr for Reindent {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Reindent, String> {
+        match value {
+            "spaces" => Ok(Reindent::Spaces),
+            "tabs" => Ok(Reindent::Tabs),
+            "keep" => Ok(Reindent::Keep),
+            _ => Err(format!("unknown indentation {:?}, expected spaces, tabs or keep", value)),
+        }
+    }
+}
+
+/// The lines of the content with whether each one continues a multi-line string, whose
+/// leading whitespace is part of the string's value
+fn code_lines(content: &str) -> Vec<(&str, bool)> {
+    let string_spans = string_spans(content);
+    let mut line_start = 0;
+    content.split_inclusive('\n')
+        .map(|line| {
+            let inside_string = string_spans.iter().any(|(start, end)| *start < line_start && line_start < *end);
+            line_start += line.len();
+            (line, inside_string)
+        })
+        .collect()
+}
+
+/// The number of spaces a space-indented file uses per level: the smallest step in
+/// indentation after a line opening a block. Defaults to 4 when nothing is indented.
+pub fn indent_width(content: &str) -> usize {
+    let mut width: Option<usize> = None;
+    let mut opener_indent: Option<usize> = None;
+    for (line, inside_string) in code_lines(content) {
+        let code = line.trim_end();
+        if inside_string || code.trim_start().is_empty() || code.trim_start().starts_with('#') {
+            continue;
+        }
+        let indent = code.len() - code.trim_start_matches(' ').len();
+        if let Some(opener) = opener_indent {
+            if indent > opener {
+                width = Some(width.map_or(indent - opener, |width| width.min(indent - opener)));
+            }
+        }
+        opener_indent = if code.ends_with(':') { Some(indent) } else { None };
+    }
+    width.unwrap_or(SPACES_PER_LEVEL)
+}
+
+/// Convert the leading indentation of every line to tabs or spaces, one tab per level. Lines
+/// continuing a multi-line string are left alone. Spaces that don't make up a whole level
+/// (aligned continuation lines) stay spaces.
+pub fn reindent(content: &str, style: Reindent) -> String {
+    if style == Reindent::Keep {
+        return content.to_string();
+    }
+    let width = indent_width(content);
+    let mut result = String::with_capacity(content.len());
+    for (line, inside_string) in code_lines(content) {
+        let code = line.trim_start_matches([' ', '\t']);
+        if inside_string {
+            result.push_str(line);
+            continue;
+        }
+        let leading = &line[..line.len() - code.len()];
+        let tabs = leading.chars().take_while(|c| *c == '\t').count();
+        let rest = &leading[tabs..];
+        if style == Reindent::Spaces {
+            result.push_str(&" ".repeat(tabs * SPACES_PER_LEVEL));
+            result.push_str(&rest.replace('\t', &" ".repeat(SPACES_PER_LEVEL)));
+        } else {
+            let spaces = rest.matches(' ').count();
+            result.push_str(&"\t".repeat(tabs + rest.matches('\t').count() + spaces / width));
+            result.push_str(&" ".repeat(spaces % width));
+        }
+        result.push_str(code);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reindent() {
+        let tabbed = "def f():\n\tif x:\n\t\treturn '''a\n\tb'''\n\treturn (1,\n\t        2)\n";
+        assert_eq!(reindent(tabbed, Reindent::Spaces), "def f():\n    if x:\n        return '''a\n\tb'''\n    return (1,\n            2)\n");
+        let spaced = "class A:\n  def f(self):\n    pass\n";
+        assert_eq!(indent_width(spaced), 2);
+        assert_eq!(reindent(spaced, Reindent::Tabs), "class A:\n\tdef f(self):\n\t\tpass\n");
+        assert_eq!(reindent(tabbed, Reindent::Keep), tabbed);
+        assert_eq!("tabs".parse::<Reindent>(), Ok(Reindent::Tabs));
+    }
+}
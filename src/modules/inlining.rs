@@ -0,0 +1,1090 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use crate::modules::annotations::rewrite_string_annotations;
+use crate::modules::debug_code::strip_debug_code;
+use crate::modules::defines::apply_defines;
+use crate::modules::entry_point::normalize_entry_point;
+use crate::modules::errors::{InlinerError, SourceSite};
+use crate::modules::file_system::{wildcard_match, FileSystem};
+use crate::modules::import_profiling::{start_timer, stop_timer};
+use crate::modules::indentation::reindent;
+use crate::modules::options::Opt;
+use crate::modules::module_cache::{module_artifact, ImportSite, ModuleArtifact, ModuleTransformation, ModuleWarning, Segment};
+use crate::modules::reexports::{import_aliases, literal_all, star_exports, top_level_bindings};
+use crate::modules::report::ImportEdge;
+use crate::modules::resolution::choose_resolution;
+use crate::modules::resolver::ModuleResolver;
+use crate::modules::rope::Rope;
+use crate::modules::self_check::{binds_alias, changed_multiline_string, dunder_file_references, dynamic_imports};
+use crate::modules::terminal::Style;
+use crate::modules::source_analysis::{insert_preamble, mask_strings_and_comments, statement_end, string_spans};
+use crate::modules::warnings::{allowed_at, WarningCategory};
+use crate::modules::directives::skips_import;
+use crate::modules::collisions::rename_names;
+use crate::modules::tree_shake::remove_definitions;
+use crate::{file_module_names, line_number, note_size, read_source};
+
+/// Indent every line of code by `indent`. Empty lines stay empty, and lines that continue a
+/// multi-line string are left alone, since indenting them would change the string's value.
+pub fn indent_code(content: &str, indent: &str) -> String {
+    let string_spans = string_spans(content);
+    let mut result = String::new();
+    let mut line_start = 0;
+    for line in content.split_inclusive('\n') {
+        let inside_string = string_spans.iter().any(|(start, end)| *start < line_start && line_start < *end);
+        line_start += line.len();
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if !line.is_empty() && !inside_string {
+            result.push_str(indent);
+        }
+        result.push_str(line);
+        result.push('\n');
+    }
+    result
+}
+
+/// Record that a transformation was applied to an inlined file, for --report
+fn note_transformation(opt: &Opt, file: &Path, transformation: &'static str) {
+    if opt.report.is_some() {
+        opt.transformations.borrow_mut().entry(file.to_path_buf()).or_default().insert(transformation);
+    }
+}
+
+/// Record that `file` imports the file an import resolved to, for --report and the progress line
+fn note_import(opt: &Opt, file: &Path, resolved: &Path, import: &ImportSite) {
+    opt.terminal.progress.borrow_mut().discover(resolved);
+    if opt.report.is_some() {
+        opt.imports.borrow_mut().insert(ImportEdge {
+            importer: file.to_path_buf(),
+            imported: resolved.to_path_buf(),
+            module: import.module.clone(),
+            site: import.site.clone(),
+        });
+    }
+}
+
+/// Record that `file` had an import replaced by inlined files, which were indented along with
+/// the import if `indent` isn't empty
+fn note_inlined<'a>(opt: &Opt, file: &Path, indent: &str, inlined: impl Iterator<Item = &'a PathBuf>) {
+    note_transformation(opt, file, "imports_inlined");
+    if !indent.is_empty() {
+        for path in inlined {
+            note_transformation(opt, path, "indented");
+        }
+    }
+}
+
+/// Record a warning about the code at `offset` of `source`, raised from `site`, unless an
+/// `# inliner: allow(...)` comment there allows it
+pub fn warn_at(opt: &Opt, category: WarningCategory, subject: &str, source: &str, offset: usize, site: String) {
+    if !allowed_at(source, offset).contains(&category) {
+        opt.warnings.warn(category, subject, Some(site));
+    }
+}
+
+/// Say on the unresolved-module warning about `subject` why `name` didn't resolve with `resolver`
+pub fn note_unresolved(opt: &Opt, resolver: &ModuleResolver, subject: &str, name: &str) {
+    if let Some(reason) = resolver.failure(name).filter(|_| !name.is_empty()) {
+        opt.warnings.note(WarningCategory::UnresolvedModule, subject, reason);
+    }
+}
+
+/// Record a hazard the self-check found in how `subject` was inlined, raised from `site`, unless
+/// --no-self-check was given
+fn report_hazard(opt: &Opt, category: WarningCategory, subject: &Path, site: String) {
+    if !opt.no_self_check {
+        opt.warnings.warn(category, &subject.display().to_string(), Some(site));
+    }
+}
+
+/// `file:line` of an import found at `offset` in the content inline_imports() processes, which
+/// has the (sorted) TYPE_CHECKING blocks of the original `content` cut out
+fn import_site(file: &Path, content: &str, type_checking_blocks: &[(usize, usize)], offset: usize) -> String {
+    let mut original_offset = offset;
+    for (block_start, block_end) in type_checking_blocks {
+        if *block_start <= original_offset {
+            original_offset += block_end - block_start;
+        }
+    }
+    format!("{}:{}", file.display(), line_number(content, original_offset.min(content.len())))
+}
+
+/// Find all TYPE_CHECKING block ranges in the content
+/// Returns a vector of (start_pos, end_pos) tuples for each TYPE_CHECKING block
+pub fn find_type_checking_blocks(content: &str) -> Vec<(usize, usize)> {
+    let mut blocks = Vec::new();
+    let type_checking_regex = Regex::new(r"(?m)^([ \t]*)if\s+TYPE_CHECKING\s*:").unwrap();
+
+    for cap in type_checking_regex.captures_iter(content) {
+        let block_start = cap.get(0).unwrap().start();
+        let indent = &cap[1];
+        let indent_len = indent.len();
+
+        // Find the end of this indented block
+        // The block ends when we find a line with equal or lesser indentation (non-empty)
+        let after_colon = cap.get(0).unwrap().end();
+        let lines_after = &content[after_colon..];
+
+        let mut block_end = after_colon;
+        let mut found_content = false;
+
+        for line in lines_after.lines() {
+            let line_start = block_end;
+            let line_len = line.len();
+
+            // Skip empty lines (they're part of the block)
+            if line.trim().is_empty() {
+                block_end = line_start + line_len + 1; // +1 for newline
+                continue;
+            }
+
+            // Check indentation of non-empty line
+            let line_indent = line.len() - line.trim_start().len();
+
+            if !found_content {
+                // First non-empty line after if TYPE_CHECKING:
+                if line_indent > indent_len {
+                    found_content = true;
+                    block_end = line_start + line_len + 1;
+                } else {
+                    // No indented content found, block is empty
+                    break;
+                }
+            } else {
+                // Subsequent lines
+                if line_indent > indent_len {
+                    // Still inside the block
+                    block_end = line_start + line_len + 1;
+                } else {
+                    // End of block (dedent)
+                    break;
+                }
+            }
+        }
+
+        blocks.push((block_start, block_end));
+    }
+
+    blocks
+}
+
+/// Everything done to a file on its own, before any module is inlined into it: normalizing its
+/// indentation, setting --define constants, dropping unused definitions, debug code and
+/// TYPE_CHECKING blocks (or, with --rewrite-annotations, marking the imports in them), and
+/// finding the imports of modules to inline
+pub fn process_module(fs: &dyn FileSystem, file: &Path, module_names: &str, opt: &Opt) -> Result<ModuleArtifact, InlinerError> {
+    let mut artifact = ModuleArtifact::default();
+    let source = read_source(fs, file)?;
+    let module_names = &file_module_names(&source, module_names);
+    // Normalized up front, so the indentation inlined modules get matches the code around them
+    let mut content = reindent(&source, opt.reindent);
+    if content != source {
+        artifact.transformations.push(ModuleTransformation::Reindent);
+        if let Some(offset) = changed_multiline_string(&source, &content).filter(|_| !opt.no_self_check) {
+            let site = format!("{}:{}", file.display(), line_number(&source, offset));
+            artifact.warnings.push(ModuleWarning { category: WarningCategory::ReindentedString, subject: file.display().to_string(), site });
+        }
+    }
+    if !opt.define.is_empty() {
+        let defined = apply_defines(&content, &opt.define, opt.prune_defines);
+        if defined != content {
+            content = defined;
+            artifact.transformations.push(ModuleTransformation::Defines);
+        }
+    }
+    // Before renaming too, so the footer calls the function by its new name
+    if let Some(function) = opt.entry_function.as_ref().filter(|_| opt.input_file.as_deref() == Some(file)) {
+        content = normalize_entry_point(&content, function).map_err(InlinerError::usage)?;
+        artifact.transformations.push(ModuleTransformation::EntryFunction);
+    }
+    // Before tree shaking, which finds the definitions under their new names
+    if let Some(renames) = opt.renamed_definitions.get(file) {
+        content = rename_names(&content, renames);
+        artifact.transformations.push(ModuleTransformation::RenameCollisions);
+    }
+    if let Some(unused) = opt.unused_definitions.get(file) {
+        if opt.verbose {
+            let mut names: Vec<&String> = unused.iter().collect();
+            names.sort();
+            println!("Tree shaking {}: removing {:?}", file.display(), names);
+        }
+        content = remove_definitions(&content, unused);
+        artifact.transformations.push(ModuleTransformation::TreeShake);
+    }
+    if opt.strip_asserts || !opt.strip_pattern.is_empty() {
+        let stripped = strip_debug_code(&content, opt.strip_asserts, &opt.strip_pattern);
+        if stripped != content {
+            content = stripped;
+            artifact.transformations.push(ModuleTransformation::StripDebugCode);
+        }
+    }
+    if !opt.no_self_check && opt.input_file.as_deref() != Some(file) {
+        for offset in dunder_file_references(&content) {
+            if !allowed_at(&content, offset).contains(&WarningCategory::InlinedDunderFile) {
+                let site = format!("{}:{}", file.display(), line_number(&content, offset));
+                artifact.warnings.push(ModuleWarning { category: WarningCategory::InlinedDunderFile, subject: file.display().to_string(), site });
+            }
+        }
+    }
+    if !opt.no_self_check {
+        let inlined_name = Regex::new(&format!(r"^(?:{})$", module_names)).unwrap();
+        let eager_roots: Vec<String> = eager_patterns(opt).iter().map(|(_, _, pattern)| pattern_root(pattern)).collect();
+        for call in dynamic_imports(&content) {
+            // A literal name of a module that isn't inlined still imports, as do the names under
+            // a package bundled up front
+            let covered = call.prefix.as_deref().is_some_and(|prefix| {
+                (!call.computed && !inlined_name.is_match(prefix))
+                    || eager_roots.iter().any(|root| !root.is_empty() && (prefix == root || prefix.starts_with(&format!("{}.", root))))
+            });
+            if !covered && !allowed_at(&content, call.offset).contains(&WarningCategory::DynamicImport) {
+                let site = format!("{}:{}", file.display(), line_number(&content, call.offset));
+                artifact.warnings.push(ModuleWarning { category: WarningCategory::DynamicImport, subject: file.display().to_string(), site });
+            }
+        }
+    }
+
+    // Find all TYPE_CHECKING blocks and strip them from the content
+    // TYPE_CHECKING is always False at runtime, so these blocks are only for static type checkers
+    let type_checking_blocks = find_type_checking_blocks(&content);
+    let (type_checking_blocks, kept_blocks) = match opt.rewrite_annotations {
+        true => (Vec::new(), type_checking_blocks),
+        false => (type_checking_blocks, Vec::new()),
+    };
+    if !type_checking_blocks.is_empty() {
+        artifact.transformations.push(ModuleTransformation::TypeCheckingRemoved);
+    }
+
+    let import_regex = Regex::new(&format!(r"(?m)^([ \t]*)(?:from\s+({0})\s+import\s+(.+)|import\s+({0})(?:\s+as\s+\w+)?[ \t]*\r?)$", module_names)).unwrap();
+    // if opt.verbose {
+    //     println!("Import regex: {}", import_regex);
+    // }
+    let mut result = String::new();
+
+    // First, skip over any TYPE_CHECKING blocks when copying content
+    let mut current_pos = 0;
+    for (block_start, block_end) in &type_checking_blocks {
+        // Copy content before this TYPE_CHECKING block
+        if current_pos < *block_start {
+            result.push_str(&content[current_pos..*block_start]);
+        }
+        // Skip the TYPE_CHECKING block entirely (don't copy it)
+        if opt.verbose {
+            let block_content = &content[*block_start..*block_end];
+            println!("Stripping TYPE_CHECKING block:\n{}", block_content.lines().take(3).collect::<Vec<_>>().join("\n"));
+        }
+        current_pos = *block_end;
+    }
+    // Copy any remaining content after the last TYPE_CHECKING block
+    let content_after_blocks = if current_pos < content.len() {
+        content[current_pos..].to_string()
+    } else {
+        String::new()
+    };
+
+    // Now process imports in the content (excluding TYPE_CHECKING blocks)
+    let content_to_process = result + &content_after_blocks;
+    let mut last_end = 0;
+
+    // Match against a copy with strings and comments blanked out, so import-looking text in
+    // docstrings or string literals is left alone. Offsets are identical in both copies.
+    let masked_content = mask_strings_and_comments(&content_to_process);
+    let captures = import_regex.captures_iter(&masked_content);
+    for cap in captures {
+        // if opt.verbose {
+        //     println!("Capture: {:?}", cap);
+        // }
+        let indent = &cap[1];
+        let submodule = cap.get(2).or_else(|| cap.get(4)).unwrap().as_str();
+        let start = cap.get(0).unwrap().start();
+        // The whole statement, over the lines a parenthesized list or backslashes continue it
+        // on, with its line break
+        let end = statement_end(&masked_content, start);
+        if skips_import(&content_to_process, start) {
+            if opt.verbose {
+                println!("{}", opt.terminal.paint(&format!("Leaving {} as an import, as its `# inliner: skip` comment asks", submodule), Style::Skip));
+            }
+            continue;
+        }
+        if last_end < start {
+            artifact.segments.push(Segment::Code(content_to_process[last_end..start].to_string()));
+        }
+        artifact.segments.push(Segment::Import(ImportSite {
+            indent: indent.to_string(),
+            module: submodule.to_string(),
+            statement: content_to_process[start..end].to_string(),
+            site: import_site(file, &content, &type_checking_blocks, start),
+            allowed: allowed_at(&content_to_process, start),
+            binds_alias: binds_alias(&masked_content[start..end]),
+            type_checking: kept_blocks.iter().any(|(block_start, block_end)| (*block_start..*block_end).contains(&start)),
+        }));
+        last_end = end;
+    }
+    artifact.segments.push(Segment::Code(content_to_process[last_end..].to_string()));
+    Ok(artifact)
+}
+
+/// Inline the imports of the modules to inline in a file, recursively, skipping files that are
+/// already in `processed`. Returns the file's content with the imports replaced.
+pub fn inline_imports(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, file: &Path, module_names: &str, processed: &mut HashSet<PathBuf>, opt: &Opt) -> Result<Rope, InlinerError> {
+    let artifact = module_artifact(fs, python_sys_path, file, module_names, opt)?;
+    for transformation in &artifact.transformations {
+        note_transformation(opt, file, transformation.name());
+    }
+    for warning in &artifact.warnings {
+        opt.warnings.warn(warning.category, &warning.subject, Some(warning.site.clone()));
+    }
+
+    let annotation_targets = match opt.rewrite_annotations {
+        true => type_checking_targets(fs, python_sys_path, file, &artifact, opt)?,
+        false => HashMap::new(),
+    };
+    let inlined_name = Regex::new(&format!(r"^(?:{})$", module_names)).unwrap();
+
+    let mut result = Rope::new();
+    for (index, segment) in artifact.segments.iter().enumerate() {
+        let import = match segment {
+            Segment::Code(code) if opt.rewrite_annotations => {
+                let rewrite = |name: &str| annotation_target(fs, python_sys_path, file, name, &inlined_name, &annotation_targets, opt);
+                result.push_str(&rewrite_string_annotations(code, &rewrite));
+                continue;
+            }
+            Segment::Code(code) => {
+                result.push_str(code);
+                continue;
+            }
+            Segment::Import(import) => import,
+        };
+        let replacement_start = result.mark();
+        let submodule = import.module.as_str();
+        // Record a warning about the import, unless an `# inliner: allow(...)` comment allows it
+        let warn = |category: WarningCategory, subject: &str| {
+            if !import.allowed.contains(&category) {
+                opt.warnings.warn(category, subject, Some(import.site.clone()));
+            }
+        };
+        // Modules the user declared as external (e.g. PEP 723 dependencies) are left as real imports
+        let top_level_module = submodule.split('.').next().unwrap_or(submodule);
+        if !submodule.starts_with('.') && opt.external_modules.iter().any(|m| m == top_level_module) {
+            if opt.verbose {
+                println!("{}", opt.terminal.paint(&format!("Leaving external module {:?} as an import", submodule), Style::Skip));
+            }
+            result.push_str(&import.statement);
+            continue;
+        }
+        if import.type_checking {
+            result.push_str(&type_checking_import(fs, python_sys_path, file, import, opt)?);
+            continue;
+        }
+
+        let mut found = false;
+        if is_plain_import(&import.statement) {
+            let inlined = inline_dotted_import(fs, python_sys_path, file, import, module_names, processed, opt)
+                .map_err(|e| InlinerError::located(e, || import_source_site(file, import)))?;
+            if let Some(inlined) = inlined {
+                found = true;
+                result.append(inlined);
+            }
+        } else if let Some(resolved_path) = resolve_from_import(fs, python_sys_path, file, submodule, opt).map_err(|e| InlinerError::located(e, || import_source_site(file, import)))? {
+            found = true;
+            note_import(opt, file, &resolved_path, import);
+            result.append(inline_module_file(fs, python_sys_path, file, &resolved_path, submodule, import, module_names, processed, opt)?);
+            result.push_str(&import_bindings(fs, file, &resolved_path, import, opt)?);
+            if is_star_import(&import.statement) {
+                result.append(star_import_bindings(fs, python_sys_path, file, &resolved_path, import, module_names, processed, opt)?);
+            } else if is_package_init(&resolved_path) {
+                // `from package import name` also imports the submodule `name`, unless the
+                // package binds the name itself
+                result.append(inline_package_submodules(fs, python_sys_path, file, &resolved_path, import, module_names, processed, opt)?);
+            }
+        } else if let Some(init_path) = namespace_package_init(fs, file, import)? {
+            found = true;
+            result.append(inline_package_submodules(fs, python_sys_path, file, &init_path, import, module_names, processed, opt)?);
+        }
+        if !found {
+            warn(WarningCategory::UnresolvedModule, submodule);
+            let resolver = match submodule.starts_with('.') {
+                true => ModuleResolver::new(fs, &[relative_base_dir(file, submodule)]).cached(&opt.resolution_cache),
+                false => ModuleResolver::new(fs, python_sys_path).cached(&opt.resolution_cache),
+            };
+            note_unresolved(opt, &resolver, submodule, submodule.trim_start_matches('.'));
+            result.push_str(&import.statement);
+        }
+        // An import of a module inlined already can be all that's in its block
+        if !import.indent.is_empty() && !result.any_line_since(replacement_start, &is_code_line) && !block_continues(&artifact.segments[index + 1..], &import.indent) {
+            result.push_str(&format!("{}pass\n", import.indent));
+        }
+    }
+    Ok(result)
+}
+
+/// Whether a line of code is more than a comment or blank
+fn is_code_line(line: &str) -> bool {
+    !line.trim().is_empty() && !line.trim_start().starts_with('#')
+}
+
+/// Whether the block an import indented by `indent` is in has more statements after it: an
+/// import at least as deep (which sees to its own block), or a line of code
+fn block_continues(segments: &[Segment], indent: &str) -> bool {
+    segments.iter().find_map(|segment| match segment {
+        Segment::Import(import) => Some(import.indent.len() >= indent.len()),
+        Segment::Code(code) => code.lines().find(|line| is_code_line(line)).map(|line| line.len() - line.trim_start().len() >= indent.len()),
+    }).unwrap_or(false)
+}
+
+/// What a name an import in a TYPE_CHECKING block binds stands for in the bundle
+#[derive(Debug, Clone, PartialEq)]
+enum AnnotationTarget {
+    /// A name the inlined module binds, under its name in the bundle
+    Name(String),
+    /// An inlined module, whose names are in the bundle's namespace
+    Module(PathBuf),
+}
+
+/// A name an import binds and what it stands for
+type GuardedName = (String, AnnotationTarget);
+
+/// The names an import in a TYPE_CHECKING block binds, as (bound name, target) pairs. None when
+/// the module isn't one that's inlined, so the import is kept for the type checker.
+fn guarded_import_targets(fs: &dyn FileSystem, python_sys_path: &[PathBuf], file: &Path, import: &ImportSite, opt: &Opt) -> Result<Option<Vec<GuardedName>>, InlinerError> {
+    let masked_statement = mask_strings_and_comments(&import.statement);
+    if is_plain_import(&import.statement) {
+        let Some(paths) = resolve_dotted_import(fs, python_sys_path, &import.module, opt)? else {
+            return Ok(None);
+        };
+        // Without an alias the statement binds the top-level package, which the annotations
+        // name in full
+        let alias = Regex::new(r"\sas\s+(\w+)").unwrap().captures(&masked_statement).map(|cap| cap[1].to_string());
+        return Ok(Some(alias.into_iter().map(|alias| (alias, AnnotationTarget::Module(paths.last().unwrap().clone()))).collect()));
+    }
+    let Some(path) = resolve_from_import(fs, python_sys_path, file, &import.module, opt)? else {
+        return Ok(None);
+    };
+    let submodules = if is_package_init(&path) { package_submodules(fs, &path, &import.statement)? } else { Vec::new() };
+    let mut targets: Vec<GuardedName> = submodules.iter()
+        .map(|(_, bound, submodule_path)| (bound.clone(), AnnotationTarget::Module(submodule_path.clone())))
+        .collect();
+    let Some(names_start) = Regex::new(r"\simport\s").unwrap().find(&masked_statement).map(|found| found.end()) else {
+        return Ok(Some(targets));
+    };
+    let renames = opt.renamed_definitions.get(&path);
+    for (name, bound) in import_aliases(&masked_statement[names_start..]) {
+        if submodules.iter().all(|(submodule, _, _)| *submodule != name) {
+            let target = renames.and_then(|renames| renames.get(&name)).unwrap_or(&name);
+            targets.push((bound, AnnotationTarget::Name(target.clone())));
+        }
+    }
+    Ok(Some(targets))
+}
+
+/// The targets of the names the TYPE_CHECKING imports of a processed file bind
+fn type_checking_targets(fs: &dyn FileSystem, python_sys_path: &[PathBuf], file: &Path, artifact: &ModuleArtifact, opt: &Opt) -> Result<HashMap<String, AnnotationTarget>, InlinerError> {
+    let mut targets = HashMap::new();
+    for segment in &artifact.segments {
+        let Segment::Import(import) = segment else { continue };
+        if import.type_checking {
+            targets.extend(guarded_import_targets(fs, python_sys_path, file, import, opt)?.into_iter().flatten());
+        }
+    }
+    Ok(targets)
+}
+
+/// What an import of an inlined module in a TYPE_CHECKING block becomes: the assignments that
+/// tell a type checker where the names it binds are in the bundle, or `pass` when they're there
+/// under the same names. Imports of other modules are kept.
+fn type_checking_import(fs: &dyn FileSystem, python_sys_path: &[PathBuf], file: &Path, import: &ImportSite, opt: &Opt) -> Result<String, InlinerError> {
+    let Some(targets) = guarded_import_targets(fs, python_sys_path, file, import, opt)? else {
+        return Ok(import.statement.clone());
+    };
+    let bindings: String = targets.iter()
+        .filter_map(|(bound, target)| match target {
+            AnnotationTarget::Name(name) if name != bound => Some(format!("{}{} = {}\n", import.indent, bound, name)),
+            _ => None,
+        })
+        .collect();
+    Ok(if bindings.is_empty() { format!("{}pass\n", import.indent) } else { bindings })
+}
+
+/// The name in the bundle of what the dotted name in a string annotation of `file` refers to:
+/// `Thing` for `mypkg.models.Thing` when mypkg.models is inlined, the renamed name of a name
+/// the file defines or a TYPE_CHECKING import binds, or of an attribute of a module such an
+/// import binds. None when it's left alone.
+fn annotation_target(fs: &dyn FileSystem, python_sys_path: &[PathBuf], file: &Path, name: &str, inlined_name: &Regex, targets: &HashMap<String, AnnotationTarget>, opt: &Opt) -> Option<String> {
+    let parts: Vec<&str> = name.split('.').collect();
+    let renamed = |path: &Path, name: &str| opt.renamed_definitions.get(path).and_then(|renames| renames.get(name)).cloned().unwrap_or_else(|| name.to_string());
+    let with_rest = |first: String, rest: &[&str]| [first.as_str()].iter().chain(rest).copied().collect::<Vec<_>>().join(".");
+    for length in (1..parts.len()).rev() {
+        let module = parts[..length].join(".");
+        if !inlined_name.is_match(&module) {
+            continue;
+        }
+        if let Ok(Some(paths)) = resolve_dotted_import(fs, python_sys_path, &module, opt) {
+            return Some(with_rest(renamed(paths.last().unwrap(), parts[length]), &parts[length + 1..]));
+        }
+    }
+    match targets.get(parts[0]) {
+        Some(AnnotationTarget::Name(target)) => Some(with_rest(target.clone(), &parts[1..])),
+        Some(AnnotationTarget::Module(path)) if parts.len() > 1 => Some(with_rest(renamed(path, parts[1]), &parts[2..])),
+        _ => opt.renamed_definitions.get(file).and_then(|renames| renames.get(parts[0])).map(|renamed| with_rest(renamed.clone(), &parts[1..])),
+    }
+}
+
+/// Where an import statement of `file` is, for errors about it
+fn import_source_site(file: &Path, import: &ImportSite) -> SourceSite {
+    let line = import.site.rsplit_once(':').and_then(|(_, line)| line.parse().ok()).unwrap_or(1);
+    let text = import.statement.lines().next().unwrap_or_default();
+    let text = if text.starts_with(&import.indent) { text.to_string() } else { format!("{}{}", import.indent, text) };
+    SourceSite::new(file, line, &text, &import.module)
+}
+
+/// The file `from module import ...` in `file` imports, the module being relative to the file's
+/// package if it starts with dots: one dot is the file's directory, and each further dot
+/// the directory above
+pub fn resolve_from_import(fs: &dyn FileSystem, python_sys_path: &[PathBuf], file: &Path, module: &str, opt: &Opt) -> Result<Option<PathBuf>, InlinerError> {
+    if module.starts_with('.') {
+        return Ok(ModuleResolver::new(fs, &[relative_base_dir(file, module)]).cached(&opt.resolution_cache).candidates(module.trim_start_matches('.')).first().cloned());
+    }
+    let candidates = ModuleResolver::new(fs, python_sys_path).cached(&opt.resolution_cache).candidates(module);
+    choose_resolution(fs, module, &candidates, opt)
+}
+
+/// The directory the leading dots of a relative module name in `file` stand for
+fn relative_base_dir(file: &Path, module: &str) -> PathBuf {
+    let level = module.len() - module.trim_start_matches('.').len();
+    let mut base_dir = file.parent().unwrap().to_path_buf();
+    for _ in 1..level {
+        base_dir.pop();
+    }
+    base_dir
+}
+
+/// For `from . import a, b` in a directory without an `__init__.py` (a namespace package, or
+/// the directory of the entry script itself), where the names can only be submodules: the
+/// path the package's `__init__.py` would have, when every name is a module in the directory
+fn namespace_package_init(fs: &dyn FileSystem, file: &Path, import: &ImportSite) -> Result<Option<PathBuf>, InlinerError> {
+    if !import.module.chars().all(|c| c == '.') || is_plain_import(&import.statement) || is_star_import(&import.statement) {
+        return Ok(None);
+    }
+    let init_path = relative_base_dir(file, &import.module).join("__init__.py");
+    if fs.exists(&init_path).map_err(|e| InlinerError::io(&init_path, e))? {
+        return Ok(None);
+    }
+    let masked_statement = mask_strings_and_comments(&import.statement);
+    let names = Regex::new(r"\simport\s").unwrap().find(&masked_statement).map_or(Vec::new(), |found| import_aliases(&masked_statement[found.end()..]));
+    let submodules = package_submodules(fs, &init_path, &import.statement)?;
+    Ok((!names.is_empty() && submodules.len() == names.len()).then_some(init_path))
+}
+
+/// The files of each part of the module `import pkg.sub.mod` imports, None when a part of the
+/// name doesn't resolve
+fn resolve_dotted_import(fs: &dyn FileSystem, python_sys_path: &[PathBuf], module: &str, opt: &Opt) -> Result<Option<Vec<PathBuf>>, InlinerError> {
+    let parts: Vec<&str> = module.split('.').collect();
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for (index, part) in parts.iter().enumerate() {
+        let resolved = match paths.last() {
+            None => choose_resolution(fs, part, &ModuleResolver::new(fs, python_sys_path).cached(&opt.resolution_cache).candidates(part), opt)?,
+            // Only packages have submodules
+            Some(package) if is_package_init(package) => ModuleResolver::new(fs, &[package.parent().unwrap().to_path_buf()]).cached(&opt.resolution_cache).candidates(part).first().cloned(),
+            Some(_) => None,
+        };
+        let Some(path) = resolved else {
+            if opt.verbose && index > 0 {
+                println!("Can't resolve {} in {}", part, parts[..index].join("."));
+            }
+            return Ok(None);
+        };
+        paths.push(path);
+    }
+    Ok(Some(paths))
+}
+
+/// A submodule imported by name from its package: (name, bound name, path)
+type SubmoduleImport = (String, String, PathBuf);
+
+/// The submodules `from package import ...` brings in: the imported names that the package's
+/// `__init__.py` doesn't bind (it may import them lazily, in a module `__getattr__`) and that
+/// are modules in the package's directory
+fn package_submodules(fs: &dyn FileSystem, init_path: &Path, statement: &str) -> Result<Vec<SubmoduleImport>, InlinerError> {
+    let masked_statement = mask_strings_and_comments(statement);
+    let Some(names_start) = Regex::new(r"\simport\s").unwrap().find(&masked_statement).map(|found| found.end()) else {
+        return Ok(Vec::new());
+    };
+    // A namespace package has no `__init__.py` to bind anything
+    let bindings = match fs.exists(init_path).map_err(|e| InlinerError::io(init_path, e))? {
+        true => top_level_bindings(&read_source(fs, init_path)?),
+        false => HashSet::new(),
+    };
+    let package_dir = [init_path.parent().unwrap().to_path_buf()];
+    Ok(import_aliases(&masked_statement[names_start..]).into_iter()
+        .filter(|(name, _)| !bindings.contains(name))
+        .filter_map(|(name, bound)| ModuleResolver::new(fs, &package_dir).candidates(&name).first().cloned().map(|path| (name, bound, path)))
+        .collect())
+}
+
+/// The dotted name of the submodule `name` of `package`: `pkg.helper`, or `.helper` for the
+/// package `.` of `from . import helper`
+fn submodule_label(package: &str, name: &str) -> String {
+    let separator = if package.ends_with('.') { "" } else { "." };
+    format!("{}{}{}", package, separator, name)
+}
+
+fn is_package_init(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == "__init__.py")
+}
+
+/// Whether an import statement is `import X` rather than `from X import ...`
+pub fn is_plain_import(statement: &str) -> bool {
+    statement.trim_start().starts_with("import")
+}
+
+/// Whether an import statement is `from X import *`
+pub fn is_star_import(statement: &str) -> bool {
+    Regex::new(r"\simport\s+\*").unwrap().is_match(&mask_strings_and_comments(statement))
+}
+
+/// How markers name a module file: ("package", "package") or ("module", "submodule")
+fn module_kinds(path: &Path) -> (&'static str, &'static str) {
+    if is_package_init(path) { ("package", "package") } else { ("module", "submodule") }
+}
+
+/// Assignments recreating the names a `from X import ...` statement in `file` binds that X's
+/// inlined code doesn't bind itself: `b = a` for each `a as b` with --topological, where X's code
+/// is elsewhere in the bundle, and for the names renamed in X or in `file` (--rename-collisions,
+/// --mangle-names) `a = _x__a` under the file's name for `a`. Submodules of a package are bound
+/// by inline_package_submodules().
+fn import_bindings(fs: &dyn FileSystem, file: &Path, module_file_path: &Path, import: &ImportSite, opt: &Opt) -> Result<String, InlinerError> {
+    let renames = opt.renamed_definitions.get(module_file_path);
+    let importer_renames = opt.renamed_definitions.get(file);
+    if !(opt.topological && import.binds_alias) && renames.is_none() && importer_renames.is_none() {
+        return Ok(String::new());
+    }
+    let masked_statement = mask_strings_and_comments(&import.statement);
+    let Some(names_start) = Regex::new(r"\simport\s").unwrap().find(&masked_statement).map(|found| found.end()) else {
+        return Ok(String::new());
+    };
+    let submodules: Vec<String> = match is_package_init(module_file_path) {
+        true => package_submodules(fs, module_file_path, &import.statement)?.into_iter().map(|(name, _, _)| name).collect(),
+        false => Vec::new(),
+    };
+    let mut bindings = String::new();
+    for (name, bound) in import_aliases(&masked_statement[names_start..]) {
+        let renamed = renames.and_then(|renames| renames.get(&name));
+        let renamed_bound = importer_renames.and_then(|renames| renames.get(&bound));
+        let aliased = opt.topological && name != bound;
+        if !submodules.contains(&name) && (renamed.is_some() || renamed_bound.is_some() || aliased) {
+            bindings.push_str(&format!("{}{} = {}\n", import.indent, renamed_bound.unwrap_or(&bound), renamed.unwrap_or(&name)));
+        }
+    }
+    Ok(bindings)
+}
+
+/// What `from X import *` binds that X's inlined code doesn't: the names of X's `__all__` (see
+/// star_exports()) renamed in X or in `file`, assigned under the names the importer knows, and
+/// for a package, the submodules its `__all__` lists, inlined as `from X import name` would
+#[allow(clippy::too_many_arguments)]
+fn star_import_bindings(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, file: &Path, module_file_path: &Path, import: &ImportSite, module_names: &str, processed: &mut HashSet<PathBuf>, opt: &Opt) -> Result<Rope, InlinerError> {
+    let exports = star_exports(&read_source(fs, module_file_path)?);
+    let mut bindings = Rope::new();
+    if let Some(statement) = star_import_statement(fs, module_file_path, import)?.filter(|_| is_package_init(module_file_path)) {
+        let listed = ImportSite { statement, ..import.clone() };
+        bindings.append(inline_package_submodules(fs, python_sys_path, file, module_file_path, &listed, module_names, processed, opt)?);
+    }
+    let renames = opt.renamed_definitions.get(module_file_path);
+    let importer_renames = opt.renamed_definitions.get(file);
+    for name in &exports {
+        let renamed = renames.and_then(|renames| renames.get(name));
+        let renamed_bound = importer_renames.and_then(|renames| renames.get(name));
+        if renamed.is_some() || renamed_bound.is_some() {
+            bindings.push_str(&format!("{}{} = {}\n", import.indent, renamed_bound.unwrap_or(name), renamed.unwrap_or(name)));
+        }
+    }
+    Ok(bindings)
+}
+
+/// `from X import *` spelled out as the import of the names of X's literal `__all__`, None when
+/// X has none
+fn star_import_statement(fs: &dyn FileSystem, module_file_path: &Path, import: &ImportSite) -> Result<Option<String>, InlinerError> {
+    Ok(literal_all(&read_source(fs, module_file_path)?)
+        .filter(|all| !all.is_empty())
+        .map(|all| format!("{}from {} import {}\n", import.indent, import.module, all.join(", "))))
+}
+
+/// A module file (or a package's `__init__.py`) inlined in place of an import of it, `label`
+/// naming it in the markers, or a marker that it was inlined already
+#[allow(clippy::too_many_arguments)]
+fn inline_module_file(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, file: &Path, module_file_path: &Path, label: &str, import: &ImportSite, module_names: &str, processed: &mut HashSet<PathBuf>, opt: &Opt) -> Result<Rope, InlinerError> {
+    let indent = import.indent.as_str();
+    let (kind, inlined_kind) = module_kinds(module_file_path);
+    let warn = |category: WarningCategory, subject: &str| {
+        if !import.allowed.contains(&category) {
+            opt.warnings.warn(category, subject, Some(import.site.clone()));
+        }
+    };
+    let module_file_path = module_file_path.to_path_buf();
+    let mut result = Rope::new();
+    if opt.topological {
+        // The module's code is at the top of the bundle already (see topological_bundle())
+        note_inlined(opt, file, "", std::iter::empty());
+        if !opt.release {
+            result.push_str(&format!("{indent}# →→ {} ←← {} inlined above\n", label, kind));
+        }
+        return Ok(result);
+    }
+    if processed.insert(module_file_path.clone()) {
+        opt.terminal.progress.borrow_mut().inline(&module_file_path);
+        if opt.verbose {
+            println!("Inlining {} {}", kind, module_file_path.display());
+        }
+        let inlined_before = processed.clone();
+        let module_content = inline_imports(fs, python_sys_path, &module_file_path, module_names, processed, opt)?;
+        note_inlined(opt, file, indent, processed.difference(&inlined_before).chain([&module_file_path]));
+        if !indent.is_empty() {
+            opt.indented_inlines.borrow_mut().insert(module_file_path.clone());
+        }
+        if !opt.release {
+            result.push_str(&format!("{indent}# ↓↓↓ inlined {}: {}\n", inlined_kind, label));
+        }
+        if opt.profile_imports {
+            result.push_str(&start_timer(indent));
+        }
+        // Whether indenting changes one of the multi-line strings of the module, or of a module
+        // inlined into it, which were checked as they were inlined. Any indent does if one does.
+        let changes_strings = !opt.no_self_check && (module_content.nested_changes_strings()
+            || module_content.own_text().any(|text| changed_multiline_string(text, &indent_code(text, "    ")).is_some()));
+        if changes_strings && !indent.is_empty() {
+            report_hazard(opt, WarningCategory::ReindentedString, &module_file_path, import.site.clone());
+        }
+        // Add import context indentation to all lines of inlined content
+        let mut indented = Rope::new();
+        indented.push_indented(indent, module_content, changes_strings);
+        note_size(opt, &module_file_path, &indented, processed.difference(&inlined_before));
+        result.append(indented);
+        // Ensure trailing newline after inlined content to prevent concatenation
+        // (especially important in release mode where closing comments are omitted)
+        result.push('\n');
+        if opt.profile_imports {
+            result.push_str(&stop_timer(indent, label));
+        }
+        if !opt.release {
+            result.push_str(&format!("{indent}# ↑↑↑ inlined {}: {}\n", inlined_kind, label));
+        }
+    } else {
+        warn(WarningCategory::AlreadyInlined, &module_file_path.display().to_string());
+        if !opt.no_self_check && (import.binds_alias || opt.indented_inlines.borrow().contains(&module_file_path)) {
+            warn(WarningCategory::UnboundDuplicateImport, &module_file_path.display().to_string());
+        }
+        if !opt.release {
+            result.push_str(&format!("{indent}# →→ {} ←← {} already inlined\n", label, kind));
+        }
+    }
+    Ok(result)
+}
+
+/// `import pkg.sub.mod`: the packages on the way and the module inlined in turn, as Python runs
+/// each one's code, and the binding the statement makes recreated: `pkg`, or the `as` name, as a
+/// namespace whose attribute `sub` is a namespace whose attribute `mod` holds the names the
+/// module defines. Intermediate namespaces get their package's names and keep attributes from
+/// earlier imports. None when a part of the name doesn't resolve.
+#[allow(clippy::too_many_arguments)]
+fn inline_dotted_import(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, file: &Path, import: &ImportSite, module_names: &str, processed: &mut HashSet<PathBuf>, opt: &Opt) -> Result<Option<Rope>, InlinerError> {
+    let Some(paths) = resolve_dotted_import(fs, python_sys_path, &import.module, opt)? else {
+        return Ok(None);
+    };
+    let parts: Vec<&str> = import.module.split('.').collect();
+    let alias = Regex::new(r"\sas\s+(\w+)").unwrap().captures(&mask_strings_and_comments(&import.statement)).map(|cap| cap[1].to_string());
+    // The names are bound below, so an alias isn't lost when the module was inlined already
+    let import = ImportSite { binds_alias: false, ..import.clone() };
+    let indent = import.indent.as_str();
+    let namespace = "__import__(\"types\").SimpleNamespace";
+
+    let mut result = Rope::new();
+    let mut bindings = String::new();
+    for (index, path) in paths.iter().enumerate() {
+        let is_leaf = index + 1 == parts.len();
+        note_import(opt, file, path, &import);
+        // A package already inlined on the way to another of its submodules is expected
+        if is_leaf || !processed.contains(path) {
+            result.append(inline_module_file(fs, python_sys_path, file, path, &parts[..=index].join("."), &import, module_names, processed, opt)?);
+        }
+        let names = namespace_arguments(fs, path, opt)?;
+        let target = match &alias {
+            Some(alias) if is_leaf => {
+                bindings.push_str(&format!("{indent}{} = {namespace}({})\n", alias, names));
+                continue;
+            }
+            Some(_) => continue,
+            None if index == 0 => {
+                bindings.push_str(&format!("{indent}{0} = vars().get(\"{0}\") or {namespace}()\n", parts[0]));
+                parts[0].to_string()
+            }
+            None => {
+                let (parent, target) = (parts[..index].join("."), parts[..=index].join("."));
+                bindings.push_str(&format!("{indent}{} = getattr({}, \"{}\", None) or {namespace}()\n", target, parent, parts[index]));
+                target
+            }
+        };
+        if !names.is_empty() {
+            bindings.push_str(&format!("{indent}vars({}).update({})\n", target, names));
+        }
+    }
+    result.push_str(&bindings);
+    Ok(Some(result))
+}
+
+/// `name=renamed, ...` for each name the module binds at the top level, renamed or not, to build
+/// a namespace of the module from
+fn namespace_arguments(fs: &dyn FileSystem, path: &Path, opt: &Opt) -> Result<String, InlinerError> {
+    let mut names: Vec<String> = top_level_bindings(&read_source(fs, path)?).into_iter().collect();
+    names.sort();
+    let renames = opt.renamed_definitions.get(path);
+    Ok(names.iter()
+        .map(|name| format!("{}={}", name, renames.and_then(|renames| renames.get(name)).unwrap_or(name)))
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+/// The submodules `from package import ...` brings in (see package_submodules()), inlined after
+/// the package itself. Inlined code shares the bundle's namespace, so the name is bound to the
+/// bundle's own module, which then has the submodule's attributes, unless the submodule's names
+/// were renamed and it gets a namespace of its own.
+#[allow(clippy::too_many_arguments)]
+fn inline_package_submodules(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, file: &Path, init_path: &Path, import: &ImportSite, module_names: &str, processed: &mut HashSet<PathBuf>, opt: &Opt) -> Result<Rope, InlinerError> {
+    let mut result = Rope::new();
+    for (name, bound, submodule_path) in package_submodules(fs, init_path, &import.statement)? {
+        // `from . import helper` names the submodule `.helper`
+        let label = submodule_label(&import.module, &name);
+        // A copy inlined only inside a block, like the package's own lazy import, isn't visible
+        // at the top level
+        if import.indent.is_empty() && opt.indented_inlines.borrow_mut().remove(&submodule_path) {
+            processed.remove(&submodule_path);
+        }
+        note_import(opt, file, &submodule_path, import);
+        // The name is bound below, so the statement's alias isn't lost when the module was inlined already
+        let import = ImportSite { binds_alias: false, ..import.clone() };
+        result.append(inline_module_file(fs, python_sys_path, file, &submodule_path, &label, &import, module_names, processed, opt)?);
+        let bound = opt.renamed_definitions.get(file).and_then(|renames| renames.get(&bound)).unwrap_or(&bound);
+        match opt.renamed_definitions.contains_key(&submodule_path) {
+            true => result.push_str(&format!("{}{} = __import__(\"types\").SimpleNamespace({})\n", import.indent, bound, namespace_arguments(fs, &submodule_path, opt)?)),
+            false => result.push_str(&format!("{}{} = __import__(\"sys\").modules[__name__]\n", import.indent, bound)),
+        }
+    }
+    Ok(result)
+}
+
+/// The modules of a package, as (dotted name, path) pairs: its `__init__.py` and every `.py` file
+/// under its directory, each package ahead of its submodules as Python imports them
+fn package_modules(fs: &dyn FileSystem, init_path: &Path, package: &str) -> Result<Vec<(String, PathBuf)>, InlinerError> {
+    let package_dir = init_path.parent().unwrap();
+    let mut modules: Vec<(Vec<String>, PathBuf)> = Vec::new();
+    let pattern = package_dir.join("**").join("*.py");
+    for path in fs.glob(&pattern).map_err(|e| InlinerError::io(&pattern, e))? {
+        let Ok(relative) = path.strip_prefix(package_dir) else { continue };
+        let mut parts: Vec<String> = package.split('.').map(str::to_string).collect();
+        parts.extend(relative.with_extension("").iter().map(|part| part.to_string_lossy().to_string()));
+        if parts.last().is_some_and(|part| part == "__init__") {
+            parts.pop();
+        }
+        // A file name that isn't an identifier (`setup-old.py`) can't be imported
+        if parts.iter().all(|part| !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '_')) {
+            modules.push((parts, path));
+        }
+    }
+    modules.sort();
+    modules.dedup();
+    Ok(modules.into_iter().map(|(parts, path)| (parts.join("."), path)).collect())
+}
+
+/// What --inline-package and --dynamic-map bundle up front, as (option, value, pattern of module
+/// names) triples: a package given whole is everything under it
+pub fn eager_patterns(opt: &Opt) -> Vec<(&'static str, &String, String)> {
+    let packages = opt.inline_package.iter().map(|package| ("--inline-package", package, format!("{}.*", package)));
+    packages.chain(opt.dynamic_map.iter().map(|pattern| ("--dynamic-map", pattern, pattern.clone()))).collect()
+}
+
+/// The module a pattern of module names is rooted at: its parts before the first wildcard
+pub fn pattern_root(pattern: &str) -> String {
+    pattern.split('.').take_while(|part| !part.contains(['*', '?'])).collect::<Vec<_>>().join(".")
+}
+
+/// The modules of the packages given with --inline-package, and those matching --dynamic-map,
+/// with the packages they're in, as imports in the order Python would run them
+fn eager_modules(fs: &dyn FileSystem, python_sys_path: &[PathBuf], opt: &Opt) -> Result<Vec<(ImportSite, PathBuf)>, InlinerError> {
+    let mut eager: Vec<(ImportSite, PathBuf)> = Vec::new();
+    for (option, value, pattern) in eager_patterns(opt) {
+        let root = pattern_root(&pattern);
+        if root.is_empty() {
+            return Err(InlinerError::usage(format!("{} {}: the pattern has to start with a package name", option, value)));
+        }
+        // Importing a submodule runs the packages it's in first
+        let parts: Vec<&str> = root.split('.').collect();
+        let mut modules: Vec<(String, PathBuf)> = Vec::new();
+        for index in 1..parts.len() {
+            let package = parts[..index].join(".");
+            let candidates = ModuleResolver::new(fs, python_sys_path).cached(&opt.resolution_cache).candidates(&package);
+            if let Some(path) = choose_resolution(fs, &package, &candidates, opt)?.filter(|path| is_package_init(path)) {
+                modules.push((package, path));
+            }
+        }
+        let candidates = ModuleResolver::new(fs, python_sys_path).cached(&opt.resolution_cache).candidates(&root);
+        modules.extend(match choose_resolution(fs, &root, &candidates, opt)? {
+            Some(path) if is_package_init(&path) => {
+                let modules = package_modules(fs, &path, &root)?;
+                let matched: Vec<&String> = modules.iter().map(|(name, _)| name).filter(|name| wildcard_match(&pattern, name)).collect();
+                // The package and the subpackages a matched module is in run first
+                let wanted = |name: &String| *name == root || matched.iter().any(|m| *m == name || m.starts_with(&format!("{}.", name)));
+                modules.iter().filter(|(name, _)| wanted(name)).cloned().collect()
+            }
+            Some(path) if pattern == root => vec![(root.clone(), path)],
+            Some(path) => return Err(InlinerError::usage(format!("{} {}: {} is a module, not a package", option, value, path.display()))),
+            None => return Err(InlinerError::usage(format!("{} {}: no module {} on the search path", option, value, root))),
+        });
+        if opt.verbose {
+            println!("Inlining {} modules up front for {} {}", modules.len(), option, value);
+        }
+        for (name, path) in modules {
+            if eager.iter().all(|(_, eager_path)| *eager_path != path) {
+                let import = ImportSite {
+                    indent: String::new(),
+                    module: name,
+                    statement: String::new(),
+                    site: format!("{} {}", option, value),
+                    allowed: Vec::new(),
+                    binds_alias: false,
+                    type_checking: false,
+                };
+                eager.push((import, path));
+            }
+        }
+    }
+    Ok(eager)
+}
+
+/// Statements registering modules bundled up front in sys.modules, as the bundle's own module
+fn register_modules(modules: &[(ImportSite, PathBuf)]) -> String {
+    modules.iter()
+        .map(|(import, _)| format!("__import__(\"sys\").modules.setdefault(\"{}\", __import__(\"sys\").modules[__name__])\n", import.module))
+        .collect()
+}
+
+/// The modules of eager_modules(), inlined ahead of the entry script's code. Each module inlines
+/// what it imports first, so the modules run in dependency order. Imports the static pass can't
+/// see, like `importlib.import_module("plugins.formats." + name)`, find the names in
+/// sys.modules, bound to the bundle's own module as the submodules imported from a package are.
+fn inline_packages_eagerly(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, file: &Path, module_names: &str, processed: &mut HashSet<PathBuf>, opt: &Opt) -> Result<Rope, InlinerError> {
+    let modules = eager_modules(fs, python_sys_path, opt)?;
+    let mut result = Rope::new();
+    for (import, path) in &modules {
+        // A module that was only inlined inside a block, like a lazy import, isn't visible at
+        // the top level
+        if opt.indented_inlines.borrow_mut().remove(path) {
+            processed.remove(path);
+        }
+        if !processed.contains(path) {
+            note_import(opt, file, path, import);
+            result.append(inline_module_file(fs, python_sys_path, file, path, &import.module, import, module_names, processed, opt)?);
+        }
+    }
+    result.push_str(&register_modules(&modules));
+    Ok(result)
+}
+
+/// The entry script with its imports inlined, before the passes over the whole bundle
+pub fn render_bundle(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, input_file: &Path, module_names: &str, processed: &mut HashSet<PathBuf>, opt: &Opt) -> Result<String, InlinerError> {
+    if opt.topological {
+        return topological_bundle(fs, python_sys_path, input_file, module_names, processed, opt);
+    }
+    let eager = inline_packages_eagerly(fs, python_sys_path, input_file, module_names, processed, opt)?;
+    // The bundle is only put together in one piece here, for the passes over all of it
+    let entry = inline_imports(fs, python_sys_path, input_file, module_names, processed, opt)?.render();
+    Ok(if eager.is_empty() { entry } else { insert_preamble(&entry, &eager.render()) })
+}
+
+/// The modules the imports of a file bring in, as (dotted name, path) pairs in the order
+/// inline_imports() would inline them
+fn module_dependencies(fs: &dyn FileSystem, python_sys_path: &[PathBuf], file: &Path, module_names: &str, opt: &Opt) -> Result<Vec<(String, PathBuf)>, InlinerError> {
+    let artifact = module_artifact(fs, python_sys_path, file, module_names, opt)?;
+    let mut dependencies = Vec::new();
+    for segment in &artifact.segments {
+        let Segment::Import(import) = segment else { continue };
+        let module = import.module.as_str();
+        let top_level_module = module.split('.').next().unwrap_or(module);
+        if import.type_checking || (!module.starts_with('.') && opt.external_modules.iter().any(|m| m == top_level_module)) {
+            continue;
+        }
+        if is_plain_import(&import.statement) {
+            let parts: Vec<&str> = module.split('.').collect();
+            for (index, path) in resolve_dotted_import(fs, python_sys_path, module, opt)?.into_iter().flatten().enumerate() {
+                dependencies.push((parts[..=index].join("."), path));
+            }
+        } else if let Some(path) = resolve_from_import(fs, python_sys_path, file, module, opt)? {
+            let statement = match is_star_import(&import.statement) {
+                true => star_import_statement(fs, &path, import)?.unwrap_or_default(),
+                false => import.statement.clone(),
+            };
+            let submodules = if is_package_init(&path) { package_submodules(fs, &path, &statement)? } else { Vec::new() };
+            dependencies.push((module.to_string(), path));
+            dependencies.extend(submodules.into_iter().map(|(name, _, path)| (submodule_label(module, &name), path)));
+        } else if let Some(init_path) = namespace_package_init(fs, file, import)? {
+            dependencies.extend(package_submodules(fs, &init_path, &import.statement)?.into_iter().map(|(name, _, path)| (submodule_label(module, &name), path)));
+        }
+    }
+    Ok(dependencies)
+}
+
+/// Depth-first, each module after the modules it imports
+#[allow(clippy::too_many_arguments)]
+fn visit_dependencies(fs: &dyn FileSystem, python_sys_path: &[PathBuf], label: &str, file: &Path, module_names: &str, visited: &mut HashSet<PathBuf>, order: &mut Vec<(String, PathBuf)>, opt: &Opt) -> Result<(), InlinerError> {
+    if !visited.insert(file.to_path_buf()) {
+        return Ok(());
+    }
+    for (dependency_label, dependency) in module_dependencies(fs, python_sys_path, file, module_names, opt)? {
+        visit_dependencies(fs, python_sys_path, &dependency_label, &dependency, module_names, visited, order, opt)?;
+    }
+    order.push((label.to_string(), file.to_path_buf()));
+    Ok(())
+}
+
+/// The bundle of --topological: every module the entry script depends on, directly or not,
+/// once at the top level ahead of the entry's code. Each module comes after the modules it
+/// imports, so module bodies run in the order Python finishes them, with imports inside blocks
+/// counted as if they ran at import time. In an import cycle the module reached first comes
+/// last. Every import is replaced by the names it binds (see inline_module_file()).
+fn topological_bundle(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, input_file: &Path, module_names: &str, processed: &mut HashSet<PathBuf>, opt: &Opt) -> Result<String, InlinerError> {
+    let eager = eager_modules(fs, python_sys_path, opt)?;
+    let mut visited = HashSet::new();
+    let mut order = Vec::new();
+    for (import, path) in &eager {
+        note_import(opt, input_file, path, import);
+        visit_dependencies(fs, python_sys_path, &import.module, path, module_names, &mut visited, &mut order, opt)?;
+    }
+    visit_dependencies(fs, python_sys_path, "", input_file, module_names, &mut visited, &mut order, opt)?;
+
+    let mut modules = Rope::new();
+    for (label, path) in order.iter().filter(|(_, path)| path != input_file) {
+        let (kind, inlined_kind) = module_kinds(path);
+        if opt.verbose {
+            println!("Inlining {} {}", kind, path.display());
+        }
+        processed.insert(path.clone());
+        let content = inline_imports(fs, python_sys_path, path, module_names, processed, opt)?;
+        if !opt.release {
+            modules.push_str(&format!("# ↓↓↓ inlined {}: {}\n", inlined_kind, label));
+        }
+        if opt.profile_imports {
+            modules.push_str(&start_timer(""));
+        }
+        note_size(opt, path, &content, std::iter::empty());
+        modules.append(content);
+        modules.push('\n');
+        if opt.profile_imports {
+            modules.push_str(&stop_timer("", label));
+        }
+        if !opt.release {
+            modules.push_str(&format!("# ↑↑↑ inlined {}: {}\n", inlined_kind, label));
+        }
+    }
+    modules.push_str(&register_modules(&eager));
+    let entry = inline_imports(fs, python_sys_path, input_file, module_names, processed, opt)?.render();
+    Ok(if modules.is_empty() { entry } else { insert_preamble(&entry, &modules.render()) })
+}
@@ -0,0 +1,195 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use crate::modules::file_system::FileSystem;
+use crate::modules::requirements::{metadata_field, record_paths};
+
+/// The license of a distribution the bundle inlines code from
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistributionLicense {
+    pub name: String,
+    pub version: String,
+    /// What METADATA declares: its License-Expression, its License when that's a single line,
+    /// else its license classifiers
+    pub license: Option<String>,
+    /// The license files the distribution installs, by path relative to the search path
+    /// directory, with their text
+    pub files: Vec<(String, String)>,
+}
+
+/// File names of license texts: those setuptools and hatchling pick up by default
+const LICENSE_FILE_PREFIXES: &[&str] = &["LICENSE", "LICENCE", "COPYING", "NOTICE", "AUTHORS", "COPYRIGHT"];
+
+/// Whether a path of a RECORD is a license file of its dist-info directory: one with a license
+/// file name, or anything under `licenses/`, where PEP 639 puts them
+fn is_license_file(path: &str) -> bool {
+    let mut parts = path.split('/');
+    if !parts.next().is_some_and(|top| top.ends_with(".dist-info")) {
+        return false;
+    }
+    let rest: Vec<&str> = parts.collect();
+    let name = rest.last().copied().unwrap_or_default().to_ascii_uppercase();
+    rest.first() == Some(&"licenses") || LICENSE_FILE_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// Every value of a METADATA header that can repeat, each with its continuation lines
+fn metadata_values(metadata: &str, field: &str) -> Vec<String> {
+    let mut values: Vec<String> = Vec::new();
+    let mut continuing = false;
+    for line in metadata.lines().take_while(|line| !line.is_empty()) {
+        if line.starts_with([' ', '\t']) {
+            if continuing {
+                let value = values.last_mut().unwrap();
+                value.push('\n');
+                // Continuation lines are indented, by 7 spaces and a `|` with older setuptools
+                value.push_str(line.trim_start().strip_prefix('|').unwrap_or(line.trim_start()));
+            }
+            continue;
+        }
+        continuing = false;
+        if let Some((_, value)) = line.split_once(':').filter(|(name, _)| name.eq_ignore_ascii_case(field)) {
+            values.push(value.trim().to_string());
+            continuing = true;
+        }
+    }
+    values
+}
+
+/// The license a METADATA declares, and the full text of its License field when that holds the
+/// license itself
+pub fn declared_license(metadata: &str) -> (Option<String>, Option<String>) {
+    if let Some(expression) = metadata_field(metadata, "License-Expression") {
+        return (Some(expression.to_string()), None);
+    }
+    let license = metadata_values(metadata, "License").into_iter().next()
+        .filter(|license| !license.trim().is_empty() && license.trim() != "UNKNOWN");
+    match license {
+        Some(license) if !license.trim().contains('\n') => return (Some(license.trim().to_string()), None),
+        _ => {}
+    }
+    let classifiers: Vec<String> = metadata_values(metadata, "Classifier").into_iter()
+        .filter_map(|classifier| classifier.strip_prefix("License ::").map(|rest| rest.rsplit("::").next().unwrap_or(rest).trim().to_string()))
+        .collect();
+    let summary = (!classifiers.is_empty()).then(|| classifiers.join(", "));
+    (summary, license)
+}
+
+/// The licenses of the installed distributions owning any of the files, found through the
+/// RECORD of each `*.dist-info` directory of the search path, sorted by name. Files that no
+/// distribution installed (the project's own, editable installs) have no license to collect.
+pub fn inlined_licenses(fs: &dyn FileSystem, python_sys_path: &[PathBuf], files: &HashSet<PathBuf>) -> Vec<DistributionLicense> {
+    let mut licenses = Vec::new();
+    for dir in python_sys_path {
+        let relative_files: Vec<String> = files.iter()
+            .filter_map(|file| file.strip_prefix(dir).ok())
+            .map(|relative| relative.to_string_lossy().replace('\\', "/"))
+            .collect();
+        if relative_files.is_empty() {
+            continue;
+        }
+        let Ok(mut dist_infos) = fs.glob(&dir.join("*.dist-info")) else { continue };
+        dist_infos.sort();
+        for dist_info in dist_infos {
+            let Ok(record) = fs.read_to_string(&dist_info.join("RECORD")) else { continue };
+            let paths = record_paths(&record);
+            if !relative_files.iter().any(|file| paths.contains(file)) {
+                continue;
+            }
+            let dir_name = dist_info.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let (dir_distribution, dir_version) = dir_name.split_once('-').unwrap_or((&dir_name, ""));
+            let metadata = fs.read_to_string(&dist_info.join("METADATA")).unwrap_or_default();
+            let (license, license_text) = declared_license(&metadata);
+
+            let mut license_paths: Vec<&String> = paths.iter().filter(|path| is_license_file(path)).collect();
+            license_paths.sort();
+            let mut license_files: Vec<(String, String)> = license_paths.into_iter()
+                .filter_map(|path| Some((path.clone(), fs.read_to_string(&dir.join(path)).ok()?)))
+                .collect();
+            if let (true, Some(text)) = (license_files.is_empty(), license_text) {
+                license_files.push((format!("{}/METADATA (License)", dist_info.file_name().unwrap_or_default().to_string_lossy()), text));
+            }
+            licenses.push(DistributionLicense {
+                name: metadata_field(&metadata, "Name").unwrap_or(dir_distribution).to_string(),
+                version: metadata_field(&metadata, "Version").unwrap_or(dir_version).to_string(),
+                license,
+                files: license_files,
+            });
+        }
+    }
+    licenses.sort_by_key(|license| license.name.to_lowercase());
+    licenses.dedup_by(|a, b| a.name.eq_ignore_ascii_case(&b.name) && a.version == b.version);
+    licenses
+}
+
+/// The third-party notices of the bundles: for each distribution, its name and version, the
+/// license it declares and the text of each of its license files
+pub fn render_notices(bundles: &[&Path], licenses: &[DistributionLicense]) -> String {
+    let names: Vec<String> = bundles.iter().map(|bundle| bundle.display().to_string()).collect();
+    let rule = "=".repeat(78);
+    let mut notices = format!("Third-party notices of {}, generated by python-inliner\n", names.join(", "));
+    if licenses.is_empty() {
+        notices.push_str("\nNo code from installed distributions is inlined.\n");
+        return notices;
+    }
+    notices.push_str("\nCode from the following distributions is inlined:\n\n");
+    for license in licenses {
+        notices.push_str(&format!("  {} {}\n", license.name, license.version));
+    }
+    for license in licenses {
+        notices.push_str(&format!("\n{}\n{} {}\n", rule, license.name, license.version));
+        notices.push_str(&format!("License: {}\n", license.license.as_deref().unwrap_or("not declared")));
+        notices.push_str(&format!("{}\n", rule));
+        for (path, text) in &license.files {
+            notices.push_str(&format!("\n--- {} ---\n\n", path));
+            for line in text.trim_end().lines() {
+                notices.push_str(line.trim_end());
+                notices.push('\n');
+            }
+        }
+    }
+    notices
+}
+
+/// The notices as a comment block, for the top of a bundle
+pub fn license_comment(notices: &str) -> String {
+    notices.lines()
+        .map(|line| if line.is_empty() { "#\n".to_string() } else { format!("# {}\n", line) })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+
+    #[test]
+    fn test_inlined_licenses() {
+        let fs = VirtualFileSystem::new();
+        let site_packages = PathBuf::from("/venv/lib/python3.12/site-packages");
+        for dir in ["jinja2", "jinja2-3.0.0.dist-info/licenses", "attrs-23.1.0.dist-info", "legacy-1.0.dist-info", "idna-3.7.dist-info"] {
+            fs.mkdir_p(&site_packages.join(dir)).unwrap();
+        }
+        fs.write(&site_packages.join("jinja2-3.0.0.dist-info/RECORD"), b"jinja2/__init__.py,sha256=abc,100\njinja2-3.0.0.dist-info/licenses/LICENSE.txt,,\njinja2-3.0.0.dist-info/RECORD,,\n").unwrap();
+        fs.write(&site_packages.join("jinja2-3.0.0.dist-info/METADATA"), b"Metadata-Version: 2.4\nName: Jinja2\nVersion: 3.0.0\nLicense-Expression: BSD-3-Clause\nLicense-File: LICENSE.txt\n").unwrap();
+        fs.write(&site_packages.join("jinja2-3.0.0.dist-info/licenses/LICENSE.txt"), b"Copyright 2007 Pallets  \r\n\r\nRedistribution is permitted.\n\n").unwrap();
+        fs.write(&site_packages.join("attrs-23.1.0.dist-info/RECORD"), b"attr/__init__.py,,\nattrs-23.1.0.dist-info/COPYING,,\n").unwrap();
+        fs.write(&site_packages.join("attrs-23.1.0.dist-info/METADATA"), b"Name: attrs\nVersion: 23.1.0\nLicense: UNKNOWN\nClassifier: Programming Language :: Python\nClassifier: License :: OSI Approved :: MIT License\n").unwrap();
+        fs.write(&site_packages.join("attrs-23.1.0.dist-info/COPYING"), b"MIT\n").unwrap();
+        fs.write(&site_packages.join("legacy-1.0.dist-info/RECORD"), b"legacy.py,,\n").unwrap();
+        fs.write(&site_packages.join("legacy-1.0.dist-info/METADATA"), b"Name: legacy\nVersion: 1.0\nLicense: Copyright Someone\n        \n        All rights reserved.\nSummary: old\n").unwrap();
+        fs.write(&site_packages.join("idna-3.7.dist-info/RECORD"), b"idna/__init__.py,,\n").unwrap();
+
+        let files: HashSet<PathBuf> = ["jinja2/__init__.py", "attr/__init__.py", "legacy.py"].iter().map(|file| site_packages.join(file))
+            .chain([PathBuf::from("/src/mylib/__init__.py")])
+            .collect();
+        let licenses = inlined_licenses(&fs, &[PathBuf::from("/src"), site_packages], &files);
+        assert_eq!(licenses.iter().map(|license| license.name.as_str()).collect::<Vec<_>>(), ["attrs", "Jinja2", "legacy"]);
+        assert_eq!(licenses[0].license.as_deref(), Some("MIT License"));
+        assert_eq!(licenses[2].license, None);
+        assert_eq!(licenses[2].files, vec![("legacy-1.0.dist-info/METADATA (License)".to_string(), "Copyright Someone\n\nAll rights reserved.".to_string())]);
+
+        let notices = render_notices(&[Path::new("dist/app.py")], &licenses[1..2]);
+        let rule = "=".repeat(78);
+        assert_eq!(notices, format!("Third-party notices of dist/app.py, generated by python-inliner\n\nCode from the following distributions is inlined:\n\n  Jinja2 3.0.0\n\n{rule}\nJinja2 3.0.0\nLicense: BSD-3-Clause\n{rule}\n\n--- jinja2-3.0.0.dist-info/licenses/LICENSE.txt ---\n\nCopyright 2007 Pallets\n\nRedistribution is permitted.\n"));
+        assert_eq!(license_comment("Notices\n\n  a 1.0\n"), "# Notices\n#\n#   a 1.0\n");
+    }
+}
@@ -0,0 +1,66 @@
+use std::str::FromStr;
+
+/// Line ending of the written bundle
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    /// Whatever the entry script uses
+    #[default]
+    Keep,
+}
+
+impl FromStr for LineEnding {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<LineEnding, String> {
+        match value {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            "keep" => Ok(LineEnding::Keep),
+            _ => Err(format!("unknown line ending {:?}, expected lf, crlf or keep", value)),
+        }
+    }
+}
+
+/// Convert CRLF and lone CR line endings to LF, as Python's universal newlines do when reading
+/// source. Everything downstream only has to deal with `\n`.
+pub fn normalize_line_endings(content: &str) -> String {
+    if !content.contains('\r') {
+        return content.to_string();
+    }
+    content.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// The line ending a file uses, going by its first line break
+pub fn detect_line_ending(content: &str) -> LineEnding {
+    match content.find('\n') {
+        Some(position) if content[..position].ends_with('\r') => LineEnding::Crlf,
+        _ => LineEnding::Lf,
+    }
+}
+
+/// Write normalized content with the given ending. `original` is the entry script, consulted for Keep.
+pub fn apply_line_ending(content: &str, ending: LineEnding, original: &str) -> String {
+    let ending = if ending == LineEnding::Keep { detect_line_ending(original) } else { ending };
+    match ending {
+        LineEnding::Crlf => content.replace('\n', "\r\n"),
+        _ => content.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_endings() {
+        assert_eq!(normalize_line_endings("a\r\nb\rc\n"), "a\nb\nc\n");
+        assert_eq!(detect_line_ending("a\r\nb\n"), LineEnding::Crlf);
+        assert_eq!(detect_line_ending("a\nb\r\n"), LineEnding::Lf);
+        assert_eq!(apply_line_ending("a\nb\n", LineEnding::Keep, "x\r\n"), "a\r\nb\r\n");
+        assert_eq!(apply_line_ending("a\nb\n", LineEnding::Lf, "x\r\n"), "a\nb\n");
+        assert_eq!("crlf".parse::<LineEnding>(), Ok(LineEnding::Crlf));
+        assert!("dos".parse::<LineEnding>().is_err());
+    }
+}
@@ -0,0 +1,132 @@
+/// Escape text for roff: backslashes and dashes, and a leading `.` or `'` that would start a
+/// request
+fn escape(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    match escaped.starts_with(['.', '\'']) {
+        true => format!("\\&{}", escaped),
+        false => escaped,
+    }
+}
+
+/// A section of the help text: its heading and lines
+fn sections(help: &str) -> Vec<(&str, Vec<&str>)> {
+    let mut sections: Vec<(&str, Vec<&str>)> = vec![("", Vec::new())];
+    for line in help.lines() {
+        match line.strip_suffix(':').filter(|heading| !heading.is_empty() && heading.chars().all(|c| c.is_ascii_uppercase())) {
+            Some(heading) => sections.push((heading, Vec::new())),
+            None => sections.last_mut().unwrap().1.push(line),
+        }
+    }
+    sections
+}
+
+/// The entries of a FLAGS, OPTIONS, ARGS or SUBCOMMANDS section: what's written and its
+/// description, which may follow it after a gap or be on the lines under it. Clap indents
+/// entries by 4 columns, or 8 for flags without a short form, and descriptions further.
+fn entries<'a>(lines: &[&'a str]) -> Vec<(&'a str, String)> {
+    let mut entries: Vec<(&str, String)> = Vec::new();
+    for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+        let indent = line.len() - line.trim_start().len();
+        match entries.last_mut() {
+            Some((_, description)) if indent > 8 => {
+                if !description.is_empty() {
+                    description.push(' ');
+                }
+                description.push_str(line.trim());
+            }
+            _ => {
+                let trimmed = line.trim();
+                let (term, description) = trimmed.split_once("  ").unwrap_or((trimmed, ""));
+                entries.push((term, description.trim().to_string()));
+            }
+        }
+    }
+    entries
+}
+
+/// A man page in roff, from the clap help text of the command: its first lines become the NAME
+/// and AUTHOR sections, USAGE the SYNOPSIS, and every other section a list of its entries
+pub fn render_man_page(help: &str) -> String {
+    let sections = sections(help);
+    let preamble: Vec<&str> = sections[0].1.iter().map(|line| line.trim()).filter(|line| !line.is_empty()).collect();
+    let (name, version) = preamble.first().and_then(|line| line.split_once(' ')).unwrap_or(("", ""));
+    let about = preamble.last().copied().unwrap_or_default();
+
+    let mut page = format!(".TH {} 1 \"\" \"{} {}\"\n", escape(&name.to_uppercase()), escape(name), escape(version));
+    page.push_str(&format!(".SH NAME\n{} \\- {}\n", escape(name), escape(about)));
+    for (heading, lines) in &sections[1..] {
+        if *heading == "USAGE" {
+            page.push_str(".SH SYNOPSIS\n");
+            for line in lines.iter().map(|line| line.trim()).filter(|line| !line.is_empty()) {
+                let (command, arguments) = line.split_once(' ').unwrap_or((line, ""));
+                page.push_str(&format!("\\fB{}\\fR {}\n.br\n", escape(command), escape(arguments)));
+            }
+            continue;
+        }
+        page.push_str(&format!(".SH {}\n", heading));
+        for (term, description) in entries(lines) {
+            page.push_str(&format!(".TP\n\\fB{}\\fR\n", escape(term)));
+            if !description.is_empty() {
+                page.push_str(&format!("{}\n", escape(&description)));
+            }
+        }
+    }
+    if preamble.len() > 2 {
+        page.push_str(&format!(".SH AUTHOR\n{}\n", escape(preamble[1])));
+    }
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_man_page() {
+        let help = "\
+tool 1.2.0
+Jane Doe <jane@example.com>
+Does things - https://example.com
+
+USAGE:
+    tool [FLAGS] [OPTIONS] <input>
+
+FLAGS:
+    -h, --help       Prints help information
+        --dry-run    Don't write
+                     anything
+
+OPTIONS:
+        --cache-dir <cache-dir>
+            Where to keep .cache files
+
+ARGS:
+    <input>
+";
+        assert_eq!(render_man_page(help), "\
+.TH TOOL 1 \"\" \"tool 1.2.0\"
+.SH NAME
+tool \\- Does things \\- https://example.com
+.SH SYNOPSIS
+\\fBtool\\fR [FLAGS] [OPTIONS] <input>
+.br
+.SH FLAGS
+.TP
+\\fB\\-h, \\-\\-help\\fR
+Prints help information
+.TP
+\\fB\\-\\-dry\\-run\\fR
+Don't write anything
+.SH OPTIONS
+.TP
+\\fB\\-\\-cache\\-dir <cache\\-dir>\\fR
+Where to keep .cache files
+.SH ARGS
+.TP
+\\fB<input>\\fR
+.SH AUTHOR
+Jane Doe <jane@example.com>
+");
+        assert_eq!(escape(".hidden \\d"), "\\&.hidden \\ed");
+    }
+}
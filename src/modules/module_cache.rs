@@ -0,0 +1,359 @@
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::io;
+use crate::modules::errors::InlinerError;
+use crate::modules::file_system::FileSystem;
+use crate::modules::inlining::{eager_patterns, process_module};
+use crate::modules::options::Opt;
+#[cfg(feature = "remote-cache")]
+use crate::modules::remote_cache::RemoteCache;
+use crate::modules::warnings::WarningCategory;
+use crate::search_path_relative;
+use crate::utils::sha256::sha256_hex;
+
+/// A transformation a module's own pass can apply, which is recorded with its artifact
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ModuleTransformation {
+    Reindent,
+    Defines,
+    EntryFunction,
+    RenameCollisions,
+    TreeShake,
+    StripDebugCode,
+    TypeCheckingRemoved,
+}
+
+impl ModuleTransformation {
+    /// Every transformation, in declaration order, so a cached artifact can name any of them
+    pub const ALL: &'static [ModuleTransformation] = &[
+        ModuleTransformation::Reindent,
+        ModuleTransformation::Defines,
+        ModuleTransformation::EntryFunction,
+        ModuleTransformation::RenameCollisions,
+        ModuleTransformation::TreeShake,
+        ModuleTransformation::StripDebugCode,
+        ModuleTransformation::TypeCheckingRemoved,
+    ];
+
+    /// The name in artifacts and in the --report
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModuleTransformation::Reindent => "reindent",
+            ModuleTransformation::Defines => "defines",
+            ModuleTransformation::EntryFunction => "entry_function",
+            ModuleTransformation::RenameCollisions => "rename_collisions",
+            ModuleTransformation::TreeShake => "tree_shake",
+            ModuleTransformation::StripDebugCode => "strip_debug_code",
+            ModuleTransformation::TypeCheckingRemoved => "type_checking_removed",
+        }
+    }
+}
+
+// A variant left out of ALL would make every artifact with it a cache miss
+const _: () = assert!(ModuleTransformation::ALL.len() == ModuleTransformation::TypeCheckingRemoved as usize + 1);
+
+/// An import of a module to inline, found by the per-module pass
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportSite {
+    pub indent: String,
+    /// The (possibly relative) dotted module name
+    pub module: String,
+    /// The statement as written, including its newline, kept when the module isn't inlined
+    pub statement: String,
+    /// `file:line` of the statement
+    pub site: String,
+    /// The warnings `# inliner: allow(...)` comments allow at the statement
+    pub allowed: Vec<WarningCategory>,
+    /// Whether the statement renames what it imports with `as`
+    pub binds_alias: bool,
+    /// Whether the statement is in an `if TYPE_CHECKING:` block kept for --rewrite-annotations
+    pub type_checking: bool,
+}
+
+/// A piece of a processed module: code to copy as is, or an import to inline in its place
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Code(String),
+    Import(ImportSite),
+}
+
+/// A warning the per-module pass raised, replayed whenever the artifact is used
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleWarning {
+    pub category: WarningCategory,
+    pub subject: String,
+    pub site: String,
+}
+
+/// What the per-module pass makes of one file, which only depends on the file's contents and
+/// the options in its cache key: the file with TYPE_CHECKING blocks removed (unless they're kept
+/// with --rewrite-annotations), cut up at the
+/// imports to inline. Inlining the imported modules is left to whoever assembles the bundle.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModuleArtifact {
+    pub segments: Vec<Segment>,
+    /// In the order they were applied
+    pub transformations: Vec<ModuleTransformation>,
+    pub warnings: Vec<ModuleWarning>,
+}
+
+/// Stands for the module's own path in the import sites and warnings of a stored artifact, so
+/// the artifact serves the same module in a checkout at another path
+const MODULE_PATH: &str = "<module>";
+
+/// `text` with the path `from` it starts with, followed by nothing or by `:line`, replaced by `to`
+fn relocate(text: &str, from: &str, to: &str) -> String {
+    match text.strip_prefix(from) {
+        Some(rest) if rest.is_empty() || rest.starts_with(':') => format!("{}{}", to, rest),
+        _ => text.to_string(),
+    }
+}
+
+impl ModuleArtifact {
+    /// The artifact of `file`, written without the file's path
+    pub fn to_json(&self, file: &Path) -> String {
+        let file = file.display().to_string();
+        let segments: Vec<Value> = self.segments.iter()
+            .map(|segment| match segment {
+                Segment::Code(code) => json!({ "code": code }),
+                Segment::Import(import) => json!({
+                    "indent": import.indent,
+                    "module": import.module,
+                    "statement": import.statement,
+                    "site": relocate(&import.site, &file, MODULE_PATH),
+                    "allowed": import.allowed.iter().map(WarningCategory::code).collect::<Vec<_>>(),
+                    "binds_alias": import.binds_alias,
+                    "type_checking": import.type_checking,
+                }),
+            })
+            .collect();
+        let warnings: Vec<Value> = self.warnings.iter()
+            .map(|warning| json!({
+                "code": warning.category.code(),
+                "subject": relocate(&warning.subject, &file, MODULE_PATH),
+                "site": relocate(&warning.site, &file, MODULE_PATH),
+            }))
+            .collect();
+        let transformations: Vec<&str> = self.transformations.iter().map(ModuleTransformation::name).collect();
+        let artifact = json!({ "segments": segments, "transformations": transformations, "warnings": warnings });
+        // Serializing a Value can't fail
+        serde_json::to_string(&artifact).unwrap()
+    }
+
+    /// The artifact of `file` from what to_json() wrote for the same module, wherever it was
+    pub fn from_json(content: &str, file: &Path) -> Result<ModuleArtifact, String> {
+        let file = file.display().to_string();
+        let artifact: Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+        let array = |value: &Value, field: &str| -> Result<Vec<Value>, String> {
+            value.get(field).and_then(Value::as_array).cloned().ok_or_else(|| format!("cached module has no `{}`", field))
+        };
+        let string = |value: &Value, field: &str| -> Result<String, String> {
+            value.get(field).and_then(Value::as_str).map(String::from).ok_or_else(|| format!("cached module has no string `{}`", field))
+        };
+
+        let mut segments = Vec::new();
+        for segment in array(&artifact, "segments")? {
+            if segment.get("code").is_some() {
+                segments.push(Segment::Code(string(&segment, "code")?));
+                continue;
+            }
+            let allowed = array(&segment, "allowed")?.iter()
+                .map(|code| code.as_str().ok_or("non-string warning code")?.parse::<WarningCategory>())
+                .collect::<Result<Vec<_>, String>>()?;
+            segments.push(Segment::Import(ImportSite {
+                indent: string(&segment, "indent")?,
+                module: string(&segment, "module")?,
+                statement: string(&segment, "statement")?,
+                site: relocate(&string(&segment, "site")?, MODULE_PATH, &file),
+                allowed,
+                binds_alias: segment.get("binds_alias").and_then(Value::as_bool).unwrap_or(false),
+                type_checking: segment.get("type_checking").and_then(Value::as_bool).unwrap_or(false),
+            }));
+        }
+        let transformations = array(&artifact, "transformations")?.iter()
+            .map(|name| ModuleTransformation::ALL.iter().find(|known| name.as_str() == Some(known.name())).copied()
+                .ok_or_else(|| format!("unknown transformation {}", name)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let mut warnings = Vec::new();
+        for warning in array(&artifact, "warnings")? {
+            warnings.push(ModuleWarning {
+                category: string(&warning, "code")?.parse()?,
+                subject: relocate(&string(&warning, "subject")?, MODULE_PATH, &file),
+                site: relocate(&string(&warning, "site")?, MODULE_PATH, &file),
+            });
+        }
+        Ok(ModuleArtifact { segments, transformations, warnings })
+    }
+}
+
+/// The cache key of a module's artifact: a hash of the tool version, the module's path
+/// relative to the search path it was found on (`mylib/utils.py`), its contents, and `options`,
+/// which must spell out every option the per-module pass depends on. Nothing in it depends on
+/// where the project is checked out, so machines sharing a --remote-cache share its entries.
+pub fn cache_key(module_path: &str, contents: &[u8], options: &str) -> String {
+    let key = format!("{}\0{}\0{}\0{}", env!("CARGO_PKG_VERSION"), module_path, options, sha256_hex(contents));
+    sha256_hex(key.as_bytes())
+}
+
+/// The name an artifact is stored under, in a --cache-dir and a --remote-cache alike
+pub fn artifact_file_name(key: &str) -> String {
+    format!("{}.json", key)
+}
+
+fn artifact_path(cache_dir: &Path, key: &str) -> PathBuf {
+    cache_dir.join(artifact_file_name(key))
+}
+
+/// The cached artifact of `file` with the key, if there is one. An unreadable entry, e.g. one
+/// written by an interrupted run, is a miss.
+pub fn load_artifact(fs: &dyn FileSystem, cache_dir: &Path, key: &str, file: &Path) -> Option<ModuleArtifact> {
+    let content = fs.read_to_string(&artifact_path(cache_dir, key)).ok()?;
+    ModuleArtifact::from_json(&content, file).ok()
+}
+
+pub fn store_artifact(fs: &dyn FileSystem, cache_dir: &Path, key: &str, file: &Path, artifact: &ModuleArtifact) -> io::Result<()> {
+    fs.mkdir_p(cache_dir)?;
+    fs.write(&artifact_path(cache_dir, key), artifact.to_json(file).as_bytes())
+}
+
+/// Remove every artifact from the cache directory, and the directory once it's empty. Other
+/// files are left alone, in case the directory was shared by mistake. Returns how many
+/// artifacts were removed.
+pub fn clear_cache(fs: &dyn FileSystem, cache_dir: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+    for path in fs.read_dir(cache_dir)? {
+        let is_artifact = path.extension().is_some_and(|extension| extension == "json")
+            && path.file_stem().and_then(|stem| stem.to_str()).is_some_and(|stem| stem.len() == 64 && stem.bytes().all(|b| b.is_ascii_hexdigit()));
+        if is_artifact {
+            fs.remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    if fs.read_dir(cache_dir)?.is_empty() {
+        fs.remove_dir(cache_dir)?;
+    }
+    Ok(removed)
+}
+
+/// The options the per-module pass depends on, for the cache key of a module's artifact
+fn module_options(file: &Path, module_names: &str, opt: &Opt) -> String {
+    let mut unused: Vec<&String> = opt.unused_definitions.get(file).map(|names| names.iter().collect()).unwrap_or_default();
+    unused.sort();
+    format!("modules={} reindent={:?} entry={} self_check={} unused={:?} eager={:?} renames={:?} annotations={} strip_asserts={} strip={:?} defines={:?} prune={} entry_function={:?}", module_names, opt.reindent, opt.input_file.as_deref() == Some(file), !opt.no_self_check, unused, eager_patterns(opt), opt.renamed_definitions.get(file), opt.rewrite_annotations, opt.strip_asserts, opt.strip_pattern, opt.define, opt.prune_defines, opt.entry_function)
+}
+
+/// The per-module pass over a file (see process_module()), reusing the artifact of an earlier
+/// run from --cache-dir, or failing that from --remote-cache, when neither the file nor the
+/// options it depends on have changed. Artifacts are keyed by the file's path under the search
+/// path, not its absolute path, so they carry over to checkouts elsewhere. An artifact from the
+/// remote cache is kept in the --cache-dir too, and one processed here is stored in both.
+pub fn module_artifact(fs: &dyn FileSystem, python_sys_path: &[PathBuf], file: &Path, module_names: &str, opt: &Opt) -> Result<ModuleArtifact, InlinerError> {
+    #[cfg(feature = "remote-cache")]
+    let remote_cache = opt.remote_cache.as_ref();
+    #[cfg(not(feature = "remote-cache"))]
+    let remote_cache: Option<()> = None;
+    if opt.cache_dir.is_none() && remote_cache.is_none() {
+        return process_module(fs, file, module_names, opt);
+    }
+    let contents = fs.read(file).map_err(|e| InlinerError::io(file, e))?;
+    let key = cache_key(&search_path_relative(file, python_sys_path), &contents, &module_options(file, module_names, opt));
+    if let Some(artifact) = opt.cache_dir.as_ref().and_then(|cache_dir| load_artifact(fs, cache_dir, &key, file)) {
+        if opt.verbose {
+            println!("Reusing cached processing of {}", file.display());
+        }
+        return Ok(artifact);
+    }
+    let store_locally = |artifact: &ModuleArtifact| match &opt.cache_dir {
+        Some(cache_dir) => store_artifact(fs, cache_dir, &key, file, artifact).map_err(|e| InlinerError::io(cache_dir, e)),
+        None => Ok(()),
+    };
+    #[cfg(feature = "remote-cache")]
+    if let Some(remote) = remote_cache {
+        let content = remote.get(fs, &key).unwrap_or_else(|e| {
+            remote_cache_failed(remote, e, opt);
+            None
+        });
+        if let Some(artifact) = content.and_then(|content| ModuleArtifact::from_json(&content, file).ok()) {
+            if opt.verbose {
+                println!("Reusing the remote cache's processing of {}", file.display());
+            }
+            store_locally(&artifact)?;
+            return Ok(artifact);
+        }
+    }
+    let artifact = process_module(fs, file, module_names, opt)?;
+    store_locally(&artifact)?;
+    #[cfg(feature = "remote-cache")]
+    if let Some(remote) = remote_cache {
+        if let Err(e) = remote.put(fs, &key, &artifact.to_json(file)) {
+            remote_cache_failed(remote, e, opt);
+        }
+    }
+    Ok(artifact)
+}
+
+/// A remote cache that can't be reached only costs the time it would have saved, so its
+/// failures are warnings, reported once however many modules ran into them
+#[cfg(feature = "remote-cache")]
+fn remote_cache_failed(remote: &RemoteCache, error: io::Error, opt: &Opt) {
+    opt.warnings.warn(WarningCategory::UnreachableRemoteCache, &remote.location(), None);
+    opt.warnings.note(WarningCategory::UnreachableRemoteCache, &remote.location(), error.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+
+    #[test]
+    fn test_artifact_round_trip() {
+        let artifact = ModuleArtifact {
+            segments: vec![
+                Segment::Code("import os\n".to_string()),
+                Segment::Import(ImportSite {
+                    indent: "    ".to_string(),
+                    module: ".utils".to_string(),
+                    statement: "    from .utils import helper as h\n".to_string(),
+                    site: "/src/mylib/__init__.py:2".to_string(),
+                    allowed: vec![WarningCategory::AlreadyInlined],
+                    binds_alias: true,
+                    type_checking: true,
+                }),
+            ],
+            transformations: ModuleTransformation::ALL.to_vec(),
+            warnings: vec![ModuleWarning { category: WarningCategory::InlinedDunderFile, subject: "/src/mylib/__init__.py".to_string(), site: "/src/mylib/__init__.py:1".to_string() }],
+        };
+        let fs = VirtualFileSystem::new();
+        let file = Path::new("/src/mylib/__init__.py");
+        let key = cache_key("mylib/__init__.py", b"import os\n", "reindent=keep");
+        assert_ne!(key, cache_key("mylib/__init__.py", b"import os\n", "reindent=tabs"));
+        assert_eq!(load_artifact(&fs, Path::new("/cache"), &key, file), None);
+        store_artifact(&fs, Path::new("/cache"), &key, file, &artifact).unwrap();
+        assert!(!fs.read_to_string(&artifact_path(Path::new("/cache"), &key)).unwrap().contains("/src/"));
+        assert_eq!(load_artifact(&fs, Path::new("/cache"), &key, file), Some(artifact.clone()));
+
+        // The same module checked out elsewhere gets its own path in the sites
+        let elsewhere = load_artifact(&fs, Path::new("/cache"), &key, Path::new("/ci/src/mylib/__init__.py")).unwrap();
+        let Segment::Import(import) = &elsewhere.segments[1] else { panic!() };
+        assert_eq!(import.site, "/ci/src/mylib/__init__.py:2");
+        assert_eq!(elsewhere.warnings[0].subject, "/ci/src/mylib/__init__.py");
+
+        fs.write(&artifact_path(Path::new("/cache"), &key), b"{\"segments\": [").unwrap();
+        assert_eq!(load_artifact(&fs, Path::new("/cache"), &key, file), None);
+    }
+
+    #[test]
+    fn test_clear_cache() {
+        let fs = VirtualFileSystem::new();
+        let key = cache_key("a.py", b"", "");
+        store_artifact(&fs, Path::new("/cache"), &key, Path::new("/src/a.py"), &ModuleArtifact { segments: Vec::new(), transformations: Vec::new(), warnings: Vec::new() }).unwrap();
+        fs.write(Path::new("/cache/notes.json"), b"{}").unwrap();
+        assert_eq!(clear_cache(&fs, Path::new("/cache")).unwrap(), 1);
+        assert!(fs.exists(Path::new("/cache/notes.json")).unwrap());
+
+        fs.remove_file(Path::new("/cache/notes.json")).unwrap();
+        store_artifact(&fs, Path::new("/cache"), &key, Path::new("/src/a.py"), &ModuleArtifact { segments: Vec::new(), transformations: Vec::new(), warnings: Vec::new() }).unwrap();
+        assert_eq!(clear_cache(&fs, Path::new("/cache")).unwrap(), 1);
+        assert!(!fs.exists(Path::new("/cache")).unwrap());
+    }
+}
@@ -0,0 +1,365 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+use regex::Regex;
+use structopt::StructOpt;
+use crate::modules::indentation::Reindent;
+use crate::modules::licenses::DistributionLicense;
+use crate::modules::line_endings::LineEnding;
+#[cfg(feature = "remote-cache")]
+use crate::modules::remote_cache::RemoteCache;
+use crate::modules::output_writer::OutputFormat;
+use crate::modules::report::{BundleReport, ImportEdge};
+use crate::modules::resolution::ResolutionChoices;
+use crate::modules::resolver::ResolutionCache;
+use crate::modules::sbom::{SbomComponent, SbomFormat};
+use crate::modules::size_report::{ByteSize, ModuleSize};
+use crate::modules::terminal::Terminal;
+use crate::modules::warnings::{WarningAggregator, WarningCategory};
+
+/// The options and arguments of a bundle: those of `inline`, `check` and `watch`, and of
+/// python-inliner without a subcommand
+#[derive(StructOpt, Debug, Default, Clone)]
+pub struct Opt {
+    #[structopt(parse(from_os_str))]
+    pub input_file: Option<PathBuf>,
+
+    #[structopt(parse(from_os_str))]
+    pub output_file: Option<PathBuf>,
+
+    #[structopt(help = "comma-separated list module names to be inlined", default_value = "")]
+    pub module_names: String,
+
+    #[structopt(parse(from_os_str), help = "More entry scripts, with --out-dir or --name-template")]
+    pub more_input_files: Vec<PathBuf>,
+
+    #[structopt(long, parse(from_os_str), help = "Bundle each entry script into this directory: the positional arguments are then `<entry>... [module-names]`")]
+    pub out_dir: Option<PathBuf>,
+
+    #[structopt(long, help = "File name of each bundle, with {stem} standing for the entry script's name without `.py` (default: {stem}.py, {stem}.pyz with --zipapp). Without --out-dir, bundles are written next to their entry scripts")]
+    pub name_template: Option<String>,
+
+    #[structopt(long, help = "Run the program by calling this function of the entry script from a single `if __name__ == \"__main__\":` block at the end, which its top-level calls of the function and `__main__` blocks move into, so exec'ing or importing the bundle runs nothing")]
+    pub entry_function: Option<String>,
+
+    #[structopt(long, parse(from_os_str), help = "Write the bundle into this file in place of its `# {{INLINED_CODE}}` line, indented as that line is, for a fixed prologue and epilogue around the code")]
+    pub template: Option<PathBuf>,
+
+    #[structopt(long, short = "r", help = "Suppress comments in the output, and consolidate imports", takes_value = false)]
+    pub release: bool,
+
+    #[structopt(long, short = "v", help = "Print verbose debug information", takes_value = false)]
+    pub verbose: bool,
+
+    #[structopt(long, help = "Inline the dependencies declared in the entry script's PEP 723 metadata block instead of treating them as external", takes_value = false)]
+    pub inline_script_deps: bool,
+
+    #[structopt(long, help = "Remove top-level functions, classes and constants of inlined modules that are never referenced", takes_value = false)]
+    pub tree_shake: bool,
+
+    #[structopt(long, help = "Remove top-level imports whose names are never referenced in the output", takes_value = false)]
+    pub remove_unused_imports: bool,
+
+    #[structopt(long, help = "With --remove-unused-imports, keep the imports of this module or its submodules, which are imported for their side effects (repeatable)", number_of_values = 1)]
+    pub keep_import: Vec<String>,
+
+    #[structopt(long, help = "Write a PEP 723 `# /// script` block listing the bundle's remaining external dependencies", takes_value = false)]
+    pub emit_script_metadata: bool,
+
+    #[structopt(long, help = "Python version specifier for --emit-script-metadata (defaults to the entry script's, then the interpreter's)")]
+    pub requires_python: Option<String>,
+
+    #[structopt(long, help = "Minify the output: strip docstrings, comments, blank lines and trailing whitespace", takes_value = false)]
+    pub minify: bool,
+
+    #[structopt(long, help = "Keep docstrings when minifying (for code that reads __doc__ at runtime)", takes_value = false)]
+    pub keep_docstrings: bool,
+
+    #[structopt(long, help = "Remove assert statements and `if __debug__:` blocks, which python -O skips", takes_value = false)]
+    pub strip_asserts: bool,
+
+    #[structopt(long, help = "Remove the statements starting with this code (e.g. `logger.debug`), with the block of one that opens a block (repeatable)", number_of_values = 1)]
+    pub strip_pattern: Vec<String>,
+
+    #[structopt(long, parse(try_from_str = parse_define), help = "Set a module-level constant, `NAME=VALUE` with VALUE a Python expression: the top-level assignments of NAME in the bundled files get the value instead (repeatable)", number_of_values = 1)]
+    pub define: Vec<(String, String)>,
+
+    #[structopt(long, help = "With --define, also remove the `if NAME:` blocks and `else` clauses the defined values rule out", takes_value = false)]
+    pub prune_defines: bool,
+
+    #[structopt(long, help = "Ask which file to use when a module resolves to several files or to none, and whether to inline each distribution from site-packages, remembering the answers", takes_value = false)]
+    pub interactive: bool,
+
+    #[structopt(long, help = "Embed each module's source with an import hook instead of inlining it textually, so modules keep their own namespaces, __name__ and __package__", takes_value = false)]
+    pub embed_modules: bool,
+
+    #[structopt(long, help = "Put each inlined module once at the top level, in dependency order, and replace imports with the names they bind", takes_value = false)]
+    pub topological: bool,
+
+    #[structopt(long, help = "Rename the top-level names that several inlined modules define, prefixing each with its module's name", takes_value = false)]
+    pub rename_collisions: bool,
+
+    #[structopt(long, help = "Rename every top-level name of the inlined modules to one unique to its module, e.g. _m_mypkg_utils__helper", takes_value = false)]
+    pub mangle_names: bool,
+
+    #[structopt(long, help = "Keep TYPE_CHECKING imports for type checkers and point string annotations at the inlined names", takes_value = false)]
+    pub rewrite_annotations: bool,
+
+    #[structopt(long, help = "Format the output with an external formatter (ruff format or black, see --formatter)", takes_value = false)]
+    pub format: bool,
+
+    #[structopt(long, help = "Formatter command for --format, reading source on stdin and writing it to stdout (e.g. \"black -q -\")")]
+    pub formatter: Option<String>,
+
+    #[structopt(long, help = "Time each inlined module's body when the bundle runs, reporting in `python -X importtime` format (see --profile-sink)", takes_value = false)]
+    pub profile_imports: bool,
+
+    #[structopt(long, help = "Where --profile-imports writes its timings: stderr (the default), stdout, or a file to append to")]
+    pub profile_sink: Option<String>,
+
+    #[structopt(long, help = "Line ending of the output: lf, crlf, or keep the entry script's", default_value = "keep", possible_values = &["lf", "crlf", "keep"])]
+    pub line_ending: LineEnding,
+
+    #[structopt(long, help = "Indentation of the output: spaces, tabs, or keep each file's", default_value = "keep", possible_values = &["spaces", "tabs", "keep"])]
+    pub reindent: Reindent,
+
+    #[structopt(long, help = "Write the output as an executable zipapp (.pyz) with the bundle as __main__.py, short for --output-format zipapp", takes_value = false)]
+    pub zipapp: bool,
+
+    #[structopt(long, help = "How to write the output: a single file, an executable zipapp, or the entry script with the modules copied into a _vendor package next to it, imported absolutely (vendor) or relatively, for a relocatable package (package)", default_value = "file", possible_values = &["file", "zipapp", "vendor", "package"])]
+    pub output_format: OutputFormat,
+
+    #[structopt(long, help = "Byte-compile the output with `python -m py_compile` and fail if it has a syntax error", takes_value = false)]
+    pub check_syntax: bool,
+
+    #[structopt(long, help = "When the output file exists, print a unified diff of the new bundle against it, and the modules added, removed or changed", takes_value = false)]
+    pub diff: bool,
+
+    #[structopt(long, help = "Don't write the output, and exit with an error if it would change (for pre-commit and CI freshness checks)", takes_value = false)]
+    pub check: bool,
+
+    #[structopt(long, help = "Fail without writing the output when the bundle is larger than this, e.g. 512K or 50M (powers of 1024)")]
+    pub max_size: Option<ByteSize>,
+
+    #[structopt(long, help = "Print how many bytes and lines each inlined module contributes to the bundle, largest first", takes_value = false)]
+    pub size_report: bool,
+
+    #[structopt(long, help = "Also write a .pyi stub of the bundle's public functions, classes and variables next to the output", takes_value = false)]
+    pub stub: bool,
+
+    #[structopt(long, help = "Don't check the bundle for hazards of inlining (rebound duplicate imports, changed multi-line strings, __file__ in inlined code, misplaced future imports, dynamic imports, name collisions)", takes_value = false)]
+    pub no_self_check: bool,
+
+    #[structopt(long, help = "Don't color the output or draw the progress bar and summary table, which are only shown on a terminal (NO_COLOR does the same)", takes_value = false)]
+    pub no_color: bool,
+
+    #[structopt(long, help = "Start the output with a comment naming the tool version and the SHA-256 of the entry script and of every inlined file", takes_value = false)]
+    pub stamp: bool,
+
+    #[structopt(long, help = "With --stamp, also stamp the build time: SOURCE_DATE_EPOCH if set, else the current time", takes_value = false)]
+    pub stamp_time: bool,
+
+    #[structopt(long, help = "Check the inlined third-party distributions for known vulnerabilities with pip-audit, failing if any are found", takes_value = false)]
+    pub audit: bool,
+
+    #[structopt(long, help = "Like --audit, but only warn about vulnerabilities", takes_value = false)]
+    pub audit_warn_only: bool,
+
+    #[structopt(long, help = "Exit with an error if nothing was inlined (e.g. a misspelled module list)", takes_value = false)]
+    pub expect_inlines: bool,
+
+    #[structopt(flatten)]
+    pub environment: EnvironmentArgs,
+
+    #[structopt(long, parse(try_from_str = parse_shim), help = "Inline the stub file in place of a module, `module=path/to/stub.py` (repeatable)", number_of_values = 1)]
+    pub shim: Vec<(String, PathBuf)>,
+
+    #[structopt(long, help = "Inline every module of this package up front, not just the ones imported, for packages that load their own modules with importlib (repeatable)", number_of_values = 1)]
+    pub inline_package: Vec<String>,
+
+    #[structopt(long, help = "Inline the modules matching this pattern up front (`plugins.*`, `*` matching any part of a dotted name) and register them in sys.modules, for imports by computed name (repeatable)", number_of_values = 1)]
+    pub dynamic_map: Vec<String>,
+
+    #[structopt(long, parse(from_os_str), help = "Write a JSON report of the run: the search path, and for each bundle the inlined files with their SHA-256, unresolved imports, skipped duplicates and timing")]
+    pub report: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str), help = "Write the installed distributions providing the bundle's remaining third-party imports to this file as pinned requirements")]
+    pub emit_requirements: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str), help = "Write the declared license and the license files of every inlined third-party distribution to this notices file")]
+    pub licenses: Option<PathBuf>,
+
+    #[structopt(long, help = "Put the third-party notices of the inlined distributions at the top of the bundle as a comment", takes_value = false)]
+    pub embed_licenses: bool,
+
+    #[structopt(long, parse(from_os_str), help = "Write a software bill of materials of the inlined third-party distributions to this file: name, version, license, package URL and the SHA-256 of each inlined file")]
+    pub sbom: Option<PathBuf>,
+
+    #[structopt(long, help = "Format of --sbom: cyclonedx (CycloneDX 1.5 JSON) or spdx (SPDX 2.3 JSON) [default: spdx for *.spdx.json, else cyclonedx]", possible_values = &["cyclonedx", "spdx"])]
+    pub sbom_format: Option<SbomFormat>,
+
+    #[structopt(long, parse(from_os_str), help = "Keep each module's processed source in this directory, keyed by its content hash and the options used, and reuse it on later runs while the module is unchanged")]
+    pub cache_dir: Option<PathBuf>,
+
+    #[cfg(feature = "remote-cache")]
+    #[structopt(long, help = "Share the processing of modules with other machines through this directory or http(s):// URL, keyed as in a --cache-dir; read after the --cache-dir and written along with it (default: $PYTHON_INLINER_REMOTE_CACHE)")]
+    pub remote_cache: Option<RemoteCache>,
+
+    /// What to do instead of bundling, for `env export` and `explain`
+    #[structopt(skip)]
+    pub query: Option<EnvironmentQuery>,
+
+    /// Top-level module names that must never be inlined (e.g. PEP 723 script dependencies)
+    #[structopt(skip)]
+    pub external_modules: Vec<String>,
+
+    /// Top-level definitions to drop from each inlined file, computed by the tree shaking pass
+    #[structopt(skip)]
+    pub unused_definitions: HashMap<PathBuf, HashSet<String>>,
+
+    /// Top-level names to give each inlined file's definitions, for --rename-collisions
+    #[structopt(skip)]
+    pub renamed_definitions: HashMap<PathBuf, BTreeMap<String, String>>,
+
+    /// Remembered answers to resolution prompts, shared by every pass of a run
+    #[structopt(skip)]
+    pub resolution_choices: Rc<RefCell<ResolutionChoices>>,
+
+    /// Warnings raised while inlining, reported once each at the end of the pass
+    #[structopt(skip)]
+    pub warnings: Arc<WarningAggregator>,
+
+    /// What went into each bundle of the run, for --report
+    #[structopt(skip)]
+    pub bundle_reports: Rc<RefCell<Vec<BundleReport>>>,
+
+    /// The transformations inline_imports() applied to each file, for --report
+    #[structopt(skip)]
+    pub transformations: Rc<RefCell<HashMap<PathBuf, BTreeSet<&'static str>>>>,
+
+    /// The third-party modules each bundle of the run still imports, for --emit-requirements
+    #[structopt(skip)]
+    pub remaining_imports: Rc<RefCell<Vec<RemainingImports>>>,
+
+    /// What each file inlined into the bundle adds to it, for --size-report and --max-size
+    #[structopt(skip)]
+    pub module_sizes: Rc<RefCell<HashMap<PathBuf, ModuleSize>>>,
+
+    /// The output files --check found out of date
+    #[structopt(skip)]
+    pub stale_outputs: Rc<RefCell<Vec<PathBuf>>>,
+
+    /// The licenses of the distributions each bundle of the run inlines code from, for --licenses
+    #[structopt(skip)]
+    pub inlined_licenses: Rc<RefCell<Vec<BundleLicenses>>>,
+
+    /// The distributions each bundle of the run inlines code from, for --sbom
+    #[structopt(skip)]
+    pub sbom_components: Rc<RefCell<Vec<BundleComponents>>>,
+
+    /// The imports between the files of the bundle, for --report
+    #[structopt(skip)]
+    pub imports: Rc<RefCell<BTreeSet<ImportEdge>>>,
+
+    /// Files first inlined into an indented block, whose names are only bound in that scope
+    #[structopt(skip)]
+    pub indented_inlines: Rc<RefCell<HashSet<PathBuf>>>,
+
+    /// Colors and the progress line, when the run is shown on a terminal
+    #[structopt(skip)]
+    pub terminal: Terminal,
+
+    /// The entry scripts and the files inlined into them, which `watch` watches
+    #[structopt(skip)]
+    pub inlined_files: Rc<RefCell<BTreeSet<PathBuf>>>,
+
+    /// The module names looked up so far, shared by every pass of a run
+    #[structopt(skip)]
+    pub resolution_cache: Rc<ResolutionCache>,
+
+    /// Print neither warnings nor where the bundle went, for inline_from_sources()
+    #[structopt(skip)]
+    pub quiet: bool,
+
+    #[structopt(long, help = "Don't report warnings with this code (e.g. PI0101), repeatable or comma-separated", number_of_values = 1, use_delimiter = true)]
+    pub allow: Vec<WarningCategory>,
+}
+
+// How to find the environment modules resolve against, shared by bundles, `explain` and
+// `env export`. Not a doc comment, which would become the about text of the subcommands it's
+// flattened into.
+#[derive(StructOpt, Debug, Default, Clone)]
+pub struct EnvironmentArgs {
+    #[structopt(long, help = "The interpreter whose sys.path to resolve modules against and to run --check-syntax with, e.g. a virtualenv's python or `py -3.11` (default: $PYTHON_INLINER_PYTHON, else python3)")]
+    pub python: Option<String>,
+
+    #[structopt(long, parse(from_os_str), help = "Directories to search before PYTHONPATH and the interpreter's path, `:`-separated (`;` on Windows)")]
+    pub pythonpath: Option<OsString>,
+
+    #[structopt(long, parse(from_os_str), help = "A directory to add to the search path, as a sitecustomize or usercustomize hook would (repeatable)", number_of_values = 1)]
+    pub extra_path: Vec<PathBuf>,
+
+    #[structopt(long, parse(from_os_str), help = "Resolve modules against an environment snapshot from `env export` instead of querying Python")]
+    pub env: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str), help = "Resolve modules against the site-packages of this virtualenv or conda environment, read from its pyvenv.cfg and directory layout instead of querying Python")]
+    pub venv: Option<PathBuf>,
+
+    #[structopt(long, help = "With --env, add the user site-packages directory (site.USER_SITE) the snapshot's interpreter version would use", takes_value = false)]
+    pub user_site: bool,
+
+    #[structopt(long, help = "Don't run Python: search only the --pythonpath, PYTHONPATH and --extra-path directories, for hermetic builds", takes_value = false)]
+    pub offline: bool,
+
+    #[structopt(long, help = "Make the same bytes on any machine, for container layer caching and attestation: like --offline, but PYTHONPATH is ignored too, --stamp-time stamps the epoch and --report times nothing. Searches only --pythonpath, --extra-path, --env or --venv", takes_value = false)]
+    pub deterministic: bool,
+}
+
+/// What a run resolving the environment does instead of bundling
+#[derive(Debug, Clone)]
+pub enum EnvironmentQuery {
+    /// Write the environment to a snapshot file
+    Export(PathBuf),
+    /// Show how the module name resolves
+    Explain(String),
+}
+
+/// A bundle and the third-party modules it still imports
+pub type RemainingImports = (PathBuf, Vec<String>);
+
+/// A bundle and the licenses of the distributions it inlines code from
+pub type BundleLicenses = (PathBuf, Vec<DistributionLicense>);
+
+/// A bundle and the distributions it inlines code from, with their inlined files
+pub type BundleComponents = (PathBuf, Vec<SbomComponent>);
+
+fn parse_shim(value: &str) -> Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((module, stub)) if !module.is_empty() && !stub.is_empty() => Ok((module.to_string(), PathBuf::from(stub))),
+        _ => Err(format!("expected module=path/to/stub.py, got {:?}", value)),
+    }
+}
+
+fn parse_define(value: &str) -> Result<(String, String), String> {
+    let identifier = Regex::new(r"^[A-Za-z_]\w*$").unwrap();
+    match value.split_once('=') {
+        Some((name, value)) if identifier.is_match(name.trim()) && !value.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("expected NAME=VALUE, got {:?}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_define_and_shim() {
+        assert!(parse_define("DEBUG=False").is_ok() && parse_define("LEVEL = 'a=b'").unwrap().1 == "'a=b'");
+        assert!(parse_define("DEBUG").is_err() && parse_define("my.DEBUG=1").is_err() && parse_define("DEBUG=").is_err());
+        assert_eq!(parse_shim("mylib.native=stubs/native.py").unwrap(), ("mylib.native".to_string(), PathBuf::from("stubs/native.py")));
+        assert!(parse_shim("mylib.native").is_err());
+    }
+}
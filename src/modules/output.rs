@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use crate::modules::bundle_diff::{module_sections, render_report};
+use crate::modules::embedded_modules::EmbeddedModule;
+use crate::modules::errors::InlinerError;
+use crate::modules::file_system::FileSystem;
+use crate::modules::indentation::Reindent;
+use crate::modules::line_endings::{detect_line_ending, LineEnding};
+use crate::modules::options::Opt;
+use crate::modules::output_writer::{OutputFile, OutputFormat, OutputWriter, SingleFileWriter, VendorWriter};
+#[cfg(feature = "zipapp")]
+use crate::modules::zipapp::ZipappWriter;
+use crate::modules::size_report::{render_size_report, ByteSize, ModuleSize};
+use crate::modules::text_diff::unified_diff;
+use crate::search_path_relative;
+
+/// The --output-format of the run, which --zipapp is short for
+pub fn output_format(opt: &Opt) -> OutputFormat {
+    if opt.zipapp { OutputFormat::Zipapp } else { opt.output_format }
+}
+
+/// Refuse the options that transform the single-file bundle, which vendored output doesn't write
+pub fn check_vendor_options(opt: &Opt) -> Result<(), String> {
+    if opt.zipapp && opt.output_format != OutputFormat::File {
+        return Err("--zipapp is short for --output-format zipapp, it can't be combined with another --output-format".to_string());
+    }
+    let format = match output_format(opt) {
+        OutputFormat::Vendor => "vendor",
+        OutputFormat::Package => "package",
+        _ => return Ok(()),
+    };
+    let bundle_options = [
+        (opt.embed_modules, "--embed-modules"),
+        (opt.topological, "--topological"),
+        (opt.release, "--release"),
+        (opt.minify, "--minify"),
+        (opt.strip_asserts || !opt.strip_pattern.is_empty(), "--strip-asserts and --strip-pattern"),
+        (!opt.define.is_empty(), "--define"),
+        (opt.entry_function.is_some(), "--entry-function"),
+        (opt.template.is_some(), "--template"),
+        (opt.tree_shake, "--tree-shake"),
+        (opt.remove_unused_imports, "--remove-unused-imports"),
+        (opt.rename_collisions, "--rename-collisions"),
+        (opt.mangle_names, "--mangle-names"),
+        (opt.rewrite_annotations, "--rewrite-annotations"),
+        (opt.format || opt.formatter.is_some(), "--format"),
+        (opt.profile_imports || opt.profile_sink.is_some(), "--profile-imports"),
+        (opt.emit_script_metadata, "--emit-script-metadata"),
+        (opt.embed_licenses, "--embed-licenses"),
+        (opt.stamp, "--stamp"),
+        (opt.stub, "--stub"),
+        (opt.size_report || opt.max_size.is_some(), "--size-report and --max-size"),
+        (opt.reindent != Reindent::Keep, "--reindent"),
+    ];
+    let used: Vec<&str> = bundle_options.iter().filter(|(used, _)| *used).map(|(_, option)| *option).collect();
+    if !used.is_empty() {
+        return Err(format!("--output-format {} copies the modules as they are, so it can't be combined with {}", format, used.join(", ")));
+    }
+    Ok(())
+}
+
+/// The writer of the run's --output-format, the vendoring ones' with the modules collected.
+/// Package output makes the output file's directory a package when it isn't one.
+fn output_writer(fs: &dyn FileSystem, output_file: &Path, opt: &Opt, vendored: BTreeMap<String, EmbeddedModule>, entry_source: &str) -> Result<Box<dyn OutputWriter>, InlinerError> {
+    let format = output_format(opt);
+    Ok(match format {
+        OutputFormat::File => Box::new(SingleFileWriter),
+        #[cfg(feature = "zipapp")]
+        OutputFormat::Zipapp => Box::new(ZipappWriter),
+        #[cfg(not(feature = "zipapp"))]
+        OutputFormat::Zipapp => unreachable!("check_features() refuses --output-format zipapp without the zipapp feature"),
+        OutputFormat::Vendor | OutputFormat::Package => {
+            let line_ending = if opt.line_ending == LineEnding::Keep { detect_line_ending(entry_source) } else { opt.line_ending };
+            let relative = format == OutputFormat::Package;
+            let init_path = output_file.with_file_name("__init__.py");
+            let package_init = relative && !fs.exists(&init_path).map_err(|e| InlinerError::io(&init_path, e))?;
+            Box::new(VendorWriter { modules: vendored, line_ending, relative, package_init })
+        }
+    })
+}
+
+/// Write the files the run's --output-format makes of the bundle, printing where they went.
+/// --diff first prints how they differ from the files on disk, and --check writes nothing,
+/// recording an out-of-date output instead. Returns the files written, None with --check.
+pub fn write_output(fs: &dyn FileSystem, output_file: &Path, content: &str, vendored: BTreeMap<String, EmbeddedModule>, entry_source: &str, opt: &Opt) -> Result<Option<Vec<OutputFile>>, InlinerError> {
+    let writer = output_writer(fs, output_file, opt, vendored, entry_source)?;
+    let files = writer.render(output_file, content);
+    if opt.diff || opt.check {
+        let changed = show_output_changes(fs, &files, opt)?;
+        if opt.check {
+            match (changed, fs.exists(output_file).map_err(|e| InlinerError::io(output_file, e))?) {
+                (false, _) => println!("{:?} is up to date", output_file),
+                (true, true) => println!("{:?} is out of date", output_file),
+                (true, false) => println!("{:?} doesn't exist yet", output_file),
+            }
+            if changed {
+                opt.stale_outputs.borrow_mut().push(output_file.to_path_buf());
+            }
+            return Ok(None);
+        }
+    }
+    for file in &files {
+        if let Some(dir) = file.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs.mkdir_p(dir).map_err(|e| InlinerError::io(dir, e))?;
+        }
+        fs.write(&file.path, &file.contents).map_err(|e| InlinerError::io(&file.path, e))?;
+        if file.executable {
+            fs.set_executable(&file.path).map_err(|e| InlinerError::io(&file.path, e))?;
+        }
+    }
+    if !opt.quiet {
+        println!("{}", writer.summary(output_file));
+    }
+    Ok(Some(files))
+}
+
+/// Whether any of the files to write differs from what's on disk, printing for --diff how: a
+/// unified diff of each text file, after the modules added, removed or changed when both
+/// versions have markers
+pub fn show_output_changes(fs: &dyn FileSystem, files: &[OutputFile], opt: &Opt) -> Result<bool, InlinerError> {
+    let mut changed = false;
+    for file in files {
+        if !fs.exists(&file.path).map_err(|e| InlinerError::io(&file.path, e))? {
+            changed = true;
+            continue;
+        }
+        let existing = fs.read(&file.path).map_err(|e| InlinerError::io(&file.path, e))?;
+        if existing == file.contents {
+            continue;
+        }
+        changed = true;
+        if !opt.diff {
+            continue;
+        }
+        if !file.text {
+            println!("{:?} changed ({} -> {} bytes)", file.path, existing.len(), file.contents.len());
+            continue;
+        }
+        let (existing, content) = (String::from_utf8_lossy(&existing), String::from_utf8_lossy(&file.contents));
+        if let (Some(old_sections), Some(new_sections)) = (module_sections(&existing), module_sections(&content)) {
+            print!("{}", render_report(&old_sections, &new_sections));
+        }
+        let label = file.path.display();
+        print!("{}", unified_diff(&existing, &content, &format!("a/{}", label), &format!("b/{}", label), 3));
+    }
+    Ok(changed)
+}
+
+/// Print the --size-report of the bundle, and fail when it's over --max-size
+pub fn check_size(opt: &Opt, python_sys_path: &[PathBuf], output_file: &Path, content: &str) -> Result<(), InlinerError> {
+    let modules: Vec<(String, ModuleSize)> = opt.module_sizes.borrow().iter()
+        .map(|(path, size)| (search_path_relative(path, python_sys_path), size.clone()))
+        .collect();
+    let report = render_size_report(output_file, content.len(), content.lines().count(), &modules);
+    let over_budget = opt.max_size.filter(|max_size| content.len() as u64 > max_size.0);
+    if opt.size_report || over_budget.is_some() {
+        print!("{}", report);
+    }
+    match over_budget {
+        Some(max_size) => Err(InlinerError::check(format!("{} is {}, over the --max-size of {}, output not written", output_file.display(), ByteSize(content.len() as u64), max_size))),
+        None => Ok(()),
+    }
+}
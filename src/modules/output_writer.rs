@@ -0,0 +1,301 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use regex::Regex;
+use crate::modules::embedded_modules::EmbeddedModule;
+use crate::modules::line_endings::{apply_line_ending, LineEnding};
+use crate::modules::source_analysis::mask_strings_and_comments;
+
+/// The package vendor mode copies the modules into, next to the entry script
+pub const VENDOR_PACKAGE: &str = "_vendor";
+
+/// How the bundle is written, for --output-format
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    /// A single Python file
+    #[default]
+    File,
+    /// An executable zip archive with the bundle as `__main__.py`
+    Zipapp,
+    /// The entry script, with the modules it would inline copied into a `_vendor` package
+    Vendor,
+    /// Like Vendor, for an entry module of a package: every import of a vendored module is
+    /// relative, so the package can be moved or renamed
+    Package,
+}
+
+impl OutputFormat {
+    /// Whether the format copies the modules instead of inlining them
+    pub fn vendors(&self) -> bool {
+        matches!(self, OutputFormat::Vendor | OutputFormat::Package)
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<OutputFormat, String> {
+        match value {
+            "file" => Ok(OutputFormat::File),
+            "zipapp" => Ok(OutputFormat::Zipapp),
+            "vendor" => Ok(OutputFormat::Vendor),
+            "package" => Ok(OutputFormat::Package),
+            _ => Err(format!("unknown output format {:?}, expected file, zipapp, vendor or package", value)),
+        }
+    }
+}
+
+/// A file the output stage writes
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputFile {
+    pub path: PathBuf,
+    pub contents: Vec<u8>,
+    pub executable: bool,
+    /// Whether the contents are text that --diff can show line by line
+    pub text: bool,
+}
+
+impl OutputFile {
+    fn text(path: PathBuf, contents: String) -> OutputFile {
+        OutputFile { path, contents: contents.into_bytes(), executable: false, text: true }
+    }
+}
+
+/// Turns the finished bundle into the files written for it
+pub trait OutputWriter {
+    /// The files to write for the bundle, the output file first
+    fn render(&self, output_file: &Path, content: &str) -> Vec<OutputFile>;
+
+    /// The message printed once the files are written
+    fn summary(&self, output_file: &Path) -> String;
+}
+
+/// Writes the bundle as it is
+pub struct SingleFileWriter;
+
+impl OutputWriter for SingleFileWriter {
+    fn render(&self, output_file: &Path, content: &str) -> Vec<OutputFile> {
+        vec![OutputFile::text(output_file.to_path_buf(), content.to_string())]
+    }
+
+    fn summary(&self, output_file: &Path) -> String {
+        format!("Inlined content written to {:?}", output_file)
+    }
+}
+
+/// Writes the entry script in place of the bundle, and a copy of every module it would inline
+/// under the `_vendor` package next to it, collected as --embed-modules collects them, with
+/// their imports of each other pointed there. The content it's given is the entry script.
+pub struct VendorWriter {
+    /// The modules by absolute name, namespace packages with an empty source
+    pub modules: BTreeMap<String, EmbeddedModule>,
+    pub line_ending: LineEnding,
+    /// Whether the imports of vendored modules are relative, for --output-format package
+    pub relative: bool,
+    /// Whether to write an empty `__init__.py` next to the entry, which makes the directory a
+    /// package when it isn't one yet
+    pub package_init: bool,
+}
+
+impl OutputWriter for VendorWriter {
+    fn render(&self, output_file: &Path, content: &str) -> Vec<OutputFile> {
+        let vendored: BTreeSet<String> = self.modules.keys()
+            .map(|name| name.split('.').next().unwrap_or(name).to_string())
+            .collect();
+        let vendor_dir = output_file.parent().unwrap_or(Path::new("")).join(VENDOR_PACKAGE);
+        let absolute = format!("{}.", VENDOR_PACKAGE);
+        let entry_reference = if self.relative { format!(".{}", absolute) } else { absolute.clone() };
+        let entry = vendor_imports(content, &vendored, true, &entry_reference);
+        let mut files = vec![OutputFile::text(output_file.to_path_buf(), apply_line_ending(&entry, self.line_ending, ""))];
+        if self.package_init {
+            files.push(OutputFile::text(output_file.with_file_name("__init__.py"), String::new()));
+        }
+        files.push(OutputFile::text(vendor_dir.join("__init__.py"), String::new()));
+        for (name, module) in &self.modules {
+            let path = name.split('.').fold(vendor_dir.clone(), |path, part| path.join(part));
+            let path = if module.is_package { path.join("__init__.py") } else { path.with_extension("py") };
+            // A dot for the module's package, and one more for each package up to `_vendor`
+            let reference = match self.relative {
+                true => ".".repeat(name.split('.').count() + module.is_package as usize),
+                false => absolute.clone(),
+            };
+            let source = vendor_imports(&module.source, &vendored, false, &reference);
+            files.push(OutputFile::text(path, apply_line_ending(&source, self.line_ending, "")));
+        }
+        files
+    }
+
+    fn summary(&self, output_file: &Path) -> String {
+        let vendor_dir = output_file.parent().unwrap_or(Path::new("")).join(VENDOR_PACKAGE);
+        format!("Entry script written to {:?}, with {} modules vendored in {:?}", output_file, self.modules.len(), vendor_dir)
+    }
+}
+
+/// The package a reference to the vendored modules names: `_vendor` itself, or the package
+/// its dots lead to
+fn reference_package(reference: &str) -> &str {
+    reference.strip_suffix('.').filter(|package| !package.ends_with('.')).unwrap_or(reference)
+}
+
+/// An item of an `import` statement with its module moved into the vendor package, binding the
+/// same name. Relative to an absolute `_vendor.`, `a` becomes `_vendor.a as a`, and `a.b`,
+/// which binds `a`, imports the submodule and binds its vendored top-level package. `import`
+/// can't be relative, so relative to dots the item becomes a statement of its own: a from-import,
+/// or for `a.b` a call of `__import__()`, which returns the package its name starts with.
+fn vendored_import_item(module: &str, alias: Option<&str>, reference: &str) -> String {
+    let top = module.split('.').next().unwrap_or(module);
+    if !reference.starts_with('.') {
+        return match alias {
+            Some(alias) => format!("{}{} as {}", reference, module, alias),
+            None if module == top => format!("{}{} as {}", reference, module, module),
+            None => format!("{}{}, {}{} as {}", reference, module, reference, top, top),
+        };
+    }
+    let alias_suffix = alias.map(|alias| format!(" as {}", alias)).unwrap_or_default();
+    match (module.rsplit_once('.'), alias) {
+        (None, _) => format!("from {} import {}{}", reference_package(reference), module, alias_suffix),
+        (Some((parent, name)), Some(_)) => format!("from {}{} import {}{}", reference, parent, name, alias_suffix),
+        (Some(_), None) => {
+            let level = reference.len() - reference.trim_start_matches('.').len();
+            let name = format!("{}{}", &reference[level..], module);
+            // Relative to the package above `_vendor`, the package returned is `_vendor`
+            let attribute = if level < reference.len() { format!(".{}", top) } else { String::new() };
+            format!("{} = __import__({:?}, globals(), level={}){}", top, name, level, attribute)
+        }
+    }
+}
+
+/// The source with its imports of the vendored top-level modules pointed into the vendor
+/// package through `reference`, which a vendored module's name is appended to: `_vendor.`, or
+/// relative to the file, `._vendor.` or just the dots up to `_vendor`. Relative imports are
+/// left alone, except in the entry script, where a single dot stands for its directory.
+pub fn vendor_imports(content: &str, vendored: &BTreeSet<String>, is_entry: bool, reference: &str) -> String {
+    let masked = mask_strings_and_comments(content);
+    let from_regex = Regex::new(r"(?m)^[ \t]*from[ \t]+((\.*)([A-Za-z_][\w.]*))[ \t]+import\b").unwrap();
+    let import_regex = Regex::new(r"(?m)^[ \t]*(import[ \t]+([^\n;]+))").unwrap();
+    let item_regex = Regex::new(r"^([A-Za-z_][\w.]*)(?:\s+as\s+(\w+))?$").unwrap();
+    let is_vendored = |module: &str| vendored.contains(module.split('.').next().unwrap_or(module));
+    let relative = reference.starts_with('.');
+
+    // (start, end, replacement) of each rewritten span, in order
+    let mut replacements: Vec<(usize, usize, String)> = Vec::new();
+    for cap in from_regex.captures_iter(&masked) {
+        let (dots, module) = (&cap[2], &cap[3]);
+        if is_vendored(module) && (dots.is_empty() || (is_entry && dots == ".")) {
+            let span = cap.get(1).unwrap();
+            replacements.push((span.start(), span.end(), format!("{}{}", reference, module)));
+        }
+    }
+    for cap in import_regex.captures_iter(&masked) {
+        let (statement, list) = (cap.get(1).unwrap(), cap.get(2).unwrap());
+        let list_end = list.start() + list.as_str().trim_end().len();
+        let items: Option<Vec<(&str, Option<&str>)>> = masked[list.start()..list_end].split(',')
+            .map(|item| item_regex.captures(item.trim()).map(|item| (item.get(1).unwrap().as_str(), item.get(2).map(|alias| alias.as_str()))))
+            .collect();
+        // Continued lines and the like are left for Python to report
+        let Some(items) = items else { continue };
+        if !items.iter().any(|(module, _)| is_vendored(module)) {
+            continue;
+        }
+        let rendered: Vec<String> = items.into_iter()
+            .map(|(module, alias)| {
+                if is_vendored(module) {
+                    return vendored_import_item(module, alias, reference);
+                }
+                let item = alias.map_or(module.to_string(), |alias| format!("{} as {}", module, alias));
+                if relative { format!("import {}", item) } else { item }
+            })
+            .collect();
+        let replacement = if relative { rendered.join("; ") } else { format!("import {}", rendered.join(", ")) };
+        replacements.push((statement.start(), list_end, replacement));
+    }
+    replacements.sort_by_key(|(start, _, _)| *start);
+
+    let mut rewritten = String::with_capacity(content.len());
+    let mut last_end = 0;
+    for (start, end, replacement) in replacements {
+        rewritten.push_str(&content[last_end..start]);
+        rewritten.push_str(&replacement);
+        last_end = end;
+    }
+    rewritten.push_str(&content[last_end..]);
+    rewritten
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vendor_imports() {
+        let vendored: BTreeSet<String> = ["mylib".to_string(), "helper".to_string()].into();
+        let source = "\
+import os, mylib.utils
+import helper as h
+from mylib.models import (
+    Model,
+)
+from .mylib import run
+from ..helper import x
+from mylibrary import y
+
+def main():
+    import mylib  # the package
+    s = 'import mylib'
+";
+        assert_eq!(vendor_imports(source, &vendored, true, "_vendor."), "\
+import os, _vendor.mylib.utils, _vendor.mylib as mylib
+import _vendor.helper as h
+from _vendor.mylib.models import (
+    Model,
+)
+from _vendor.mylib import run
+from ..helper import x
+from mylibrary import y
+
+def main():
+    import _vendor.mylib as mylib  # the package
+    s = 'import mylib'
+");
+        assert_eq!(vendor_imports("from .mylib import run\n", &vendored, false, "_vendor."), "from .mylib import run\n");
+        assert_eq!(vendor_imports(source, &vendored, true, "._vendor."), "\
+import os; mylib = __import__(\"_vendor.mylib.utils\", globals(), level=1).mylib
+from ._vendor import helper as h
+from ._vendor.mylib.models import (
+    Model,
+)
+from ._vendor.mylib import run
+from ..helper import x
+from mylibrary import y
+
+def main():
+    from ._vendor import mylib  # the package
+    s = 'import mylib'
+");
+        assert_eq!(vendor_imports("import mylib.utils as u, mylib.cli\n", &vendored, false, ".."), "from ..mylib import utils as u; mylib = __import__(\"mylib.cli\", globals(), level=2)\n");
+    }
+
+    #[test]
+    fn test_vendor_writer() {
+        let writer = VendorWriter {
+            modules: BTreeMap::from([
+                ("mylib".to_string(), EmbeddedModule { is_package: true, source: "from mylib.cli import run\n".to_string() }),
+                ("mylib.cli".to_string(), EmbeddedModule { is_package: false, source: "from . import util\n".to_string() }),
+            ]),
+            line_ending: LineEnding::Crlf,
+            relative: false,
+            package_init: false,
+        };
+        let files: Vec<(PathBuf, String)> = writer.render(Path::new("dist/app.py"), "from mylib import run\nrun()\n").into_iter()
+            .map(|file| (file.path, String::from_utf8(file.contents).unwrap()))
+            .collect();
+        assert_eq!(files, vec![
+            (PathBuf::from("dist/app.py"), "from _vendor.mylib import run\r\nrun()\r\n".to_string()),
+            (PathBuf::from("dist/_vendor/__init__.py"), String::new()),
+            (PathBuf::from("dist/_vendor/mylib/__init__.py"), "from _vendor.mylib.cli import run\r\n".to_string()),
+            (PathBuf::from("dist/_vendor/mylib/cli.py"), "from . import util\r\n".to_string()),
+        ]);
+        assert_eq!("vendor".parse::<OutputFormat>(), Ok(OutputFormat::Vendor));
+        assert!("tarball".parse::<OutputFormat>().is_err());
+    }
+}
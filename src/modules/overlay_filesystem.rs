@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::io;
+use crate::modules::file_system::{copy_contents, CopyStrategy, FileSystem};
+
+/// Reads through to the wrapped file system, except for the paths given overrides, which read
+/// as the override's contents whether or not a file is there. Overridden paths are read-only,
+/// so a bundle can't be written over a stub. Used by --shim to substitute modules.
+pub struct OverlayFileSystem<FS: FileSystem> {
+    inner: FS,
+    overrides: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl<FS: FileSystem> OverlayFileSystem<FS> {
+    pub fn new(inner: FS) -> Self {
+        OverlayFileSystem {
+            inner,
+            overrides: BTreeMap::new(),
+        }
+    }
+
+    /// Serve `contents` at `path`. The path should be absolute and normalized, as module
+    /// resolution yields them; the directory it's in has to exist in the wrapped file system.
+    pub fn add_override(&mut self, path: PathBuf, contents: Vec<u8>) {
+        self.overrides.insert(path, contents);
+    }
+
+    fn read_only_error(path: &Path) -> io::Error {
+        io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is overridden by a shim", path.display()))
+    }
+}
+
+impl<FS: FileSystem> FileSystem for OverlayFileSystem<FS> {
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.inner.canonicalize(path) {
+            Err(_) if self.overrides.contains_key(path) => Ok(path.to_path_buf()),
+            result => result,
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        match self.overrides.contains_key(path) {
+            true => Err(Self::read_only_error(path)),
+            false => self.inner.write(path, contents),
+        }
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        match self.overrides.get(path) {
+            Some(contents) => String::from_utf8(contents.clone()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => self.inner.read_to_string(path),
+        }
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.overrides.get(path) {
+            Some(contents) => Ok(contents.clone()),
+            None => self.inner.read(path),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut entries = self.inner.read_dir(path)?;
+        entries.extend(self.overrides.keys().filter(|overridden| overridden.parent() == Some(path)).cloned());
+        entries.sort();
+        entries.dedup();
+        Ok(entries)
+    }
+
+    fn mkdir_p(&self, path: &Path) -> io::Result<()> {
+        self.inner.mkdir_p(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        match self.overrides.contains_key(path) {
+            true => Err(Self::read_only_error(path)),
+            false => self.inner.remove_file(path),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> io::Result<bool> {
+        match self.overrides.contains_key(path) {
+            true => Ok(true),
+            false => self.inner.is_file(path),
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> io::Result<bool> {
+        match self.overrides.contains_key(path) {
+            true => Ok(false),
+            false => self.inner.is_dir(path),
+        }
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        match self.overrides.contains_key(path) {
+            true => Ok(true),
+            false => self.inner.exists(path),
+        }
+    }
+
+    fn is_symlink(&self, path: &Path) -> io::Result<bool> {
+        match self.overrides.contains_key(path) {
+            true => Ok(false),
+            false => self.inner.is_symlink(path),
+        }
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.overrides.contains_key(path) {
+            true => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is overridden by a shim, not a symbolic link", path.display()))),
+            false => self.inner.read_link(path),
+        }
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path, strategy: CopyStrategy) -> io::Result<bool> {
+        if !self.overrides.contains_key(from) && !self.overrides.contains_key(to) {
+            return self.inner.copy_file(from, to, strategy);
+        }
+        copy_contents(self, from, to)
+    }
+
+    fn set_executable(&self, path: &Path) -> io::Result<()> {
+        match self.overrides.contains_key(path) {
+            true => Err(Self::read_only_error(path)),
+            false => self.inner.set_executable(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+
+    #[test]
+    fn test_overlay_filesystem() {
+        let inner = VirtualFileSystem::new();
+        inner.mkdir_p(Path::new("/lib/mylib")).unwrap();
+        inner.write(Path::new("/lib/mylib/native.py"), b"import _native\n").unwrap();
+        inner.write(Path::new("/lib/mylib/utils.py"), b"X = 1\n").unwrap();
+
+        let mut fs = OverlayFileSystem::new(inner);
+        fs.add_override(PathBuf::from("/lib/mylib/native.py"), b"def speedup(): pass\n".to_vec());
+        fs.add_override(PathBuf::from("/lib/mylib/extra.py"), b"Y = 2\n".to_vec());
+
+        assert_eq!(fs.read_to_string(Path::new("/lib/mylib/native.py")).unwrap(), "def speedup(): pass\n");
+        assert_eq!(fs.read_to_string(Path::new("/lib/mylib/utils.py")).unwrap(), "X = 1\n");
+        assert!(fs.is_file(Path::new("/lib/mylib/extra.py")).unwrap());
+        assert_eq!(fs.canonicalize(Path::new("/lib/mylib/extra.py")).unwrap(), PathBuf::from("/lib/mylib/extra.py"));
+        assert_eq!(fs.read_dir(Path::new("/lib/mylib")).unwrap(), vec![PathBuf::from("/lib/mylib/extra.py"), PathBuf::from("/lib/mylib/native.py"), PathBuf::from("/lib/mylib/utils.py")]);
+        assert_eq!(fs.write(Path::new("/lib/mylib/native.py"), b"").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        // Paths without an override are written through
+        fs.write(Path::new("/lib/mylib/utils.py"), b"X = 3\n").unwrap();
+        assert_eq!(fs.read_to_string(Path::new("/lib/mylib/utils.py")).unwrap(), "X = 3\n");
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use crate::modules::errors::InlinerError;
+
+/// Formatters tried in order when --format is given without --formatter
+pub const DEFAULT_FORMATTERS: &[&str] = &["ruff format -", "black -q -"];
+
+/// Pipe `content` through an external command that reads source on stdin and writes the result
+/// to stdout. The command line is split on whitespace; there is no shell quoting.
+pub fn run_external_hook(command: &str, content: &str) -> io::Result<String> {
+    let mut words = command.split_whitespace();
+    let program = words.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+    let mut child = Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Feed stdin from another thread so a command that writes before it has read everything
+    // can't deadlock against us
+    let mut stdin = child.stdin.take().unwrap();
+    let input = content.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let mut output = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut output)?;
+    let mut errors = String::new();
+    child.stderr.take().unwrap().read_to_string(&mut errors)?;
+    let status = child.wait()?;
+    // A command that exits without reading all of its input breaks the pipe; its status says why
+    let written = writer.join().unwrap();
+
+    if !status.success() {
+        return Err(io::Error::other(format!("`{}` failed with {}:\n{}", command, status, errors.trim_end())));
+    }
+    written?;
+    Ok(output)
+}
+
+/// Format the bundle with `command`, or with the first of DEFAULT_FORMATTERS that is installed
+pub fn format_source(command: Option<&str>, content: &str) -> Result<String, InlinerError> {
+    let failed = |command: &str, e: io::Error| InlinerError::Tool { tool: command.to_string(), reason: e.to_string() };
+    if let Some(command) = command {
+        return run_external_hook(command, content).map_err(|e| failed(command, e));
+    }
+    for command in DEFAULT_FORMATTERS {
+        match run_external_hook(command, content) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            result => return result.map_err(|e| failed(command, e)),
+        }
+    }
+    Err(InlinerError::Tool {
+        tool: "formatter".to_string(),
+        reason: format!("No formatter found (tried {}), install one or pass --formatter", DEFAULT_FORMATTERS.join(", ")),
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_external_hook() {
+        assert_eq!(run_external_hook("tr a-z A-Z", "x = 1\n").unwrap(), "X = 1\n");
+        let error = run_external_hook("false", "x = 1\n").unwrap_err();
+        assert!(error.to_string().starts_with("`false` failed with exit status: 1"), "{}", error);
+        assert_eq!(run_external_hook("python-inliner-no-such-formatter -", "").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(format_source(Some("cat"), "y = 2\n").unwrap(), "y = 2\n");
+    }
+}
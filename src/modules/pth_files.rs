@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use crate::modules::file_system::FileSystem;
+
+/// The directories the `.pth` files of a site-packages directory put on sys.path, in the order
+/// the site module adds them: files by name, then line by line, each path relative to the site
+/// directory and kept only if it's a directory. Lines starting with `import` are code the site
+/// module runs; the only code understood is the finder setuptools installs for editable
+/// installs, whose mapped package directories are searched through their parent directories.
+pub fn pth_directories(fs: &dyn FileSystem, site_dir: &Path) -> Vec<PathBuf> {
+    let Ok(mut entries) = fs.read_dir(site_dir) else {
+        return Vec::new();
+    };
+    entries.sort();
+    let finder_import = Regex::new(r"^import\s+(__editable___\w+_finder)\b").unwrap();
+
+    let mut directories: Vec<PathBuf> = Vec::new();
+    let mut add = |directory: PathBuf| {
+        if matches!(fs.is_dir(&directory), Ok(true)) && !directories.contains(&directory) {
+            directories.push(directory);
+        }
+    };
+    for pth_file in entries.iter().filter(|entry| is_pth_file(entry)) {
+        let Ok(content) = fs.read_to_string(pth_file) else { continue };
+        for line in content.lines() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            if line.starts_with("import ") || line.starts_with("import\t") {
+                let Some(finder) = finder_import.captures(line) else { continue };
+                let Ok(finder_source) = fs.read_to_string(&site_dir.join(format!("{}.py", &finder[1]))) else { continue };
+                for package_dir in finder_mapping(&finder_source) {
+                    if let Some(parent) = package_dir.parent() {
+                        add(parent.to_path_buf());
+                    }
+                }
+                continue;
+            }
+            add(site_dir.join(line.trim_end()));
+        }
+    }
+    directories
+}
+
+/// `.pth` files the site module reads; hidden ones are skipped, as Python 3.13 does
+fn is_pth_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    name.ends_with(".pth") && !name.starts_with('.')
+}
+
+/// The package directories of an editable finder's `MAPPING = {'mylib': '/src/mylib', ...}`
+pub fn finder_mapping(source: &str) -> Vec<PathBuf> {
+    let mapping = Regex::new(r"(?m)^MAPPING\s*(?::[^=\n]*)?=\s*\{([^}]*)\}").unwrap();
+    let entry = Regex::new(r#"(?:'[^']*'|"[^"]*")\s*:\s*(?:'([^']*)'|"([^"]*)")"#).unwrap();
+    let Some(captures) = mapping.captures(source) else {
+        return Vec::new();
+    };
+    entry.captures_iter(&captures[1])
+        .filter_map(|entry| entry.get(1).or_else(|| entry.get(2)))
+        // A repr()'d Windows path has its backslashes doubled
+        .map(|path| PathBuf::from(path.as_str().replace(r"\\", r"\")))
+        .collect()
+}
+
+/// The distribution an editable finder module belongs to: `my_lib` for
+/// `__editable___my_lib_1_0_finder.py`, the version being the parts from the first digit on
+pub fn finder_distribution(finder_path: &Path) -> Option<String> {
+    let stem = finder_path.file_stem()?.to_str()?;
+    let name = stem.strip_prefix("__editable___")?.strip_suffix("_finder")?;
+    let parts: Vec<&str> = name.split('_').take_while(|part| !part.starts_with(|c: char| c.is_ascii_digit())).collect();
+    Some(parts.join("_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+
+    #[test]
+    fn test_pth_directories() {
+        let fs = VirtualFileSystem::new();
+        for dir in ["/venv/site-packages/extras", "/src/legacy", "/src/mylib/src/mylib", "/src/other"] {
+            fs.mkdir_p(Path::new(dir)).unwrap();
+        }
+        fs.write(Path::new("/venv/site-packages/b.pth"), b"# comment\n\nextras\n/src/legacy\n/src/missing\nimport sys; sys.__plen = len(sys.path)\n").unwrap();
+        fs.write(Path::new("/venv/site-packages/a.pth"), b"/src/other\n").unwrap();
+        fs.write(Path::new("/venv/site-packages/.hidden.pth"), b"/src/legacy\n").unwrap();
+        fs.write(Path::new("/venv/site-packages/__editable__.mylib-1.0.pth"), b"import __editable___mylib_1_0_finder; __editable___mylib_1_0_finder.install()\n").unwrap();
+        fs.write(Path::new("/venv/site-packages/__editable___mylib_1_0_finder.py"), b"import sys\nMAPPING: dict[str, str] = {'mylib': '/src/mylib/src/mylib'}\nNAMESPACES: dict[str, list[str]] = {}\n").unwrap();
+
+        assert_eq!(pth_directories(&fs, Path::new("/venv/site-packages")), vec![
+            PathBuf::from("/src/mylib/src"),
+            PathBuf::from("/src/other"),
+            PathBuf::from("/venv/site-packages/extras"),
+            PathBuf::from("/src/legacy"),
+        ]);
+        assert!(pth_directories(&fs, Path::new("/missing")).is_empty());
+        assert_eq!(finder_distribution(Path::new("/venv/site-packages/__editable___my_lib_2_0_1_finder.py")).as_deref(), Some("my_lib"));
+        assert_eq!(finder_distribution(Path::new("/venv/site-packages/mylib.py")), None);
+        assert_eq!(finder_mapping(r"MAPPING = {'a': 'C:\\src\\a', 'b': '/src/b'}"), vec![PathBuf::from(r"C:\src\a"), PathBuf::from("/src/b")]);
+    }
+}
@@ -0,0 +1,86 @@
+use regex::Regex;
+
+/// The entry script a non-release bundle stands for, so that it can be bundled again: each
+/// outermost inlined region, from its `# ↓↓↓ inlined ...` marker to the matching `# ↑↑↑`
+/// marker, becomes a star import of the module the markers name, at the markers' indentation.
+/// Inlining that import puts the module's current code where its old code was, in a region of
+/// its own. Everything outside the regions, such as the bindings the bundle adds after them, is
+/// kept as it is. None when the content has no markers; the line and reason of the marker that
+/// doesn't pair up otherwise.
+pub fn restore_imports(bundle: &str) -> Result<Option<String>, (usize, String)> {
+    let marker_regex = Regex::new(r"^([ \t]*)# (↓↓↓|↑↑↑) inlined (package|submodule): (\S+)\s*$").unwrap();
+    let mut restored = String::with_capacity(bundle.len());
+    // The label and line of each region we're inside of
+    let mut stack: Vec<(String, usize)> = Vec::new();
+    let mut saw_marker = false;
+
+    for (index, line) in bundle.split_inclusive('\n').enumerate() {
+        let Some(cap) = marker_regex.captures(line.trim_end_matches(['\n', '\r'])) else {
+            if stack.is_empty() {
+                restored.push_str(line);
+            }
+            continue;
+        };
+        saw_marker = true;
+        let label = &cap[4];
+        if &cap[2] == "↓↓↓" {
+            if stack.is_empty() {
+                restored.push_str(&format!("{}from {} import *\n", &cap[1], label));
+            }
+            stack.push((label.to_string(), index + 1));
+            continue;
+        }
+        match stack.pop() {
+            Some((opened, _)) if opened == label => {}
+            Some((opened, opened_line)) => return Err((index + 1, format!("the end marker of {} closes the region of {} opened on line {}", label, opened, opened_line))),
+            None => return Err((index + 1, format!("the end marker of {} has no start marker", label))),
+        }
+    }
+    if let Some((opened, opened_line)) = stack.pop() {
+        return Err((opened_line, format!("the region of {} is never closed", opened)));
+    }
+    Ok(saw_marker.then_some(restored))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_imports() {
+        let bundle = "\
+import sys
+# ↓↓↓ inlined package: mylib
+X = 1
+# ↓↓↓ inlined submodule: .utils
+def helper():
+    pass
+# ↑↑↑ inlined submodule: .utils
+# ↑↑↑ inlined package: mylib
+helper = mylib.utils.helper
+
+def main():
+    # ↓↓↓ inlined submodule: mylib.cli
+    run = 1
+    # ↑↑↑ inlined submodule: mylib.cli
+    # →→ mylib ←← module already inlined
+    return run
+";
+        assert_eq!(restore_imports(bundle).unwrap().unwrap(), "\
+import sys
+from mylib import *
+helper = mylib.utils.helper
+
+def main():
+    from mylib.cli import *
+    # →→ mylib ←← module already inlined
+    return run
+");
+        assert_eq!(restore_imports("import sys\n"), Ok(None));
+        assert_eq!(
+            restore_imports("# ↓↓↓ inlined package: a\n# ↓↓↓ inlined submodule: .b\n# ↑↑↑ inlined package: a\n"),
+            Err((3, "the end marker of a closes the region of .b opened on line 2".to_string()))
+        );
+        assert_eq!(restore_imports("x = 1\n# ↓↓↓ inlined package: a\n"), Err((2, "the region of a is never closed".to_string())));
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashSet;
+use regex::Regex;
+use crate::modules::source_analysis::{mask_strings_and_comments, top_level_statements, StatementKind};
+
+/// The (name, bound name) pairs of the names list of a `from X import ...` statement:
+/// `"a, b as c"` -> `[("a", "a"), ("b", "c")]`. Star imports bind nothing that's known.
+pub fn import_aliases(imports: &str) -> Vec<(String, String)> {
+    imports
+        .replace(['(', ')', '\\'], " ")
+        .split(',')
+        .filter_map(|item| {
+            let words: Vec<&str> = item.split_whitespace().collect();
+            match words.as_slice() {
+                [name] if *name != "*" => Some((name.to_string(), name.to_string())),
+                [name, "as", alias] => Some((name.to_string(), alias.to_string())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The names a module binds at the top level: its functions, classes and assignments, and what
+/// it imports. Imports of its own package's submodules (`from . import helper`) don't count,
+/// since `from pkg import helper` has to find those submodules itself.
+pub fn top_level_bindings(content: &str) -> HashSet<String> {
+    let masked = mask_strings_and_comments(content);
+    let from_import = Regex::new(r"^from\s+(\S+)\s+import\s+(\([^)]*\)|[^\n]*)").unwrap();
+    let plain_import = Regex::new(r"^import\s+([^\n]*)").unwrap();
+
+    let mut bindings = HashSet::new();
+    for statement in top_level_statements(content) {
+        if statement.kind != StatementKind::Import {
+            bindings.extend(statement.name);
+            continue;
+        }
+        let code = &masked[statement.start..statement.end];
+        if let Some(cap) = from_import.captures(code) {
+            if !cap[1].chars().all(|c| c == '.') {
+                bindings.extend(import_aliases(&cap[2]).into_iter().map(|(_, bound)| bound));
+            }
+        } else if let Some(cap) = plain_import.captures(code) {
+            for item in cap[1].split(',') {
+                let words: Vec<&str> = item.split_whitespace().collect();
+                match words.as_slice() {
+                    [_, "as", alias] => bindings.insert(alias.to_string()),
+                    // `import a.b` binds `a`
+                    [module] => bindings.insert(module.split('.').next().unwrap_or(module).to_string()),
+                    _ => false,
+                };
+            }
+        }
+    }
+    bindings
+}
+
+/// The names of a module's `__all__` when it's assigned a literal list or tuple of strings,
+/// with those `__all__ += [...]` adds. None when the module has no `__all__` or computes it.
+pub fn literal_all(content: &str) -> Option<Vec<String>> {
+    let masked = mask_strings_and_comments(content);
+    let all_statement = Regex::new(r"^__all__\b").unwrap();
+    // Strings are masked, so a literal list is only brackets, commas and whitespace
+    let literal_assignment = Regex::new(r"^__all__\s*(?::[^=]*)?(\+?=)\s*[\[(][\s,]*[\])]$").unwrap();
+    let name = Regex::new(r#"['"]([A-Za-z_]\w*)['"]"#).unwrap();
+
+    let mut all: Option<Vec<String>> = None;
+    for statement in top_level_statements(content) {
+        let code = masked[statement.start..statement.end].trim_end();
+        if !all_statement.is_match(code) {
+            continue;
+        }
+        let cap = literal_assignment.captures(code)?;
+        let names = name.captures_iter(&content[statement.start..statement.end]).map(|cap| cap[1].to_string());
+        match &cap[1] {
+            "=" => all = Some(names.collect()),
+            _ => all.as_mut()?.extend(names),
+        }
+    }
+    all
+}
+
+/// The names `from module import *` binds: the module's `__all__`, else its top-level names
+/// that don't start with an underscore, sorted
+pub fn star_exports(content: &str) -> Vec<String> {
+    let mut names = literal_all(content).unwrap_or_else(|| top_level_bindings(content).into_iter().filter(|name| !name.starts_with('_')).collect());
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_level_bindings() {
+        assert_eq!(import_aliases("a, b as c, (d,\n e), *"), vec![
+            ("a".to_string(), "a".to_string()),
+            ("b".to_string(), "c".to_string()),
+            ("d".to_string(), "d".to_string()),
+            ("e".to_string(), "e".to_string()),
+        ]);
+
+        let init = "\"\"\"import fake\"\"\"\nfrom .core import run, Config as Settings\nfrom . import helper\nimport os.path, json as js\nVERSION = '1.0'\n\ndef __getattr__(name):\n    from . import lazy\n    return lazy\n\nclass Thing:\n    pass\n";
+        let bindings = top_level_bindings(init);
+        let mut names: Vec<&str> = bindings.iter().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["Settings", "Thing", "VERSION", "__getattr__", "js", "os", "run"]);
+    }
+
+    #[test]
+    fn test_literal_all() {
+        let module = "import os\n__all__ = [\n    'run',  # the entry point\n    \"Config\",\n]\n__all__ += ('helpers',)\n\ndef run():\n    pass\n";
+        assert_eq!(literal_all(module), Some(vec!["run".to_string(), "Config".to_string(), "helpers".to_string()]));
+        assert_eq!(literal_all("__all__ = core.__all__ + ['x']\n"), None);
+        assert_eq!(literal_all("__all__ = ['x']\n__all__.extend(more)\n"), None);
+        assert_eq!(literal_all("X = 1\n"), None);
+
+        assert_eq!(star_exports(module), ["Config", "helpers", "run"]);
+        assert_eq!(star_exports("import os as _os\nfrom .core import run\n_cache = {}\nLIMIT = 3\n"), ["LIMIT", "run"]);
+    }
+}
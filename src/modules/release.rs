@@ -0,0 +1,609 @@
+use std::collections::HashSet;
+use regex::Regex;
+use crate::modules::debug_code::strip_debug_code;
+use crate::modules::defines::apply_defines;
+use crate::modules::imports::{group_imports, merge_imports, parse_import_line, separate_import_sections, ImportStatement};
+use crate::modules::options::Opt;
+use crate::modules::source_analysis::{identifier_counts, mask_strings_and_comments, string_spans};
+
+/// The passes --release, --remove-unused-imports and --minify make over the whole bundle, in
+/// the order they have to run: imports are consolidated and comments stripped before unused
+/// imports are looked for, and blank lines only stripped after
+pub fn release_passes(content: &str, first_party: &[String], opt: &Opt) -> String {
+    let mut content = content.to_string();
+    if opt.release {
+        content = post_process_imports(&content, first_party);
+        content = strip_docstrings(&content);
+        content = strip_comments(&content);
+    }
+    if opt.remove_unused_imports {
+        content = remove_unused_imports(&content, &opt.keep_import);
+    }
+    if opt.release {
+        content = strip_blank_lines(&content);
+        // Blank line stripping also removes the separators between import sections
+        content = separate_import_sections(&content, first_party);
+    }
+    if opt.minify {
+        if !opt.keep_docstrings {
+            content = strip_docstrings(&content);
+        }
+        content = strip_comments(&content);
+        content = strip_blank_lines(&content);
+        content = strip_trailing_whitespace(&content);
+    }
+    content
+}
+
+/// The --define values and the stripping --release, --minify, --strip-asserts and
+/// --strip-pattern apply, for a single embedded module. Imports aren't consolidated: they run
+/// through the loader, so they have to stay where they are.
+pub fn strip_source(content: &str, opt: &Opt) -> String {
+    let content = apply_defines(content, &opt.define, opt.prune_defines);
+    let mut content = strip_debug_code(&content, opt.strip_asserts, &opt.strip_pattern);
+    if opt.release || (opt.minify && !opt.keep_docstrings) {
+        content = strip_docstrings(&content);
+    }
+    if opt.release || opt.minify {
+        content = strip_comments(&content);
+        content = strip_blank_lines(&content);
+    }
+    if opt.minify {
+        content = strip_trailing_whitespace(&content);
+    }
+    content
+}
+
+pub fn post_process_imports(content: &str, first_party: &[String]) -> String {
+    let mut imports = HashSet::new();
+    let mut header_content = Vec::new();
+    let mut other_content = Vec::new();
+
+    // Improved regex that validates actual import statements:
+    // - "from module.name import something" - requires valid module name and 'import' keyword
+    // - "import module.name" - requires valid module name after import
+    // Module names must start with letter/underscore and contain word chars, dots, and underscores
+    // Only top-level (column 0) imports are consolidated. Indented imports live inside a function,
+    // class or conditional block on purpose (lazy loading, circular import avoidance, optional
+    // dependencies), so they are left exactly where they are.
+    let import_regex = Regex::new(
+        r"^(?:from\s+[a-zA-Z_][\w.]*\s+import\s+|import\s+[a-zA-Z_][\w.,\s*]+)"
+    ).unwrap();
+
+    // Filter out JavaScript-style imports (import X from '...'), which Python never uses
+    let js_import_filter = Regex::new(
+        "^\\s*import\\s+[\\w.*]+\\s+from\\s+['\"]"
+    ).unwrap();
+
+    let shebang_regex = Regex::new(r"^#!").unwrap();
+    let pep723_start_regex = Regex::new(r"^#\s*///").unwrap();
+
+    let mut lines = content.lines().collect::<Vec<&str>>();
+    // Import matching runs on a masked copy so that import-looking lines inside strings
+    // (e.g. docstring examples) aren't hoisted. Masking keeps lines aligned with the original.
+    let masked_content = mask_strings_and_comments(content);
+    let mut masked_lines = masked_content.lines().collect::<Vec<&str>>();
+
+    if let Some(first_line) = lines.first() {
+        if shebang_regex.is_match(first_line) {
+            header_content.push(first_line.to_string());
+            header_content.push("\n".to_string());
+            lines.remove(0);
+            masked_lines.remove(0);
+        }
+    }
+
+    // Check for and extract PEP 723 inline script metadata block
+    if !lines.is_empty() {
+        let first_line_after_shebang = lines[0].trim_start();
+        if pep723_start_regex.is_match(first_line_after_shebang) {
+            // Found PEP 723 start marker
+            let mut idx = 0;
+
+            while idx < lines.len() {
+                let line = lines[idx];
+                let trimmed = line.trim_start();
+
+                if pep723_start_regex.is_match(trimmed) {
+                    // Check if this is the end marker (just "# ///" or "#///" with nothing after)
+                    let is_end_marker = trimmed == "# ///" || trimmed == "#///";
+                    if is_end_marker && !header_content.is_empty() {
+                        // End of PEP 723 block
+                        header_content.push(line.to_string());
+                        idx += 1;
+                        break;
+                    }
+                }
+
+                header_content.push(line.to_string());
+                idx += 1;
+            }
+
+            // Remove the PEP 723 block from the remaining lines
+            lines = lines[idx..].to_vec();
+            masked_lines = masked_lines[idx..].to_vec();
+        }
+    }
+
+    let mut line_index = 0;
+    while line_index < lines.len() {
+        let line = lines[line_index];
+        let masked_line = masked_lines[line_index];
+        line_index += 1;
+        if !import_regex.is_match(masked_line) || js_import_filter.is_match(line) {
+            other_content.push(line.to_string());
+            continue;
+        }
+
+        // A parenthesized import spanning several lines is consolidated as one statement
+        let code = &line[..masked_line.trim_end().len()];
+        let depth = |code: &str| code.matches('(').count() as i32 - code.matches(')').count() as i32;
+        if depth(code) > 0 {
+            let mut statement = code.to_string();
+            let mut open = depth(code);
+            while line_index < lines.len() && open > 0 {
+                let code = lines[line_index][..masked_lines[line_index].trim_end().len()].trim();
+                open += depth(code);
+                statement.push(' ');
+                statement.push_str(code);
+                line_index += 1;
+            }
+            let joined = statement.replace(['(', ')'], " ");
+            let (head, names) = joined.split_once(" import ").unwrap_or((&joined, ""));
+            let names: Vec<&str> = names.split(',').map(str::trim).filter(|name| !name.is_empty()).collect();
+            imports.insert(format!("{} import {}", head.trim(), names.join(", ")));
+            continue;
+        }
+
+        imports.insert(line.to_string());
+    }
+
+    let mut result = String::new();
+    result.push_str(&header_content.join("\n"));
+    let imports_vec = group_imports(merge_imports(imports.into_iter().collect()), first_party);
+
+    // Check if header contains a PEP 723 block (looks for "# ///" marker)
+    let has_pep723 = header_content.iter().any(|line| line.contains("# ///"));
+
+    if !imports_vec.is_empty() {
+        // Add extra blank line after header if it contains PEP 723 block
+        if has_pep723 {
+            result.push('\n');
+        }
+        result.push_str(&imports_vec.join("\n"));
+        result.push('\n');
+    } else if has_pep723 {
+        // No imports but PEP 723 block exists - add blank line after it
+        result.push('\n');
+    }
+
+    result.push_str(&other_content.join("\n"));
+    result.push('\n');
+    result
+}
+
+/// Remove top-level imports whose bound names are never referenced anywhere else in the content.
+/// Multi-name imports are trimmed down to the names that are used. `from __future__` imports,
+/// star imports, imports of the `keep` modules or their submodules and anything that doesn't
+/// parse as a simple import are always kept.
+pub fn remove_unused_imports(content: &str, keep: &[String]) -> String {
+    let is_kept = |module: &str| keep.iter().any(|kept| module == kept || module.strip_prefix(kept.as_str()).is_some_and(|rest| rest.starts_with('.')));
+    let lines: Vec<&str> = content.lines().collect();
+    let is_top_level_import = |line: &str| !line.starts_with([' ', '\t']) && parse_import_line(line).is_some();
+
+    // Count identifier usage everywhere except import lines, which bind names rather than use them
+    let other_lines: Vec<&str> = lines.iter().copied().filter(|line| parse_import_line(line).is_none()).collect();
+    let usage = identifier_counts(&other_lines.join("\n"));
+    let is_used = |name: &str| usage.contains_key(name);
+
+    let mut result: Vec<String> = Vec::new();
+    for line in lines {
+        if !is_top_level_import(line) {
+            result.push(line.to_string());
+            continue;
+        }
+        let statement = parse_import_line(line).unwrap();
+        let kept = match &statement {
+            ImportStatement::From { module, .. } if module == "__future__" => Some(line.to_string()),
+            ImportStatement::From { names, .. } if names.iter().any(|(name, _)| name == "*") => Some(line.to_string()),
+            ImportStatement::From { module, .. } if is_kept(module) => Some(line.to_string()),
+            ImportStatement::From { module, names } => {
+                let used: Vec<(String, Option<String>)> = names.iter()
+                    .filter(|(name, alias)| is_used(alias.as_deref().unwrap_or(name)))
+                    .cloned()
+                    .collect();
+                if used.len() == names.len() {
+                    Some(line.to_string())
+                } else if used.is_empty() {
+                    None
+                } else {
+                    Some(ImportStatement::From { module: module.clone(), names: used }.render())
+                }
+            }
+            ImportStatement::Import(modules) => {
+                let used: Vec<(String, Option<String>)> = modules.iter()
+                    .filter(|(module, alias)| is_kept(module) || is_used(alias.as_deref().unwrap_or_else(|| module.split('.').next().unwrap_or(module))))
+                    .cloned()
+                    .collect();
+                if used.len() == modules.len() {
+                    Some(line.to_string())
+                } else if used.is_empty() {
+                    None
+                } else {
+                    Some(ImportStatement::Import(used).render())
+                }
+            }
+        };
+        if let Some(kept) = kept {
+            result.push(kept);
+        }
+    }
+
+    let mut result = result.join("\n");
+    if content.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
+
+/// Strip docstrings from Python code.
+/// Removes function and class docstrings (triple-quoted strings that are NOT assigned to variables).
+/// Preserves variable assignments that use triple-quoted strings.
+pub fn strip_docstrings(content: &str) -> String {
+    // Patterns to check what comes before a triple-quoted string
+    // Assignment pattern now handles: var=, self.attr=, obj.attr.nested=, etc.
+    let assignment_pattern = Regex::new(r"^\s*[a-zA-Z_]\w*(\.[a-zA-Z_]\w*)*\s*=").unwrap();
+    let import_pattern = Regex::new(r"^\s*(from|import)\s+").unwrap();
+    let decorator_pattern = Regex::new(r"^\s*@").unwrap();
+
+    let mut result = String::new();
+    let mut last_pos = 0;
+    let bytes = content.as_bytes();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        // Check for triple-quoted strings (""" or ''')
+        if pos + 2 < bytes.len() {
+            let is_triple_double = bytes[pos] == b'"' && bytes[pos + 1] == b'"' && bytes[pos + 2] == b'"';
+            let is_triple_single = bytes[pos] == b'\'' && bytes[pos + 1] == b'\'' && bytes[pos + 2] == b'\'';
+
+            if is_triple_double || is_triple_single {
+                let quote_byte = bytes[pos];
+                let start_pos = pos;
+
+                // Make sure this is exactly 3 quotes, not 4+
+                if pos + 3 < bytes.len() && bytes[pos + 3] == quote_byte {
+                    // This is 4+ quotes, skip the first one and continue
+                    pos += 1;
+                    continue;
+                }
+
+                // Find the closing triple quote
+                let mut end_pos = pos + 3;
+                let mut found_closing = false;
+
+                while end_pos + 2 < bytes.len() {
+                    if bytes[end_pos] == quote_byte && bytes[end_pos + 1] == quote_byte && bytes[end_pos + 2] == quote_byte {
+                        // Make sure it's exactly 3 quotes, not part of 4+
+                        let has_fourth = end_pos + 3 < bytes.len() && bytes[end_pos + 3] == quote_byte;
+                        if !has_fourth {
+                            end_pos += 3;
+                            found_closing = true;
+                            break;
+                        }
+                    }
+                    end_pos += 1;
+                }
+
+                if !found_closing {
+                    // No closing quote found, treat as regular content
+                    pos += 1;
+                    continue;
+                }
+
+                // Check if this should be preserved
+                let before_string = &content[..start_pos];
+                let line_start = before_string.rfind('\n').map(|p| p + 1).unwrap_or(0);
+                let line_before = &content[line_start..start_pos];
+
+                let trimmed = line_before.trim_end();
+                let is_f_string = trimmed.ends_with('f');
+
+                let should_preserve = assignment_pattern.is_match(line_before)
+                    || import_pattern.is_match(line_before)
+                    || decorator_pattern.is_match(line_before)
+                    || is_f_string;
+
+                // Copy everything from last position to start of this string
+                result.push_str(&content[last_pos..start_pos]);
+
+                if should_preserve {
+                    // Keep the triple-quoted string
+                    result.push_str(&content[start_pos..end_pos]);
+                }
+                // else: skip it (it's a docstring) - just don't add it to result
+
+                last_pos = end_pos;
+                pos = end_pos;
+                continue;
+            }
+        }
+
+        pos += 1;
+    }
+
+    // Copy any remaining content
+    result.push_str(&content[last_pos..]);
+
+    result
+}
+
+pub fn strip_comments(content: &str) -> String {
+    let shebang_regex = Regex::new(r"^#!").unwrap();
+    let pep723_start_regex = Regex::new(r"^#\s*///").unwrap(); // Match # /// with optional text after
+
+    let mut result = String::new();
+    let mut lines = content.lines().enumerate().peekable();
+    let mut in_multiline_string = None::<char>; // Track if we're inside a multi-line triple-quoted string
+    let mut in_pep723_block = false; // Track if we're inside a PEP 723 metadata block
+
+    while let Some((line_num, line)) = lines.next() {
+        let trimmed = line.trim_start();
+
+        // Preserve shebang line (only on first line)
+        if line_num == 0 && shebang_regex.is_match(trimmed) {
+            result.push_str(line);
+            if lines.peek().is_some() {
+                result.push('\n');
+            }
+            continue;
+        }
+
+        // Handle PEP 723 inline script metadata blocks
+        if pep723_start_regex.is_match(trimmed) {
+            // Check if this is the end marker (just "# ///" with nothing after, or only whitespace)
+            let is_end_marker = trimmed == "# ///" || trimmed == "#///";
+            if in_pep723_block && is_end_marker {
+                // End of PEP 723 block
+                in_pep723_block = false;
+                result.push_str(line);
+                if lines.peek().is_some() {
+                    result.push('\n');
+                }
+                continue;
+            } else if !in_pep723_block {
+                // Start of PEP 723 block
+                in_pep723_block = true;
+                result.push_str(line);
+                if lines.peek().is_some() {
+                    result.push('\n');
+                }
+                continue;
+            }
+        }
+
+        // Preserve all lines inside PEP 723 block (including comments)
+        if in_pep723_block {
+            result.push_str(line);
+            if lines.peek().is_some() {
+                result.push('\n');
+            }
+            continue;
+        }
+
+        // Find inline comment position (not inside strings)
+        let mut in_string = in_multiline_string; // Start with multi-line state
+        let mut chars = line.chars().peekable();
+        let mut comment_pos = None;
+        let mut i = 0;
+
+        while let Some(&ch) = chars.peek() {
+            let pos = i;
+            i += ch.len_utf8();
+            chars.next();
+
+            // Check for triple quotes
+            if ch == '"' || ch == '\'' {
+                if let Some(&next1) = chars.peek() {
+                    if next1 == ch {
+                        chars.next();
+                        if let Some(&next2) = chars.peek() {
+                            if next2 == ch {
+                                chars.next();
+                                // Triple quote
+                                if in_string == Some(ch) {
+                                    in_string = None;
+                                    in_multiline_string = None;
+                                } else if in_string.is_none() {
+                                    in_string = Some(ch);
+                                    in_multiline_string = Some(ch);
+                                }
+                                continue;
+                            }
+                        }
+                    }
+                }
+
+                // Single/double quote (only if not in multi-line string)
+                if in_multiline_string.is_none() {
+                    if in_string.is_none() {
+                        in_string = Some(ch);
+                    } else if in_string == Some(ch) {
+                        in_string = None;
+                    }
+                }
+            } else if ch == '#' && in_string.is_none() {
+                // Found a comment outside a string
+                comment_pos = Some(pos);
+                break;
+            }
+        }
+
+        // Add the line up to the comment (or whole line if no comment)
+        // Skip whole-line comments (if comment starts at position 0 or only whitespace)
+        if let Some(pos) = comment_pos {
+            let before_comment = &line[..pos];
+            if before_comment.trim().is_empty() {
+                // This is a whole-line comment, skip it
+            } else {
+                // Inline comment, keep the part before it
+                let trimmed_content = before_comment.trim_end();
+                if !trimmed_content.is_empty() {
+                    result.push_str(trimmed_content);
+                    if lines.peek().is_some() {
+                        result.push('\n');
+                    }
+                }
+            }
+        } else {
+            if !line.trim().is_empty() {
+                result.push_str(line);
+                if lines.peek().is_some() {
+                    result.push('\n');
+                }
+            }
+        }
+    }
+
+    // Preserve final newline if original content ended with one
+    if content.ends_with('\n') {
+        result.push('\n');
+    }
+
+    result
+}
+
+/// Remove trailing whitespace from every line, unless it's part of a multi-line string
+pub fn strip_trailing_whitespace(content: &str) -> String {
+    let strings = string_spans(content);
+    let mut result = String::new();
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let body = line.trim_end_matches(['\n', '\r']);
+        let trimmed = body.trim_end();
+        let whitespace_start = line_start + trimmed.len();
+        let inside_string = strings.iter().any(|(start, end)| *start <= whitespace_start && whitespace_start < *end);
+        result.push_str(if inside_string { body } else { trimmed });
+        result.push_str(&line[body.len()..]);
+    }
+    result
+}
+
+/// Strip all blank lines from Python code.
+/// Removes both single blank lines and multiple consecutive blank lines.
+pub fn strip_blank_lines(content: &str) -> String {
+    let mut result = String::new();
+    let mut lines = content.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+
+        // Skip blank lines
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        result.push_str(line);
+
+        if lines.peek().is_some() {
+            result.push('\n');
+        }
+    }
+
+    // Preserve final newline if original content ended with one
+    if content.ends_with('\n') {
+        result.push('\n');
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_unused_imports() {
+        let input = r#"#!/usr/bin/env python3
+from __future__ import annotations
+from os import path, sep as separator
+from typing import *
+import json, re
+import os.path
+import sys
+
+def main():
+    print(path.join("a", "b"), re.compile("x"))
+    if False:
+        import sys
+"#;
+
+        let expected = r#"#!/usr/bin/env python3
+from __future__ import annotations
+from os import path
+from typing import *
+import re
+
+def main():
+    print(path.join("a", "b"), re.compile("x"))
+    if False:
+        import sys
+"#;
+
+        assert_eq!(remove_unused_imports(input, &[]), expected);
+
+        // Imports kept for their side effects survive, whether or not anything uses their names
+        let input = "import readline\nimport codecs, json\nfrom encodings.idna import Codec\nimport xml.dom.minidom\n\nprint(json.dumps([]))\n";
+        let keep = ["readline".to_string(), "encodings".to_string(), "xml.dom".to_string()];
+        let expected = "import readline\nimport json\nfrom encodings.idna import Codec\nimport xml.dom.minidom\n\nprint(json.dumps([]))\n";
+        assert_eq!(remove_unused_imports(input, &keep), expected);
+    }
+
+    #[test]
+    fn test_post_process_imports_leaves_scoped_imports() {
+        // Only top-level imports are hoisted; function-local and conditional imports stay put,
+        // and multi-line parenthesized imports are consolidated as a single statement
+        let input = r#"import sys
+from typing import (
+    Dict,
+    List,  # comment
+)
+from os.path import (join,
+    split)
+
+def lazy():
+    import json
+    return json
+
+try:
+    import tomllib
+except ImportError:
+    tomllib = None
+"#;
+
+        let expected = r#"import sys
+from os.path import join, split
+from typing import Dict, List
+
+def lazy():
+    import json
+    return json
+
+try:
+    import tomllib
+except ImportError:
+    tomllib = None
+"#;
+
+        assert_eq!(post_process_imports(input, &[]), expected);
+    }
+
+    #[test]
+    fn test_post_process_imports_merges_from_imports() {
+        let input = "from os import path\nimport os\nfrom os import sep, path\n\nprint(os, path, sep)\n";
+        let expected = "import os\nfrom os import path, sep\n\nprint(os, path, sep)\n";
+        assert_eq!(post_process_imports(input, &[]), expected);
+    }
+}
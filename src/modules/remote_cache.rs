@@ -0,0 +1,199 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use crate::modules::file_system::FileSystem;
+use crate::modules::module_cache::artifact_file_name;
+
+/// A cache of module artifacts shared between machines, for --remote-cache. Entries have the
+/// names they have in a --cache-dir, so one can be copied into the other.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemoteCache {
+    /// A directory other machines reach too, such as a network mount or a CI cache volume
+    Directory(PathBuf),
+    /// A server storing what is PUT to `<url>/<key>.json` and answering a GET of it, such as a
+    /// WebDAV share or bazel-remote. curl does the transfers, with the credentials of ~/.netrc.
+    Http(String),
+}
+
+impl FromStr for RemoteCache {
+    type Err = String;
+
+    fn from_str(location: &str) -> Result<RemoteCache, String> {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            return Ok(RemoteCache::Http(location.trim_end_matches('/').to_string()));
+        }
+        match location.trim() {
+            "" => Err("--remote-cache needs a directory or an http(s):// URL".to_string()),
+            directory => Ok(RemoteCache::Directory(PathBuf::from(directory))),
+        }
+    }
+}
+
+impl RemoteCache {
+    /// Where the cache is, for messages
+    pub fn location(&self) -> String {
+        match self {
+            RemoteCache::Directory(directory) => directory.display().to_string(),
+            RemoteCache::Http(url) => url.clone(),
+        }
+    }
+
+    /// The stored artifact with the key, None when there isn't one
+    pub fn get(&self, fs: &dyn FileSystem, key: &str) -> io::Result<Option<String>> {
+        match self {
+            RemoteCache::Directory(directory) => match fs.read_to_string(&directory.join(artifact_file_name(key))) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                result => result.map(Some),
+            },
+            RemoteCache::Http(url) => {
+                let url = format!("{}/{}", url, artifact_file_name(key));
+                match curl(&url, &[], None)? {
+                    (200, body) => String::from_utf8(body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+                    (404, _) => Ok(None),
+                    (status, _) => Err(io::Error::other(format!("GET {} answered HTTP {}", url, status))),
+                }
+            }
+        }
+    }
+
+    /// Store the artifact under the key. A directory entry that is being written can be read
+    /// half-written by another machine, which load_artifact() treats as a miss.
+    pub fn put(&self, fs: &dyn FileSystem, key: &str, artifact: &str) -> io::Result<()> {
+        match self {
+            RemoteCache::Directory(directory) => {
+                fs.mkdir_p(directory)?;
+                fs.write(&directory.join(artifact_file_name(key)), artifact.as_bytes())
+            }
+            RemoteCache::Http(url) => {
+                let url = format!("{}/{}", url, artifact_file_name(key));
+                // Without `Expect:`, curl waits for a 100 Continue that some servers never send
+                let upload = ["--request", "PUT", "--data-binary", "@-", "--header", "Content-Type: application/json", "--header", "Expect:"];
+                match curl(&url, &upload, Some(artifact.as_bytes()))? {
+                    (200..=299, _) => Ok(()),
+                    (status, _) => Err(io::Error::other(format!("PUT {} answered HTTP {}", url, status))),
+                }
+            }
+        }
+    }
+}
+
+/// Request `url` with curl, sending `upload` as the body: the response's status and body
+fn curl(url: &str, args: &[&str], upload: Option<&[u8]>) -> io::Result<(u32, Vec<u8>)> {
+    let child = Command::new("curl")
+        .args(["--silent", "--show-error", "--location", "--netrc-optional", "--connect-timeout", "10", "--output", "-", "--write-out", "\n%{http_code}"])
+        .args(args)
+        .arg(url)
+        .stdin(if upload.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(io::Error::other("curl not found, install it to use an http(s) --remote-cache")),
+        result => result?,
+    };
+    if let Some(upload) = upload {
+        // curl reads all of a `@-` body before it sends the request, so this can't block on it
+        child.stdin.take().unwrap().write_all(upload)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("curl failed with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim_end())));
+    }
+    // The status follows the body, on a line of its own
+    let mut body = output.stdout;
+    let newline = body.iter().rposition(|&b| b == b'\n').unwrap_or(0);
+    let status = std::str::from_utf8(&body[newline..]).ok().and_then(|status| status.trim().parse().ok())
+        .ok_or_else(|| io::Error::other(format!("curl gave no HTTP status for {}", url)))?;
+    body.truncate(newline);
+    Ok((status, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn test_parse_remote_cache() {
+        assert_eq!("https://cache.example.com/inliner/".parse(), Ok(RemoteCache::Http("https://cache.example.com/inliner".to_string())));
+        assert_eq!("/mnt/shared/inliner".parse(), Ok(RemoteCache::Directory(PathBuf::from("/mnt/shared/inliner"))));
+        assert!(" ".parse::<RemoteCache>().is_err());
+    }
+
+    #[test]
+    fn test_directory_cache() {
+        let fs = VirtualFileSystem::new();
+        let cache = RemoteCache::Directory(PathBuf::from("/shared/cache"));
+        assert_eq!(cache.get(&fs, "abc").unwrap(), None);
+        cache.put(&fs, "abc", "{\"segments\": []}").unwrap();
+        assert_eq!(cache.get(&fs, "abc").unwrap().as_deref(), Some("{\"segments\": []}"));
+        assert!(fs.exists(Path::new("/shared/cache/abc.json")).unwrap());
+    }
+
+    /// Serve `requests` HTTP requests, keeping what is PUT in `stored`
+    fn serve(listener: TcpListener, requests: usize, stored: Arc<Mutex<HashMap<String, Vec<u8>>>>) {
+        for stream in listener.incoming().take(requests) {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let (method, path) = request_line.split_once(' ').map(|(method, rest)| (method, rest.split(' ').next().unwrap())).unwrap();
+            let response = match method {
+                "PUT" => {
+                    stored.lock().unwrap().insert(path.to_string(), body);
+                    (201, Vec::new())
+                }
+                _ => match stored.lock().unwrap().get(path) {
+                    Some(body) => (200, body.clone()),
+                    None => (404, Vec::new()),
+                },
+            };
+            write!(stream, "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", response.0, response.1.len()).unwrap();
+            stream.write_all(&response.1).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_http_cache() {
+        if Command::new("curl").arg("--version").output().is_err() {
+            return;
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/inliner", listener.local_addr().unwrap());
+        let stored = Arc::new(Mutex::new(HashMap::new()));
+        let server = thread::spawn({
+            let stored = Arc::clone(&stored);
+            move || serve(listener, 3, stored)
+        });
+
+        let fs = VirtualFileSystem::new();
+        let cache: RemoteCache = url.parse().unwrap();
+        assert_eq!(cache.get(&fs, "abc").unwrap(), None);
+        cache.put(&fs, "abc", "{\"code\": \"x = 1\\n\"}").unwrap();
+        assert_eq!(cache.get(&fs, "abc").unwrap().as_deref(), Some("{\"code\": \"x = 1\\n\"}"));
+        server.join().unwrap();
+        assert!(stored.lock().unwrap().contains_key("/inliner/abc.json"));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use crate::modules::collisions::{collision_name, find_collisions, mangled_name, rename_names};
+use crate::modules::errors::InlinerError;
+use crate::modules::file_system::FileSystem;
+use crate::modules::module_cache::Segment;
+use crate::modules::options::Opt;
+use crate::modules::reexports::{import_aliases, literal_all};
+use crate::modules::source_analysis::{mask_strings_and_comments, top_level_statements, StatementKind};
+use crate::modules::warnings::{allowed_at, WarningCategory};
+use crate::modules::inlining::{is_plain_import, is_star_import, resolve_from_import};
+use crate::modules::module_cache::module_artifact;
+use crate::{module_name, read_source, search_path_relative};
+
+/// The renames of the top-level names of the inlined files that the bundle is rendered again
+/// with: those of --mangle-names, or of --rename-collisions, and those hiding the names a
+/// module's `__all__` leaves out of a star import of it. Name collisions are reported on the way.
+pub fn bundle_renames(fs: &dyn FileSystem, python_sys_path: &[PathBuf], input_file: &Path, module_names: &str, processed: &HashSet<PathBuf>, opt: &Opt) -> Result<HashMap<PathBuf, BTreeMap<String, String>>, InlinerError> {
+    let hidden_names = star_import_renames(fs, python_sys_path, input_file, module_names, processed, opt)?;
+    let mut renamed_definitions = match opt.mangle_names {
+        true => mangled_definitions(fs, python_sys_path, input_file, module_names, processed, opt)?,
+        false => check_collisions(fs, python_sys_path, input_file, processed, &hidden_names, opt)?,
+    };
+    for (path, hidden) in hidden_names {
+        let renames = renamed_definitions.entry(path).or_default();
+        for (name, renamed) in hidden {
+            renames.entry(name).or_insert(renamed);
+        }
+    }
+    Ok(renamed_definitions)
+}
+
+/// Report the top-level names that several of the files inlined at the top level define,
+/// the entry script included (see find_collisions()). Returns the renames --rename-collisions
+/// makes: the inlined modules' definitions of each name get their module's name as a prefix,
+/// while the entry script keeps its own. The names in `hidden` are renamed already and can't
+/// collide.
+fn check_collisions(fs: &dyn FileSystem, python_sys_path: &[PathBuf], input_file: &Path, processed: &HashSet<PathBuf>, hidden: &HashMap<PathBuf, BTreeMap<String, String>>, opt: &Opt) -> Result<HashMap<PathBuf, BTreeMap<String, String>>, InlinerError> {
+    let mut renames: HashMap<PathBuf, BTreeMap<String, String>> = HashMap::new();
+    if opt.no_self_check && !opt.rename_collisions {
+        return Ok(renames);
+    }
+    // Modules inlined only inside a block define their names there
+    let mut inlined: Vec<&PathBuf> = processed.iter().filter(|path| !opt.indented_inlines.borrow().contains(*path)).collect();
+    inlined.sort();
+    let mut files = vec![(input_file.to_path_buf(), read_source(fs, input_file)?)];
+    for path in inlined {
+        let source = read_source(fs, path)?;
+        files.push((path.clone(), match hidden.get(path) {
+            Some(renames) => rename_names(&source, renames),
+            None => source,
+        }));
+    }
+
+    for (name, definitions) in find_collisions(&files) {
+        let subject = format!("{} ({})", name, definitions.iter().map(|definition| search_path_relative(&definition.file, python_sys_path)).collect::<Vec<_>>().join(", "));
+        let source = |file: &Path| files.iter().find(|(path, _)| path == file).map(|(_, content)| content.as_str()).unwrap_or("");
+        // A collision allowed at any of its definitions is intended
+        if definitions.iter().any(|definition| allowed_at(source(&definition.file), definition.offset).contains(&WarningCategory::NameCollision)) {
+            continue;
+        }
+        for definition in definitions.iter().filter(|_| !opt.no_self_check) {
+            opt.warnings.warn(WarningCategory::NameCollision, &subject, Some(format!("{}:{}", definition.file.display(), definition.line)));
+        }
+        if opt.rename_collisions {
+            for definition in definitions.iter().filter(|definition| definition.file != input_file) {
+                let renamed = collision_name(&module_name(&definition.file, python_sys_path), &name);
+                renames.entry(definition.file.clone()).or_default().insert(name.clone(), renamed);
+            }
+        }
+    }
+    Ok(renames)
+}
+
+/// The top-level names of an inlined module that can be renamed: those it defines, and those
+/// its imports of inlined modules bind, which it assigns itself. Dunder names are left out.
+fn renameable_names(fs: &dyn FileSystem, python_sys_path: &[PathBuf], path: &Path, module_names: &str, opt: &Opt) -> Result<Vec<String>, InlinerError> {
+    let names_start = Regex::new(r"\simport\s").unwrap();
+    let mut names: Vec<String> = top_level_statements(&read_source(fs, path)?).into_iter()
+        .filter(|statement| matches!(statement.kind, StatementKind::Function | StatementKind::Class | StatementKind::Assignment))
+        .filter_map(|statement| statement.name)
+        .collect();
+    for segment in &module_artifact(fs, python_sys_path, path, module_names, opt)?.segments {
+        let Segment::Import(import) = segment else { continue };
+        if !import.indent.is_empty() || is_plain_import(&import.statement) || resolve_from_import(fs, python_sys_path, path, &import.module, opt)?.is_none() {
+            continue;
+        }
+        let masked_statement = mask_strings_and_comments(&import.statement);
+        if let Some(names_start) = names_start.find(&masked_statement).map(|found| found.end()) {
+            names.extend(import_aliases(&masked_statement[names_start..]).into_iter().map(|(_, bound)| bound));
+        }
+    }
+    names.retain(|name| !(name.starts_with("__") && name.ends_with("__")));
+    Ok(names)
+}
+
+/// The renames of --mangle-names: every top-level name of each module inlined at the top level,
+/// defined there or bound by an import that's inlined, prefixed with `_m_` and the module's name
+fn mangled_definitions(fs: &dyn FileSystem, python_sys_path: &[PathBuf], input_file: &Path, module_names: &str, processed: &HashSet<PathBuf>, opt: &Opt) -> Result<HashMap<PathBuf, BTreeMap<String, String>>, InlinerError> {
+    let mut renames: HashMap<PathBuf, BTreeMap<String, String>> = HashMap::new();
+    for path in processed.iter().filter(|path| *path != input_file && !opt.indented_inlines.borrow().contains(*path)) {
+        let module = module_name(path, python_sys_path);
+        let module_renames = renames.entry(path.clone()).or_default();
+        for name in renameable_names(fs, python_sys_path, path, module_names, opt)? {
+            let mangled = mangled_name(&module, &name);
+            module_renames.insert(name, mangled);
+        }
+    }
+    Ok(renames)
+}
+
+/// The renames that keep the names a module leaves out of its literal `__all__` out of the
+/// namespace of a `from module import *` it's inlined for, as --rename-collisions renames them.
+/// Its own code, and explicit imports of those names, use the new names.
+fn star_import_renames(fs: &dyn FileSystem, python_sys_path: &[PathBuf], input_file: &Path, module_names: &str, processed: &HashSet<PathBuf>, opt: &Opt) -> Result<HashMap<PathBuf, BTreeMap<String, String>>, InlinerError> {
+    let mut renames: HashMap<PathBuf, BTreeMap<String, String>> = HashMap::new();
+    let files: BTreeSet<&Path> = processed.iter().map(PathBuf::as_path).chain([input_file]).collect();
+    for file in files {
+        for segment in &module_artifact(fs, python_sys_path, file, module_names, opt)?.segments {
+            let Segment::Import(import) = segment else { continue };
+            if !is_star_import(&import.statement) {
+                continue;
+            }
+            let Some(path) = resolve_from_import(fs, python_sys_path, file, &import.module, opt)? else { continue };
+            if path == input_file || renames.contains_key(&path) || opt.indented_inlines.borrow().contains(&path) {
+                continue;
+            }
+            let Some(all) = literal_all(&read_source(fs, &path)?) else { continue };
+            let module = module_name(&path, python_sys_path);
+            let hidden: BTreeMap<String, String> = renameable_names(fs, python_sys_path, &path, module_names, opt)?.into_iter()
+                .filter(|name| !all.contains(name))
+                .map(|name| (name.clone(), collision_name(&module, &name)))
+                .collect();
+            if !hidden.is_empty() {
+                renames.insert(path, hidden);
+            }
+        }
+    }
+    Ok(renames)
+}
@@ -0,0 +1,141 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use serde_json::{json, Value};
+use crate::modules::warnings::{WarningCategory, WarningRecord};
+
+/// A source file inlined into a bundle
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlinedFile {
+    pub path: PathBuf,
+    /// Hex SHA-256 of the file as read from disk
+    pub sha256: String,
+    pub bytes: usize,
+    /// The encoding the file was decoded from, e.g. `utf-8`, `utf-8-sig` or `latin-1`
+    pub encoding: String,
+    pub lines: usize,
+    /// What was done to the file's source on the way into the bundle, e.g. `reindent`, sorted
+    pub transformations: Vec<String>,
+}
+
+/// An import of one inlined file by another, whether it was inlined there or skipped as a
+/// duplicate
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ImportEdge {
+    pub importer: PathBuf,
+    pub imported: PathBuf,
+    /// The module name as written in the import, possibly relative
+    pub module: String,
+    /// `file:line` of the import
+    pub site: String,
+}
+
+/// What went into one bundle, for --report
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundleReport {
+    pub input_file: PathBuf,
+    pub output_file: PathBuf,
+    /// The module names as requested
+    pub module_names: String,
+    /// Sorted by path
+    pub inlined: Vec<InlinedFile>,
+    /// Sorted
+    pub imports: Vec<ImportEdge>,
+    pub warnings: Vec<WarningRecord>,
+    pub elapsed: Duration,
+}
+
+impl BundleReport {
+    fn to_value(&self) -> Value {
+        let warnings_of = |category: WarningCategory, key: &str| -> Vec<Value> {
+            self.warnings.iter()
+                .filter(|warning| warning.category == category)
+                .map(|warning| json!({ key: warning.subject, "count": warning.count, "sites": warning.sites }))
+                .collect()
+        };
+        json!({
+            "input": self.input_file.to_string_lossy(),
+            "output": self.output_file.to_string_lossy(),
+            "modules": self.module_names,
+            "inlined": self.inlined.iter()
+                .map(|file| json!({
+                    "path": file.path.to_string_lossy(),
+                    "sha256": file.sha256,
+                    "bytes": file.bytes,
+                    "encoding": file.encoding,
+                    "lines": file.lines,
+                    "transformations": file.transformations,
+                }))
+                .collect::<Vec<_>>(),
+            "imports": self.imports.iter()
+                .map(|edge| json!({
+                    "from": edge.importer.to_string_lossy(),
+                    "to": edge.imported.to_string_lossy(),
+                    "module": edge.module,
+                    "site": edge.site,
+                }))
+                .collect::<Vec<_>>(),
+            "unresolved_imports": warnings_of(WarningCategory::UnresolvedModule, "module"),
+            "skipped_duplicates": warnings_of(WarningCategory::AlreadyInlined, "path"),
+            "warnings": self.warnings.iter()
+                .map(|warning| json!({ "code": warning.category.code(), "subject": warning.subject, "count": warning.count, "sites": warning.sites, "note": warning.note }))
+                .collect::<Vec<_>>(),
+            "elapsed_ms": self.elapsed.as_millis() as u64,
+        })
+    }
+}
+
+/// Render the --report JSON for a run: the search path it resolved against and each bundle built
+pub fn render_run_report(sys_path: &[PathBuf], bundles: &[BundleReport], elapsed: Duration) -> String {
+    let value = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "sys_path": sys_path.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>(),
+        "bundles": bundles.iter().map(BundleReport::to_value).collect::<Vec<_>>(),
+        "elapsed_ms": elapsed.as_millis() as u64,
+    });
+    // Serializing a Value can't fail
+    serde_json::to_string_pretty(&value).unwrap() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_run_report() {
+        let bundle = BundleReport {
+            input_file: PathBuf::from("/src/main.py"),
+            output_file: PathBuf::from("/dist/main.py"),
+            module_names: "mylib".to_string(),
+            inlined: vec![InlinedFile {
+                path: PathBuf::from("/src/mylib/__init__.py"),
+                sha256: "ab".repeat(32),
+                bytes: 12,
+                encoding: "utf-8".to_string(),
+                lines: 1,
+                transformations: vec!["reindent".to_string()],
+            }],
+            imports: vec![ImportEdge {
+                importer: PathBuf::from("/src/main.py"),
+                imported: PathBuf::from("/src/mylib/__init__.py"),
+                module: "mylib".to_string(),
+                site: "/src/main.py:1".to_string(),
+            }],
+            warnings: vec![
+                WarningRecord { category: WarningCategory::UnresolvedModule, subject: "mylib.gone".to_string(), count: 2, sites: vec!["/src/main.py:3".to_string()], note: Some("package mylib has no submodule gone".to_string()) },
+                WarningRecord { category: WarningCategory::AlreadyInlined, subject: "/src/mylib/a.py".to_string(), count: 1, sites: vec![], note: None },
+            ],
+            elapsed: Duration::from_millis(7),
+        };
+        let report: Value = serde_json::from_str(&render_run_report(&[PathBuf::from("/src")], &[bundle], Duration::from_millis(9))).unwrap();
+        assert_eq!(report["sys_path"], json!(["/src"]));
+        assert_eq!(report["elapsed_ms"], 9);
+        let bundle = &report["bundles"][0];
+        assert_eq!(bundle["inlined"][0]["path"], "/src/mylib/__init__.py");
+        assert_eq!(bundle["inlined"][0]["transformations"], json!(["reindent"]));
+        assert_eq!(bundle["imports"], json!([{ "from": "/src/main.py", "to": "/src/mylib/__init__.py", "module": "mylib", "site": "/src/main.py:1" }]));
+        assert_eq!(bundle["unresolved_imports"], json!([{ "module": "mylib.gone", "count": 2, "sites": ["/src/main.py:3"] }]));
+        assert_eq!(bundle["skipped_duplicates"][0]["path"], "/src/mylib/a.py");
+        assert_eq!(bundle["warnings"][0]["note"], "package mylib has no submodule gone");
+        assert_eq!(bundle["warnings"][1]["code"], "PI0101");
+    }
+}
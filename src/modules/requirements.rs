@@ -0,0 +1,189 @@
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use crate::modules::file_system::FileSystem;
+use crate::modules::source_analysis::mask_strings_and_comments;
+use crate::utils::stdlib::is_stdlib_module;
+
+/// Collect the top-level names of every module the content still imports that is neither part
+/// of the standard library nor a relative import. These are the packages the bundle needs
+/// installed at runtime. Returned sorted and deduplicated.
+pub fn external_imports(content: &str) -> Vec<String> {
+    // Mask strings and comments so embedded code samples don't count as imports
+    let masked = mask_strings_and_comments(content);
+    let from_regex = Regex::new(r"(?m)^[ \t]*from\s+([A-Za-z_][\w.]*)\s+import\b").unwrap();
+    let import_regex = Regex::new(r"(?m)^[ \t]*import\s+([A-Za-z_][\w., \t]*(?:\s+as\s+\w+)?)").unwrap();
+
+    let mut modules = BTreeSet::new();
+    for cap in from_regex.captures_iter(&masked) {
+        modules.insert(cap[1].to_string());
+    }
+    for cap in import_regex.captures_iter(&masked) {
+        for item in cap[1].split(',') {
+            if let Some(module) = item.split_whitespace().next() {
+                modules.insert(module.to_string());
+            }
+        }
+    }
+
+    modules
+        .into_iter()
+        .map(|module| module.split('.').next().unwrap_or(&module).to_string())
+        .filter(|module| !is_stdlib_module(module))
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect()
+}
+
+/// The paths listed in a dist-info RECORD file (CSV of path, hash, size), relative to site-packages
+pub fn record_paths(record: &str) -> HashSet<String> {
+    record.lines()
+        .filter_map(|line| match line.strip_prefix('"') {
+            // Paths containing commas are quoted
+            Some(quoted) => quoted.split('"').next(),
+            None => line.split(',').next(),
+        })
+        .filter(|path| !path.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// A distribution installed in a directory of the search path, with the top-level modules it
+/// provides
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstalledDistribution {
+    pub name: String,
+    pub version: String,
+    pub import_names: Vec<String>,
+}
+
+/// The value of a header of a dist-info METADATA file (RFC 822 style)
+pub fn metadata_field<'a>(metadata: &'a str, field: &str) -> Option<&'a str> {
+    metadata.lines()
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| line.split_once(':').filter(|(name, _)| name.eq_ignore_ascii_case(field)))
+        .map(|(_, value)| value.trim())
+}
+
+/// The top-level modules of the files a RECORD lists: packages, modules and extension modules,
+/// leaving out the dist-info directory, scripts and data installed elsewhere
+fn record_modules(record: &str) -> Vec<String> {
+    let mut modules = BTreeSet::new();
+    for path in record_paths(record) {
+        let Some(top) = path.split('/').next() else { continue };
+        let is_module = [".py", ".so", ".pyd"].iter().any(|suffix| path.ends_with(suffix));
+        if !is_module || top.starts_with('.') || top.ends_with(".dist-info") || top.ends_with(".data") || top == "__pycache__" {
+            continue;
+        }
+        let name = top.split('.').next().unwrap_or(top);
+        if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            modules.insert(name.to_string());
+        }
+    }
+    modules.into_iter().collect()
+}
+
+/// The distributions installed in the directories of the search path, found through their
+/// `*.dist-info` directories, in search path order. The name and version come from METADATA
+/// (the directory name when it's missing) and the modules from `top_level.txt`, else RECORD.
+pub fn installed_distributions(fs: &dyn FileSystem, python_sys_path: &[PathBuf]) -> Vec<InstalledDistribution> {
+    let mut distributions = Vec::new();
+    for dir in python_sys_path {
+        let Ok(mut dist_infos) = fs.glob(&dir.join("*.dist-info")) else { continue };
+        dist_infos.sort();
+        for dist_info in dist_infos {
+            let read = |name: &str| fs.read_to_string(&dist_info.join(name)).ok();
+            let dir_name = dist_info.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let (dir_distribution, dir_version) = dir_name.split_once('-').unwrap_or((&dir_name, ""));
+            let metadata = read("METADATA").unwrap_or_default();
+            let import_names = match read("top_level.txt") {
+                Some(top_level) => top_level.lines().map(str::trim).filter(|name| !name.is_empty()).map(String::from).collect(),
+                None => read("RECORD").map(|record| record_modules(&record)).unwrap_or_default(),
+            };
+            distributions.push(InstalledDistribution {
+                name: metadata_field(&metadata, "Name").unwrap_or(dir_distribution).to_string(),
+                version: metadata_field(&metadata, "Version").unwrap_or(dir_version).to_string(),
+                import_names,
+            });
+        }
+    }
+    distributions
+}
+
+/// A pinned requirements file for the modules: `name==version` of the first installed
+/// distribution on the search path providing each (every one of them, for a namespace package
+/// several distributions share), sorted by name. Also returns the modules no distribution
+/// provides, which are listed as comments.
+pub fn render_requirements(modules: &[String], distributions: &[InstalledDistribution], bundles: &[&Path]) -> (String, Vec<String>) {
+    let mut pinned = BTreeSet::new();
+    let mut missing = Vec::new();
+    for module in modules {
+        let providers: Vec<&InstalledDistribution> = distributions.iter()
+            .filter(|distribution| distribution.import_names.contains(module))
+            .collect();
+        let Some(first) = providers.first() else {
+            missing.push(module.clone());
+            continue;
+        };
+        // Every distribution of a namespace package, but only the first of one that's shadowed
+        let shadowed = |distribution: &InstalledDistribution| distribution.name.eq_ignore_ascii_case(&first.name) && distribution.version != first.version;
+        for distribution in providers.iter().filter(|distribution| !shadowed(distribution)) {
+            pinned.insert((distribution.name.to_lowercase(), format!("{}=={}", distribution.name, distribution.version)));
+        }
+    }
+
+    let names: Vec<String> = bundles.iter().map(|bundle| bundle.display().to_string()).collect();
+    let mut content = format!("# Pinned requirements of the external imports of {}, generated by python-inliner\n", names.join(", "));
+    for (_, requirement) in pinned {
+        content.push_str(&requirement);
+        content.push('\n');
+    }
+    for module in &missing {
+        content.push_str(&format!("# {}: no installed distribution provides it\n", module));
+    }
+    (content, missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+
+    #[test]
+    fn test_external_imports() {
+        let content = r#"import os, requests.adapters
+from yaml import safe_load
+from .local import thing
+from typing import List
+
+def lazy():
+    import numpy as np
+    s = "import not_a_module"
+    # import commented_out
+"#;
+        assert_eq!(external_imports(content), vec!["numpy", "requests", "yaml"]);
+    }
+
+    #[test]
+    fn test_installed_distributions() {
+        let fs = VirtualFileSystem::new();
+        let site_packages = Path::new("/venv/lib/python3.12/site-packages");
+        for dir in ["PyYAML-6.0.1.dist-info", "requests-2.31.0.dist-info", "protobuf-4.25.0.dist-info", "google_auth-2.0.0.dist-info"] {
+            fs.mkdir_p(&site_packages.join(dir)).unwrap();
+        }
+        fs.write(&site_packages.join("PyYAML-6.0.1.dist-info/METADATA"), b"Metadata-Version: 2.1\nName: PyYAML\nVersion: 6.0.1\n\nYAML parser\nName: not a header\n").unwrap();
+        fs.write(&site_packages.join("PyYAML-6.0.1.dist-info/top_level.txt"), b"_yaml\nyaml\n").unwrap();
+        fs.write(&site_packages.join("requests-2.31.0.dist-info/RECORD"), b"requests/__init__.py,sha256=abc,100\nrequests/__pycache__/api.cpython-312.pyc,,\nrequests-2.31.0.dist-info/RECORD,,\n../../../bin/requests-cli,,\n").unwrap();
+        fs.write(&site_packages.join("protobuf-4.25.0.dist-info/RECORD"), b"google/protobuf/__init__.py,,\n").unwrap();
+        fs.write(&site_packages.join("google_auth-2.0.0.dist-info/RECORD"), b"google/auth/__init__.py,,\n").unwrap();
+
+        let distributions = installed_distributions(&fs, &[PathBuf::from("/src"), site_packages.to_path_buf()]);
+        assert_eq!(distributions[0], InstalledDistribution { name: "PyYAML".to_string(), version: "6.0.1".to_string(), import_names: vec!["_yaml".to_string(), "yaml".to_string()] });
+        assert_eq!(distributions[3].import_names, ["requests"]);
+
+        let modules: Vec<String> = ["google", "requests", "yaml", "missing"].iter().map(|module| module.to_string()).collect();
+        let (content, missing) = render_requirements(&modules, &distributions, &[Path::new("dist/app.py")]);
+        assert_eq!(content, "# Pinned requirements of the external imports of dist/app.py, generated by python-inliner\ngoogle_auth==2.0.0\nprotobuf==4.25.0\nPyYAML==6.0.1\nrequests==2.31.0\n# missing: no installed distribution provides it\n");
+        assert_eq!(missing, ["missing"]);
+    }
+}
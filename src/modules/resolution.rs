@@ -0,0 +1,338 @@
+use std::io::{self, BufRead, Write};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use crate::modules::errors::InlinerError;
+use crate::modules::file_system::FileSystem;
+use crate::modules::options::Opt;
+use crate::modules::requirements::installed_distributions;
+use crate::modules::terminal::Style;
+use crate::modules::warnings::WarningCategory;
+
+/// Name of the per-project config file holding remembered resolution choices
+pub const CONFIG_FILE_NAME: &str = ".python-inliner.json";
+
+/// What to do with an import that resolved ambiguously or not at all
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolutionChoice {
+    /// Inline this file (a `.py` module or a package's `__init__.py`)
+    Path(PathBuf),
+    /// Leave the import statement as it is
+    Skip,
+}
+
+/// The answer to the --interactive question about inlining a distribution from site-packages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistributionChoice {
+    Inline,
+    Skip,
+    /// Inline this and every other distribution without asking again
+    InlineAll,
+    /// Leave the imports of this and every other distribution as they are
+    SkipAll,
+}
+
+/// Resolution choices keyed by the module name as written in the import, loaded from and saved
+/// to the `resolutions` object of the config file so later runs reuse them. Whether to inline
+/// each distribution from site-packages is kept the same way, under `distributions`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResolutionChoices {
+    choices: BTreeMap<String, ResolutionChoice>,
+    /// Whether to inline the modules of each distribution, by distribution name
+    distributions: BTreeMap<String, bool>,
+    /// The answer for every distribution not in `distributions`, for the rest of the run
+    pub all_distributions: Option<bool>,
+    /// Everything else in the config file, written back untouched
+    other_settings: Map<String, Value>,
+    /// Whether a new choice was made since loading
+    pub changed: bool,
+}
+
+impl ResolutionChoices {
+    pub fn from_json(content: &str) -> Result<ResolutionChoices, String> {
+        let mut other_settings: Map<String, Value> = serde_json::from_str(content).map_err(|e| e.to_string())?;
+        let mut choices = BTreeMap::new();
+        if let Some(resolutions) = other_settings.remove("resolutions") {
+            let resolutions = resolutions.as_object().ok_or("`resolutions` must be an object")?;
+            for (module, choice) in resolutions {
+                let choice = match choice.as_str() {
+                    Some("skip") => ResolutionChoice::Skip,
+                    Some(path) => ResolutionChoice::Path(PathBuf::from(path)),
+                    None => return Err(format!("resolution for {:?} must be a path or \"skip\"", module)),
+                };
+                choices.insert(module.clone(), choice);
+            }
+        }
+        let mut distributions = BTreeMap::new();
+        if let Some(choices) = other_settings.remove("distributions") {
+            let choices = choices.as_object().ok_or("`distributions` must be an object")?;
+            for (distribution, choice) in choices {
+                let inline = match choice.as_str() {
+                    Some("inline") => true,
+                    Some("skip") => false,
+                    _ => return Err(format!("choice for distribution {:?} must be \"inline\" or \"skip\"", distribution)),
+                };
+                distributions.insert(distribution.clone(), inline);
+            }
+        }
+        Ok(ResolutionChoices { choices, distributions, all_distributions: None, other_settings, changed: false })
+    }
+
+    pub fn to_json(&self) -> String {
+        let resolutions: Map<String, Value> = self.choices.iter()
+            .map(|(module, choice)| {
+                let value = match choice {
+                    ResolutionChoice::Path(path) => json!(path.to_string_lossy()),
+                    ResolutionChoice::Skip => json!("skip"),
+                };
+                (module.clone(), value)
+            })
+            .collect();
+        let mut config = self.other_settings.clone();
+        config.insert("resolutions".to_string(), Value::Object(resolutions));
+        if !self.distributions.is_empty() {
+            let distributions: Map<String, Value> = self.distributions.iter()
+                .map(|(distribution, inline)| (distribution.clone(), json!(if *inline { "inline" } else { "skip" })))
+                .collect();
+            config.insert("distributions".to_string(), Value::Object(distributions));
+        }
+        // Serializing a Value can't fail
+        serde_json::to_string_pretty(&config).unwrap() + "\n"
+    }
+
+    pub fn get(&self, module: &str) -> Option<&ResolutionChoice> {
+        self.choices.get(module)
+    }
+
+    pub fn remember(&mut self, module: &str, choice: ResolutionChoice) {
+        self.choices.insert(module.to_string(), choice);
+        self.changed = true;
+    }
+
+    /// Whether the modules of the distribution are to be inlined, if that's been decided
+    pub fn distribution(&self, distribution: &str) -> Option<bool> {
+        self.distributions.get(distribution).copied().or(self.all_distributions)
+    }
+
+    pub fn has_distribution_choices(&self) -> bool {
+        !self.distributions.is_empty()
+    }
+
+    pub fn remember_distribution(&mut self, distribution: &str, choice: DistributionChoice) {
+        let inline = matches!(choice, DistributionChoice::Inline | DistributionChoice::InlineAll);
+        if matches!(choice, DistributionChoice::InlineAll | DistributionChoice::SkipAll) {
+            self.all_distributions = Some(inline);
+        }
+        self.distributions.insert(distribution.to_string(), inline);
+        self.changed = true;
+    }
+}
+
+/// Ask the user whether to inline `module`, found in `location`, and with it the rest of the
+/// installed distribution it belongs to. Returns None if the user chose to abort.
+pub fn prompt_distribution<R: BufRead, W: Write>(distribution: &str, module: &str, location: &Path, input: &mut R, output: &mut W) -> io::Result<Option<DistributionChoice>> {
+    writeln!(output, "Module {:?} is part of the installed distribution {:?}, in {}.", module, distribution, location.display())?;
+    loop {
+        write!(output, "Inline {}? (y)es, (n)o, (Y)es to all, (N)o to all or (a)bort: ", distribution)?;
+        output.flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        return Ok(Some(match answer.trim() {
+            "y" | "yes" => DistributionChoice::Inline,
+            "n" | "no" => DistributionChoice::Skip,
+            "Y" | "all" => DistributionChoice::InlineAll,
+            "N" | "none" => DistributionChoice::SkipAll,
+            "a" | "abort" => return Ok(None),
+            "" => continue,
+            _ => {
+                writeln!(output, "Please enter y, n, Y, N or a")?;
+                continue;
+            }
+        }));
+    }
+}
+
+/// Ask the user how to resolve `module` given the candidate files found on the search path.
+/// Returns None if the user chose to abort.
+pub fn prompt_resolution<R: BufRead, W: Write>(module: &str, candidates: &[PathBuf], input: &mut R, output: &mut W) -> io::Result<Option<ResolutionChoice>> {
+    if candidates.is_empty() {
+        writeln!(output, "Module {:?} could not be resolved.", module)?;
+    } else {
+        writeln!(output, "Module {:?} resolves to several files:", module)?;
+        for (index, candidate) in candidates.iter().enumerate() {
+            writeln!(output, "  [{}] {}", index + 1, candidate.display())?;
+        }
+    }
+
+    loop {
+        if candidates.is_empty() {
+            write!(output, "Enter a path to inline, (s)kip or (a)bort: ")?;
+        } else {
+            write!(output, "Pick 1-{}, enter a path, (s)kip or (a)bort: ", candidates.len())?;
+        }
+        output.flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            // End of input, nobody is there to answer
+            return Ok(None);
+        }
+        let answer = answer.trim();
+        match answer {
+            "s" | "skip" => return Ok(Some(ResolutionChoice::Skip)),
+            "a" | "abort" => return Ok(None),
+            "" => continue,
+            _ => {}
+        }
+        if let Ok(index) = answer.parse::<usize>() {
+            if (1..=candidates.len()).contains(&index) {
+                return Ok(Some(ResolutionChoice::Path(candidates[index - 1].clone())));
+            }
+            writeln!(output, "No candidate {}", index)?;
+            continue;
+        }
+        if Path::new(answer).is_absolute() {
+            return Ok(Some(ResolutionChoice::Path(PathBuf::from(answer))));
+        }
+        writeln!(output, "Please enter a candidate number, an absolute path, s or a")?;
+    }
+}
+
+/// Pick the file an absolute import resolves to (see pick_resolution()), then check that the
+/// distribution it's installed from, if any, is to be inlined. Returns None when the import
+/// should be left alone.
+pub fn choose_resolution(fs: &dyn FileSystem, module: &str, candidates: &[PathBuf], opt: &Opt) -> Result<Option<PathBuf>, InlinerError> {
+    let Some(path) = pick_resolution(fs, module, candidates, opt)? else {
+        return Ok(None);
+    };
+    if !opt.interactive && !opt.resolution_choices.borrow().has_distribution_choices() {
+        return Ok(Some(path));
+    }
+    let Some((distribution, site_packages)) = site_packages_distribution(fs, &path) else {
+        return Ok(Some(path));
+    };
+    let mut choices = opt.resolution_choices.borrow_mut();
+    let inline = match choices.distribution(&distribution) {
+        Some(inline) => inline,
+        // A config file's answers apply to later runs, but only --interactive asks
+        None if !opt.interactive => true,
+        None => {
+            let choice = prompt_distribution(&distribution, module, &site_packages, &mut io::stdin().lock(), &mut io::stdout())
+                .map_err(|e| InlinerError::Resolve { module: module.to_string(), reason: format!("the --interactive prompt failed: {}", e), site: None })?
+                .ok_or_else(|| InlinerError::Resolve { module: module.to_string(), reason: format!("aborted while confirming distribution {:?}", distribution), site: None })?;
+            choices.remember_distribution(&distribution, choice);
+            choices.distribution(&distribution).unwrap_or(true)
+        }
+    };
+    if !inline && opt.verbose {
+        println!("{}", opt.terminal.paint(&format!("Leaving {} as an import, as distribution {:?} isn't to be inlined", module, distribution), Style::Skip));
+    }
+    Ok(inline.then_some(path))
+}
+
+/// The installed distribution a file in a site-packages directory is part of, with that
+/// directory. The distribution is named by the dist-info directory listing the file's top-level
+/// module, else by the module. None for any other file, such as the project's own or an
+/// editable install's.
+fn site_packages_distribution(fs: &dyn FileSystem, path: &Path) -> Option<(String, PathBuf)> {
+    let site_packages = path.ancestors().skip(1)
+        .find(|dir| dir.file_name().is_some_and(|name| name == "site-packages" || name == "dist-packages"))?;
+    let top = path.strip_prefix(site_packages).ok()?.components().next()?.as_os_str().to_string_lossy().to_string();
+    let top = top.strip_suffix(".py").unwrap_or(&top).to_string();
+    let distribution = installed_distributions(fs, &[site_packages.to_path_buf()]).into_iter()
+        .find(|distribution| distribution.import_names.contains(&top))
+        .map_or(top, |distribution| distribution.name);
+    Some((distribution, site_packages.to_path_buf()))
+}
+
+/// Pick the file an absolute import resolves to. A remembered choice from the config file wins;
+/// otherwise, with --interactive, the user is asked whenever there is more than one candidate or
+/// none at all; otherwise the first candidate on the search path is used, as Python would.
+/// Returns None when the import should be left alone.
+fn pick_resolution(fs: &dyn FileSystem, module: &str, candidates: &[PathBuf], opt: &Opt) -> Result<Option<PathBuf>, InlinerError> {
+    let mut choices = opt.resolution_choices.borrow_mut();
+    match choices.get(module) {
+        Some(ResolutionChoice::Skip) => return Ok(None),
+        Some(ResolutionChoice::Path(path)) if fs.exists(path).map_err(|e| InlinerError::io(path, e))? => return Ok(Some(path.clone())),
+        Some(ResolutionChoice::Path(path)) => {
+            opt.warnings.warn(WarningCategory::StaleResolution, module, Some(path.display().to_string()));
+        }
+        None => {}
+    }
+
+    if opt.interactive && candidates.len() != 1 {
+        let choice = prompt_resolution(module, candidates, &mut io::stdin().lock(), &mut io::stdout())
+            .map_err(|e| InlinerError::Resolve { module: module.to_string(), reason: format!("the --interactive prompt failed: {}", e), site: None })?
+            .ok_or_else(|| InlinerError::Resolve { module: module.to_string(), reason: "aborted at the --interactive prompt".to_string(), site: None })?;
+        choices.remember(module, choice.clone());
+        return Ok(match choice {
+            ResolutionChoice::Path(path) => Some(path),
+            ResolutionChoice::Skip => None,
+        });
+    }
+    Ok(candidates.first().cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolution_choices_round_trip() {
+        let mut choices = ResolutionChoices::from_json(r#"{"other": 1, "resolutions": {"mylib": "skip"}}"#).unwrap();
+        assert_eq!(choices.get("mylib"), Some(&ResolutionChoice::Skip));
+        choices.remember("tools", ResolutionChoice::Path(PathBuf::from("/src/tools/__init__.py")));
+        assert!(choices.changed);
+
+        let reloaded = ResolutionChoices::from_json(&choices.to_json()).unwrap();
+        assert_eq!(reloaded.get("tools"), Some(&ResolutionChoice::Path(PathBuf::from("/src/tools/__init__.py"))));
+        assert!(reloaded.to_json().contains("\"other\": 1"));
+        assert!(ResolutionChoices::from_json(r#"{"resolutions": {"mylib": 3}}"#).is_err());
+    }
+
+    #[test]
+    fn test_distribution_choices() {
+        let mut choices = ResolutionChoices::from_json(r#"{"distributions": {"requests": "skip"}}"#).unwrap();
+        assert_eq!(choices.distribution("requests"), Some(false));
+        assert_eq!(choices.distribution("attrs"), None);
+        choices.remember_distribution("attrs", DistributionChoice::InlineAll);
+        assert_eq!(choices.distribution("rich"), Some(true));
+        assert_eq!(choices.distribution("requests"), Some(false));
+
+        // Only the answers for single distributions outlive the run
+        let reloaded = ResolutionChoices::from_json(&choices.to_json()).unwrap();
+        assert_eq!(reloaded.distribution("attrs"), Some(true));
+        assert_eq!(reloaded.distribution("rich"), None);
+        assert!(ResolutionChoices::from_json(r#"{"distributions": {"requests": true}}"#).is_err());
+    }
+
+    #[test]
+    fn test_prompt_distribution() {
+        let mut output = Vec::new();
+        let choice = prompt_distribution("requests", "requests.adapters", Path::new("/venv/site-packages"), &mut "maybe\nN\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(choice, Some(DistributionChoice::SkipAll));
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Module \"requests.adapters\" is part of the installed distribution \"requests\", in /venv/site-packages.\n"));
+        assert!(output.contains("Please enter y, n, Y, N or a"));
+        assert_eq!(prompt_distribution("requests", "requests", Path::new("/venv"), &mut "y\n".as_bytes(), &mut Vec::new()).unwrap(), Some(DistributionChoice::Inline));
+        assert_eq!(prompt_distribution("requests", "requests", Path::new("/venv"), &mut "".as_bytes(), &mut Vec::new()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_prompt_resolution() {
+        let candidates = vec![PathBuf::from("/a/mylib/__init__.py"), PathBuf::from("/b/mylib.py")];
+        let mut output = Vec::new();
+        let choice = prompt_resolution("mylib", &candidates, &mut "7\n2\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(choice, Some(ResolutionChoice::Path(PathBuf::from("/b/mylib.py"))));
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("  [2] /b/mylib.py"));
+        assert!(output.contains("No candidate 7"));
+
+        assert_eq!(prompt_resolution("mylib", &[], &mut "s\n".as_bytes(), &mut Vec::new()).unwrap(), Some(ResolutionChoice::Skip));
+        assert_eq!(prompt_resolution("mylib", &candidates, &mut "a\n".as_bytes(), &mut Vec::new()).unwrap(), None);
+        assert_eq!(prompt_resolution("mylib", &candidates, &mut "".as_bytes(), &mut Vec::new()).unwrap(), None);
+    }
+}
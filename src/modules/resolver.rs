@@ -0,0 +1,385 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::modules::file_system::FileSystem;
+use crate::modules::resolution::{ResolutionChoice, ResolutionChoices, CONFIG_FILE_NAME};
+
+/// What a module name is in a search directory, or on the whole search path
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution {
+    /// A regular package, by its `__init__.py`
+    Package(PathBuf),
+    /// A `.py` file
+    Module(PathBuf),
+    /// A directory without `__init__.py`, which namespace packages are made of
+    Namespace(PathBuf),
+    /// A compiled extension module (`.so`, `.pyd`)
+    Extension(PathBuf),
+    NotFound,
+}
+
+impl Resolution {
+    /// The file to inline: a package's `__init__.py` or a module. Namespace packages and
+    /// extension modules have none.
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            Resolution::Package(path) | Resolution::Module(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// What was found, e.g. `package /src/mylib/__init__.py`
+    pub fn describe(&self) -> String {
+        match self {
+            Resolution::Package(path) => format!("package {}", path.display()),
+            Resolution::Module(path) => format!("module {}", path.display()),
+            Resolution::Namespace(path) => format!("namespace package {}", path.display()),
+            Resolution::Extension(path) => format!("extension module {}", path.display()),
+            Resolution::NotFound => "nothing".to_string(),
+        }
+    }
+}
+
+/// One search directory tried for a name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub dir: PathBuf,
+    /// Whether `dir` is a directory; anything else on the search path is skipped
+    pub searched: bool,
+    /// What the directory has under the name. Files are canonical, so that a file reached by
+    /// several paths (through symlinks, or in another case on case-insensitive file systems) is
+    /// recognized as one.
+    pub found: Resolution,
+    /// A `.py` file next to the package of the same name, which the package wins over
+    pub shadowed_module: Option<PathBuf>,
+    /// The earlier candidate, by index, that found the same file
+    pub same_as: Option<usize>,
+}
+
+/// The candidates tried for a name, in search path order, and what the name resolves to: the
+/// first package or module found, else an extension module or namespace package, which are
+/// reported but can't be inlined
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub candidates: Vec<Candidate>,
+    pub resolution: Resolution,
+}
+
+impl Trace {
+    /// The index of the candidate the name resolves to
+    pub fn matched(&self) -> Option<usize> {
+        self.candidates.iter().position(|candidate| candidate.found == self.resolution && candidate.same_as.is_none() && candidate.found != Resolution::NotFound)
+    }
+}
+
+/// The traces of the names looked up during a run, by search directories and name, so that a
+/// module imported from many files is looked for on disk once rather than once per import. The
+/// directories searched are taken not to change during the run.
+#[derive(Debug, Default)]
+pub struct ResolutionCache {
+    traces: RefCell<HashMap<(Vec<PathBuf>, String), Trace>>,
+}
+
+/// Finds the file a module name stands for in a list of search directories, keeping track of
+/// every candidate it tries so that `explain` and the warnings can say why a name resolved the
+/// way it did
+#[derive(Clone)]
+pub struct ModuleResolver<'a> {
+    fs: &'a dyn FileSystem,
+    search_dirs: Vec<PathBuf>,
+    cache: Option<&'a ResolutionCache>,
+}
+
+impl<'a> ModuleResolver<'a> {
+    pub fn new(fs: &'a dyn FileSystem, search_dirs: &[PathBuf]) -> ModuleResolver<'a> {
+        ModuleResolver { fs, search_dirs: search_dirs.to_vec(), cache: None }
+    }
+
+    /// The resolver, looking names up in `cache` first and remembering them there
+    pub fn cached(self, cache: &'a ResolutionCache) -> ModuleResolver<'a> {
+        ModuleResolver { cache: Some(cache), ..self }
+    }
+
+    /// A resolver for the submodules of the package whose `__init__.py` is `package_init`
+    pub fn within(&self, package_init: &Path) -> ModuleResolver<'a> {
+        ModuleResolver { search_dirs: vec![package_init.parent().unwrap().to_path_buf()], ..self.clone() }
+    }
+
+    /// Look `module` up in every search directory. A dotted name is looked up as a path below
+    /// each directory, without checking that its parents are packages.
+    pub fn trace(&self, module: &str) -> Trace {
+        let Some(cache) = self.cache else {
+            return self.search(module);
+        };
+        let key = (self.search_dirs.clone(), module.to_string());
+        if let Some(trace) = cache.traces.borrow().get(&key) {
+            return trace.clone();
+        }
+        let trace = self.search(module);
+        cache.traces.borrow_mut().insert(key, trace.clone());
+        trace
+    }
+
+    fn search(&self, module: &str) -> Trace {
+        let mut candidates: Vec<Candidate> = Vec::new();
+        for dir in &self.search_dirs {
+            let mut candidate = Candidate { dir: dir.clone(), searched: false, found: Resolution::NotFound, shadowed_module: None, same_as: None };
+            if self.fs.is_dir(dir).unwrap_or(false) {
+                candidate.searched = true;
+                let module_path = module.split('.').fold(dir.clone(), |path, part| path.join(part));
+                let module_file = module_path.with_extension("py");
+                let canonical = |path: &Path| self.fs.canonicalize(path).unwrap_or(path.to_path_buf());
+                // A package takes precedence over a module of the same name in the same
+                // directory, and a module's source over its compiled extension, as it can be inlined
+                candidate.found = if self.exists(&module_path.join("__init__.py")) {
+                    if self.exists(&module_file) {
+                        candidate.shadowed_module = Some(module_file);
+                    }
+                    Resolution::Package(canonical(&module_path.join("__init__.py")))
+                } else if self.exists(&module_file) {
+                    Resolution::Module(canonical(&module_file))
+                } else if let Some(extension) = self.extension_module(&module_path) {
+                    Resolution::Extension(extension)
+                } else if self.fs.is_dir(&module_path).unwrap_or(false) {
+                    Resolution::Namespace(module_path)
+                } else {
+                    Resolution::NotFound
+                };
+                if candidate.found != Resolution::NotFound {
+                    candidate.same_as = candidates.iter().position(|earlier| earlier.found == candidate.found);
+                }
+            }
+            candidates.push(candidate);
+        }
+        let first = |wanted: fn(&Resolution) -> bool| candidates.iter().map(|candidate| &candidate.found).find(|found| wanted(found)).cloned();
+        let resolution = first(|found| found.file().is_some())
+            .or_else(|| first(|found| matches!(found, Resolution::Extension(_))))
+            .or_else(|| first(|found| matches!(found, Resolution::Namespace(_))))
+            .unwrap_or(Resolution::NotFound);
+        Trace { candidates, resolution }
+    }
+
+    /// The files `module` can be inlined from, in search path order, each once
+    pub fn candidates(&self, module: &str) -> Vec<PathBuf> {
+        self.trace(module).candidates.iter()
+            .filter(|candidate| candidate.same_as.is_none())
+            .filter_map(|candidate| candidate.found.file().map(Path::to_path_buf))
+            .collect()
+    }
+
+    /// Why the dotted `module` doesn't resolve to a file to inline, part by part as an import
+    /// resolves it, or None if it does
+    pub fn failure(&self, module: &str) -> Option<String> {
+        let parts: Vec<&str> = module.split('.').collect();
+        let mut resolver = self.clone();
+        for (index, part) in parts.iter().enumerate() {
+            let name = parts[..=index].join(".");
+            match resolver.trace(part).resolution {
+                Resolution::Package(init) => resolver = resolver.within(&init),
+                Resolution::Module(path) if index + 1 < parts.len() => {
+                    return Some(format!("{} is the module {}, not a package, so it has no submodules", name, path.display()));
+                }
+                Resolution::Module(_) => {}
+                Resolution::Extension(path) => return Some(format!("{} is the compiled extension module {}, which can't be inlined", name, path.display())),
+                Resolution::Namespace(path) => return Some(format!("{} is the namespace package {}, a directory without __init__.py, which isn't inlined", name, path.display())),
+                Resolution::NotFound if index > 0 => return Some(format!("package {} has no submodule {}", parts[..index].join("."), part)),
+                Resolution::NotFound => return Some(format!("none of the {} directories searched has {}", self.search_dirs.len(), name)),
+            }
+        }
+        None
+    }
+
+    /// How an import of `module` resolves, for `explain <module>`: for each part of the name,
+    /// every directory searched for it, which candidate is picked and why the others aren't.
+    /// Remembered --interactive answers win over the search path for top-level modules.
+    pub fn explain(&self, module: &str, choices: &ResolutionChoices) -> String {
+        let mut explanation = format!("Resolving {} on a search path of {} directories\n", module, self.search_dirs.len());
+        let parts: Vec<&str> = module.split('.').collect();
+        let mut resolver = self.clone();
+        let mut resolved: Option<Resolution> = None;
+        for (index, part) in parts.iter().enumerate() {
+            let name = parts[..=index].join(".");
+            match &resolved {
+                None => explanation.push_str(&format!("\n{}\n", name)),
+                Some(Resolution::Package(init)) => {
+                    resolver = resolver.within(init);
+                    explanation.push_str(&format!("\n{}, in package {}\n", name, init.parent().unwrap().display()));
+                }
+                Some(_) => {
+                    explanation.push_str(&format!("\nCould not find module {}: {}\n", module, self.failure(module).unwrap_or_default()));
+                    return explanation;
+                }
+            }
+            let trace = resolver.trace(part);
+            let matched = trace.matched();
+            for (number, candidate) in trace.candidates.iter().enumerate() {
+                let verdict = match (&candidate.found, candidate.same_as) {
+                    _ if !candidate.searched => "not a directory, skipped".to_string(),
+                    (Resolution::NotFound, _) => format!("no {}/__init__.py or {}.py", part, part),
+                    (found, Some(earlier)) => format!("{}, the same file as in directory {}", found.describe(), earlier + 1),
+                    (found @ Resolution::Namespace(_), None) => format!("{}, a directory without __init__.py, which isn't inlined", found.describe()),
+                    (found @ Resolution::Extension(_), None) => format!("{}, which can't be inlined", found.describe()),
+                    (found, None) if Some(number) == matched => format!("{}, matched", found.describe()),
+                    (found, None) => format!("{}, shadowed by the match in directory {}", found.describe(), matched.map_or(0, |matched| matched + 1)),
+                };
+                let shadowed = match &candidate.shadowed_module {
+                    Some(_) => format!(" ({}.py next to it loses to the package)", part),
+                    None => String::new(),
+                };
+                explanation.push_str(&format!("  {}. {}: {}{}\n", number + 1, candidate.dir.display(), verdict, shadowed));
+            }
+            let mut resolution = trace.resolution;
+            if index == 0 {
+                match choices.get(&name) {
+                    Some(ResolutionChoice::Skip) => {
+                        explanation.push_str(&format!("\n{} remembers to leave {} as an import\n", CONFIG_FILE_NAME, name));
+                        return explanation;
+                    }
+                    Some(ResolutionChoice::Path(path)) if self.exists(path) => {
+                        explanation.push_str(&format!("  {} remembers {} instead\n", CONFIG_FILE_NAME, path.display()));
+                        resolution = match path.file_name().is_some_and(|file_name| file_name == "__init__.py") {
+                            true => Resolution::Package(path.clone()),
+                            false => Resolution::Module(path.clone()),
+                        };
+                    }
+                    Some(ResolutionChoice::Path(path)) => {
+                        explanation.push_str(&format!("  {} remembers {}, which no longer exists\n", CONFIG_FILE_NAME, path.display()));
+                    }
+                    None => {}
+                }
+            }
+            if resolution.file().is_none() {
+                explanation.push_str(&format!("\nCould not find module {}: {}\n", module, self.failure(module).unwrap_or_default()));
+                return explanation;
+            }
+            resolved = Some(resolution);
+        }
+        explanation.push_str(&format!("\n{} resolves to the {}\n", module, resolved.unwrap().describe()));
+        explanation
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.fs.exists(path).unwrap_or(false)
+    }
+
+    /// A compiled extension module for `module_path`: `name.so`, `name.pyd`, or one with a
+    /// platform tag, such as `name.cpython-312-x86_64-linux-gnu.so`
+    fn extension_module(&self, module_path: &Path) -> Option<PathBuf> {
+        let name = module_path.file_name()?.to_str()?;
+        let entries = self.fs.read_dir(module_path.parent()?).ok()?;
+        entries.into_iter().filter(|entry| self.fs.is_file(entry).unwrap_or(false)).find(|entry| {
+            let file_name = entry.file_name().and_then(|file_name| file_name.to_str()).unwrap_or_default();
+            file_name.strip_prefix(name).and_then(|rest| rest.strip_prefix('.'))
+                .is_some_and(|rest| ["so", "pyd"].iter().any(|suffix| rest == *suffix || rest.ends_with(&format!(".{}", suffix))))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+
+    fn project() -> VirtualFileSystem {
+        let mock_fs = VirtualFileSystem::new();
+        for dir in ["/project/mylib", "/site/mylib", "/site/other", "/site/namespace"] {
+            mock_fs.mkdir_p(Path::new(dir)).unwrap();
+        }
+        mock_fs.write(Path::new("/project/mylib/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/project/mylib/sub.py"), b"").unwrap();
+        mock_fs.write(Path::new("/project/mylib.py"), b"").unwrap();
+        mock_fs.write(Path::new("/site/mylib/__init__.py"), b"").unwrap();
+        mock_fs.write(Path::new("/site/other.py"), b"").unwrap();
+        mock_fs.write(Path::new("/site/_speedups.cpython-312-x86_64-linux-gnu.so"), b"").unwrap();
+        mock_fs
+    }
+
+    fn search_dirs() -> Vec<PathBuf> {
+        vec![PathBuf::from("/project"), PathBuf::from("/missing"), PathBuf::from("/site")]
+    }
+
+    #[test]
+    fn test_trace() {
+        let mock_fs = project();
+        let resolver = ModuleResolver::new(&mock_fs, &search_dirs());
+        let trace = resolver.trace("mylib");
+        assert_eq!(trace.resolution, Resolution::Package(PathBuf::from("/project/mylib/__init__.py")));
+        assert_eq!(trace.matched(), Some(0));
+        assert_eq!(trace.candidates[0].shadowed_module, Some(PathBuf::from("/project/mylib.py")));
+        assert!(!trace.candidates[1].searched);
+        assert_eq!(resolver.candidates("mylib"), vec![PathBuf::from("/project/mylib/__init__.py"), PathBuf::from("/site/mylib/__init__.py")]);
+        assert_eq!(resolver.trace("mylib.sub").resolution, Resolution::Module(PathBuf::from("/project/mylib/sub.py")));
+
+        assert_eq!(resolver.trace("_speedups").resolution, Resolution::Extension(PathBuf::from("/site/_speedups.cpython-312-x86_64-linux-gnu.so")));
+        assert_eq!(resolver.trace("namespace").resolution, Resolution::Namespace(PathBuf::from("/site/namespace")));
+        assert!(resolver.candidates("namespace").is_empty());
+
+        // The same directory twice on the search path finds one file
+        let resolver = ModuleResolver::new(&mock_fs, &[PathBuf::from("/site"), PathBuf::from("/site/mylib/..")]);
+        assert_eq!(resolver.trace("other").candidates[1].same_as, Some(0));
+        assert_eq!(resolver.candidates("other"), vec![PathBuf::from("/site/other.py")]);
+    }
+
+    #[test]
+    fn test_cached_trace() {
+        let mock_fs = project();
+        let cache = ResolutionCache::default();
+        let resolver = ModuleResolver::new(&mock_fs, &search_dirs()).cached(&cache);
+        assert_eq!(resolver.candidates("other"), vec![PathBuf::from("/site/other.py")]);
+        assert_eq!(resolver.failure("mylib.gone").unwrap(), "package mylib has no submodule gone");
+        // other, mylib, and gone in the package directory
+        assert_eq!(cache.traces.borrow().len(), 3);
+
+        // Looking a name up again doesn't go to the file system
+        mock_fs.remove_file(Path::new("/site/other.py")).unwrap();
+        assert_eq!(resolver.candidates("other"), vec![PathBuf::from("/site/other.py")]);
+        assert!(ModuleResolver::new(&mock_fs, &search_dirs()).candidates("other").is_empty());
+        // A lookup in other directories is a different one
+        assert!(ModuleResolver::new(&mock_fs, &[PathBuf::from("/site")]).cached(&cache).candidates("other").is_empty());
+        assert_eq!(cache.traces.borrow().len(), 4);
+    }
+
+    #[test]
+    fn test_failure() {
+        let mock_fs = project();
+        let resolver = ModuleResolver::new(&mock_fs, &search_dirs());
+        assert_eq!(resolver.failure("mylib.sub"), None);
+        assert_eq!(resolver.failure("mylib.gone").unwrap(), "package mylib has no submodule gone");
+        assert_eq!(resolver.failure("other.sub").unwrap(), "other is the module /site/other.py, not a package, so it has no submodules");
+        assert_eq!(resolver.failure("_speedups").unwrap(), "_speedups is the compiled extension module /site/_speedups.cpython-312-x86_64-linux-gnu.so, which can't be inlined");
+        assert_eq!(resolver.failure("namespace.sub").unwrap(), "namespace is the namespace package /site/namespace, a directory without __init__.py, which isn't inlined");
+        assert_eq!(resolver.failure("absent").unwrap(), "none of the 3 directories searched has absent");
+    }
+
+    #[test]
+    fn test_explain() {
+        let mock_fs = project();
+        let resolver = ModuleResolver::new(&mock_fs, &search_dirs());
+        assert_eq!(resolver.explain("mylib.sub", &ResolutionChoices::default()), "\
+Resolving mylib.sub on a search path of 3 directories
+
+mylib
+  1. /project: package /project/mylib/__init__.py, matched (mylib.py next to it loses to the package)
+  2. /missing: not a directory, skipped
+  3. /site: package /site/mylib/__init__.py, shadowed by the match in directory 1
+
+mylib.sub, in package /project/mylib
+  1. /project/mylib: module /project/mylib/sub.py, matched
+
+mylib.sub resolves to the module /project/mylib/sub.py
+");
+        let explanation = resolver.explain("namespace", &ResolutionChoices::default());
+        assert!(explanation.contains("  1. /project: no namespace/__init__.py or namespace.py\n"));
+        assert!(explanation.contains("  3. /site: namespace package /site/namespace, a directory without __init__.py, which isn't inlined\n"));
+        assert!(explanation.ends_with("\nCould not find module namespace: namespace is the namespace package /site/namespace, a directory without __init__.py, which isn't inlined\n"));
+        let explanation = resolver.explain("other.sub", &ResolutionChoices::default());
+        assert!(explanation.ends_with("\nCould not find module other.sub: other is the module /site/other.py, not a package, so it has no submodules\n"));
+
+        let mut choices = ResolutionChoices::default();
+        choices.remember("mylib", ResolutionChoice::Path(PathBuf::from("/site/mylib/__init__.py")));
+        let explanation = resolver.explain("mylib", &choices);
+        assert!(explanation.ends_with(&format!("  {} remembers /site/mylib/__init__.py instead\n\nmylib resolves to the package /site/mylib/__init__.py\n", CONFIG_FILE_NAME)));
+        choices.remember("mylib", ResolutionChoice::Skip);
+        assert!(resolver.explain("mylib", &choices).ends_with("remembers to leave mylib as an import\n"));
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt::{self, Write};
+use crate::modules::source_analysis::string_spans;
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Text(String),
+    /// A rope whose lines get `indent` when written out
+    Indented { indent: String, body: Rope, changes_strings: bool },
+}
+
+/// Bundle text kept as the pieces it was built from, so that inlining a module into another
+/// doesn't copy the module's text again at every level of nesting. A module inlined into an
+/// indented block keeps its indent alongside, which is only applied, line by line, when the
+/// rope is written out.
+#[derive(Debug, Clone, Default)]
+pub struct Rope {
+    pieces: Vec<Piece>,
+}
+
+/// A position in a rope, to look at what was added after it
+#[derive(Debug, Clone, Copy)]
+pub struct RopeMark {
+    pieces: usize,
+    text_len: usize,
+}
+
+impl Rope {
+    pub fn new() -> Rope {
+        Rope::default()
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.pieces.last_mut() {
+            Some(Piece::Text(last)) => last.push_str(text),
+            _ => self.pieces.push(Piece::Text(text.to_string())),
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.push_str(c.encode_utf8(&mut [0; 4]));
+    }
+
+    pub fn append(&mut self, rope: Rope) {
+        for piece in rope.pieces {
+            match piece {
+                Piece::Text(text) => self.push_str(&text),
+                piece => self.pieces.push(piece),
+            }
+        }
+    }
+
+    /// Add `body` with `indent` in front of each of its lines, except blank lines and lines that
+    /// continue a multi-line string, whose value indenting them would change. Each line ends
+    /// with a newline. `changes_strings` is whether indenting the body's text changes one of its
+    /// multi-line strings anyway, for nested_changes_strings().
+    pub fn push_indented(&mut self, indent: &str, body: Rope, changes_strings: bool) {
+        self.pieces.push(Piece::Indented { indent: indent.to_string(), body, changes_strings });
+    }
+
+    /// Whether the rope writes out nothing
+    pub fn is_empty(&self) -> bool {
+        self.pieces.iter().all(|piece| match piece {
+            Piece::Text(text) => text.is_empty(),
+            Piece::Indented { body, .. } => body.is_empty(),
+        })
+    }
+
+    /// The text of the rope itself, leaving out the ropes indented into it
+    pub fn own_text(&self) -> impl Iterator<Item = &str> {
+        self.pieces.iter().filter_map(|piece| match piece {
+            Piece::Text(text) => Some(text.as_str()),
+            Piece::Indented { .. } => None,
+        })
+    }
+
+    /// Whether a rope indented into this one, or into one of those, changes one of its
+    /// multi-line strings when indented
+    pub fn nested_changes_strings(&self) -> bool {
+        self.pieces.iter().any(|piece| matches!(piece, Piece::Indented { changes_strings: true, .. }))
+    }
+
+    pub fn mark(&self) -> RopeMark {
+        let text_len = match self.pieces.last() {
+            Some(Piece::Text(text)) => text.len(),
+            _ => 0,
+        };
+        RopeMark { pieces: self.pieces.len(), text_len }
+    }
+
+    /// Whether a line added after `mark` passes `predicate`, with the indentation of indented
+    /// ropes left out
+    pub fn any_line_since(&self, mark: RopeMark, predicate: &dyn Fn(&str) -> bool) -> bool {
+        let grown = match mark.pieces.checked_sub(1).map(|index| &self.pieces[index]) {
+            Some(Piece::Text(text)) => text[mark.text_len..].lines().any(predicate),
+            _ => false,
+        };
+        grown || self.pieces[mark.pieces..].iter().any(|piece| match piece {
+            Piece::Text(text) => text.lines().any(predicate),
+            Piece::Indented { body, .. } => body.any_line_since(RopeMark { pieces: 0, text_len: 0 }, predicate),
+        })
+    }
+
+    /// The rope as one string, with every indent applied
+    pub fn render(&self) -> String {
+        let mut rendered = String::new();
+        self.write(&mut rendered, "", false, &mut true).unwrap();
+        rendered
+    }
+
+    /// Write the rope out. Inside an indented rope (`indenting`), `prefix` is put in front of
+    /// each line; `at_line_start` is whether the last character written ended a line.
+    fn write(&self, out: &mut dyn Write, prefix: &str, indenting: bool, at_line_start: &mut bool) -> fmt::Result {
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) if !indenting => {
+                    out.write_str(text)?;
+                    *at_line_start = text.ends_with('\n');
+                }
+                Piece::Text(text) => {
+                    let spans = string_spans(text);
+                    let mut line_start = 0;
+                    for line in text.split_inclusive('\n') {
+                        let inside_string = spans.iter().any(|(start, end)| *start < line_start && line_start < *end);
+                        line_start += line.len();
+                        let ends_line = line.ends_with('\n');
+                        let line = line.strip_suffix('\n').unwrap_or(line);
+                        let line = line.strip_suffix('\r').unwrap_or(line);
+                        if *at_line_start && !line.is_empty() && !inside_string {
+                            out.write_str(prefix)?;
+                        }
+                        out.write_str(line)?;
+                        if ends_line {
+                            out.write_char('\n')?;
+                        }
+                        *at_line_start = ends_line || (*at_line_start && line.is_empty());
+                    }
+                }
+                Piece::Indented { indent, body, .. } => {
+                    *at_line_start = true;
+                    body.write(out, &format!("{}{}", prefix, indent), true, at_line_start)?;
+                    if !*at_line_start {
+                        out.write_char('\n')?;
+                        *at_line_start = true;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, "", false, &mut true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut inner = Rope::new();
+        inner.push_str("def f():\n    return '''a\nb'''\n\nx = 1");
+        let mut module = Rope::new();
+        module.push_str("# ↓↓↓ inlined module: inner\n");
+        module.push_indented("  ", inner, false);
+        module.push_str("y = 2\r\n");
+        let mut bundle = Rope::new();
+        bundle.push_str("if True:\n");
+        bundle.push_indented("    ", module, true);
+        bundle.push('\n');
+        bundle.push_str("z = 3\n");
+        // Indents add up, and lines continuing a string, blank lines and the text outside
+        // every indented rope are left alone
+        assert_eq!(bundle.render(), "if True:\n    # ↓↓↓ inlined module: inner\n      def f():\n          return '''a\nb'''\n\n      x = 1\n    y = 2\n\nz = 3\n");
+        assert_eq!(bundle.to_string(), bundle.render());
+        assert!(bundle.nested_changes_strings());
+        assert_eq!(bundle.own_text().collect::<Vec<_>>(), vec!["if True:\n", "\nz = 3\n"]);
+    }
+
+    #[test]
+    fn test_any_line_since() {
+        let mut rope = Rope::new();
+        rope.push_str("x = 1\n");
+        let mark = rope.mark();
+        rope.push_str("# comment\n");
+        let is_code = |line: &str| !line.trim().is_empty() && !line.trim_start().starts_with('#');
+        assert!(!rope.any_line_since(mark, &is_code));
+        let mut body = Rope::new();
+        body.push_str("\ny = 2\n");
+        rope.push_indented("    ", body, false);
+        assert!(rope.any_line_since(mark, &is_code));
+
+        assert!(Rope::new().is_empty());
+        let mut empty = Rope::new();
+        empty.push_indented("  ", Rope::new(), false);
+        assert!(empty.is_empty());
+        assert_eq!(empty.render(), "");
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use regex::Regex;
+use serde_json::{json, Value};
+use crate::modules::file_system::FileSystem;
+use crate::modules::licenses::declared_license;
+use crate::modules::requirements::{metadata_field, record_paths};
+use crate::modules::stamp::utc_timestamp;
+use crate::utils::sha1::sha1_hex;
+use crate::utils::sha256::sha256_hex;
+
+/// The format of the --sbom document
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SbomFormat {
+    /// CycloneDX 1.5 JSON
+    CycloneDx,
+    /// SPDX 2.3 JSON
+    Spdx,
+}
+
+impl FromStr for SbomFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<SbomFormat, String> {
+        match value {
+            "cyclonedx" => Ok(SbomFormat::CycloneDx),
+            "spdx" => Ok(SbomFormat::Spdx),
+            _ => Err(format!("unknown SBOM format {:?}, expected cyclonedx or spdx", value)),
+        }
+    }
+}
+
+impl SbomFormat {
+    /// The format a file name asks for: SPDX for `*.spdx.json` and `*.spdx`, else CycloneDX
+    pub fn for_file(path: &Path) -> SbomFormat {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+        if name.ends_with(".spdx.json") || name.ends_with(".spdx") { SbomFormat::Spdx } else { SbomFormat::CycloneDx }
+    }
+}
+
+/// A file of a distribution that was inlined, as it was read
+#[derive(Debug, Clone, PartialEq)]
+pub struct SbomFile {
+    /// Relative to the search path directory the distribution is installed in
+    pub path: String,
+    pub sha1: String,
+    pub sha256: String,
+}
+
+/// An installed distribution the bundle inlines code from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SbomComponent {
+    pub name: String,
+    pub version: String,
+    /// The license METADATA declares, as --licenses reads it
+    pub license: Option<String>,
+    pub files: Vec<SbomFile>,
+}
+
+impl SbomComponent {
+    /// The package URL: `pkg:pypi/` and the name normalized as PEP 503 does it
+    pub fn purl(&self) -> String {
+        let name = Regex::new(r"[-_.]+").unwrap().replace_all(&self.name.to_lowercase(), "-").to_string();
+        format!("pkg:pypi/{}@{}", name, self.version)
+    }
+}
+
+/// The installed distributions owning any of the files, found through the RECORD of each
+/// `*.dist-info` directory of the search path, with the files of each that were inlined.
+/// Sorted by name; the project's own files and editable installs belong to none.
+pub fn inlined_components(fs: &dyn FileSystem, python_sys_path: &[PathBuf], files: &HashSet<PathBuf>) -> Vec<SbomComponent> {
+    let mut components = Vec::new();
+    for dir in python_sys_path {
+        let relative_files: Vec<(String, &PathBuf)> = files.iter()
+            .filter_map(|file| Some((file.strip_prefix(dir).ok()?.to_string_lossy().replace('\\', "/"), file)))
+            .collect();
+        if relative_files.is_empty() {
+            continue;
+        }
+        let Ok(mut dist_infos) = fs.glob(&dir.join("*.dist-info")) else { continue };
+        dist_infos.sort();
+        for dist_info in dist_infos {
+            let Ok(record) = fs.read_to_string(&dist_info.join("RECORD")) else { continue };
+            let paths = record_paths(&record);
+            let mut inlined: Vec<SbomFile> = relative_files.iter()
+                .filter(|(relative, _)| paths.contains(relative))
+                .filter_map(|(relative, file)| {
+                    let content = fs.read(file).ok()?;
+                    Some(SbomFile { path: relative.clone(), sha1: sha1_hex(&content), sha256: sha256_hex(&content) })
+                })
+                .collect();
+            if inlined.is_empty() {
+                continue;
+            }
+            inlined.sort_by(|a, b| a.path.cmp(&b.path));
+            let dir_name = dist_info.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let (dir_distribution, dir_version) = dir_name.split_once('-').unwrap_or((&dir_name, ""));
+            let metadata = fs.read_to_string(&dist_info.join("METADATA")).unwrap_or_default();
+            components.push(SbomComponent {
+                name: metadata_field(&metadata, "Name").unwrap_or(dir_distribution).to_string(),
+                version: metadata_field(&metadata, "Version").unwrap_or(dir_version).to_string(),
+                license: declared_license(&metadata).0,
+                files: inlined,
+            });
+        }
+    }
+    components.sort_by_key(|component| component.name.to_lowercase());
+    components
+}
+
+/// The license as an SPDX license expression, if it reads as one (`MIT`, `Apache-2.0 OR MIT`)
+/// rather than as a classifier or free text (`BSD License`)
+fn spdx_expression(license: &str) -> Option<&str> {
+    let expression = Regex::new(r"^\(*[A-Za-z0-9.+-]+\)*(\s+(AND|OR|WITH)\s+\(*[A-Za-z0-9.+-]+\)*)*$").unwrap();
+    expression.is_match(license).then_some(license)
+}
+
+/// Every distribution of the bundles once, in name order
+fn distinct_components(bundles: &[(PathBuf, Vec<SbomComponent>)]) -> Vec<&SbomComponent> {
+    let mut components: Vec<&SbomComponent> = bundles.iter().flat_map(|(_, components)| components).collect();
+    components.sort_by_key(|component| (component.name.to_lowercase(), component.version.clone()));
+    components.dedup_by(|a, b| a.purl() == b.purl());
+    components
+}
+
+/// The SBOM of the bundles of a run, each with the distributions it inlines code from. `created`
+/// is the time of the document, in seconds since the Unix epoch.
+pub fn render_sbom(format: SbomFormat, bundles: &[(PathBuf, Vec<SbomComponent>)], created: u64) -> String {
+    let value = match format {
+        SbomFormat::CycloneDx => cyclonedx(bundles, created),
+        SbomFormat::Spdx => spdx(bundles, created),
+    };
+    // Serializing a Value can't fail
+    serde_json::to_string_pretty(&value).unwrap() + "\n"
+}
+
+/// The bundles as applications depending on the distributions, each with its inlined files
+fn cyclonedx(bundles: &[(PathBuf, Vec<SbomComponent>)], created: u64) -> Value {
+    let bundle_ref = |bundle: &Path| format!("bundle:{}", bundle.display());
+    let mut components: Vec<Value> = bundles.iter()
+        .map(|(bundle, _)| json!({ "type": "application", "bom-ref": bundle_ref(bundle), "name": bundle.display().to_string() }))
+        .collect();
+    components.extend(distinct_components(bundles).into_iter().map(|component| {
+        let mut value = json!({
+            "type": "library",
+            "bom-ref": component.purl(),
+            "name": component.name,
+            "version": component.version,
+            "purl": component.purl(),
+            "components": component.files.iter().map(|file| json!({
+                "type": "file",
+                "bom-ref": format!("{}#{}", component.purl(), file.path),
+                "name": file.path,
+                "hashes": [{ "alg": "SHA-256", "content": file.sha256 }, { "alg": "SHA-1", "content": file.sha1 }],
+            })).collect::<Vec<_>>(),
+        });
+        if let Some(license) = &component.license {
+            value["licenses"] = match spdx_expression(license) {
+                Some(expression) => json!([{ "expression": expression }]),
+                None => json!([{ "license": { "name": license } }]),
+            };
+        }
+        value
+    }));
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": utc_timestamp(created),
+            "tools": { "components": [{ "type": "application", "name": "python-inliner", "version": env!("CARGO_PKG_VERSION") }] },
+        },
+        "components": components,
+        "dependencies": bundles.iter()
+            .map(|(bundle, components)| json!({ "ref": bundle_ref(bundle), "dependsOn": components.iter().map(SbomComponent::purl).collect::<Vec<_>>() }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// The bundles as packages described by the document, containing the distributions' packages,
+/// which contain their inlined files. The namespace is derived from the content, so that the
+/// same bundles always get the same document.
+fn spdx(bundles: &[(PathBuf, Vec<SbomComponent>)], created: u64) -> Value {
+    let components = distinct_components(bundles);
+    let package_id = |component: &SbomComponent| {
+        format!("SPDXRef-Package-{}", components.iter().position(|distinct| distinct.purl() == component.purl()).unwrap() + 1)
+    };
+    let no_assertion = "NOASSERTION";
+    let mut packages = Vec::new();
+    let mut files = Vec::new();
+    let mut relationships = Vec::new();
+    for (index, (bundle, bundle_components)) in bundles.iter().enumerate() {
+        let id = format!("SPDXRef-Bundle-{}", index + 1);
+        packages.push(json!({
+            "SPDXID": id,
+            "name": bundle.display().to_string(),
+            "downloadLocation": no_assertion,
+            "filesAnalyzed": false,
+            "primaryPackagePurpose": "APPLICATION",
+            "licenseConcluded": no_assertion,
+            "licenseDeclared": no_assertion,
+            "copyrightText": no_assertion,
+        }));
+        relationships.push(json!({ "spdxElementId": "SPDXRef-DOCUMENT", "relationshipType": "DESCRIBES", "relatedSpdxElement": id }));
+        for component in bundle_components {
+            relationships.push(json!({ "spdxElementId": id, "relationshipType": "CONTAINS", "relatedSpdxElement": package_id(component) }));
+        }
+    }
+    for component in &components {
+        let id = package_id(component);
+        let mut package = json!({
+            "SPDXID": id,
+            "name": component.name,
+            "versionInfo": component.version,
+            "downloadLocation": no_assertion,
+            "filesAnalyzed": false,
+            "primaryPackagePurpose": "LIBRARY",
+            "licenseConcluded": no_assertion,
+            "licenseDeclared": component.license.as_deref().and_then(spdx_expression).unwrap_or(no_assertion),
+            "copyrightText": no_assertion,
+            "externalRefs": [{ "referenceCategory": "PACKAGE-MANAGER", "referenceType": "purl", "referenceLocator": component.purl() }],
+        });
+        if let Some(license) = component.license.as_deref().filter(|license| spdx_expression(license).is_none()) {
+            package["licenseComments"] = json!(format!("METADATA declares: {}", license));
+        }
+        packages.push(package);
+        for (index, file) in component.files.iter().enumerate() {
+            let file_id = format!("{}-File-{}", id, index + 1);
+            files.push(json!({
+                "SPDXID": file_id,
+                "fileName": format!("./{}", file.path),
+                "checksums": [{ "algorithm": "SHA1", "checksumValue": file.sha1 }, { "algorithm": "SHA256", "checksumValue": file.sha256 }],
+                "licenseConcluded": no_assertion,
+                "copyrightText": no_assertion,
+            }));
+            relationships.push(json!({ "spdxElementId": id, "relationshipType": "CONTAINS", "relatedSpdxElement": file_id }));
+        }
+    }
+    let names: Vec<String> = bundles.iter().map(|(bundle, _)| bundle.display().to_string()).collect();
+    let mut document = json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": names.join(", "),
+        "creationInfo": {
+            "created": utc_timestamp(created),
+            "creators": [format!("Tool: python-inliner-{}", env!("CARGO_PKG_VERSION"))],
+        },
+        "packages": packages,
+        "files": files,
+        "relationships": relationships,
+    });
+    document["documentNamespace"] = json!(format!("https://spdx.org/spdxdocs/python-inliner-{}", sha256_hex(document.to_string().as_bytes())));
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+
+    fn component(name: &str, license: Option<&str>) -> SbomComponent {
+        SbomComponent {
+            name: name.to_string(),
+            version: "1.0".to_string(),
+            license: license.map(str::to_string),
+            files: vec![SbomFile { path: format!("{}/__init__.py", name.to_lowercase()), sha1: "1".repeat(40), sha256: "2".repeat(64) }],
+        }
+    }
+
+    #[test]
+    fn test_inlined_components() {
+        let fs = VirtualFileSystem::new();
+        let site_packages = Path::new("/venv/site-packages");
+        fs.mkdir_p(&site_packages.join("extlib")).unwrap();
+        fs.mkdir_p(&site_packages.join("extlib-1.2.dist-info")).unwrap();
+        fs.mkdir_p(Path::new("/src/mylib")).unwrap();
+        fs.write(&site_packages.join("extlib/__init__.py"), b"abc").unwrap();
+        fs.write(&site_packages.join("extlib/unused.py"), b"").unwrap();
+        fs.write(&site_packages.join("extlib-1.2.dist-info/RECORD"), b"extlib/__init__.py,,\nextlib/unused.py,,\n").unwrap();
+        fs.write(&site_packages.join("extlib-1.2.dist-info/METADATA"), b"Name: Ext_Lib\nVersion: 1.2\nLicense-Expression: MIT\n").unwrap();
+        fs.write(Path::new("/src/mylib/__init__.py"), b"").unwrap();
+
+        let files = HashSet::from([site_packages.join("extlib/__init__.py"), PathBuf::from("/src/mylib/__init__.py")]);
+        let components = inlined_components(&fs, &[site_packages.to_path_buf(), PathBuf::from("/src")], &files);
+        assert_eq!(components, vec![SbomComponent {
+            name: "Ext_Lib".to_string(),
+            version: "1.2".to_string(),
+            license: Some("MIT".to_string()),
+            files: vec![SbomFile {
+                path: "extlib/__init__.py".to_string(),
+                sha1: "a9993e364706816aba3e25717850c26c9cd0d89d".to_string(),
+                sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string(),
+            }],
+        }]);
+        assert_eq!(components[0].purl(), "pkg:pypi/ext-lib@1.2");
+    }
+
+    #[test]
+    fn test_cyclonedx() {
+        let bundles = vec![
+            (PathBuf::from("dist/app.py"), vec![component("Jinja2", Some("BSD License")), component("attrs", Some("MIT"))]),
+            (PathBuf::from("dist/tool.py"), vec![component("attrs", Some("MIT"))]),
+        ];
+        let sbom: Value = serde_json::from_str(&render_sbom(SbomFormat::CycloneDx, &bundles, 0)).unwrap();
+        assert_eq!((&sbom["bomFormat"], &sbom["specVersion"], &sbom["metadata"]["timestamp"]), (&json!("CycloneDX"), &json!("1.5"), &json!("1970-01-01T00:00:00Z")));
+        let names: Vec<&str> = sbom["components"].as_array().unwrap().iter().map(|component| component["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["dist/app.py", "dist/tool.py", "attrs", "Jinja2"]);
+        let attrs = &sbom["components"][2];
+        assert_eq!(attrs["licenses"], json!([{ "expression": "MIT" }]));
+        assert_eq!(attrs["components"][0]["hashes"][0], json!({ "alg": "SHA-256", "content": "2".repeat(64) }));
+        assert_eq!(sbom["components"][3]["licenses"], json!([{ "license": { "name": "BSD License" } }]));
+        assert_eq!(sbom["dependencies"][0], json!({ "ref": "bundle:dist/app.py", "dependsOn": ["pkg:pypi/jinja2@1.0", "pkg:pypi/attrs@1.0"] }));
+    }
+
+    #[test]
+    fn test_spdx() {
+        let bundles = vec![(PathBuf::from("dist/app.py"), vec![component("attrs", Some("MIT")), component("Jinja2", Some("BSD License")), component("legacy", None)])];
+        let rendered = render_sbom(SbomFormat::Spdx, &bundles, 86400);
+        assert_eq!(rendered, render_sbom(SbomFormat::Spdx, &bundles, 86400));
+        let sbom: Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(sbom["creationInfo"]["created"], "1970-01-02T00:00:00Z");
+        assert!(sbom["documentNamespace"].as_str().unwrap().starts_with("https://spdx.org/spdxdocs/python-inliner-"));
+        let packages = sbom["packages"].as_array().unwrap();
+        assert_eq!(packages.len(), 4);
+        assert_eq!((&packages[1]["name"], &packages[1]["licenseDeclared"]), (&json!("attrs"), &json!("MIT")));
+        assert_eq!((&packages[2]["licenseDeclared"], &packages[2]["licenseComments"]), (&json!("NOASSERTION"), &json!("METADATA declares: BSD License")));
+        assert_eq!(packages[3]["licenseDeclared"], "NOASSERTION");
+        assert_eq!(sbom["files"][0]["fileName"], "./attrs/__init__.py");
+        let relationships: Vec<String> = sbom["relationships"].as_array().unwrap().iter()
+            .map(|relationship| format!("{} {} {}", relationship["spdxElementId"].as_str().unwrap(), relationship["relationshipType"].as_str().unwrap(), relationship["relatedSpdxElement"].as_str().unwrap()))
+            .collect();
+        assert_eq!(&relationships[..3], ["SPDXRef-DOCUMENT DESCRIBES SPDXRef-Bundle-1", "SPDXRef-Bundle-1 CONTAINS SPDXRef-Package-1", "SPDXRef-Bundle-1 CONTAINS SPDXRef-Package-2"]);
+        assert!(relationships.contains(&"SPDXRef-Package-1 CONTAINS SPDXRef-Package-1-File-1".to_string()), "{:?}", relationships);
+    }
+
+    #[test]
+    fn test_sbom_format() {
+        assert_eq!(SbomFormat::for_file(Path::new("out/bom.SPDX.json")), SbomFormat::Spdx);
+        assert_eq!(SbomFormat::for_file(Path::new("out.cdx.json")), SbomFormat::CycloneDx);
+        assert_eq!("spdx".parse::<SbomFormat>(), Ok(SbomFormat::Spdx));
+        assert_eq!(spdx_expression("(Apache-2.0 OR MIT) AND BSD-3-Clause"), Some("(Apache-2.0 OR MIT) AND BSD-3-Clause"));
+        assert_eq!(spdx_expression("MIT License"), None);
+    }
+}
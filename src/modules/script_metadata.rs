@@ -0,0 +1,234 @@
+use regex::Regex;
+use crate::modules::requirements::{external_imports, InstalledDistribution};
+
+/// Parsed PEP 723 inline script metadata (`# /// script` ... `# ///`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptMetadata {
+    /// The raw comment block, including the opening and closing markers
+    pub block: String,
+    /// The `requires-python` specifier, if present
+    pub requires_python: Option<String>,
+    /// The PEP 508 requirement strings listed under `dependencies`
+    pub dependencies: Vec<String>,
+}
+
+fn script_block_regex() -> Regex {
+    // Taken from the reference implementation in PEP 723
+    Regex::new(r"(?m)^# /// (?P<type>[a-zA-Z0-9-]+)[ \t]*\r?\n(?P<content>(?:^#(?:| .*)\r?\n)*?)^# ///[ \t]*$").unwrap()
+}
+
+/// Find the `# /// script` block in the content and parse the fields we care about.
+/// Returns None if the content has no script metadata block.
+pub fn parse_script_metadata(content: &str) -> Option<ScriptMetadata> {
+    let block_regex = script_block_regex();
+    let cap = block_regex.captures_iter(content).find(|cap| &cap["type"] == "script")?;
+
+    // Strip the leading "# " (or "#" for empty lines) to recover the embedded TOML
+    let toml: String = cap["content"]
+        .lines()
+        .map(|line| line.strip_prefix("# ").unwrap_or_else(|| line.trim_start_matches('#')))
+        .collect::<Vec<&str>>()
+        .join("\n");
+
+    let requires_python_regex = Regex::new(r#"(?m)^\s*requires-python\s*=\s*["']([^"']*)["']"#).unwrap();
+    let requires_python = requires_python_regex.captures(&toml).map(|c| c[1].to_string());
+
+    let dependencies_regex = Regex::new(r#"(?m)^\s*dependencies\s*=\s*\[((?:\s*(?:"[^"]*"|'[^']*')\s*,?)*)\s*\]"#).unwrap();
+    let string_regex = Regex::new(r#""([^"]*)"|'([^']*)'"#).unwrap();
+    let dependencies = match dependencies_regex.captures(&toml) {
+        Some(deps) => string_regex
+            .captures_iter(&deps[1])
+            .filter_map(|c| c.get(1).or_else(|| c.get(2)).map(|m| m.as_str().trim().to_string()))
+            .filter(|d| !d.is_empty())
+            .collect(),
+        None => Vec::new(),
+    };
+
+    Some(ScriptMetadata {
+        block: cap.get(0).unwrap().as_str().to_string(),
+        requires_python,
+        dependencies,
+    })
+}
+
+/// Extract the distribution name from a PEP 508 requirement string
+/// e.g. `"prompt-toolkit[extra]>=3.0; python_version > '3.8'"` -> `"prompt-toolkit"`
+pub fn requirement_name(requirement: &str) -> Option<String> {
+    let name_regex = Regex::new(r"^\s*([A-Za-z0-9][A-Za-z0-9._-]*)").unwrap();
+    name_regex.captures(requirement).map(|c| c[1].to_string())
+}
+
+/// Best-effort mapping from a distribution name to the top-level import name.
+/// Most distributions follow the convention of lowercasing and replacing `-`/`.` with `_`.
+pub fn distribution_import_name(distribution: &str) -> String {
+    distribution.to_lowercase().replace(['-', '.'], "_")
+}
+
+/// Build a metadata block for the given Python requirement and dependency list
+pub fn render_script_metadata(requires_python: Option<&str>, dependencies: &[String]) -> ScriptMetadata {
+    let mut lines = vec!["# /// script".to_string()];
+    if let Some(requires_python) = requires_python {
+        lines.push(format!("# requires-python = \"{}\"", requires_python));
+    }
+    if dependencies.is_empty() {
+        lines.push("# dependencies = []".to_string());
+    } else {
+        lines.push("# dependencies = [".to_string());
+        for dependency in dependencies {
+            lines.push(format!("#     \"{}\",", dependency));
+        }
+        lines.push("# ]".to_string());
+    }
+    lines.push("# ///".to_string());
+
+    ScriptMetadata {
+        block: lines.join("\n"),
+        requires_python: requires_python.map(String::from),
+        dependencies: dependencies.to_vec(),
+    }
+}
+
+/// Move the entry script's metadata block to the top of the content (after the shebang),
+/// and drop any `script` blocks that came along with inlined modules. Blocks of other types
+/// aren't the bundle's metadata, and stay where they are.
+pub fn hoist_script_metadata(content: &str, metadata: &ScriptMetadata) -> String {
+    let block_regex = script_block_regex();
+    let mut body = String::new();
+    let mut last_end = 0;
+    for cap in block_regex.captures_iter(content).filter(|cap| &cap["type"] == "script") {
+        let m = cap.get(0).unwrap();
+        body.push_str(&content[last_end..m.start()]);
+        last_end = m.end();
+        // Swallow the newline that terminated the closing marker
+        if content[last_end..].starts_with("\r\n") {
+            last_end += 2;
+        } else if content[last_end..].starts_with('\n') {
+            last_end += 1;
+        }
+    }
+    body.push_str(&content[last_end..]);
+
+    let mut result = String::new();
+    let mut rest = body.as_str();
+    if rest.starts_with("#!") {
+        let shebang_end = rest.find('\n').map(|p| p + 1).unwrap_or(rest.len());
+        result.push_str(&rest[..shebang_end]);
+        if !result.ends_with('\n') {
+            result.push('\n');
+        }
+        rest = &rest[shebang_end..];
+    }
+    result.push_str(&metadata.block);
+    result.push('\n');
+    result.push_str(rest);
+    result
+}
+
+/// The installed distribution providing a top-level module, if any
+fn module_distribution<'a>(module: &str, distributions: &'a [InstalledDistribution]) -> Option<&'a str> {
+    distributions.iter()
+        .find(|distribution| distribution.import_names.iter().any(|name| name == module))
+        .map(|distribution| distribution.name.as_str())
+}
+
+/// Whether a requirement is for the distribution providing a top-level module, or for one
+/// named like the module when no installed distribution provides it
+fn requires_module(requirement: &str, module: &str, distributions: &[InstalledDistribution]) -> bool {
+    requirement_name(requirement).is_some_and(|name| match module_distribution(module, distributions) {
+        Some(distribution) => distribution_import_name(&name) == distribution_import_name(distribution),
+        None => distribution_import_name(&name) == module,
+    })
+}
+
+/// Replace any script metadata in the bundle with a block listing its external dependencies.
+/// Every requirement the entry script declares is kept as written, imported or not (it may be
+/// loaded dynamically or by a plugin), except those for the `inlined` top-level modules, whose
+/// code the bundle now carries. The external imports no requirement covers are added, each
+/// named by the installed distribution providing the module (`PyYAML` for `yaml`), or by the
+/// module itself when none does.
+pub fn emit_script_metadata(content: &str, entry_metadata: Option<&ScriptMetadata>, requires_python: Option<&str>, distributions: &[InstalledDistribution], inlined: &[String]) -> String {
+    let mut dependencies: Vec<String> = entry_metadata
+        .map(|metadata| metadata.dependencies.iter()
+            .filter(|requirement| !inlined.iter().any(|module| requires_module(requirement, module, distributions)))
+            .cloned()
+            .collect())
+        .unwrap_or_default();
+    for module in external_imports(content) {
+        if dependencies.iter().any(|requirement| requires_module(requirement, &module, distributions)) {
+            continue;
+        }
+        let dependency = module_distribution(&module, distributions).map(String::from).unwrap_or(module);
+        if !dependencies.contains(&dependency) {
+            dependencies.push(dependency);
+        }
+    }
+    let requires_python = requires_python.or_else(|| entry_metadata.and_then(|metadata| metadata.requires_python.as_deref()));
+    hoist_script_metadata(content, &render_script_metadata(requires_python, &dependencies))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"#!/usr/bin/env python3
+# /// script
+# requires-python = ">=3.11"
+# dependencies = [
+#     "requests<3",
+#     "prompt-toolkit[extra]>=3.0.47",
+#   'rich',
+# ]
+# ///
+import requests
+"#;
+
+    #[test]
+    fn test_parse_script_metadata() {
+        let metadata = parse_script_metadata(SCRIPT).unwrap();
+        assert_eq!(metadata.requires_python.as_deref(), Some(">=3.11"));
+        assert_eq!(metadata.dependencies, vec!["requests<3", "prompt-toolkit[extra]>=3.0.47", "rich"]);
+        assert!(metadata.block.starts_with("# /// script\n"));
+        assert!(metadata.block.ends_with("# ///"));
+        assert!(parse_script_metadata("import os\n").is_none());
+    }
+
+    #[test]
+    fn test_requirement_to_import_name() {
+        assert_eq!(requirement_name("prompt-toolkit[extra]>=3.0.47").as_deref(), Some("prompt-toolkit"));
+        assert_eq!(requirement_name("rich ; python_version > '3.8'").as_deref(), Some("rich"));
+        assert_eq!(distribution_import_name("Prompt-Toolkit"), "prompt_toolkit");
+        assert_eq!(distribution_import_name("zope.interface"), "zope_interface");
+    }
+
+    #[test]
+    fn test_render_script_metadata() {
+        let metadata = render_script_metadata(Some(">=3.10"), &["requests<3".to_string(), "rich".to_string()]);
+        assert_eq!(metadata.block, "# /// script\n# requires-python = \">=3.10\"\n# dependencies = [\n#     \"requests<3\",\n#     \"rich\",\n# ]\n# ///");
+        // The rendered block must round-trip through the parser
+        assert_eq!(parse_script_metadata(&format!("{}\n", metadata.block)).unwrap(), metadata);
+        assert_eq!(render_script_metadata(None, &[]).block, "# /// script\n# dependencies = []\n# ///");
+    }
+
+    #[test]
+    fn test_emit_script_metadata() {
+        let metadata = parse_script_metadata("# /// script\n# dependencies = [\"plugins>=2\", \"mylib<2\", \"requests<3\"]\n# ///\n").unwrap();
+        let distributions = [InstalledDistribution { name: "PyYAML".to_string(), version: "6.0.1".to_string(), import_names: vec!["yaml".to_string()] }];
+        let content = "import requests\nimport yaml\nimport rich\n";
+        let emitted = emit_script_metadata(content, Some(&metadata), None, &distributions, &["mylib".to_string()]);
+        // plugins isn't imported but stays declared, mylib is inlined
+        assert!(emitted.starts_with("# /// script\n# dependencies = [\n#     \"plugins>=2\",\n#     \"requests<3\",\n#     \"rich\",\n#     \"PyYAML\",\n# ]\n# ///\n"), "Got:\n{}", emitted);
+    }
+
+    #[test]
+    fn test_hoist_script_metadata() {
+        let metadata = parse_script_metadata(SCRIPT).unwrap();
+        let content = format!("#!/usr/bin/env python3\n\"\"\"Doc.\"\"\"\n{}\nx = 1\n# /// script\n# dependencies = []\n# ///\n", metadata.block);
+        let hoisted = hoist_script_metadata(&content, &metadata);
+        assert_eq!(hoisted, format!("#!/usr/bin/env python3\n{}\n\"\"\"Doc.\"\"\"\nx = 1\n", metadata.block));
+
+        // Blocks of other types are left alone
+        let content = format!("{}\n# /// pyproject\n# [tool.example]\n# ///\nx = 1\n", metadata.block);
+        let hoisted = hoist_script_metadata(&content, &metadata);
+        assert_eq!(hoisted, format!("{}\n# /// pyproject\n# [tool.example]\n# ///\nx = 1\n", metadata.block));
+    }
+}
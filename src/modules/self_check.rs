@@ -0,0 +1,117 @@
+use regex::Regex;
+use crate::modules::source_analysis::{first_code_statement, mask_strings_and_comments, string_spans};
+
+/// Offsets of every `__file__` reference in the code of the content, outside strings and
+/// comments. Inlined code shares the bundle's `__file__`, which names the bundle rather than
+/// the module the code came from.
+pub fn dunder_file_references(content: &str) -> Vec<usize> {
+    let dunder_file = Regex::new(r"\b__file__\b").unwrap();
+    dunder_file.find_iter(&mask_strings_and_comments(content)).map(|m| m.start()).collect()
+}
+
+/// The string literals of the content that span several lines, whose values include the
+/// indentation of their continuation lines
+fn multiline_strings(content: &str) -> Vec<(usize, &str)> {
+    string_spans(content).into_iter()
+        .map(|(start, end)| (start, &content[start..end]))
+        .filter(|(_, literal)| literal.contains('\n'))
+        .collect()
+}
+
+/// The offset in `before` of the first multi-line string literal that reads differently in
+/// `after`, the same code re-indented. None if re-indenting left all of their values alone.
+pub fn changed_multiline_string(before: &str, after: &str) -> Option<usize> {
+    let before_strings = multiline_strings(before);
+    let after_strings: Vec<&str> = multiline_strings(after).into_iter().map(|(_, literal)| literal).collect();
+    for (index, (start, literal)) in before_strings.iter().enumerate() {
+        if after_strings.get(index) != Some(literal) {
+            return Some(*start);
+        }
+    }
+    (after_strings.len() > before_strings.len()).then_some(before.len())
+}
+
+/// Whether an import statement, with its strings and comments masked, binds a name under an
+/// alias (`from a import b as c`)
+pub fn binds_alias(masked_statement: &str) -> bool {
+    Regex::new(r"\bas\b").unwrap().is_match(masked_statement)
+}
+
+/// Offsets of the `from __future__` imports of a rendered bundle that come after its first real
+/// statement, or are indented into a block, where Python rejects them
+pub fn misplaced_future_imports(content: &str) -> Vec<usize> {
+    let future_import = Regex::new(r"(?m)^[ \t]*from[ \t]+__future__[ \t]+import\b").unwrap();
+    let code_start = first_code_statement(content);
+    future_import.find_iter(&mask_strings_and_comments(content))
+        .map(|m| m.start())
+        .filter(|offset| *offset >= code_start || content[*offset..].starts_with([' ', '\t']))
+        .collect()
+}
+
+/// A call importing a module by name at runtime, which the static pass can't follow
+#[derive(Debug, PartialEq)]
+pub struct DynamicImport {
+    pub offset: usize,
+    /// The string literal the name starts with: `plugins.` of `"plugins." + name` or of
+    /// `f"plugins.{name}"`
+    pub prefix: Option<String>,
+    /// Whether the name is more than a plain string literal
+    pub computed: bool,
+}
+
+/// The `importlib.import_module(...)`, `import_module(...)` and `__import__(...)` calls in the
+/// code of the content, outside strings and comments
+pub fn dynamic_imports(content: &str) -> Vec<DynamicImport> {
+    let call = Regex::new(r"(?:\bimportlib\s*\.\s*)?\b(?:import_module|__import__)\s*\(").unwrap();
+    let literal = Regex::new(r#"^\s*(?i:[rfu]{0,2})(?:'([^'\\\n{]*)(.)|"([^"\\\n{]*)(.))"#).unwrap();
+    let closes = Regex::new(r"^\s*[,)]").unwrap();
+    let masked = mask_strings_and_comments(content);
+    call.find_iter(&masked)
+        // Methods of other objects, and definitions of functions of the same name
+        .filter(|m| !masked[..m.start()].ends_with('.') && !masked[..m.start()].trim_end().ends_with("def"))
+        .map(|m| {
+            let argument = &content[m.end()..];
+            let Some(cap) = literal.captures(argument) else {
+                return DynamicImport { offset: m.start(), prefix: None, computed: true };
+            };
+            let (prefix, end) = match cap.get(1) {
+                Some(prefix) => (prefix.as_str(), cap.get(2).unwrap()),
+                None => (cap.get(3).unwrap().as_str(), cap.get(4).unwrap()),
+            };
+            // A literal that stops at its closing quote, followed by the end of the argument
+            let whole = matches!(end.as_str(), "'" | "\"") && closes.is_match(&argument[end.end()..]);
+            DynamicImport { offset: m.start(), prefix: Some(prefix.to_string()), computed: !whole }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_checks() {
+        let content = "path = __file__  # __file__\nprint('__file__', os.path.dirname(__file__))\n";
+        assert_eq!(dunder_file_references(content), vec![7, content.rfind("__file__").unwrap()]);
+
+        let before = "def f():\n    return '''a\n    b''' + 'c'\n";
+        assert_eq!(changed_multiline_string(before, "def f():\n\treturn '''a\n    b''' + 'c'\n"), None);
+        assert_eq!(changed_multiline_string(before, "def f():\n\treturn '''a\n\tb''' + 'c'\n"), Some(20));
+
+        assert!(binds_alias("from .helpers import (\n    helper as h,\n)\n"));
+        assert!(!binds_alias("from .assets import passes\n"));
+
+        let bundle = "#!/usr/bin/env python3\n\"\"\"Doc\"\"\"\nfrom __future__ import annotations\nimport os\nfrom __future__ import division\n";
+        assert_eq!(misplaced_future_imports(bundle), vec![bundle.rfind("from __future__").unwrap()]);
+        let bundle = "def f():\n    from __future__ import annotations\n";
+        assert_eq!(misplaced_future_imports(bundle), vec![9]);
+        assert!(misplaced_future_imports("x = '''\nfrom __future__ import annotations'''\n").is_empty());
+
+        let source = "import importlib\nmod = importlib.import_module(\"plugins.\" + name)\nimport_module(f'plugins.{name}')\n__import__('json')\nloader.import_module(name)\ndef import_module(name):\n    pass\n# importlib.import_module(name)\n";
+        assert_eq!(dynamic_imports(source), vec![
+            DynamicImport { offset: source.find("importlib.import_module").unwrap(), prefix: Some("plugins.".to_string()), computed: true },
+            DynamicImport { offset: source.find("import_module(f").unwrap(), prefix: Some("plugins.".to_string()), computed: true },
+            DynamicImport { offset: source.find("__import__").unwrap(), prefix: Some("json".to_string()), computed: false },
+        ]);
+    }
+}
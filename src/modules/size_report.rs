@@ -0,0 +1,97 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A size in bytes for --max-size, written as a number with an optional `K`, `M` or `G`
+/// suffix (powers of 1024, `512K`, `1.5M`, `50MiB`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<ByteSize, String> {
+        let trimmed = value.trim();
+        let number_end = trimmed.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(number_end);
+        let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" | "KIB" => 1 << 10,
+            "M" | "MB" | "MIB" => 1 << 20,
+            "G" | "GB" | "GIB" => 1 << 30,
+            _ => return Err(format!("unknown size unit in {:?}, expected K, M or G", value)),
+        };
+        let number: f64 = number.parse().map_err(|_| format!("invalid size {:?}, expected e.g. 512K or 50M", value))?;
+        Ok(ByteSize((number * multiplier as f64).round() as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            size if size >= 1 << 20 => write!(f, "{:.1} MiB", size as f64 / (1u64 << 20) as f64),
+            size if size >= 1 << 10 => write!(f, "{:.1} KiB", size as f64 / (1u64 << 10) as f64),
+            size => write!(f, "{} bytes", size),
+        }
+    }
+}
+
+/// What an inlined module adds to a bundle on its own, not counting the modules inlined into it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModuleSize {
+    pub bytes: usize,
+    pub lines: usize,
+}
+
+/// The --size-report table of a bundle: its total size, then each module's share of it, largest
+/// first. Whatever the modules don't account for is the entry script's code, the markers and
+/// the code the bundle adds around the modules.
+pub fn render_size_report(output_file: &Path, bytes: usize, lines: usize, modules: &[(String, ModuleSize)]) -> String {
+    let mut rows: Vec<(&str, usize, usize)> = modules.iter().map(|(module, size)| (module.as_str(), size.bytes, size.lines)).collect();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let module_bytes: usize = rows.iter().map(|row| row.1).sum();
+    let module_lines: usize = rows.iter().map(|row| row.2).sum();
+    rows.push(("(entry script and bundle code)", bytes.saturating_sub(module_bytes), lines.saturating_sub(module_lines)));
+
+    let bytes_width = bytes.to_string().len().max("bytes".len());
+    let lines_width = lines.to_string().len().max("lines".len());
+    let mut report = format!("Size of {}: {} bytes ({}), {} lines\n", output_file.display(), bytes, ByteSize(bytes as u64), lines);
+    report.push_str(&format!("  {:>bytes_width$}  {:>lines_width$}   share  module\n", "bytes", "lines"));
+    for (module, module_bytes, module_lines) in rows {
+        let share = if bytes == 0 { 0.0 } else { module_bytes as f64 * 100.0 / bytes as f64 };
+        report.push_str(&format!("  {:>bytes_width$}  {:>lines_width$}  {:>5.1}%  {}\n", module_bytes, module_lines, share, module));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_size() {
+        assert_eq!("512K".parse(), Ok(ByteSize(512 * 1024)));
+        assert_eq!("1.5m".parse(), Ok(ByteSize(1536 * 1024)));
+        assert_eq!("50MiB".parse(), Ok(ByteSize(50 << 20)));
+        assert_eq!("1000".parse(), Ok(ByteSize(1000)));
+        assert!("12 parsecs".parse::<ByteSize>().is_err());
+        assert!("K".parse::<ByteSize>().is_err());
+        assert_eq!(ByteSize(512 * 1024).to_string(), "512.0 KiB");
+        assert_eq!(ByteSize(300).to_string(), "300 bytes");
+    }
+
+    #[test]
+    fn test_render_size_report() {
+        let modules = vec![
+            ("mylib/__init__.py".to_string(), ModuleSize { bytes: 120, lines: 6 }),
+            ("requests/models.py".to_string(), ModuleSize { bytes: 700, lines: 20 }),
+        ];
+        assert_eq!(render_size_report(Path::new("dist/app.py"), 1000, 30, &modules), "\
+Size of dist/app.py: 1000 bytes (1000 bytes), 30 lines
+  bytes  lines   share  module
+    700     20   70.0%  requests/models.py
+    120      6   12.0%  mylib/__init__.py
+    180      4   18.0%  (entry script and bundle code)
+");
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use regex::Regex;
+
+/// Byte ranges of every string literal (including its quotes) and comment in the content,
+/// as (start, end, is_comment), in order
+fn literal_spans(content: &str) -> Vec<(usize, usize, bool)> {
+    let bytes = content.as_bytes();
+    let mut spans = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'#' => {
+                let end = content[pos..].find('\n').map(|p| pos + p).unwrap_or(bytes.len());
+                spans.push((pos, end, true));
+                pos = end;
+            }
+            quote @ (b'"' | b'\'') => {
+                let is_triple = pos + 2 < bytes.len() && bytes[pos + 1] == quote && bytes[pos + 2] == quote;
+                let delimiter_len = if is_triple { 3 } else { 1 };
+                let body_start = pos + delimiter_len;
+                let mut end = body_start;
+                let mut closed = false;
+                while end < bytes.len() {
+                    if bytes[end] == b'\\' {
+                        end += 2;
+                        continue;
+                    }
+                    if !is_triple && bytes[end] == b'\n' {
+                        // Unterminated single-line string, stop at end of line
+                        break;
+                    }
+                    if bytes[end] == quote && (!is_triple || (end + 2 < bytes.len() && bytes[end + 1] == quote && bytes[end + 2] == quote)) {
+                        closed = true;
+                        break;
+                    }
+                    end += 1;
+                }
+                let end = if closed { end + delimiter_len } else { end.min(bytes.len()) };
+                spans.push((pos, end, false));
+                pos = end;
+            }
+            _ => pos += 1,
+        }
+    }
+
+    spans
+}
+
+/// Replace string literals (including their quotes) and comments with spaces.
+/// Newlines and byte offsets are preserved, so positions in the masked text map 1:1 onto the
+/// original content. This lets line-oriented regexes and bracket counting ignore
+/// anything that only *looks* like code.
+pub fn mask_strings_and_comments(content: &str) -> String {
+    let mut masked = content.as_bytes().to_vec();
+    for (start, end, _) in literal_spans(content) {
+        // Blank out the range, keeping line breaks so line numbers stay intact
+        for byte in masked.iter_mut().take(end).skip(start) {
+            if *byte != b'\n' && *byte != b'\r' {
+                *byte = b' ';
+            }
+        }
+    }
+
+    // Masked ranges always start and end at ASCII quotes or newlines, so the result is valid UTF-8
+    String::from_utf8(masked).expect("masking preserves UTF-8 boundaries")
+}
+
+/// Byte ranges (start, end) of the string literals in the content, including their quotes
+pub fn string_spans(content: &str) -> Vec<(usize, usize)> {
+    literal_spans(content)
+        .into_iter()
+        .filter(|(_, _, is_comment)| !is_comment)
+        .map(|(start, end, _)| (start, end))
+        .collect()
+}
+
+/// What kind of top-level statement a span holds
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementKind {
+    Function,
+    Class,
+    Assignment,
+    Import,
+    Other,
+}
+
+/// A top-level (column 0) statement, including its decorators, body and any trailing
+/// blank lines or comments, up to the start of the next top-level statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopLevelStatement {
+    pub start: usize,
+    pub end: usize,
+    pub kind: StatementKind,
+    /// The name bound by a def, class or simple assignment
+    pub name: Option<String>,
+}
+
+/// Split module content into its top-level statements.
+pub fn top_level_statements(content: &str) -> Vec<TopLevelStatement> {
+    let masked = mask_strings_and_comments(content);
+    let def_regex = Regex::new(r"^(?:async\s+)?def\s+([A-Za-z_]\w*)").unwrap();
+    let class_regex = Regex::new(r"^class\s+([A-Za-z_]\w*)").unwrap();
+    let assignment_regex = Regex::new(r"^([A-Za-z_]\w*)\s*(?::[^=]*)?=[^=]").unwrap();
+    let import_regex = Regex::new(r"^(?:import|from)\s").unwrap();
+
+    let mut statements: Vec<TopLevelStatement> = Vec::new();
+    let mut bracket_depth: i32 = 0;
+    let mut line_continues = false;
+    let mut pending_decorator_start: Option<usize> = None;
+    let mut offset = 0;
+
+    for line in masked.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let code = line.trim_end_matches(['\n', '\r']);
+
+        let starts_statement = bracket_depth == 0
+            && !line_continues
+            && !code.trim().is_empty()
+            && !code.starts_with([' ', '\t']);
+
+        for ch in code.chars() {
+            match ch {
+                '(' | '[' | '{' => bracket_depth += 1,
+                ')' | ']' | '}' => bracket_depth -= 1,
+                _ => {}
+            }
+        }
+        line_continues = code.ends_with('\\');
+
+        if !starts_statement {
+            continue;
+        }
+        if code.starts_with('@') {
+            // Decorators belong to the def/class that follows them
+            pending_decorator_start.get_or_insert(line_start);
+            continue;
+        }
+
+        let (kind, name) = if let Some(cap) = def_regex.captures(code) {
+            (StatementKind::Function, Some(cap[1].to_string()))
+        } else if let Some(cap) = class_regex.captures(code) {
+            (StatementKind::Class, Some(cap[1].to_string()))
+        } else if import_regex.is_match(code) {
+            (StatementKind::Import, None)
+        } else if let Some(cap) = assignment_regex.captures(code) {
+            (StatementKind::Assignment, Some(cap[1].to_string()))
+        } else {
+            (StatementKind::Other, None)
+        };
+
+        let start = pending_decorator_start.take().unwrap_or(line_start);
+        if let Some(previous) = statements.last_mut() {
+            previous.end = start;
+        }
+        statements.push(TopLevelStatement { start, end: content.len(), kind, name });
+    }
+
+    statements
+}
+
+/// The end of the statement starting at `start` in content masked by
+/// mask_strings_and_comments(): just past the line break ending it, which may come after lines
+/// continued by open brackets or backslashes, or the end of the content when the statement is
+/// on its last line. A `\r\n` line break is taken whole.
+pub fn statement_end(masked: &str, start: usize) -> usize {
+    let bytes = masked.as_bytes();
+    let mut depth = 0;
+    let mut position = start;
+    while position < bytes.len() {
+        match bytes[position] {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => depth -= 1,
+            b'\\' if bytes[position + 1..].starts_with(b"\r\n") => position += 2,
+            b'\\' if bytes[position + 1..].starts_with(b"\n") => position += 1,
+            b'\n' if depth <= 0 => return position + 1,
+            _ => {}
+        }
+        position += 1;
+    }
+    bytes.len()
+}
+
+/// A statement at any depth: a logical line, which may go on over several physical lines
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogicalStatement {
+    pub start: usize,
+    /// Just past the line break ending it (see statement_end())
+    pub end: usize,
+    /// The width of its indentation
+    pub indent: usize,
+    /// Its code, from the end of the indentation, with strings and comments still in it
+    pub code: String,
+    /// Whether it opens a block on the lines after it (`if x:`, but not `if x: y`)
+    pub opens_block: bool,
+}
+
+/// Split module content into its logical statements, those of blocks included, in order.
+/// Blank lines and lines holding only a comment aren't statements.
+pub fn logical_statements(content: &str) -> Vec<LogicalStatement> {
+    let masked = mask_strings_and_comments(content);
+    let mut statements = Vec::new();
+    let mut position = 0;
+    while position < content.len() {
+        let line_end = masked[position..].find('\n').map_or(masked.len(), |offset| position + offset + 1);
+        let line = &masked[position..line_end];
+        if line.trim().is_empty() {
+            position = line_end;
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+        let end = statement_end(&masked, position);
+        statements.push(LogicalStatement {
+            start: position,
+            end,
+            indent,
+            code: content[position + indent..end].trim_end().to_string(),
+            opens_block: masked[position..end].trim_end().ends_with(':'),
+        });
+        position = end;
+    }
+    statements
+}
+
+/// Count every identifier-like token in the text, including those inside strings
+/// (names listed in `__all__` or passed to getattr() count as references).
+pub fn identifier_counts(text: &str) -> HashMap<String, usize> {
+    let identifier_regex = Regex::new(r"[A-Za-z_]\w*").unwrap();
+    let mut counts = HashMap::new();
+    for m in identifier_regex.find_iter(text) {
+        *counts.entry(m.as_str().to_string()).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// The offset of a script's first real statement: the first one after the shebang and leading
+/// comments, the module docstring and any `from __future__` imports. A future import anywhere
+/// after it is a SyntaxError.
+pub fn first_code_statement(content: &str) -> usize {
+    for (index, statement) in top_level_statements(content).iter().enumerate() {
+        let text = content[statement.start..statement.end].trim_start();
+        let is_docstring = index == 0 && statement.kind == StatementKind::Other && text.starts_with(['"', '\'']);
+        let is_future_import = statement.kind == StatementKind::Import && text.starts_with("from __future__ ");
+        if !is_docstring && !is_future_import {
+            return statement.start;
+        }
+    }
+    content.len()
+}
+
+/// Insert code that has to run first into a script, ahead of its first real statement (see
+/// first_code_statement()), so after its PEP 723 metadata, encoding declaration, docstring and
+/// `from __future__` imports.
+pub fn insert_preamble(entry: &str, preamble: &str) -> String {
+    let mut position = first_code_statement(entry);
+    // Stay outside the markers of inlined modules, and of imports inlined elsewhere, that come
+    // right before the statement
+    while position > 0 {
+        let before = entry[..position].strip_suffix('\n').unwrap_or(&entry[..position]);
+        let line_start = before.rfind('\n').map_or(0, |p| p + 1);
+        let line = entry[line_start..position].trim_start();
+        if !line.starts_with("# ↓↓↓") && !line.starts_with("# →→") {
+            break;
+        }
+        position = line_start;
+    }
+
+    let mut result = entry[..position].to_string();
+    if !result.is_empty() && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    result.push_str(preamble);
+    result.push_str(&entry[position..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_strings_and_comments() {
+        let content = "x = \"from a import b\"  # import c\ny = '''\nimport d\n'''\n";
+        let masked = mask_strings_and_comments(content);
+        assert_eq!(masked.len(), content.len());
+        assert_eq!(masked, format!("x = {}\ny = {}\n{}\n{}\n", " ".repeat(29), " ".repeat(3), " ".repeat(8), " ".repeat(3)));
+    }
+
+    #[test]
+    fn test_top_level_statements() {
+        let content = r#"import os
+
+@decorator
+def helper(a,
+           b):
+    return a
+
+CONFIG = {
+    "key": "value",
+}
+TEMPLATE = """
+not_a_statement = 1
+"""
+
+class Thing:
+    pass
+if __name__ == "__main__":
+    helper(1, 2)
+"#;
+        let statements = top_level_statements(content);
+        let summary: Vec<(StatementKind, Option<&str>)> = statements.iter().map(|s| (s.kind.clone(), s.name.as_deref())).collect();
+        assert_eq!(summary, vec![
+            (StatementKind::Import, None),
+            (StatementKind::Function, Some("helper")),
+            (StatementKind::Assignment, Some("CONFIG")),
+            (StatementKind::Assignment, Some("TEMPLATE")),
+            (StatementKind::Class, Some("Thing")),
+            (StatementKind::Other, None),
+        ]);
+        assert!(content[statements[1].start..statements[1].end].starts_with("@decorator\ndef helper"));
+        assert_eq!(statements.last().unwrap().end, content.len());
+    }
+
+    #[test]
+    fn test_statement_end() {
+        let content = "from a import (b,\n    c)  # both\nfrom a import b, \\\r\n    c\r\nimport d";
+        let masked = mask_strings_and_comments(content);
+        let second = content.find("from a import b,").unwrap();
+        assert_eq!(statement_end(&masked, 0), second);
+        assert_eq!(&content[second..statement_end(&masked, second)], "from a import b, \\\r\n    c\r\n");
+        // The last line has no line break to take
+        assert_eq!(statement_end(&masked, content.find("import d").unwrap()), content.len());
+    }
+
+    #[test]
+    fn test_insert_preamble() {
+        let entry = "#!/usr/bin/env python3\n\"\"\"Entry point\"\"\"\nfrom __future__ import annotations\n\nfrom mylib import f\nf()\n";
+        assert_eq!(
+            insert_preamble(entry, "PREAMBLE\n"),
+            "#!/usr/bin/env python3\n\"\"\"Entry point\"\"\"\nfrom __future__ import annotations\n\nPREAMBLE\nfrom mylib import f\nf()\n"
+        );
+        assert_eq!(insert_preamble("# just a comment", "PREAMBLE\n"), "# just a comment\nPREAMBLE\n");
+        assert_eq!(insert_preamble("#!/bin/python\n# ↓↓↓ inlined package: mylib\nX = 1\n", "PREAMBLE\n"), "#!/bin/python\nPREAMBLE\n# ↓↓↓ inlined package: mylib\nX = 1\n");
+    }
+}
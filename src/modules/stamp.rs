@@ -0,0 +1,107 @@
+use regex::Regex;
+
+/// The build metadata stamped into a bundle with --stamp. Paths are relative to the search path
+/// entry they were found under, so the stamp doesn't depend on where the project is checked out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildStamp {
+    /// The entry script and the hex SHA-256 of its contents
+    pub input: (String, String),
+    /// The --template the bundle was written into and its hex SHA-256
+    pub template: Option<(String, String)>,
+    /// Every inlined file and its hex SHA-256, sorted by path
+    pub inlined: Vec<(String, String)>,
+    /// Seconds since the Unix epoch, with --stamp-time
+    pub timestamp: Option<u64>,
+}
+
+impl BuildStamp {
+    /// The header comment for the bundle
+    pub fn render(&self) -> String {
+        let mut stamp = format!("# Built by python-inliner {}\n", env!("CARGO_PKG_VERSION"));
+        if let Some(timestamp) = self.timestamp {
+            stamp.push_str(&format!("# Built at: {}\n", utc_timestamp(timestamp)));
+        }
+        stamp.push_str(&format!("# Input: {} sha256:{}\n", self.input.0, self.input.1));
+        if let Some((path, hash)) = &self.template {
+            stamp.push_str(&format!("# Template: {} sha256:{}\n", path, hash));
+        }
+        for (path, hash) in &self.inlined {
+            stamp.push_str(&format!("# Inlined: {} sha256:{}\n", path, hash));
+        }
+        stamp
+    }
+}
+
+/// The time to stamp bundles with: SOURCE_DATE_EPOCH when it's set, as reproducible builds
+/// expect, else the current time
+pub fn build_timestamp(source_date_epoch: Option<&str>, now: u64) -> Result<u64, String> {
+    match source_date_epoch {
+        Some(epoch) => epoch.trim().parse().map_err(|_| format!("SOURCE_DATE_EPOCH must be a number of seconds, got {:?}", epoch)),
+        None => Ok(now),
+    }
+}
+
+/// Seconds since the Unix epoch as an RFC 3339 UTC timestamp, e.g. `2024-01-31T12:00:00Z`
+pub fn utc_timestamp(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+    // Civil date from days since 1970-01-01, in 400-year eras starting on March 1st
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+/// Insert the stamp at the top of the bundle, after its shebang and encoding declaration, which
+/// have to stay on the first lines
+pub fn insert_stamp(content: &str, stamp: &str) -> String {
+    let cookie = Regex::new(r"^[ \t\f]*#.*?coding[:=]").unwrap();
+    let mut position = 0;
+    for (index, line) in content.split_inclusive('\n').take(2).enumerate() {
+        if (index == 0 && line.starts_with("#!")) || cookie.is_match(line) {
+            position += line.len();
+        } else {
+            break;
+        }
+    }
+    let mut result = content[..position].to_string();
+    if !result.is_empty() && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    result.push_str(stamp);
+    result.push_str(&content[position..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_stamp() {
+        let stamp = BuildStamp {
+            input: ("main.py".to_string(), "ab".repeat(32)),
+            template: Some(("wrapper.py".to_string(), "ef".repeat(32))),
+            inlined: vec![("mylib/__init__.py".to_string(), "cd".repeat(32))],
+            timestamp: Some(1706702400),
+        };
+        let rendered = stamp.render();
+        assert!(rendered.contains("# Built at: 2024-01-31T12:00:00Z\n"), "{}", rendered);
+        assert!(rendered.ends_with(&format!("# Input: main.py sha256:{}\n# Template: wrapper.py sha256:{}\n# Inlined: mylib/__init__.py sha256:{}\n", "ab".repeat(32), "ef".repeat(32), "cd".repeat(32))));
+        assert_eq!(utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_timestamp(951825599), "2000-02-29T11:59:59Z");
+
+        assert_eq!(insert_stamp("#!/usr/bin/env python3\n# -*- coding: utf-8 -*-\nimport os\n", "# S\n"), "#!/usr/bin/env python3\n# -*- coding: utf-8 -*-\n# S\nimport os\n");
+        assert_eq!(insert_stamp("import os\n", "# S\n"), "# S\nimport os\n");
+
+        assert_eq!(build_timestamp(Some("1700000000"), 5), Ok(1700000000));
+        assert_eq!(build_timestamp(None, 5), Ok(5));
+        assert!(build_timestamp(Some("yesterday"), 5).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use regex::Regex;
+use crate::modules::reexports::top_level_bindings;
+use crate::modules::source_analysis::{identifier_counts, mask_strings_and_comments, top_level_statements, StatementKind};
+
+/// Words that can start a line with `name:` without it being an annotated variable
+const KEYWORDS: &[&str] = &["else", "try", "finally", "except", "lambda", "match", "case"];
+
+/// Public names, and dunder names, which Python gives a meaning
+fn is_public(name: &str) -> bool {
+    !name.starts_with('_') || (name.starts_with("__") && name.ends_with("__"))
+}
+
+/// The offset of the colon that ends the def or class header starting at `start` of the masked
+/// content
+fn header_end(masked: &str, start: usize) -> usize {
+    let mut depth = 0i32;
+    for (index, byte) in masked.bytes().enumerate().skip(start) {
+        match byte {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => depth -= 1,
+            b':' if depth == 0 => return index,
+            _ => {}
+        }
+    }
+    masked.len()
+}
+
+/// A def's header up to its colon, each default value replaced with `...` as stubs have them
+fn signature(content: &str, masked: &str, start: usize, end: usize) -> String {
+    let mut result = String::new();
+    let (mut depth, mut copied, mut index) = (0i32, start, start);
+    let bytes = masked.as_bytes();
+    while index < end {
+        match bytes[index] {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => depth -= 1,
+            b'=' if depth == 1 => {
+                // Masked strings are blank, so whitespace is measured in the content
+                let value_start = index + 1 + (content[index + 1..end].len() - content[index + 1..end].trim_start().len());
+                let mut value_end = value_start;
+                let mut value_depth = 0i32;
+                while value_end < end {
+                    match bytes[value_end] {
+                        b'(' | b'[' | b'{' => value_depth += 1,
+                        b')' | b']' | b'}' if value_depth == 0 => break,
+                        b')' | b']' | b'}' => value_depth -= 1,
+                        b',' if value_depth == 0 => break,
+                        _ => {}
+                    }
+                    value_end += 1;
+                }
+                let value_end = value_start + content[value_start..value_end].trim_end().len();
+                result.push_str(&content[copied..value_start]);
+                result.push_str("...");
+                copied = value_end;
+                index = value_end;
+                continue;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    result.push_str(&content[copied..end]);
+    result
+}
+
+/// Whether a stub keeps the decorator: the ones that change how a function is called or typed
+fn kept_decorator(decorator: &str) -> bool {
+    let kept = Regex::new(r"^@\s*(?:staticmethod|classmethod|property|(?:typing\.)?overload|(?:abc\.)?abstractmethod|\w+\.(?:setter|deleter))\s*$").unwrap();
+    kept.is_match(decorator.trim())
+}
+
+/// The type of a variable's value when it's a literal, else `Any`
+fn literal_type(content: &str, masked: &str) -> &'static str {
+    let (value, masked_value) = (content.trim(), masked.trim());
+    let number = Regex::new(r"^-?\d[\d_]*$").unwrap();
+    let float = Regex::new(r"^-?(?:\d[\d_]*)?\.\d*(?:[eE][-+]?\d+)?$|^-?\d+[eE][-+]?\d+$").unwrap();
+    match masked_value {
+        "True" | "False" => "bool",
+        "None" => "None",
+        _ if number.is_match(masked_value) => "int",
+        _ if float.is_match(masked_value) => "float",
+        // Nothing but string literals, which are masked
+        _ if !value.is_empty() && masked_value.is_empty() => {
+            let prefix: String = value.chars().take_while(|c| c.is_ascii_alphabetic()).collect();
+            if prefix.contains(['b', 'B']) { "bytes" } else { "str" }
+        }
+        _ => "Any",
+    }
+}
+
+/// The stub line of a variable statement `name: T = value`, `name: T` or `name = value`, at the
+/// given indentation, or None when the statement isn't one
+fn variable(content: &str, masked: &str, indent: &str) -> Option<String> {
+    let annotated = Regex::new(r"^([A-Za-z_]\w*)[ \t]*:([^=\n]*[^=\s][^=\n]*)(?:=|$)").unwrap();
+    let assigned = Regex::new(r"^([A-Za-z_]\w*)[ \t]*=(?:[^=]|$)").unwrap();
+    let code = masked.trim_end();
+    if let Some(cap) = annotated.captures(code) {
+        let annotation = cap.get(2).unwrap();
+        return (!KEYWORDS.contains(&&cap[1]) && is_public(&cap[1]))
+            .then(|| format!("{}{}: {}\n", indent, &cap[1], content[annotation.start()..annotation.end()].trim()));
+    }
+    let cap = assigned.captures(code)?;
+    let value_start = cap.get(1).unwrap().end();
+    let value_start = value_start + masked[value_start..].find('=').unwrap() + 1;
+    is_public(&cap[1]).then(|| format!("{}{}: {}\n", indent, &cap[1], literal_type(&content[value_start..], &masked[value_start..])))
+}
+
+/// The stub of a def, with its kept decorators and `...` for a body
+fn function(content: &str, masked: &str, start: usize, end: usize, indent: &str) -> Option<String> {
+    let def = Regex::new(r"(?m)^[ \t]*((?:async[ \t]+)?def[ \t]+([A-Za-z_]\w*))").unwrap();
+    let found = def.captures(&masked[start..end])?;
+    if !is_public(&found[2]) {
+        return None;
+    }
+    let def_start = start + found.get(1).unwrap().start();
+    let mut stub = String::new();
+    for line in content[start..def_start].lines().filter(|line| kept_decorator(line)) {
+        stub.push_str(&format!("{}{}\n", indent, line.trim()));
+    }
+    stub.push_str(&format!("{}{}: ...\n", indent, signature(content, masked, def_start, header_end(masked, def_start))));
+    Some(stub)
+}
+
+/// The stub of a class: its header, and the public methods and variables its body defines
+fn class(content: &str, masked: &str, start: usize, end: usize) -> String {
+    let colon = header_end(masked, start);
+    let mut stub = format!("{}:\n", content[start..colon].trim_end());
+    let body_start = masked[colon..end].find('\n').map_or(end, |newline| colon + newline + 1);
+    let lines: Vec<(usize, &str)> = masked[body_start..end].split_inclusive('\n')
+        .scan(body_start, |offset, line| {
+            let line_start = *offset;
+            *offset += line.len();
+            Some((line_start, line))
+        })
+        .collect();
+    let body_indent = lines.iter()
+        .find(|(_, line)| !line.trim().is_empty())
+        .map(|(_, line)| &line[..line.len() - line.trim_start().len()])
+        .unwrap_or("");
+
+    let mut members = String::new();
+    let mut decorators_start: Option<usize> = None;
+    for (index, (line_start, line)) in lines.iter().enumerate() {
+        if body_indent.is_empty() || line.trim().is_empty() || !line.starts_with(body_indent) || line[body_indent.len()..].starts_with([' ', '\t']) {
+            continue;
+        }
+        let code = &line[body_indent.len()..];
+        let code_start = line_start + body_indent.len();
+        if code.starts_with('@') {
+            decorators_start.get_or_insert(*line_start);
+        } else if code.starts_with("def ") || code.starts_with("async ") {
+            // The method runs to the next line at the body's indentation
+            let method_end = lines[index + 1..].iter()
+                .find(|(_, next)| !next.trim().is_empty() && !next[body_indent.len().min(next.len())..].starts_with([' ', '\t', ')']))
+                .map_or(end, |(next_start, _)| *next_start);
+            members.extend(function(content, masked, decorators_start.take().unwrap_or(*line_start), method_end, body_indent));
+        } else {
+            decorators_start = None;
+            let line_end = line_start + line.len();
+            members.extend(variable(&content[code_start..line_end], &masked[code_start..line_end], body_indent));
+        }
+    }
+    if members.is_empty() {
+        stub.push_str("    ...\n");
+    } else {
+        stub.push_str(&members);
+    }
+    stub
+}
+
+/// A `.pyi` stub of the public API of a bundle: the signatures of its top-level functions, its
+/// classes with their public methods and variables, and its top-level variables, typed by their
+/// annotation or, for literals, their value. Names starting with an underscore are left out,
+/// except dunder names, and so are the bundle's imports that the stub doesn't refer to. A name
+/// the bundle defines again is stubbed as it's last defined, apart from `@overload`s.
+pub fn render_stub(content: &str) -> String {
+    let masked = mask_strings_and_comments(content);
+    let mut imports = Vec::new();
+    let mut definitions: Vec<(String, String)> = Vec::new();
+    for statement in top_level_statements(content) {
+        let (start, end) = (statement.start, statement.end);
+        let definition = match statement.kind {
+            StatementKind::Import if !content[start..end].starts_with("from __future__") => {
+                imports.push(&content[start..end]);
+                None
+            }
+            StatementKind::Function => function(content, &masked, start, end, ""),
+            StatementKind::Class if statement.name.as_deref().is_some_and(is_public) => Some(class(content, &masked, start, end)),
+            StatementKind::Assignment | StatementKind::Other => {
+                let line_end = masked[start..end].find('\n').map_or(end, |newline| start + newline);
+                variable(&content[start..end], &masked[start..line_end], "")
+            }
+            _ => None,
+        };
+        let Some(definition) = definition else { continue };
+        let name = statement.name.unwrap_or_else(|| definition.split(':').next().unwrap_or_default().to_string());
+        definitions.retain(|(defined, stub)| *defined != name || stub.starts_with("@overload") || stub.starts_with("@typing.overload"));
+        definitions.push((name, definition));
+    }
+    let body: String = definitions.into_iter().map(|(_, stub)| stub).collect();
+
+    let used = identifier_counts(&mask_strings_and_comments(&body));
+    let mut stub = String::new();
+    for import in imports {
+        if top_level_bindings(import).iter().any(|name| used.contains_key(name)) {
+            stub.push_str(import.trim_end());
+            stub.push('\n');
+        }
+    }
+    let binds_any = stub.lines().any(|line| top_level_bindings(line).contains("Any"));
+    if used.contains_key("Any") && !binds_any {
+        stub.push_str("from typing import Any\n");
+    }
+    if !stub.is_empty() {
+        stub.push('\n');
+    }
+    stub.push_str(&body);
+    stub
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_stub() {
+        let bundle = "\
+from __future__ import annotations
+import os
+from pathlib import Path
+from typing import Optional
+import json
+
+VERSION = '1.0'
+LIMIT = 3
+_cache = {}
+handlers = [os.getcwd]
+timeout: Optional[float] = None
+
+@functools.cache
+def load(path: Path, retries: int = LIMIT, *, cb=lambda: None) -> Optional[str]:
+    return None
+
+def _helper():
+    pass
+
+async def fetch(
+    url: str,
+    timeout: float = 1.5,
+):
+    pass
+
+class Config(Base):
+    name: str
+    DEFAULT = 'x'
+
+    def __init__(self, name: str = 'x'):
+        self.name = name
+
+    @property
+    def label(self) -> str:
+        return self.name
+
+    def _private(self):
+        pass
+
+class _Hidden:
+    pass
+
+def fetch(url):
+    pass
+
+class Empty:
+    pass
+";
+        assert_eq!(render_stub(bundle), "\
+from pathlib import Path
+from typing import Optional
+from typing import Any
+
+VERSION: str
+LIMIT: int
+handlers: Any
+timeout: Optional[float]
+def load(path: Path, retries: int = ..., *, cb=...) -> Optional[str]: ...
+class Config(Base):
+    name: str
+    DEFAULT: str
+    def __init__(self, name: str = ...): ...
+    @property
+    def label(self) -> str: ...
+def fetch(url): ...
+class Empty:
+    ...
+");
+    }
+}
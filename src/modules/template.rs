@@ -0,0 +1,86 @@
+use crate::modules::source_analysis::string_spans;
+use regex::Regex;
+
+/// The comment a --template file marks the place of the bundle with, on a line of its own
+pub const TEMPLATE_MARKER: &str = "# {{INLINED_CODE}}";
+
+/// The shebang and encoding declaration at the top of a script, which only count on its
+/// first two lines: (shebang, encoding declaration, offset of the rest)
+fn script_header(content: &str) -> (Option<&str>, Option<&str>, usize) {
+    let cookie = Regex::new(r"^[ \t\f]*#.*?coding[:=]").unwrap();
+    let (mut shebang, mut encoding, mut position) = (None, None, 0);
+    for (index, line) in content.split_inclusive('\n').take(2).enumerate() {
+        if index == 0 && line.starts_with("#!") {
+            shebang = Some(line);
+        } else if cookie.is_match(line) {
+            encoding = Some(line);
+        } else {
+            break;
+        }
+        position += line.len();
+    }
+    (shebang, encoding, position)
+}
+
+/// Put the bundle in place of the template's marker line, indented as the marker is so it can
+/// go inside a block of the template. The bundle's shebang and encoding declaration move to the
+/// top, where they have to be, unless the template has its own.
+pub fn fill_template(template: &str, bundle: &str) -> Result<String, String> {
+    let marker_regex = Regex::new(r"(?m)^([ \t]*)#[ \t]*\{\{INLINED_CODE\}\}[ \t]*(?:\n|$)").unwrap();
+    let spans = string_spans(template);
+    let markers: Vec<regex::Captures> = marker_regex.captures_iter(template)
+        .filter(|captures| !spans.iter().any(|(start, end)| *start < captures.get(0).unwrap().start() && captures.get(0).unwrap().start() < *end))
+        .collect();
+    let marker = match markers.as_slice() {
+        [marker] => marker,
+        [] => return Err(format!("there's no `{}` line to put the bundle in", TEMPLATE_MARKER)),
+        _ => return Err(format!("there are {} `{}` lines, where the bundle can only go in one", markers.len(), TEMPLATE_MARKER)),
+    };
+    let (marker_start, marker_end) = (marker.get(0).unwrap().start(), marker.get(0).unwrap().end());
+    let indent = &marker[1];
+
+    let (template_shebang, template_encoding, template_body) = script_header(template);
+    let (bundle_shebang, bundle_encoding, bundle_body) = script_header(bundle);
+    let mut result = String::new();
+    for line in [template_shebang.or(bundle_shebang), template_encoding.or(bundle_encoding)].into_iter().flatten() {
+        result.push_str(line);
+    }
+    result.push_str(&template[template_body..marker_start]);
+
+    let bundle_spans = string_spans(bundle);
+    let mut line_start = bundle_body;
+    for line in bundle[bundle_body..].split_inclusive('\n') {
+        let continues_string = bundle_spans.iter().any(|(start, end)| *start < line_start && line_start < *end);
+        if !continues_string && !line.trim().is_empty() {
+            result.push_str(indent);
+        }
+        result.push_str(line);
+        line_start += line.len();
+    }
+    if !result.ends_with('\n') && marker_end < template.len() {
+        result.push('\n');
+    }
+    result.push_str(&template[marker_end..]);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_template() {
+        let template = "# Copyright 2024 Example Corp\n# {{INLINED_CODE}}\nharness.report()\n";
+        let bundle = "#!/usr/bin/env python3\n# -*- coding: utf-8 -*-\nimport os\nprint(os.name)\n";
+        assert_eq!(fill_template(template, bundle).unwrap(), "#!/usr/bin/env python3\n# -*- coding: utf-8 -*-\n# Copyright 2024 Example Corp\nimport os\nprint(os.name)\nharness.report()\n");
+
+        // Inside a block of the template, with its own shebang
+        let template = "#!/opt/python/bin/python3\ntry:\n    #{{INLINED_CODE}}\nexcept Exception:\n    harness.fail()\n";
+        let bundle = "#!/usr/bin/env python3\nTEXT = \"\"\"a\nb\"\"\"\n\nprint(TEXT)";
+        assert_eq!(fill_template(template, bundle).unwrap(), "#!/opt/python/bin/python3\ntry:\n    TEXT = \"\"\"a\nb\"\"\"\n\n    print(TEXT)\nexcept Exception:\n    harness.fail()\n");
+
+        assert!(fill_template("print('no marker')\n", bundle).unwrap_err().contains("no `# {{INLINED_CODE}}` line"));
+        assert!(fill_template("DOC = \"\"\"\n# {{INLINED_CODE}}\n\"\"\"\n", bundle).is_err());
+        assert!(fill_template("# {{INLINED_CODE}}\n# {{INLINED_CODE}}\n", bundle).unwrap_err().contains("there are 2"));
+    }
+}
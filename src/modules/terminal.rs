@@ -0,0 +1,187 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use regex::Regex;
+
+/// How long the progress line is left as it is before it's drawn again, so that inlining many
+/// small modules isn't slowed down by the terminal
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Characters of the progress bar between its brackets
+const BAR_WIDTH: usize = 24;
+
+/// What a piece of terminal output is, which picks its color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    /// The heading of a warning, e.g. `WARNING[PI0001]`
+    Warning,
+    /// A note that an import is left as it is
+    Skip,
+    /// The labels of the summary table
+    Label,
+}
+
+impl Style {
+    fn code(&self) -> &'static str {
+        match self {
+            Style::Warning => "1;33",
+            Style::Skip => "36",
+            Style::Label => "1",
+        }
+    }
+}
+
+/// What the terminal the run is shown on can take: colors on stdout and a progress line on
+/// stderr, neither of which is used when the output is redirected, with --no-color or with
+/// NO_COLOR set
+#[derive(Debug, Clone, Default)]
+pub struct Terminal {
+    pub color: bool,
+    pub progress: Rc<RefCell<Progress>>,
+}
+
+impl Terminal {
+    /// The terminal of this process. The progress line is left out with --verbose, whose own
+    /// lines would break it up, and when the run asks questions on the terminal.
+    pub fn detect(no_color: bool, quiet_progress: bool) -> Terminal {
+        let enabled = !no_color && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+        Terminal {
+            color: enabled && io::stdout().is_terminal(),
+            progress: Rc::new(RefCell::new(Progress::new(enabled && !quiet_progress && io::stderr().is_terminal()))),
+        }
+    }
+
+    /// The text in the color of `style`, or as it is without colors
+    pub fn paint(&self, text: &str, style: Style) -> String {
+        match self.color {
+            true => format!("\x1b[{}m{}\x1b[0m", style.code(), text),
+            false => text.to_string(),
+        }
+    }
+
+    /// A warning report (see WarningAggregator::render()) with the heading of each warning colored
+    pub fn paint_warnings(&self, report: &str) -> String {
+        let heading = Regex::new(r"(?m)^WARNING\[PI\d+\]").unwrap();
+        heading.replace_all(report, |cap: &regex::Captures| self.paint(&cap[0], Style::Warning)).into_owned()
+    }
+
+    /// Two aligned columns of labels and values, labels in bold
+    pub fn summary_table(&self, rows: &[(&str, String)]) -> String {
+        let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+        rows.iter()
+            .map(|(label, value)| format!("  {}{}  {}\n", self.paint(label, Style::Label), " ".repeat(width - label.len()), value))
+            .collect()
+    }
+}
+
+/// The progress line drawn on stderr while a bundle is built: how many of the modules found so
+/// far have been inlined. More modules are found as their importers are inlined, so the bar can
+/// shrink back now and then.
+#[derive(Debug, Default)]
+pub struct Progress {
+    enabled: bool,
+    discovered: HashSet<PathBuf>,
+    inlined: HashSet<PathBuf>,
+    last_drawn: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(enabled: bool) -> Progress {
+        Progress { enabled, discovered: HashSet::new(), inlined: HashSet::new(), last_drawn: None }
+    }
+
+    /// Forget the modules of the previous bundle
+    pub fn reset(&mut self) {
+        *self = Progress::new(self.enabled);
+    }
+
+    /// A module an import resolved to, to be inlined
+    pub fn discover(&mut self, path: &Path) {
+        if self.enabled && self.discovered.insert(path.to_path_buf()) {
+            self.draw();
+        }
+    }
+
+    /// A module being inlined
+    pub fn inline(&mut self, path: &Path) {
+        if self.enabled {
+            self.discovered.insert(path.to_path_buf());
+            self.inlined.insert(path.to_path_buf());
+            self.draw();
+        }
+    }
+
+    /// Clear the progress line, before anything else is printed
+    pub fn finish(&mut self) {
+        if self.enabled && self.last_drawn.take().is_some() {
+            eprint!("\r\x1b[2K");
+            let _ = io::stderr().flush();
+        }
+    }
+
+    fn draw(&mut self) {
+        if self.last_drawn.is_some_and(|drawn| drawn.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        self.last_drawn = Some(Instant::now());
+        eprint!("\r\x1b[2K{}", self.line());
+        let _ = io::stderr().flush();
+    }
+
+    /// The progress line, e.g. `[############------------] 3/6 modules inlined`
+    pub fn line(&self) -> String {
+        let (done, total) = (self.inlined.len(), self.discovered.len());
+        let filled = match total {
+            0 => 0,
+            total => BAR_WIDTH * done / total,
+        };
+        format!("[{}{}] {}/{} modules inlined", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled), done, total)
+    }
+}
+
+// A run that fails doesn't leave its progress line under the error
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_line() {
+        // Disabled progress draws nothing and counts nothing
+        let mut progress = Progress::new(false);
+        progress.inline(Path::new("/a.py"));
+        assert_eq!(progress.line(), "[------------------------] 0/0 modules inlined");
+
+        let mut progress = Progress::new(true);
+        progress.last_drawn = Some(Instant::now());
+        for path in ["/a.py", "/b.py", "/c.py", "/a.py"] {
+            progress.discover(Path::new(path));
+        }
+        progress.inline(Path::new("/a.py"));
+        assert_eq!(progress.line(), "[########----------------] 1/3 modules inlined");
+        progress.reset();
+        assert!(progress.enabled);
+        assert_eq!(progress.line(), "[------------------------] 0/0 modules inlined");
+    }
+
+    #[test]
+    fn test_paint() {
+        let plain = Terminal::default();
+        let report = "WARNING[PI0001]: could not find module \"x\"\n    at main.py:1\n";
+        assert_eq!(plain.paint_warnings(report), report);
+
+        let colored = Terminal { color: true, ..Terminal::default() };
+        assert_eq!(colored.paint_warnings(report), "\x1b[1;33mWARNING[PI0001]\x1b[0m: could not find module \"x\"\n    at main.py:1\n");
+        assert_eq!(plain.summary_table(&[("Output", "out.py".to_string()), ("Modules inlined", "3".to_string())]),
+            "  Output           out.py\n  Modules inlined  3\n");
+        assert_eq!(colored.summary_table(&[("Time", "1.0s".to_string())]), "  \x1b[1mTime\x1b[0m  1.0s\n");
+    }
+}
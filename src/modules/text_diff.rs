@@ -0,0 +1,203 @@
+/// The most edits looked for between two texts before the lines between their common start and
+/// end are shown as replaced outright, which keeps the search's memory bounded
+const MAX_EDITS: usize = 2000;
+
+/// One step of an edit script turning the old lines into the new ones, by line index
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Keep(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// The shortest edit script between the lines (Myers' algorithm), or None when it takes more
+/// than MAX_EDITS edits
+fn shortest_edits(old: &[&str], new: &[&str]) -> Option<Vec<Edit>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    // v[k] is the furthest old index reached on diagonal k (old index - new index)
+    let mut v = vec![0isize; 2 * max + 3];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    for d in 0..=(max.min(MAX_EDITS) as isize) {
+        // What this round reads of the previous one: diagonals -d-1 to d+1
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let index = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) { v[index + 1] } else { v[index - 1] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+        }
+    }
+    None
+}
+
+/// Walk the rounds of shortest_edits() back from the end of both texts
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let previous_x = at(previous_k);
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Keep(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == previous_x {
+                edits.push(Edit::Insert(previous_y as usize));
+            } else {
+                edits.push(Edit::Delete(previous_x as usize));
+            }
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    edits.reverse();
+    edits
+}
+
+/// The edit script between the lines: the common start and end are kept, and what's between
+/// them is diffed, or replaced outright when it differs too much
+fn line_edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut edits: Vec<Edit> = (0..prefix).map(|index| Edit::Keep(index, index)).collect();
+    match shortest_edits(old_middle, new_middle) {
+        Some(middle) => edits.extend(middle.into_iter().map(|edit| match edit {
+            Edit::Keep(a, b) => Edit::Keep(a + prefix, b + prefix),
+            Edit::Delete(a) => Edit::Delete(a + prefix),
+            Edit::Insert(b) => Edit::Insert(b + prefix),
+        })),
+        None => {
+            edits.extend((prefix..old.len() - suffix).map(Edit::Delete));
+            edits.extend((prefix..new.len() - suffix).map(Edit::Insert));
+        }
+    }
+    edits.extend((0..suffix).map(|index| Edit::Keep(old.len() - suffix + index, new.len() - suffix + index)));
+    edits
+}
+
+/// A line of a hunk, marked `\ No newline at end of file` when it's the text's last and has none
+fn push_line(diff: &mut String, mark: char, line: &str) {
+    diff.push(mark);
+    diff.push_str(line);
+    if !line.ends_with('\n') {
+        diff.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// A unified diff of the two texts, as `diff -u` prints it, with `context` unchanged lines
+/// around each change. Empty when the texts are the same.
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str, context: usize) -> String {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let edits = line_edits(&old_lines, &new_lines);
+    let changes: Vec<usize> = edits.iter().enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Keep(..)))
+        .map(|(index, _)| index)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut diff = format!("--- {}\n+++ {}\n", old_label, new_label);
+    let mut first = 0;
+    while first < changes.len() {
+        // A hunk takes in every change less than two contexts from the one before it
+        let mut last = first;
+        while last + 1 < changes.len() && changes[last + 1] - changes[last] <= 2 * context + 1 {
+            last += 1;
+        }
+        let start = changes[first].saturating_sub(context);
+        let end = (changes[last] + context + 1).min(edits.len());
+        let hunk = &edits[start..end];
+
+        // Where the hunk starts in each text: the first line it has from it, else the line before
+        let old_start = hunk.iter().find_map(|edit| match edit {
+            Edit::Keep(a, _) | Edit::Delete(a) => Some(*a),
+            Edit::Insert(_) => None,
+        });
+        let new_start = hunk.iter().find_map(|edit| match edit {
+            Edit::Keep(_, b) | Edit::Insert(b) => Some(*b),
+            Edit::Delete(_) => None,
+        });
+        let old_count = hunk.iter().filter(|edit| !matches!(edit, Edit::Insert(_))).count();
+        let new_count = hunk.iter().filter(|edit| !matches!(edit, Edit::Delete(_))).count();
+        let lines_before = |edits: &[Edit], is_old: bool| edits[..start].iter()
+            .filter(|edit| !matches!((edit, is_old), (Edit::Insert(_), true) | (Edit::Delete(_), false)))
+            .count();
+        let old_start = old_start.map_or(lines_before(&edits, true), |line| line + 1);
+        let new_start = new_start.map_or(lines_before(&edits, false), |line| line + 1);
+        diff.push_str(&format!("@@ -{},{} +{},{} @@\n", old_start, old_count, new_start, new_count));
+        for edit in hunk {
+            match *edit {
+                Edit::Keep(a, _) => push_line(&mut diff, ' ', old_lines[a]),
+                Edit::Delete(a) => push_line(&mut diff, '-', old_lines[a]),
+                Edit::Insert(b) => push_line(&mut diff, '+', new_lines[b]),
+            }
+        }
+        first = last + 1;
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "old", "new", 3), "");
+
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13";
+        assert_eq!(unified_diff(old, new, "a/out.py", "b/out.py", 2), "\
+--- a/out.py
++++ b/out.py
+@@ -1,5 +1,5 @@
+ 1
+ 2
+-3
++three
+ 4
+ 5
+@@ -11,2 +11,3 @@
+ 11
+ 12
++13
+\\ No newline at end of file
+");
+        assert_eq!(unified_diff("", "x\n", "a", "b", 3), "--- a\n+++ b\n@@ -0,0 +1,1 @@\n+x\n");
+        assert_eq!(unified_diff("x\ny\n", "y\n", "a", "b", 0), "--- a\n+++ b\n@@ -1,1 +0,0 @@\n-x\n");
+    }
+
+    #[test]
+    fn test_line_edits() {
+        let old = ["a", "b", "c", "a", "b", "b", "a"];
+        let new = ["c", "b", "a", "b", "a", "c"];
+        let edits = line_edits(&old, &new);
+        // Myers' smallest script for the classic example has 5 edits
+        assert_eq!(edits.iter().filter(|edit| !matches!(edit, Edit::Keep(..))).count(), 5);
+        let rebuilt: Vec<&str> = edits.iter().filter_map(|edit| match edit {
+            Edit::Keep(a, _) => Some(old[*a]),
+            Edit::Insert(b) => Some(new[*b]),
+            Edit::Delete(_) => None,
+        }).collect();
+        assert_eq!(rebuilt, new);
+    }
+}
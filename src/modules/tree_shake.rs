@@ -0,0 +1,279 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use crate::modules::collisions::rename_names;
+use crate::modules::errors::InlinerError;
+use crate::modules::file_system::FileSystem;
+use crate::modules::options::Opt;
+use crate::modules::source_analysis::{identifier_counts, mask_strings_and_comments, string_spans, top_level_statements, StatementKind, TopLevelStatement};
+use crate::modules::inlining::render_bundle;
+use crate::read_source;
+use regex::Regex;
+
+/// Parse the names list of a `from X import ...` statement into the original (un-aliased) names.
+/// `"a, b as c, (d,\n e)"` -> `["a", "b", "d", "e"]`
+pub fn imported_names(imports: &str) -> Vec<String> {
+    imports
+        .replace(['(', ')', '\\'], " ")
+        .split(',')
+        .filter_map(|item| item.split_whitespace().next())
+        .filter(|name| *name != "*")
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Whether the statement is a definition that can go when its name is unused. `masked` is the
+/// module content masked by mask_strings_and_comments().
+fn is_shakeable(masked: &str, statement: &TopLevelStatement) -> bool {
+    let Some(name) = &statement.name else {
+        return false;
+    };
+    // Dunder names (__all__, __version__, ...) are part of the module protocol, never shake them
+    let is_dunder = name.starts_with("__") && name.ends_with("__");
+    let masked_statement = &masked[statement.start..statement.end];
+    // Decorators run when the module is imported, and often register what they decorate
+    if masked_statement.starts_with('@') {
+        return false;
+    }
+    match statement.kind {
+        StatementKind::Function => !is_dunder,
+        // So do __init_subclass__ and metaclasses, which only a class without bases or
+        // keywords can't have
+        StatementKind::Class => !is_dunder && !has_class_arguments(masked_statement),
+        StatementKind::Assignment => !is_dunder && assigns_plain_value(masked_statement),
+        _ => false,
+    }
+}
+
+/// Whether a class statement has a base list or keywords, like `class Plugin(Base):` or
+/// `class Plugin(metaclass=Registry):`
+fn has_class_arguments(masked_statement: &str) -> bool {
+    Regex::new(r"^class\s+\w+\s*\(\s*[^)\s]").unwrap().is_match(masked_statement)
+}
+
+/// Whether an assignment's right-hand side is a literal, a plain name or a collection of them,
+/// which evaluating has no effect. `X = register(...)` or `conn = connect()` must run even when
+/// X or conn is never used.
+fn assigns_plain_value(masked_statement: &str) -> bool {
+    let target_regex = Regex::new(r"^[A-Za-z_]\w*\s*(?::[^=]*)?=").unwrap();
+    let Some(target) = target_regex.find(masked_statement) else {
+        return false;
+    };
+    // Strings are masked to spaces, so what's left are names, numbers and punctuation
+    let value = &masked_statement[target.end()..];
+    let call_regex = Regex::new(r"[\w)\]}]\s*[(\[]").unwrap();
+    let token_regex = Regex::new(r"^(?:[A-Za-z_]\w*|[0-9][\w.]*)$").unwrap();
+    !call_regex.is_match(value)
+        && value
+            .split(|c: char| c.is_whitespace() || "()[]{},:-+".contains(c))
+            .filter(|token| !token.is_empty())
+            .all(|token| token_regex.is_match(token) && token != "lambda")
+}
+
+/// The end of the statement's own code, before the blank lines and column 0 comments trailing
+/// it, which separate it from the next statement
+fn code_end(content: &str, statement: &TopLevelStatement) -> usize {
+    let spans = string_spans(content);
+    let mut end = statement.start;
+    let mut line_start = statement.start;
+    for line in content[statement.start..statement.end].split_inclusive('\n') {
+        let in_string = spans.iter().any(|(start, end)| *start < line_start && line_start < *end);
+        if in_string || !(line.trim().is_empty() || line.starts_with('#')) {
+            end = line_start + line.len();
+        }
+        line_start += line.len();
+    }
+    end
+}
+
+/// Work out which top-level definitions of the inlined modules are never referenced.
+///
+/// `bundle` is the fully inlined output, `modules` holds the original content of every inlined
+/// file, and `roots` are names that must be kept regardless (e.g. everything named in an import
+/// list). A definition is unused when its name does not appear anywhere in the bundle outside its
+/// own body. Removing a definition also removes its references, so this runs to a fixed point to
+/// catch helpers that were only used by other unused definitions.
+pub fn find_unused_definitions(bundle: &str, modules: &[(PathBuf, String)], roots: &HashSet<String>) -> HashMap<PathBuf, HashSet<String>> {
+    let mut counts = identifier_counts(bundle);
+
+    // (module index, name, token counts within its own span)
+    let mut candidates: Vec<(usize, String, HashMap<String, usize>)> = Vec::new();
+    for (index, (_, content)) in modules.iter().enumerate() {
+        let masked = mask_strings_and_comments(content);
+        for statement in top_level_statements(content) {
+            if let Some(name) = &statement.name {
+                if is_shakeable(&masked, &statement) && !roots.contains(name) {
+                    let own_counts = identifier_counts(&content[statement.start..statement.end]);
+                    candidates.push((index, name.clone(), own_counts));
+                }
+            }
+        }
+    }
+
+    let mut unused: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+    let mut removed = vec![false; candidates.len()];
+    loop {
+        let mut changed = false;
+        for (candidate_index, (module_index, name, own_counts)) in candidates.iter().enumerate() {
+            if removed[candidate_index] {
+                continue;
+            }
+            let total = counts.get(name).copied().unwrap_or(0);
+            let own = own_counts.get(name).copied().unwrap_or(0);
+            if total <= own {
+                removed[candidate_index] = true;
+                changed = true;
+                // Its body no longer references anything
+                for (token, count) in own_counts {
+                    if let Some(total) = counts.get_mut(token) {
+                        *total = total.saturating_sub(*count);
+                    }
+                }
+                unused.entry(modules[*module_index].0.clone()).or_default().insert(name.clone());
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    unused
+}
+
+/// Remove the named top-level definitions from module content, leaving the blank lines and
+/// comments after them to separate what was around them
+pub fn remove_definitions(content: &str, names: &HashSet<String>) -> String {
+    let masked = mask_strings_and_comments(content);
+    let mut result = String::new();
+    let mut last_end = 0;
+    for statement in top_level_statements(content) {
+        let is_unused = statement.name.as_ref().is_some_and(|name| names.contains(name) && is_shakeable(&masked, &statement));
+        if is_unused {
+            result.push_str(&content[last_end..statement.start]);
+            last_end = code_end(content, &statement);
+        }
+    }
+    result.push_str(&content[last_end..]);
+    result
+}
+
+/// Re-run the inlining with every unreferenced top-level definition of the inlined modules removed.
+/// The first pass (`bundle`) tells us every file that gets inlined and every name the final output uses.
+pub fn tree_shake_bundle(fs: &dyn FileSystem, python_sys_path: &Vec<PathBuf>, input_file: &Path, module_names: &str, bundle: &str, processed: &HashSet<PathBuf>, opt: &Opt) -> Result<String, InlinerError> {
+    let mut modules = Vec::new();
+    let mut processed_files: Vec<&PathBuf> = processed.iter().collect();
+    processed_files.sort();
+    for path in processed_files {
+        let source = read_source(fs, path)?;
+        let source = match opt.renamed_definitions.get(path) {
+            Some(renames) => rename_names(&source, renames),
+            None => source,
+        };
+        modules.push((path.clone(), source));
+    }
+
+    // Everything named in an import list is a root, even if the importer never uses it
+    let from_import_regex = Regex::new(r"(?m)^[ \t]*from\s+\S+\s+import\s+(\([^)]*\)|.*)$").unwrap();
+    let mut roots = HashSet::new();
+    let entry_content = read_source(fs, input_file)?;
+    for content in modules.iter().map(|(_, content)| content).chain(std::iter::once(&entry_content)) {
+        for cap in from_import_regex.captures_iter(content) {
+            roots.extend(imported_names(&cap[1]));
+        }
+    }
+
+    let unused_definitions = find_unused_definitions(bundle, &modules, &roots);
+    if unused_definitions.is_empty() {
+        return Ok(bundle.to_string());
+    }
+    // The second pass would only repeat the first pass's warnings and imports, with the line
+    // numbers of the shaken files
+    let shaking_opt = Opt { unused_definitions, warnings: Arc::default(), imports: Rc::default(), ..opt.clone() };
+    render_bundle(fs, python_sys_path, input_file, module_names, &mut HashSet::new(), &shaking_opt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_imported_names() {
+        assert_eq!(imported_names("a, b as c, (d,\n e)"), vec!["a", "b", "d", "e"]);
+        assert!(imported_names("*").is_empty());
+    }
+
+    #[test]
+    fn test_find_unused_definitions_is_transitive() {
+        let utils = "def used():\n    return 1\n\ndef unused():\n    return _private()\n\ndef _private():\n    return 2\n\nCONSTANT = 3\n";
+        let modules = vec![(PathBuf::from("/utils.py"), utils.to_string())];
+        let bundle = format!("{}\nprint(used())\n", utils);
+        let unused = find_unused_definitions(&bundle, &modules, &HashSet::new());
+        let names = &unused[&PathBuf::from("/utils.py")];
+        let mut names: Vec<&String> = names.iter().collect();
+        names.sort();
+        assert_eq!(names, vec!["CONSTANT", "_private", "unused"]);
+
+        let shaken = remove_definitions(utils, &unused[&PathBuf::from("/utils.py")]);
+        assert_eq!(shaken, "def used():\n    return 1\n\n\n\n");
+    }
+
+    #[test]
+    fn test_side_effecting_assignments_are_kept() {
+        let utils = r#"X = register("plugin")
+conn = connect()
+ITEMS = [1, -2.5, "three", None]
+TABLE = {"a": NAME, "b": (1, 2)}
+FIRST = ITEMS[0]
+SIZES = (3, 4)
+ALIAS = NAME
+LABEL = "{}".format(1)
+SQUARE = lambda x: x * x
+"#;
+        let modules = vec![(PathBuf::from("/utils.py"), utils.to_string())];
+        let unused = find_unused_definitions(utils, &modules, &HashSet::new());
+        let mut names: Vec<&String> = unused[&PathBuf::from("/utils.py")].iter().collect();
+        names.sort();
+        assert_eq!(names, vec!["ALIAS", "SIZES", "TABLE"]);
+    }
+
+    #[test]
+    fn test_registering_definitions_are_kept() {
+        let plugins = r#"REGISTRY = []
+
+def register(function):
+    REGISTRY.append(function.__name__)
+    return function
+
+class Registered(type):
+    def __init__(cls, name, bases, namespace):
+        super().__init__(name, bases, namespace)
+        REGISTRY.append(name)
+
+@register
+def plugin_a():
+    pass
+
+class Plugin(metaclass=Registered):
+    pass
+
+class Base():
+    pass
+
+def unused():
+    pass
+"#;
+        let modules = vec![(PathBuf::from("/plugins.py"), plugins.to_string())];
+        let bundle = format!("{}
+print(REGISTRY)
+", plugins);
+        let unused = find_unused_definitions(&bundle, &modules, &HashSet::new());
+        let mut names: Vec<&String> = unused[&PathBuf::from("/plugins.py")].iter().collect();
+        names.sort();
+        assert_eq!(names, vec!["Base", "unused"]);
+
+        let shaken = remove_definitions(plugins, &unused[&PathBuf::from("/plugins.py")]);
+        assert!(shaken.contains("def register(function):") && shaken.contains("@register\ndef plugin_a():"));
+        assert!(shaken.contains("class Registered(type):") && shaken.contains("class Plugin(metaclass=Registered):"));
+    }
+}
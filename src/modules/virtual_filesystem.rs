@@ -1,11 +1,19 @@
 use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::io;
+use std::sync::{Mutex, MutexGuard, PoisonError};
 use crate::modules::file_system::FileSystem;
 
+/// How many symlinks resolving one path may go through, as with ELOOP on Linux
+const MAX_SYMLINKS: usize = 40;
+
+/// An in-memory file system for tests. The tree is behind a mutex, so it can be written through
+/// the `&self` methods of FileSystem and shared between threads.
 pub struct VirtualFileSystem {
-    root: VirtualNode,
+    root: Mutex<VirtualNode>,
     cwd: PathBuf,
+    /// Match names regardless of case, as the default file systems of macOS and Windows do
+    case_insensitive: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -15,73 +23,191 @@ struct VirtualDirectory {
 
 #[derive(Debug, Clone)]
 struct VirtualFile {
-    contents: String,
+    contents: Vec<u8>,
 }
 
 #[derive(Debug, Clone)]
 enum VirtualNode {
     Directory(VirtualDirectory),
     File(VirtualFile),
+    /// The target path, relative to the directory holding the link unless absolute
+    Symlink(PathBuf),
+}
+
+/// The components of a path: `/` for the root, `..` for a parent and names otherwise
+fn path_parts(path: &Path) -> io::Result<Vec<String>> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir => parts.push("/".to_string()),
+            Component::ParentDir => parts.push("..".to_string()),
+            Component::Normal(os_str) => {
+                let segment = os_str.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
+                parts.push(segment.to_string());
+            },
+            Component::Prefix(_) | Component::CurDir => {},
+        }
+    }
+    Ok(parts)
+}
+
+/// Whether the path ends in a separator or `/.`, which the OS only takes for a directory
+fn names_directory(path: &Path) -> bool {
+    let text = path.to_string_lossy();
+    let text = text.strip_suffix('.').filter(|rest| rest.is_empty() || rest.ends_with(std::path::is_separator)).unwrap_or(&text);
+    text.len() > 1 && text.ends_with(std::path::is_separator)
+}
+
+/// Whether the last name in the path is `.`, so that the directory before it has to exist
+/// already: it's looked up on the way, not made or removed
+fn ends_in_dot(path: &Path) -> bool {
+    let text = path.to_string_lossy();
+    text.trim_end_matches(std::path::is_separator).rsplit(std::path::is_separator).next() == Some(".")
 }
 
 impl VirtualFileSystem {
     #[allow(unused)]
     pub fn new() -> Self {
         VirtualFileSystem {
-            root: VirtualNode::Directory(VirtualDirectory { contents: HashMap::new() }),
+            root: Mutex::new(VirtualNode::Directory(VirtualDirectory { contents: HashMap::new() })),
             cwd: PathBuf::from("/"),
+            case_insensitive: false,
         }
     }
 
-    fn resolve_path<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<String>> {
-        let path = path.as_ref();
-        let mut current_path = if path.is_absolute() {
-            PathBuf::from("/")
-        } else {
-            self.cwd.clone()
-        };
+    /// A file system that finds `Utils.py` when asked for `utils.py`, and whose canonicalize()
+    /// returns names as they were created
+    #[allow(unused)]
+    pub fn case_insensitive() -> Self {
+        VirtualFileSystem { case_insensitive: true, ..VirtualFileSystem::new() }
+    }
 
-        let mut components = Vec::new();
-        for component in path.components() {
-            match component {
-                std::path::Component::RootDir => current_path = PathBuf::from("/"),
-                std::path::Component::ParentDir => {
-                    if current_path.pop() == false && current_path != Path::new("/") {
-                        return Err(io::Error::new(io::ErrorKind::NotFound, "Path not found"));
-                    }
+    /// The whole tree, locked. No update leaves the tree inconsistent halfway through, so a lock
+    /// poisoned by a panicking test thread is still good to use.
+    fn tree(&self) -> MutexGuard<'_, VirtualNode> {
+        self.root.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The components of the absolute path `path` leads to, following symlinks on the way, and
+    /// the last component too when `follow_last` is set. As with the OS, a `..` goes back out of
+    /// a directory only, and a path with a trailing slash can only be a directory.
+    fn resolve(&self, path: &Path, follow_last: bool) -> io::Result<Vec<String>> {
+        let root = self.tree();
+        let mut pending = path_parts(&self.cwd.join(path))?;
+        pending.reverse();
+        let mut components: Vec<String> = Vec::new();
+        let mut links_followed = 0;
+        while let Some(part) = pending.pop() {
+            match part.as_str() {
+                "/" => components.clear(),
+                ".." => {
+                    match Self::get_node(&root, &components)? {
+                        VirtualNode::Directory(_) => components.pop(),
+                        _ => return Err(io::Error::new(io::ErrorKind::NotADirectory, "Not a directory")),
+                    };
                 },
-                std::path::Component::Normal(os_str) => {
-                    let segment = os_str.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
-                    current_path.push(segment);
-                    components.push(segment.to_string());
+                _ => {
+                    let part = match Self::get_node(&root, &components) {
+                        Ok(VirtualNode::Directory(dir)) if self.case_insensitive && !dir.contents.contains_key(&part) => {
+                            let lowercase = part.to_lowercase();
+                            dir.contents.keys().find(|name| name.to_lowercase() == lowercase).cloned().unwrap_or(part)
+                        },
+                        _ => part,
+                    };
+                    components.push(part);
+                    if pending.is_empty() && !follow_last {
+                        break;
+                    }
+                    if let Ok(VirtualNode::Symlink(target)) = Self::get_node(&root, &components) {
+                        links_followed += 1;
+                        if links_followed > MAX_SYMLINKS {
+                            return Err(io::Error::other("Too many levels of symbolic links"));
+                        }
+                        // A relative target is relative to the directory holding the link
+                        components.pop();
+                        pending.extend(path_parts(target)?.into_iter().rev());
+                    }
                 },
-                _ => {}
+            }
+        }
+        if names_directory(path) {
+            match Self::get_node(&root, &components) {
+                Ok(VirtualNode::File(_)) => return Err(io::Error::new(io::ErrorKind::NotADirectory, "Not a directory")),
+                Err(e) if ends_in_dot(path) => return Err(e),
+                _ => {},
             }
         }
         Ok(components)
     }
 
-    fn get_node(&mut self, path: &[String]) -> io::Result<&VirtualNode> {
-        let mut current_node = &self.root;
+    fn resolve_path(&self, path: &Path) -> io::Result<Vec<String>> {
+        self.resolve(path, true)
+    }
+
+    /// Make a symlink at `link` pointing to `target`, which needn't exist
+    #[allow(unused)]
+    pub fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        let components = self.resolve(link, false)?;
+        let (name, parent_components) = components.split_last().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
+        let mut root = self.tree();
+        match Self::get_node_mut(&mut root, parent_components)? {
+            VirtualNode::Directory(dir) if dir.contents.contains_key(name) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "File exists")),
+            VirtualNode::Directory(dir) => {
+                dir.contents.insert(name.to_string(), VirtualNode::Symlink(target.to_path_buf()));
+                Ok(())
+            },
+            _ => Err(io::Error::new(io::ErrorKind::NotADirectory, "Not a directory")),
+        }
+    }
+
+    /// Whether there's a node at the path that passes `predicate`. Like RealFileSystem, a path
+    /// that's missing or goes through a file isn't a file or a directory, rather than an error.
+    fn node_is(&self, path: &Path, predicate: fn(&VirtualNode) -> bool) -> io::Result<bool> {
+        let components = match self.resolve_path(path) {
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::NotADirectory) => return Ok(false),
+            components => components?,
+        };
+        Ok(Self::get_node(&self.tree(), &components).is_ok_and(predicate))
+    }
+
+    /// Make one directory, in a directory that exists
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let components = self.resolve_path(path)?;
+        let Some((name, parent_components)) = components.split_last() else {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "File exists"));
+        };
+        let mut root = self.tree();
+        match Self::get_node_mut(&mut root, parent_components)? {
+            VirtualNode::Directory(dir) if dir.contents.contains_key(name) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "File exists")),
+            VirtualNode::Directory(dir) => {
+                dir.contents.insert(name.to_string(), VirtualNode::Directory(VirtualDirectory { contents: HashMap::new() }));
+                Ok(())
+            },
+            _ => Err(io::Error::new(io::ErrorKind::NotADirectory, "Not a directory")),
+        }
+    }
+
+    fn get_node<'a>(root: &'a VirtualNode, path: &[String]) -> io::Result<&'a VirtualNode> {
+        let mut current_node = root;
 
         for segment in path {
             match current_node {
                 VirtualNode::Directory(dir) => {
                     let node = dir.contents.get(segment);
                     if let Some(node) = node {
-                        current_node = &node;
+                        current_node = node;
                     } else {
                         return Err(io::Error::new(io::ErrorKind::NotFound, "Path not found"));
                     }
                 },
-                VirtualNode::File(_) => return Err(io::Error::new(io::ErrorKind::Other, "Not a directory")),
+                VirtualNode::File(_) | VirtualNode::Symlink(_) => return Err(io::Error::new(io::ErrorKind::NotADirectory, "Not a directory")),
             }
         }
         Ok(current_node)
     }
 
-    fn get_node_mut(&mut self, path: &[String]) -> io::Result<&mut VirtualNode> {
-        let mut current_node = &mut self.root;
+    fn get_node_mut<'a>(root: &'a mut VirtualNode, path: &[String]) -> io::Result<&'a mut VirtualNode> {
+        let mut current_node = root;
 
         for segment in path {
             match current_node {
@@ -89,7 +215,7 @@ impl VirtualFileSystem {
                     current_node = dir.contents.get_mut(segment)
                         .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Path not found"))?;
                 },
-                VirtualNode::File(_) => return Err(io::Error::new(io::ErrorKind::Other, "Not a directory")),
+                VirtualNode::File(_) | VirtualNode::Symlink(_) => return Err(io::Error::new(io::ErrorKind::NotADirectory, "Not a directory")),
             }
         }
         Ok(current_node)
@@ -98,8 +224,9 @@ impl VirtualFileSystem {
 }
 
 impl FileSystem for VirtualFileSystem {
-    fn canonicalize<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
         let components = self.resolve_path(path)?;
+        Self::get_node(&self.tree(), &components)?;
         let mut canonical_path = PathBuf::from("/");
         for component in components {
             canonical_path.push(component);
@@ -107,104 +234,104 @@ impl FileSystem for VirtualFileSystem {
         Ok(canonical_path)
     }
 
-    fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&mut self, path: P, contents: C) -> io::Result<()> {
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
         let components = self.resolve_path(path)?;
-        let parent_components = &components[..components.len() - 1];
-        let filename = components.last().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
+        let (filename, parent_components) = components.split_last().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
 
-        let mut parent_node = self.get_node_mut(parent_components)?;
+        let mut root = self.tree();
+        let mut parent_node = Self::get_node_mut(&mut root, parent_components)?;
 
         if let VirtualNode::Directory(dir) = &mut parent_node {
-            let contents_str = String::from_utf8_lossy(contents.as_ref()).into_owned();
-            dir.contents.insert(filename.to_string(), VirtualNode::File(VirtualFile { contents: contents_str }));
+            if names_directory(path) || matches!(dir.contents.get(filename), Some(VirtualNode::Directory(_))) {
+                return Err(io::Error::new(io::ErrorKind::IsADirectory, "Is a directory"));
+            }
+            dir.contents.insert(filename.to_string(), VirtualNode::File(VirtualFile { contents: contents.to_vec() }));
             Ok(())
         } else {
-            Err(io::Error::new(io::ErrorKind::Other, "Not a directory"))
+            Err(io::Error::new(io::ErrorKind::NotADirectory, "Not a directory"))
         }
     }
 
-    fn read_to_string<P: AsRef<Path>>(&mut self, path: P) -> io::Result<String> {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
         let components = self.resolve_path(path)?;
-        match self.get_node(&components)? {
+        match Self::get_node(&self.tree(), &components)? {
             VirtualNode::File(file) => Ok(file.contents.clone()),
-            VirtualNode::Directory(_) => Err(io::Error::new(io::ErrorKind::Other, "Is a directory")),
+            VirtualNode::Directory(_) => Err(io::Error::new(io::ErrorKind::IsADirectory, "Is a directory")),
+            // Only a link that wasn't followed is left as a symlink
+            VirtualNode::Symlink(_) => Err(io::Error::other("Too many levels of symbolic links")),
         }
     }
 
-    fn read_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Vec<PathBuf>> {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
         let components = self.resolve_path(path)?;
-        match self.get_node(&components)? {
-            VirtualNode::File(_) => Err(io::Error::new(io::ErrorKind::Other, "Is a file")),
+        match Self::get_node(&self.tree(), &components)? {
+            VirtualNode::File(_) | VirtualNode::Symlink(_) => Err(io::Error::other("Is a file")),
             VirtualNode::Directory(dir) => {
-                // return directory contents as a vector of PathBuf
-                let mut paths = Vec::new();
-                for (name, node) in dir.contents.iter() {
-                    match node {
-                        VirtualNode::File(_) => paths.push(PathBuf::from(name)),
-                        VirtualNode::Directory(_) => paths.push(PathBuf::from(name)),
-                    }
-                }
-                Ok(paths)
+                // Full paths in name order, like RealFileSystem
+                let mut names: Vec<&String> = dir.contents.keys().collect();
+                names.sort();
+                Ok(names.into_iter().map(|name| path.join(name)).collect())
             },
         }
     }
 
-    fn mkdir_p<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        let components = self.resolve_path(path)?;
-        let mut current_node = &mut self.root;
-
-        for component in components {
-            match current_node {
-                VirtualNode::Directory(dir) => {
-                    let entry = dir.contents.entry(component.to_string());
-                    current_node = match entry {
-                        std::collections::hash_map::Entry::Vacant(entry) => {
-                            let new_dir = VirtualDirectory { contents: HashMap::new() };
-                            let node = VirtualNode::Directory(new_dir);
-                            entry.insert(node)
-                        },
-                        std::collections::hash_map::Entry::Occupied(entry) => {
-                            entry.into_mut()
-                        },
-                    };
-                },
-                VirtualNode::File(_) => return Err(io::Error::new(io::ErrorKind::Other, "Not a directory")),
-            }
-
-            if let VirtualNode::File(_) = current_node {
-                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "File exists"));
-            }
+    fn mkdir_p(&self, path: &Path) -> io::Result<()> {
+        // As fs::create_dir_all() does it, so that the directories a `..` goes through are made
+        // along the way
+        if path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        match self.create_dir(path) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(_) if self.is_dir(path)? => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        match path.parent() {
+            Some(parent) => self.mkdir_p(parent)?,
+            None => return Err(io::Error::other("failed to create whole tree")),
+        }
+        match self.create_dir(path) {
+            Err(_) if self.is_dir(path)? => Ok(()),
+            result => result,
         }
-        Ok(())
     }
 
-    fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        let components = self.resolve_path(path)?;
-        let parent_components = &components[..components.len() - 1];
-        let filename = components.last().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        // Removes a symlink itself, not what it points to
+        let components = self.resolve(path, false)?;
+        let (filename, parent_components) = components.split_last().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
 
-        let mut parent_node = self.get_node_mut(parent_components)?;
+        let mut root = self.tree();
+        let mut parent_node = Self::get_node_mut(&mut root, parent_components)?;
 
         if let VirtualNode::Directory(paren_dir) = &mut parent_node {
             match paren_dir.contents.get_mut(filename) {
-                Some(VirtualNode::File(_)) => {
+                Some(VirtualNode::File(_) | VirtualNode::Symlink(_)) => {
                     paren_dir.contents.remove(filename);
                     Ok(())
                 },
-                Some(VirtualNode::Directory(_)) => Err(io::Error::new(io::ErrorKind::Other, "Is a directory")),
+                Some(VirtualNode::Directory(_)) => Err(io::Error::new(io::ErrorKind::IsADirectory, "Is a directory")),
                 None => Err(io::Error::new(io::ErrorKind::NotFound, "File not found")),
             }
         } else {
-            Err(io::Error::new(io::ErrorKind::Other, "File not found"))
+            Err(io::Error::other("File not found"))
         }
     }
 
-    fn remove_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        let components = self.resolve_path(path)?;
-        let parent_components = &components[..components.len() - 1];
-        let dirname = components.last().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let components = self.resolve(path, false)?;
+        if ends_in_dot(path) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid argument"));
+        }
+        let (dirname, parent_components) = components.split_last().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
 
-        let mut parent_node = self.get_node_mut(parent_components)?;
+        let mut root = self.tree();
+        let mut parent_node = Self::get_node_mut(&mut root, parent_components)?;
 
         if let VirtualNode::Directory(parent_dir) = &mut parent_node {
             match parent_dir.contents.get_mut(dirname) {
@@ -212,58 +339,44 @@ impl FileSystem for VirtualFileSystem {
                     parent_dir.contents.remove(dirname);
                     Ok(())
                 },
-                Some(VirtualNode::Directory(_)) => Err(io::Error::new(io::ErrorKind::Other, "Directory not empty")),
-                Some(VirtualNode::File(_)) => Err(io::Error::new(io::ErrorKind::Other, "Not a directory")),
+                Some(VirtualNode::Directory(_)) => Err(io::Error::other("Directory not empty")),
+                Some(VirtualNode::File(_) | VirtualNode::Symlink(_)) => Err(io::Error::new(io::ErrorKind::NotADirectory, "Not a directory")),
                 None => Err(io::Error::new(io::ErrorKind::NotFound, "Directory not found")),
             }
         } else {
-            Err(io::Error::new(io::ErrorKind::Other, "File not found"))
+            Err(io::Error::other("File not found"))
         }
     }
 
-    fn is_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<bool> {
-        let components = self.resolve_path(path)?;
-        let parent_components = &components[..components.len() - 1];
-        let filename = components.last().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
-
-        let mut parent_node = self.get_node_mut(parent_components)?;
+    fn is_file(&self, path: &Path) -> io::Result<bool> {
+        self.node_is(path, |node| matches!(node, VirtualNode::File(_)))
+    }
 
-        if let VirtualNode::Directory(parent_dir) = &mut parent_node {
-            match parent_dir.contents.get(filename) {
-                Some(VirtualNode::File(_)) => Ok(true),
-                Some(VirtualNode::Directory(_)) => Ok(false),
-                None => Err(io::Error::new(io::ErrorKind::NotFound, "File not found")),
-            }
-        } else {
-            Err(io::Error::new(io::ErrorKind::Other, "File not found"))
-        }
+    fn is_dir(&self, path: &Path) -> io::Result<bool> {
+        self.node_is(path, |node| matches!(node, VirtualNode::Directory(_)))
     }
 
-    fn is_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<bool> {
-        let components = self.resolve_path(path)?;
-        let parent_components = &components[..components.len() - 1];
-        let dirname = components.last().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        self.node_is(path, |_| true)
+    }
 
-        let mut parent_node = self.get_node_mut(parent_components)?;
+    fn is_symlink(&self, path: &Path) -> io::Result<bool> {
+        let components = self.resolve(path, false)?;
+        Ok(matches!(Self::get_node(&self.tree(), &components), Ok(VirtualNode::Symlink(_))))
+    }
 
-        if let VirtualNode::Directory(parent_dir) = &mut parent_node {
-            match parent_dir.contents.get(dirname) {
-                Some(VirtualNode::Directory(_)) => Ok(true),
-                Some(VirtualNode::File(_)) => Ok(false),
-                None => Err(io::Error::new(io::ErrorKind::NotFound, "Directory not found")),
-            }
-        } else {
-            Err(io::Error::new(io::ErrorKind::Other, "Directory not found"))
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        let components = self.resolve(path, false)?;
+        match Self::get_node(&self.tree(), &components)? {
+            VirtualNode::Symlink(target) => Ok(target.clone()),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Not a symbolic link")),
         }
     }
 
-    fn exists<P: AsRef<Path>>(&mut self, path: P) -> io::Result<bool> {
+    fn set_executable(&self, path: &Path) -> io::Result<()> {
+        // Permissions aren't modelled, but the file has to exist
         let components = self.resolve_path(path)?;
-        let node = self.get_node(&components);
-        match node {
-            Ok(_) => Ok(true),
-            Err(_) => Ok(false),
-        }
+        Self::get_node(&self.tree(), &components).map(|_| ())
     }
 
 }
@@ -271,41 +384,220 @@ impl FileSystem for VirtualFileSystem {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::modules::file_system::CopyStrategy;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_virtual_filesystem() {
-        let mut fs = VirtualFileSystem::new();
-        // fs.mkdir_p("/test").unwrap();
-        fs.mkdir_p("/test/dir1").unwrap();
-        fs.mkdir_p("/test/dir2").unwrap();
-        fs.write("/test/file1", "Hello").unwrap();
-        fs.write("/test/dir1/file2", "World").unwrap();
-        fs.write("/test/dir2/file3", "!").unwrap();
-
-        assert_eq!(fs.exists("/test").unwrap(), true);
-        assert_eq!(fs.exists("/test/file1").unwrap(), true);
-        assert_eq!(fs.exists("/test/dir1").unwrap(), true);
-        assert_eq!(fs.is_file("/test/file1").unwrap(), true);
-        assert_eq!(fs.is_dir("/test/file1").unwrap(), false);
-        assert_eq!(fs.is_dir("/test/dir1").unwrap(), true);
-        assert_eq!(fs.is_file("/test/dir1").unwrap(), false);
-        assert_eq!(fs.exists("/test/dir1").unwrap(), true);
-        assert_eq!(fs.exists("/test/dir3").unwrap(), false);
-        fs.mkdir_p("/test/dir3").unwrap();
-        assert_eq!(fs.exists("/test/dir3").unwrap(), true);
-        fs.remove_dir("/test/dir3").unwrap();
-        assert_eq!(fs.exists("/test/dir3").unwrap(), false);
-
-        assert_eq!(fs.read_to_string("/test/file1").unwrap(), "Hello");
-        assert_eq!(fs.read_to_string("/test/dir1/file2").unwrap(), "World");
-        assert_eq!(fs.read_to_string("/test/dir2/file3").unwrap(), "!");
-        assert_eq!(fs.read_to_string("test/file1").unwrap(), "Hello");
-        assert_eq!(fs.read_to_string("test/dir1/file2").unwrap(), "World");
-        assert_eq!(fs.read_to_string("test/dir2/file3").unwrap(), "!");
-        assert_eq!(fs.remove_dir("test/dir2").unwrap_err().kind(), io::ErrorKind::Other); // directory not empty
-        assert_eq!(fs.remove_file("test/dir2/file3").unwrap(), ());
-        assert_eq!(fs.read_to_string("test/dir2/file3").unwrap_err().kind(), io::ErrorKind::NotFound);
-        assert_eq!(fs.remove_dir("test/dir3").unwrap_err().kind(), io::ErrorKind::NotFound);
-        fs.read_to_string("unknown").unwrap_err();
+        let fs = VirtualFileSystem::new();
+        // fs.mkdir_p(Path::new("/test")).unwrap();
+        fs.mkdir_p(Path::new("/test/dir1")).unwrap();
+        fs.mkdir_p(Path::new("/test/dir2")).unwrap();
+        fs.write(Path::new("/test/file1"), b"Hello").unwrap();
+        fs.write(Path::new("/test/dir1/file2"), b"World").unwrap();
+        fs.write(Path::new("/test/dir2/file3"), b"!").unwrap();
+
+        assert_eq!(fs.exists(Path::new("/test")).unwrap(), true);
+        assert_eq!(fs.exists(Path::new("/test/file1")).unwrap(), true);
+        assert_eq!(fs.exists(Path::new("/test/dir1")).unwrap(), true);
+        assert_eq!(fs.is_file(Path::new("/test/file1")).unwrap(), true);
+        assert_eq!(fs.is_dir(Path::new("/test/file1")).unwrap(), false);
+        assert_eq!(fs.is_dir(Path::new("/test/dir1")).unwrap(), true);
+        assert_eq!(fs.is_file(Path::new("/test/dir1")).unwrap(), false);
+        assert_eq!(fs.exists(Path::new("/test/dir1")).unwrap(), true);
+        assert_eq!(fs.exists(Path::new("/test/dir3")).unwrap(), false);
+        assert!(fs.is_dir(Path::new("/")).unwrap());
+        assert!(!fs.is_file(Path::new("/test/dir3")).unwrap());
+        assert!(!fs.is_dir(Path::new("/test/file1/dir")).unwrap());
+        assert!(fs.write(Path::new("/"), b"").is_err());
+        fs.mkdir_p(Path::new("/test/dir3")).unwrap();
+        assert_eq!(fs.exists(Path::new("/test/dir3")).unwrap(), true);
+        fs.remove_dir(Path::new("/test/dir3")).unwrap();
+        assert_eq!(fs.exists(Path::new("/test/dir3")).unwrap(), false);
+
+        assert_eq!(fs.read_to_string(Path::new("/test/file1")).unwrap(), "Hello");
+        assert_eq!(fs.read_to_string(Path::new("/test/dir1/file2")).unwrap(), "World");
+        assert_eq!(fs.read_to_string(Path::new("/test/dir2/file3")).unwrap(), "!");
+        assert_eq!(fs.read_to_string(Path::new("test/file1")).unwrap(), "Hello");
+        assert_eq!(fs.read_to_string(Path::new("test/dir1/file2")).unwrap(), "World");
+        assert_eq!(fs.read_to_string(Path::new("test/dir2/file3")).unwrap(), "!");
+        assert_eq!(fs.remove_dir(Path::new("test/dir2")).unwrap_err().kind(), io::ErrorKind::Other); // directory not empty
+        assert_eq!(fs.remove_file(Path::new("test/dir2/file3")).unwrap(), ());
+        assert_eq!(fs.read_to_string(Path::new("test/dir2/file3")).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(fs.remove_dir(Path::new("test/dir3")).unwrap_err().kind(), io::ErrorKind::NotFound);
+        fs.read_to_string(Path::new("unknown")).unwrap_err();
+    }
+
+    #[test]
+    fn test_symlinks() {
+        let fs = VirtualFileSystem::new();
+        fs.mkdir_p(Path::new("/shared/pkg")).unwrap();
+        fs.mkdir_p(Path::new("/src")).unwrap();
+        fs.write(Path::new("/shared/pkg/__init__.py"), b"X = 1\n").unwrap();
+        fs.symlink(Path::new("../shared/pkg"), Path::new("/src/pkg")).unwrap();
+        fs.symlink(Path::new("/nowhere"), Path::new("/src/dangling")).unwrap();
+
+        assert_eq!(fs.read_to_string(Path::new("/src/pkg/__init__.py")).unwrap(), "X = 1\n");
+        assert!(fs.is_dir(Path::new("/src/pkg")).unwrap());
+        assert!(fs.is_symlink(Path::new("/src/pkg")).unwrap());
+        assert!(!fs.is_symlink(Path::new("/src/pkg/__init__.py")).unwrap());
+        assert_eq!(fs.read_link(Path::new("/src/pkg")).unwrap(), PathBuf::from("../shared/pkg"));
+        assert_eq!(fs.canonicalize(Path::new("/src/pkg/../pkg/__init__.py")).unwrap(), PathBuf::from("/shared/pkg/__init__.py"));
+        assert!(!fs.exists(Path::new("/src/dangling")).unwrap());
+
+        fs.symlink(Path::new("loop"), Path::new("/src/loop")).unwrap();
+        assert!(fs.read(Path::new("/src/loop")).is_err());
+        fs.remove_file(Path::new("/src/pkg")).unwrap();
+        assert!(fs.exists(Path::new("/shared/pkg/__init__.py")).unwrap());
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let fs = VirtualFileSystem::case_insensitive();
+        fs.mkdir_p(Path::new("/src/MyLib")).unwrap();
+        fs.write(Path::new("/src/MyLib/Utils.py"), b"X = 1\n").unwrap();
+        assert_eq!(fs.read_to_string(Path::new("/src/mylib/utils.py")).unwrap(), "X = 1\n");
+        assert_eq!(fs.canonicalize(Path::new("/SRC/mylib/UTILS.py")).unwrap(), PathBuf::from("/src/MyLib/Utils.py"));
+        // Writing a file under another case replaces it rather than making a second one
+        fs.write(Path::new("/src/mylib/utils.py"), b"X = 2\n").unwrap();
+        assert_eq!(fs.read_dir(Path::new("/src/MyLib")).unwrap(), vec![PathBuf::from("/src/MyLib/Utils.py")]);
+        assert!(!VirtualFileSystem::new().exists(Path::new("/src/mylib")).unwrap());
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_windows_paths() {
+        let fs = VirtualFileSystem::new();
+        fs.mkdir_p(Path::new(r"C:\proj\mylib")).unwrap();
+        fs.write(Path::new(r"C:\proj\mylib\__init__.py"), b"X = 1\n").unwrap();
+        // Either separator, and the drive letter doesn't get in the way
+        assert_eq!(fs.read_to_string(Path::new("C:/proj/mylib/__init__.py")).unwrap(), "X = 1\n");
+        assert!(fs.is_dir(&Path::new(r"C:\proj").join("mylib")).unwrap());
+        assert_eq!(fs.glob(Path::new(r"C:\proj\*\__init__.py")).unwrap(), vec![PathBuf::from(r"C:\proj\mylib\__init__.py")]);
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let fs = VirtualFileSystem::new();
+        fs.mkdir_p(Path::new("/out")).unwrap();
+        std::thread::scope(|scope| {
+            for index in 0..4 {
+                let fs = &fs;
+                scope.spawn(move || fs.write(Path::new(&format!("/out/{}.py", index)), b"x = 1\n").unwrap());
+            }
+        });
+        assert_eq!(fs.read_dir(Path::new("/out")).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_copy_file() {
+        let fs = VirtualFileSystem::new();
+        fs.mkdir_p(Path::new("/vendor")).unwrap();
+        fs.write(Path::new("/tool.py"), b"x = 1\n").unwrap();
+        assert!(fs.copy_file(Path::new("/tool.py"), Path::new("/vendor/tool.py"), CopyStrategy::HardLink).unwrap());
+        assert!(!fs.copy_file(Path::new("/tool.py"), Path::new("/vendor/tool.py"), CopyStrategy::Copy).unwrap());
+        assert_eq!(fs.read_to_string(Path::new("/vendor/tool.py")).unwrap(), "x = 1\n");
+        assert!(fs.copy_file(Path::new("/missing.py"), Path::new("/vendor/missing.py"), CopyStrategy::Copy).is_err());
+    }
+
+    /// A pseudo-random sequence of numbers below a bound (xorshift), the same for the same seed
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    /// A path below the root of the file systems compared, with `.`, `..` and a trailing slash
+    /// now and then. A `..` never climbs above the root, which RealFileSystem's temp directory
+    /// doesn't have to itself.
+    fn random_path(random: &mut Random) -> String {
+        let mut parts: Vec<&str> = Vec::new();
+        let mut depth = 0;
+        while parts.is_empty() || random.below(3) > 0 && parts.len() < 5 {
+            let part = ["a", "b", "c.py", "..", "."][random.below(5)];
+            match part {
+                ".." if depth == 0 => continue,
+                ".." => depth -= 1,
+                "." => {},
+                _ => depth += 1,
+            }
+            parts.push(part);
+        }
+        if depth == 0 {
+            parts.push("a");
+        }
+        let trailing_slash = if random.below(6) == 0 { "/" } else { "" };
+        format!("{}{}", parts.join("/"), trailing_slash)
+    }
+
+    /// What an operation at `path` below `root` returned, with paths in it made relative to
+    /// `root`: whether it went wrong is compared, and whether the path wasn't found, but not the
+    /// error messages
+    fn outcome(fs: &dyn FileSystem, root: &Path, operation: usize, path: &str) -> String {
+        let full = root.join(path);
+        let relative = |paths: Vec<PathBuf>| paths.iter().map(|p| p.strip_prefix(root).unwrap_or(p).display().to_string()).collect::<Vec<_>>().join(" ");
+        let result = match operation {
+            0 => fs.mkdir_p(&full).map(|_| "created".to_string()),
+            1 => fs.write(&full, path.as_bytes()).map(|_| "written".to_string()),
+            2 => fs.remove_file(&full).map(|_| "removed".to_string()),
+            3 => fs.remove_dir(&full).map(|_| "removed".to_string()),
+            4 => fs.read_to_string(&full),
+            5 => fs.read_dir(&full).map(relative),
+            6 => fs.is_file(&full).map(|is| is.to_string()),
+            7 => fs.is_dir(&full).map(|is| is.to_string()),
+            8 => fs.exists(&full).map(|is| is.to_string()),
+            _ => fs.canonicalize(&full).map(|path| relative(vec![path])),
+        };
+        match result {
+            Ok(value) => value,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => "not found".to_string(),
+            Err(_) => "error".to_string(),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_behaves_like_real_filesystem() {
+        use crate::modules::file_system::RealFileSystem;
+        let dir = std::env::temp_dir().join(format!("python-inliner-{}-compare", std::process::id()));
+        for seed in 1..=500u64 {
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let real_root = std::fs::canonicalize(&dir).unwrap();
+            let (real, virtual_fs) = (RealFileSystem::new(real_root.clone()), VirtualFileSystem::new());
+            let mut random = Random(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let mut steps = Vec::new();
+            for _ in 0..30 {
+                let (operation, path) = (random.below(10), random_path(&mut random));
+                steps.push(format!("{} {}", ["mkdir_p", "write", "remove_file", "remove_dir", "read", "read_dir", "is_file", "is_dir", "exists", "canonicalize"][operation], path));
+                let expected = outcome(&real, &real_root, operation, &path);
+                assert_eq!(outcome(&virtual_fs, Path::new("/"), operation, &path), expected, "seed {}, after:\n{}", seed, steps.join("\n"));
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_dir_and_glob() {
+        let fs = VirtualFileSystem::new();
+        fs.mkdir_p(Path::new("/site/b-2.0.dist-info")).unwrap();
+        fs.mkdir_p(Path::new("/site/a-1.0.dist-info")).unwrap();
+        fs.mkdir_p(Path::new("/site/a/sub")).unwrap();
+        fs.write(Path::new("/site/a-1.0.dist-info/RECORD"), b"").unwrap();
+        fs.write(Path::new("/site/b-2.0.dist-info/RECORD"), b"").unwrap();
+        fs.write(Path::new("/site/a/__init__.py"), b"").unwrap();
+        fs.write(Path::new("/site/a/sub/mod.py"), b"").unwrap();
+        fs.write(Path::new("/site/a/sub/data.txt"), b"").unwrap();
+
+        assert_eq!(fs.read_dir(Path::new("/site")).unwrap(), vec![PathBuf::from("/site/a"), PathBuf::from("/site/a-1.0.dist-info"), PathBuf::from("/site/b-2.0.dist-info")]);
+        assert_eq!(fs.glob(Path::new("/site/*.dist-info/RECORD")).unwrap(), vec![PathBuf::from("/site/a-1.0.dist-info/RECORD"), PathBuf::from("/site/b-2.0.dist-info/RECORD")]);
+        assert_eq!(fs.glob(Path::new("/site/a/**/*.py")).unwrap(), vec![PathBuf::from("/site/a/__init__.py"), PathBuf::from("/site/a/sub/mod.py")]);
+        assert_eq!(fs.glob(Path::new("site/?-1.0.dist-info")).unwrap(), vec![PathBuf::from("site/a-1.0.dist-info")]);
+        assert!(fs.glob(Path::new("/site/missing/*.py")).unwrap().is_empty());
     }
 }
\ No newline at end of file
@@ -0,0 +1,200 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use crate::modules::environment::EnvironmentSnapshot;
+use crate::modules::file_system::FileSystem;
+use crate::modules::pth_files::pth_directories;
+
+/// The environment that is activated in the shell: VIRTUAL_ENV, else CONDA_PREFIX
+pub fn active_environment() -> Option<PathBuf> {
+    ["VIRTUAL_ENV", "CONDA_PREFIX"].iter()
+        .filter_map(env::var_os)
+        .find(|prefix| !prefix.is_empty())
+        .map(PathBuf::from)
+}
+
+/// The settings of a virtualenv's `pyvenv.cfg` that decide its search path
+#[derive(Debug, Default, PartialEq)]
+struct VenvConfig {
+    /// The directory of the interpreter the environment was created from
+    home: Option<PathBuf>,
+    /// `major.minor`
+    version: Option<String>,
+    include_system_site_packages: bool,
+}
+
+fn parse_pyvenv_cfg(content: &str) -> VenvConfig {
+    let mut config = VenvConfig::default();
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once('=') else { continue };
+        let value = value.trim();
+        match key.trim() {
+            "home" => config.home = Some(PathBuf::from(value)),
+            // `version` is written by venv, `version_info` (e.g. 3.12.1.final.0) by virtualenv
+            "version" | "version_info" => config.version = major_minor(value),
+            "include-system-site-packages" => config.include_system_site_packages = value.eq_ignore_ascii_case("true"),
+            _ => {}
+        }
+    }
+    config
+}
+
+/// `3.12` of `3.12.1`
+fn major_minor(version: &str) -> Option<String> {
+    let mut parts = version.split('.');
+    match (parts.next(), parts.next()) {
+        (Some(major), Some(minor)) if major.parse::<u32>().is_ok() && minor.parse::<u32>().is_ok() => Some(format!("{}.{}", major, minor)),
+        _ => None,
+    }
+}
+
+/// The version of the `lib/pythonX.Y/site-packages` directory of a POSIX layout prefix
+fn layout_version(fs: &dyn FileSystem, prefix: &Path) -> Option<String> {
+    let site_packages = fs.glob(&prefix.join("lib").join("python*").join("site-packages")).ok()?;
+    site_packages.iter()
+        .filter_map(|dir| dir.parent()?.file_name()?.to_str()?.strip_prefix("python").and_then(major_minor))
+        .next()
+}
+
+/// The version of the python package of a conda environment, from its `conda-meta` record
+fn conda_version(fs: &dyn FileSystem, prefix: &Path) -> Option<String> {
+    let records = fs.glob(&prefix.join("conda-meta").join("python-*.json")).ok()?;
+    records.iter()
+        .filter_map(|record| record.file_name()?.to_str()?.strip_prefix("python-").and_then(major_minor))
+        .next()
+}
+
+/// The standard library and site-packages directories of a Python installed at `base`, in
+/// sys.path order
+fn installation_path(base: &Path, version: &str, windows_layout: bool) -> (Vec<PathBuf>, PathBuf) {
+    let zip_name = format!("python{}.zip", version.replace('.', ""));
+    if windows_layout {
+        let stdlib = vec![base.join(zip_name), base.join("DLLs"), base.join("Lib")];
+        return (stdlib, base.join("Lib").join("site-packages"));
+    }
+    let lib = base.join("lib").join(format!("python{}", version));
+    let stdlib = vec![base.join("lib").join(zip_name), lib.clone(), lib.join("lib-dynload")];
+    (stdlib, lib.join("site-packages"))
+}
+
+/// The search path of the virtualenv or conda environment at `prefix`, worked out from its
+/// layout without running its interpreter: the base installation's standard library, then the
+/// environment's site-packages (and the base's with `include-system-site-packages`), each
+/// followed by the directories of its `.pth` files. Paths that don't exist are left for the
+/// caller to drop, as it does with an interpreter's sys.path.
+pub fn inspect_environment(fs: &dyn FileSystem, prefix: &Path) -> Result<EnvironmentSnapshot, String> {
+    if !matches!(fs.is_dir(prefix), Ok(true)) {
+        return Err(format!("{} is not a directory", prefix.display()));
+    }
+    let config = fs.read_to_string(&prefix.join("pyvenv.cfg")).ok().map(|content| parse_pyvenv_cfg(&content));
+    let windows_layout = matches!(fs.is_dir(&prefix.join("Lib").join("site-packages")), Ok(true));
+    let version = config.as_ref().and_then(|config| config.version.clone())
+        .or_else(|| layout_version(fs, prefix))
+        .or_else(|| conda_version(fs, prefix))
+        .ok_or_else(|| format!("can't tell the Python version of {}: it has no pyvenv.cfg version or lib/pythonX.Y/site-packages", prefix.display()))?;
+
+    let mut sys_path = Vec::new();
+    let mut site_dirs = Vec::new();
+    let interpreter = match &config {
+        Some(config) => {
+            let home = config.home.as_ref().ok_or_else(|| format!("{} has no `home` setting", prefix.join("pyvenv.cfg").display()))?;
+            // `home` is the interpreter's directory: `bin` under the base prefix, except on Windows
+            let base = if windows_layout { home.clone() } else { home.parent().map(Path::to_path_buf).unwrap_or_else(|| home.clone()) };
+            let (stdlib, base_site_packages) = installation_path(&base, &version, windows_layout);
+            let (_, site_packages) = installation_path(prefix, &version, windows_layout);
+            sys_path.extend(stdlib);
+            site_dirs.push(site_packages);
+            if config.include_system_site_packages {
+                site_dirs.push(base_site_packages);
+            }
+            if windows_layout { prefix.join("Scripts").join("python.exe") } else { prefix.join("bin").join("python") }
+        }
+        // A conda environment is an installation of its own
+        None => {
+            let (stdlib, site_packages) = installation_path(prefix, &version, windows_layout);
+            sys_path.extend(stdlib);
+            site_dirs.push(site_packages);
+            if windows_layout { prefix.join("python.exe") } else { prefix.join("bin").join("python") }
+        }
+    };
+    for site_dir in site_dirs {
+        let pth_dirs = pth_directories(fs, &site_dir);
+        sys_path.push(site_dir);
+        sys_path.extend(pth_dirs.into_iter().filter(|dir| !sys_path.contains(dir)).collect::<Vec<_>>());
+    }
+
+    Ok(EnvironmentSnapshot {
+        interpreter: Some(interpreter.to_string_lossy().to_string()),
+        version: Some(version),
+        sys_path,
+        editable_installs: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::virtual_filesystem::VirtualFileSystem;
+
+    #[test]
+    fn test_inspect_virtualenv() {
+        let fs = VirtualFileSystem::new();
+        fs.mkdir_p(Path::new("/work/.venv/lib/python3.12/site-packages")).unwrap();
+        fs.write(Path::new("/work/.venv/pyvenv.cfg"), b"home = /usr/bin\ninclude-system-site-packages = false\nversion = 3.12.1\n").unwrap();
+        let snapshot = inspect_environment(&fs, Path::new("/work/.venv")).unwrap();
+        assert_eq!(snapshot.interpreter.as_deref(), Some("/work/.venv/bin/python"));
+        assert_eq!(snapshot.version.as_deref(), Some("3.12"));
+        assert_eq!(snapshot.sys_path, vec![
+            PathBuf::from("/usr/lib/python312.zip"),
+            PathBuf::from("/usr/lib/python3.12"),
+            PathBuf::from("/usr/lib/python3.12/lib-dynload"),
+            PathBuf::from("/work/.venv/lib/python3.12/site-packages"),
+        ]);
+
+        // virtualenv's spelling, and the system site-packages
+        fs.write(Path::new("/work/.venv/pyvenv.cfg"), b"home = /opt/python/bin\nversion_info = 3.11.4.final.0\ninclude-system-site-packages = true\n").unwrap();
+        let snapshot = inspect_environment(&fs, Path::new("/work/.venv")).unwrap();
+        assert_eq!(snapshot.version.as_deref(), Some("3.11"));
+        assert_eq!(snapshot.sys_path.last(), Some(&PathBuf::from("/opt/python/lib/python3.11/site-packages")));
+
+        // Without a version setting it comes from the layout
+        fs.write(Path::new("/work/.venv/pyvenv.cfg"), b"home = /usr/bin\n").unwrap();
+        assert_eq!(inspect_environment(&fs, Path::new("/work/.venv")).unwrap().version.as_deref(), Some("3.12"));
+
+        // The environment's .pth files follow its site-packages
+        fs.mkdir_p(Path::new("/work/src")).unwrap();
+        fs.write(Path::new("/work/.venv/lib/python3.12/site-packages/project.pth"), b"/work/src\n").unwrap();
+        let snapshot = inspect_environment(&fs, Path::new("/work/.venv")).unwrap();
+        assert_eq!(snapshot.sys_path[3..], [PathBuf::from("/work/.venv/lib/python3.12/site-packages"), PathBuf::from("/work/src")]);
+
+        assert!(inspect_environment(&fs, Path::new("/work/missing")).is_err());
+    }
+
+    #[test]
+    fn test_inspect_conda_environment() {
+        let fs = VirtualFileSystem::new();
+        fs.mkdir_p(Path::new("/opt/conda/envs/ml/lib/python3.10/site-packages")).unwrap();
+        let snapshot = inspect_environment(&fs, Path::new("/opt/conda/envs/ml")).unwrap();
+        assert_eq!(snapshot.sys_path, vec![
+            PathBuf::from("/opt/conda/envs/ml/lib/python310.zip"),
+            PathBuf::from("/opt/conda/envs/ml/lib/python3.10"),
+            PathBuf::from("/opt/conda/envs/ml/lib/python3.10/lib-dynload"),
+            PathBuf::from("/opt/conda/envs/ml/lib/python3.10/site-packages"),
+        ]);
+
+        // Windows puts site-packages under Lib, and conda-meta records the version
+        fs.mkdir_p(Path::new("/conda/Lib/site-packages")).unwrap();
+        fs.mkdir_p(Path::new("/conda/conda-meta")).unwrap();
+        fs.write(Path::new("/conda/conda-meta/python-3.11.5-h966fe2a_0.json"), b"{}").unwrap();
+        let snapshot = inspect_environment(&fs, Path::new("/conda")).unwrap();
+        assert_eq!(snapshot.interpreter.as_deref(), Some("/conda/python.exe"));
+        assert_eq!(snapshot.sys_path, vec![
+            PathBuf::from("/conda/python311.zip"),
+            PathBuf::from("/conda/DLLs"),
+            PathBuf::from("/conda/Lib"),
+            PathBuf::from("/conda/Lib/site-packages"),
+        ]);
+
+        fs.mkdir_p(Path::new("/empty")).unwrap();
+        assert!(inspect_environment(&fs, Path::new("/empty")).is_err());
+    }
+}
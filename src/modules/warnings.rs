@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use regex::Regex;
+use std::sync::Mutex;
+use crate::modules::directives::{header_lines, line_at};
+
+/// How many import sites are listed for a warning that occurred more than once
+pub const MAX_SITES: usize = 3;
+
+/// What a warning is about. Warnings are deduplicated per category and subject.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WarningCategory {
+    /// An import matched the modules to inline but no file was found for it
+    UnresolvedModule,
+    /// A remembered resolution choice points at a file that no longer exists
+    StaleResolution,
+    /// A zip file on sys.path couldn't be read as an archive
+    SkippedSearchPath,
+    /// A package or module was imported again after it had been inlined
+    AlreadyInlined,
+    /// A skipped duplicate import binds names the first inlining may not have bound in its scope
+    UnboundDuplicateImport,
+    /// Indenting an inlined file changed the value of one of its multi-line strings
+    ReindentedString,
+    /// Inlined code reads `__file__`, which is the bundle's path rather than the module's
+    InlinedDunderFile,
+    /// A `from __future__` import ended up after the first statement of the bundle
+    MisplacedFutureImport,
+    /// Code imports a module by name at runtime, which the bundle may not have
+    DynamicImport,
+    /// Several files inlined at the top level define the same name, the last replacing the others
+    NameCollision,
+    /// The --remote-cache couldn't be read or written
+    UnreachableRemoteCache,
+    /// An inlined distribution has a known vulnerability (--audit)
+    KnownVulnerability,
+}
+
+impl WarningCategory {
+    /// Every category, in code order
+    pub const ALL: &'static [WarningCategory] = &[
+        WarningCategory::UnresolvedModule,
+        WarningCategory::StaleResolution,
+        WarningCategory::SkippedSearchPath,
+        WarningCategory::AlreadyInlined,
+        WarningCategory::UnboundDuplicateImport,
+        WarningCategory::ReindentedString,
+        WarningCategory::InlinedDunderFile,
+        WarningCategory::MisplacedFutureImport,
+        WarningCategory::DynamicImport,
+        WarningCategory::NameCollision,
+        WarningCategory::UnreachableRemoteCache,
+        WarningCategory::KnownVulnerability,
+    ];
+
+    /// The stable code identifying the warning in output, documentation and --allow. Codes are
+    /// never reused: PI00xx are about resolution, PI01xx inlining, PI02xx caching, PI03xx auditing.
+    pub fn code(&self) -> &'static str {
+        match self {
+            WarningCategory::UnresolvedModule => "PI0001",
+            WarningCategory::StaleResolution => "PI0002",
+            WarningCategory::SkippedSearchPath => "PI0003",
+            WarningCategory::AlreadyInlined => "PI0101",
+            WarningCategory::UnboundDuplicateImport => "PI0102",
+            WarningCategory::ReindentedString => "PI0103",
+            WarningCategory::InlinedDunderFile => "PI0104",
+            WarningCategory::MisplacedFutureImport => "PI0105",
+            WarningCategory::DynamicImport => "PI0106",
+            WarningCategory::NameCollision => "PI0107",
+            WarningCategory::UnreachableRemoteCache => "PI0201",
+            WarningCategory::KnownVulnerability => "PI0301",
+        }
+    }
+
+    /// Whether the warning is a hazard found by the self-check, a likely bug in the bundle that
+    /// is reported even without --verbose
+    pub fn is_hazard(&self) -> bool {
+        matches!(self, WarningCategory::UnboundDuplicateImport | WarningCategory::ReindentedString
+            | WarningCategory::InlinedDunderFile | WarningCategory::MisplacedFutureImport | WarningCategory::DynamicImport
+            | WarningCategory::NameCollision)
+    }
+
+    /// The start of a warning line, e.g. `WARNING[PI0001]`
+    pub fn heading(&self) -> String {
+        format!("WARNING[{}]", self.code())
+    }
+
+    fn describe(&self, subject: &str) -> String {
+        match self {
+            WarningCategory::UnresolvedModule => format!("could not find module {:?}", subject),
+            WarningCategory::StaleResolution => format!("remembered resolution for {:?} no longer exists", subject),
+            WarningCategory::SkippedSearchPath => format!("skipping sys.path entry {}", subject),
+            WarningCategory::AlreadyInlined => format!("{} has already been inlined, skipped", subject),
+            WarningCategory::UnboundDuplicateImport => format!("names imported again from {} may be unbound, its code was inlined in another scope", subject),
+            WarningCategory::ReindentedString => format!("indenting {} changed the value of a multi-line string", subject),
+            WarningCategory::InlinedDunderFile => format!("inlined {} uses __file__, which will name the bundle instead", subject),
+            WarningCategory::MisplacedFutureImport => format!("{} has a `from __future__` import after its first statement", subject),
+            WarningCategory::NameCollision => format!("{} is defined at the top level of several inlined files, and the one run last replaces the others", subject),
+            WarningCategory::DynamicImport => format!("{} imports a module by name at runtime, which the bundle may not have (see --dynamic-map)", subject),
+            WarningCategory::UnreachableRemoteCache => format!("remote cache {} couldn't be used, modules were processed without it", subject),
+            WarningCategory::KnownVulnerability => format!("inlined {} has a known vulnerability", subject),
+        }
+    }
+}
+
+impl FromStr for WarningCategory {
+    type Err = String;
+
+    fn from_str(code: &str) -> Result<WarningCategory, String> {
+        WarningCategory::ALL.iter()
+            .find(|category| category.code().eq_ignore_ascii_case(code))
+            .copied()
+            .ok_or_else(|| format!("unknown warning code {:?}", code))
+    }
+}
+
+/// The codes allowed by `# inliner: allow(PI0101, PI0001)` comments on the line containing
+/// `offset`, or in the comments at the top of the file, which apply to the whole file. Unknown
+/// codes are ignored.
+pub fn allowed_at(source: &str, offset: usize) -> Vec<WarningCategory> {
+    let allow = Regex::new(r"#\s*inliner:\s*allow\(([^)]*)\)").unwrap();
+
+    let mut allowed = Vec::new();
+    for line in header_lines(source).chain([line_at(source, offset)]) {
+        for captures in allow.captures_iter(line) {
+            allowed.extend(captures[1].split(',').filter_map(|code| code.trim().parse::<WarningCategory>().ok()));
+        }
+    }
+    allowed
+}
+
+#[derive(Debug, Default)]
+struct Occurrences {
+    count: usize,
+    /// The first MAX_SITES places the warning was raised from
+    sites: Vec<String>,
+    note: Option<String>,
+}
+
+/// Collects warnings so each is reported once, with how often it occurred and where, instead of
+/// once per importer. Safe to share between threads.
+#[derive(Debug, Default)]
+pub struct WarningAggregator {
+    warnings: Mutex<BTreeMap<(WarningCategory, String), Occurrences>>,
+}
+
+impl WarningAggregator {
+    /// Record a warning about `subject`, raised from `site` (typically `file:line`) if known
+    pub fn warn(&self, category: WarningCategory, subject: &str, site: Option<String>) {
+        let mut warnings = self.warnings.lock().unwrap();
+        let occurrences = warnings.entry((category, subject.to_string())).or_default();
+        occurrences.count += 1;
+        if let Some(site) = site {
+            if occurrences.sites.len() < MAX_SITES && !occurrences.sites.contains(&site) {
+                occurrences.sites.push(site);
+            }
+        }
+    }
+
+    /// Say more about a warning recorded with warn(), such as why a module couldn't be found. The
+    /// first note about a warning is kept.
+    pub fn note(&self, category: WarningCategory, subject: &str, note: String) {
+        let mut warnings = self.warnings.lock().unwrap();
+        if let Some(occurrences) = warnings.get_mut(&(category, subject.to_string())) {
+            occurrences.note.get_or_insert(note);
+        }
+    }
+
+    /// Each distinct warning, ordered by category then subject. Warnings of the `allowed`
+    /// categories are left out.
+    pub fn records(&self, allowed: &[WarningCategory]) -> Vec<WarningRecord> {
+        self.warnings.lock().unwrap().iter()
+            .filter(|((category, _), _)| !allowed.contains(category))
+            .map(|((category, subject), occurrences)| WarningRecord {
+                category: *category,
+                subject: subject.clone(),
+                count: occurrences.count,
+                sites: occurrences.sites.clone(),
+                note: occurrences.note.clone(),
+            })
+            .collect()
+    }
+
+    /// One block per distinct warning, ordered by category then subject. Warnings of the
+    /// `allowed` categories are left out.
+    pub fn render(&self, allowed: &[WarningCategory]) -> String {
+        let mut report = String::new();
+        for record in self.records(allowed) {
+            report.push_str(&format!("{}: {}", record.category.heading(), record.category.describe(&record.subject)));
+            if record.count > 1 {
+                report.push_str(&format!(" ({} times)", record.count));
+            }
+            report.push('\n');
+            for site in &record.sites {
+                report.push_str(&format!("    at {}\n", site));
+            }
+            let unlisted = record.count.saturating_sub(record.sites.len());
+            if !record.sites.is_empty() && unlisted > 0 {
+                report.push_str(&format!("    ... and {} more\n", unlisted));
+            }
+            if let Some(note) = &record.note {
+                report.push_str(&format!("    note: {}\n", note));
+            }
+        }
+        report
+    }
+}
+
+/// A distinct warning, with how often it occurred and the first MAX_SITES places it was raised from
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarningRecord {
+    pub category: WarningCategory,
+    pub subject: String,
+    pub count: usize,
+    pub sites: Vec<String>,
+    pub note: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warnings_are_deduplicated() {
+        let warnings = WarningAggregator::default();
+        assert_eq!(warnings.render(&[]), "");
+        for line in 1..=5 {
+            warnings.warn(WarningCategory::UnresolvedModule, "mylib.missing", Some(format!("/src/app.py:{}", line)));
+        }
+        warnings.warn(WarningCategory::UnresolvedModule, "mylib.missing", Some("/src/app.py:1".to_string()));
+        warnings.warn(WarningCategory::StaleResolution, "tools", None);
+        warnings.note(WarningCategory::UnresolvedModule, "mylib.missing", "package mylib has no submodule missing".to_string());
+        warnings.note(WarningCategory::UnresolvedModule, "mylib.missing", "a later note".to_string());
+        // Notes are only kept for warnings that were raised
+        warnings.note(WarningCategory::AlreadyInlined, "/src/a.py", "never raised".to_string());
+        assert_eq!(warnings.render(&[]), "WARNING[PI0001]: could not find module \"mylib.missing\" (6 times)
+    at /src/app.py:1
+    at /src/app.py:2
+    at /src/app.py:3
+    ... and 3 more
+    note: package mylib has no submodule missing
+WARNING[PI0002]: remembered resolution for \"tools\" no longer exists
+");
+        assert_eq!(warnings.render(&[WarningCategory::UnresolvedModule]), "WARNING[PI0002]: remembered resolution for \"tools\" no longer exists\n");
+    }
+
+    #[test]
+    fn test_allowed_at() {
+        let source = "#!/usr/bin/env python\n# inliner: allow(PI0002)\nimport a  # inliner: allow(PI0001, pi0101)\nimport b\n# inliner: allow(PI0003)\n";
+        let import_a = source.find("import a").unwrap();
+        assert_eq!(allowed_at(source, import_a), vec![WarningCategory::StaleResolution, WarningCategory::UnresolvedModule, WarningCategory::AlreadyInlined]);
+        // Only the comments before the first statement apply to the whole file
+        assert_eq!(allowed_at(source, source.find("import b").unwrap()), vec![WarningCategory::StaleResolution]);
+        assert!(allowed_at("import a\n", 0).is_empty());
+    }
+
+    #[test]
+    fn test_warning_codes() {
+        assert_eq!("pi0101".parse::<WarningCategory>(), Ok(WarningCategory::AlreadyInlined));
+        assert!("PI9999".parse::<WarningCategory>().is_err());
+        let codes: Vec<&str> = WarningCategory::ALL.iter().map(WarningCategory::code).collect();
+        let mut sorted = codes.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(codes, sorted);
+    }
+}
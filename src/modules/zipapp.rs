@@ -0,0 +1,33 @@
+use std::fs;
+use std::path::Path;
+use crate::modules::errors::InlinerError;
+use crate::modules::output_writer::{OutputFile, OutputWriter};
+use crate::utils::python::{py_compile, Interpreter};
+use crate::utils::zip::{build_zip, ZipMember};
+
+/// Packages the bundle as an executable zipapp: the bundle becomes `__main__.py` of a zip
+/// archive, prefixed with the bundle's shebang (or `#!/usr/bin/env python3`) so it can be run
+/// directly
+pub struct ZipappWriter;
+
+impl OutputWriter for ZipappWriter {
+    fn render(&self, output_file: &Path, content: &str) -> Vec<OutputFile> {
+        let shebang = content.lines().next().filter(|line| line.starts_with("#!")).unwrap_or("#!/usr/bin/env python3");
+        let mut zipapp = format!("{}\n", shebang).into_bytes();
+        zipapp.extend(build_zip(&[ZipMember::stored("__main__.py", content.as_bytes())]));
+        vec![OutputFile { path: output_file.to_path_buf(), contents: zipapp, executable: true, text: false }]
+    }
+
+    fn summary(&self, output_file: &Path) -> String {
+        format!("Zipapp written to {:?}", output_file)
+    }
+}
+
+/// py_compile can't read a zipapp, so compile a copy of its `__main__.py`
+pub fn check_zipapp_syntax(python: &Interpreter, content: &str) -> Result<(), InlinerError> {
+    let main_path = std::env::temp_dir().join(format!("python-inliner-{}-__main__.py", std::process::id()));
+    fs::write(&main_path, content).map_err(|e| InlinerError::io(&main_path, e))?;
+    let result = py_compile(python, &main_path);
+    fs::remove_file(&main_path).map_err(|e| InlinerError::io(&main_path, e))?;
+    result
+}
@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+/// The local path a `file:` URL (RFC 8089) names, as pip records them for editable installs:
+/// `file:///home/me/src/mylib`, `file:///C:/Users/me/src/mylib` or, on Windows, the UNC path
+/// `file://server/share/mylib`. Percent-escapes are decoded. None for other URLs, and for URLs
+/// of a remote host where there are no UNC paths.
+pub fn file_url_to_path(url: &str) -> Option<PathBuf> {
+    let scheme_end = url.find(':')?;
+    if !url[..scheme_end].eq_ignore_ascii_case("file") {
+        return None;
+    }
+    let rest = &url[scheme_end + 1..];
+    let rest = rest.split(['?', '#']).next().unwrap_or(rest);
+    let (host, path) = match rest.strip_prefix("//") {
+        Some(authority_and_path) => match authority_and_path.find('/') {
+            Some(slash) => authority_and_path.split_at(slash),
+            None => (authority_and_path, ""),
+        },
+        None => ("", rest),
+    };
+    let path = percent_decode(path)?;
+
+    if !host.is_empty() && !host.eq_ignore_ascii_case("localhost") {
+        if cfg!(windows) {
+            return Some(PathBuf::from(format!(r"\\{}{}", host, path.replace('/', "\\"))));
+        }
+        return None;
+    }
+    // `/C:/Users` has a drive letter, `|` standing in for the colon in old URLs
+    let bytes = path.as_bytes();
+    if bytes.len() >= 3 && bytes[0] == b'/' && bytes[1].is_ascii_alphabetic() && matches!(bytes[2], b':' | b'|') && matches!(bytes.get(3), None | Some(b'/')) {
+        let drive = format!("{}:{}", &path[1..2], &path[3..]);
+        return Some(PathBuf::from(if drive.len() == 2 { format!("{}/", drive) } else { drive }));
+    }
+    Some(PathBuf::from(path))
+}
+
+/// Decode `%XX` escapes. None for a malformed escape or bytes that aren't UTF-8.
+fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_url_to_path() {
+        assert_eq!(file_url_to_path("file:///home/me/src/my%20lib"), Some(PathBuf::from("/home/me/src/my lib")));
+        assert_eq!(file_url_to_path("file://localhost/home/me/src"), Some(PathBuf::from("/home/me/src")));
+        assert_eq!(file_url_to_path("FILE:/home/me/src"), Some(PathBuf::from("/home/me/src")));
+        assert_eq!(file_url_to_path("file:///C:/Users/me/src/mylib"), Some(PathBuf::from("C:/Users/me/src/mylib")));
+        assert_eq!(file_url_to_path("file:///c|/src"), Some(PathBuf::from("c:/src")));
+        assert_eq!(file_url_to_path("file:///D:"), Some(PathBuf::from("D:/")));
+        assert_eq!(file_url_to_path("https://example.com/mylib"), None);
+        assert_eq!(file_url_to_path("file:///bad%zzescape"), None);
+        if cfg!(windows) {
+            assert_eq!(file_url_to_path("file://server/share/mylib"), Some(PathBuf::from(r"\\server\share\mylib")));
+        } else {
+            assert_eq!(file_url_to_path("file://server/share/mylib"), None);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::io;
+
+/// Minimal raw DEFLATE (RFC 1951) decoder, enough to read compressed zip archive members.
+/// Speed isn't a concern here: archives on sys.path are read once per run.
+pub fn inflate(input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut reader = BitReader { input, position: 0, bit_buffer: 0, bit_count: 0 };
+    let mut output = Vec::with_capacity(input.len() * 3);
+
+    loop {
+        let is_final_block = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                // Stored block: byte-aligned LEN, NLEN, then raw bytes
+                reader.align_to_byte();
+                let length = reader.bits(16)? as usize;
+                let inverted_length = reader.bits(16)? as usize;
+                if length != (!inverted_length & 0xffff) {
+                    return Err(invalid_data("stored block length mismatch"));
+                }
+                for _ in 0..length {
+                    output.push(reader.bits(8)? as u8);
+                }
+            }
+            1 => {
+                let (literals, distances) = fixed_tables();
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            _ => return Err(invalid_data("invalid block type")),
+        }
+        if is_final_block {
+            return Ok(output);
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt deflate stream: {}", message))
+}
+
+struct BitReader<'a> {
+    input: &'a [u8],
+    position: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl BitReader<'_> {
+    /// Read `count` bits (LSB first), up to 16 at a time
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.bit_count < count {
+            let byte = *self.input.get(self.position).ok_or_else(|| invalid_data("unexpected end of data"))?;
+            self.position += 1;
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1u32 << count) - 1);
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        let discard = self.bit_count % 8;
+        self.bit_buffer >>= discard;
+        self.bit_count -= discard;
+    }
+}
+
+/// Canonical Huffman decoding table: symbol counts per code length plus symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for length in 1..16 {
+            offsets[length] = offsets[length - 1] + counts[length - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> io::Result<u16> {
+        // Walk the code one bit at a time; codes are packed MSB-first within each length
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(invalid_data("invalid Huffman code"))
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_table = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_table.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| invalid_data("repeat without previous length"))?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            18 => (0, 11 + reader.bits(7)?),
+            _ => return Err(invalid_data("invalid code length symbol")),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(invalid_data("code lengths overflow"));
+    }
+
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn inflate_block(reader: &mut BitReader, output: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> io::Result<()> {
+    const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+    const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+    const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+    const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let index = symbol - 257;
+                let length = LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                let distance_symbol = distances.decode(reader)? as usize;
+                if distance_symbol >= 30 {
+                    return Err(invalid_data("invalid distance symbol"));
+                }
+                let distance = DISTANCE_BASE[distance_symbol] as usize + reader.bits(DISTANCE_EXTRA[distance_symbol] as u32)? as usize;
+                if distance > output.len() {
+                    return Err(invalid_data("distance too far back"));
+                }
+                // Copies may overlap their own output (run-length style), so go byte by byte
+                let start = output.len() - distance;
+                for offset in 0..length {
+                    output.push(output[start + offset]);
+                }
+            }
+            _ => return Err(invalid_data("invalid literal/length symbol")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inflate_stored_and_fixed_blocks() {
+        // A stored block containing "hi"
+        assert_eq!(inflate(&[0x01, 0x02, 0x00, 0xfd, 0xff, b'h', b'i']).unwrap(), b"hi");
+        // zlib.compress(b"hello hello hello hello", 9)[2:-4] for a fixed-Huffman block with back-references
+        let compressed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01];
+        assert_eq!(inflate(&compressed).unwrap(), b"hello hello hello hello");
+        assert!(inflate(&[0x07]).is_err());
+    }
+
+    #[test]
+    fn test_inflate_dynamic_block() {
+        // zlib.compressobj(9, zlib.DEFLATED, -15) output for the text below (a dynamic-Huffman block)
+        let hex = [
+            "6597496e5c310c44f739858f206ae0709c2cb2086078979c3f099a55caaf5e199425fd1245d6537ffefcfaf1313e7e7f",
+            "fffcf5f7efb7cf7fa17568af7076b85fe1eab05ee1c6647fc5a7e3795eb177bcfaff81dd7a7d76ecbd7d759cfd7583b8",
+            "d531d4cd5e60d4173d0085317b001a1143e3c23720d231032aab4f6190b920033aa39336a1d37a8f09a1a7974c08ad4e",
+            "c584d0dd4799105a483d946eec01a58525507aa0034ab36308f53eca82d0d9f95a109abd6441e8c19543e8ecb32d08cd",
+            "bec505a1deca17ef1d9f8550c30c0aed742c2aed810da5bb856d285dad6343a9610694620b66b413b821345bd786d040",
+            "41436860068406f628193843961c934dcf94cf9e25c2ce7e2a3f478e765c0e7f42d2735212784a52ec432ec14daec9a7",
+            "5ca42fb96adf520c7ea45cdca5a03ca4e43ca528bd9e551b43ca3a4c0a3fa6b4462c699ed8d25e71a401c3a54523a489",
+            "23a5cda3c408728855a48999e414bbc9f5f4a3dc625879c4d2d2c5f4329eae9829b699a5be3ac478cbc4996b8a75d712",
+            "6faffdf4fe3a4f34943fc951f1044ba5fcbf9eeb6d8ce7076c9848b0a12a6d2c39888d2d67b571241d36fc0d3492531b",
+            "a9a819a5ac219e089b0b280ce8fd1b0945de105104ce8514c1e7528b665aae464e1dae2a297a23a936096ad23a36b5bb",
+            "8cb02aaedad2a3465c25065cfadca65a815d60715589a1d865160e7aa1852b5eea5c466cc1dc8cdc32ce396291467239",
+            "47d4688dec5ad45362d7467a611bd20b966f5ba960e417c061245870ce11fcd8651847e26d55beed5cfa75728c0a0932",
+            "1ce228718d24632e8832e68b30634e4933e6fde803c1c833de1f81c63b26d15807441a6bc5f53d63176a5078a986ba24",
+            "d658bb976b18d0c797116cec12928d9d44b6b1db08377664e85bd188377636f9c6ee27e0e810241c5d24de9eb664dc7d",
+            "fd963a16298729a41c7d2fdfdee197733816490735241d5d98a8a353a7fe6430d28e8e4fdc910ae41dc941e0619b925f",
+            "36768187812dbf8c483cce70dd23f42bf926e43fb17f00",
+        ].concat();
+        let compressed: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+        let expected: String = (0..200).map(|i| format!("line {} value {}\n", i, (i * i) % 97)).collect();
+        assert_eq!(inflate(&compressed).unwrap(), expected.as_bytes());
+    }
+}
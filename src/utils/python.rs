@@ -1,21 +1,65 @@
+use std::path::Path;
 use std::process::{Command, Stdio};
 use std::str;
+use crate::modules::errors::InlinerError;
 
-// Create a custom error type
-#[derive(Debug)]
-pub struct CommandError(String);
+/// The error for an interpreter that couldn't be run or queried
+fn discovery_error(python: &Interpreter, reason: String) -> InlinerError {
+    InlinerError::PythonDiscovery { interpreter: python.to_string(), reason }
+}
 
-impl std::fmt::Display for CommandError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+/// The interpreter to query and compile with: a program and the arguments that pick the Python
+/// it runs, like the Windows launcher's `py -3.11`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interpreter {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter { program: "python3".to_string(), args: Vec::new() }
     }
 }
 
-impl std::error::Error for CommandError {}
+impl Interpreter {
+    /// The interpreter `option` (--python) names, else the one in `env_var`
+    /// (PYTHON_INLINER_PYTHON), else `python3`. A value that isn't the path of an existing file
+    /// is split at whitespace into the program and its arguments.
+    pub fn select(option: Option<&str>, env_var: Option<&str>) -> Result<Interpreter, String> {
+        let spec = match (option, env_var) {
+            (Some(spec), _) => spec,
+            (None, Some(spec)) if !spec.trim().is_empty() => spec,
+            _ => return Ok(Interpreter::default()),
+        };
+        if Path::new(spec).is_file() {
+            return Ok(Interpreter { program: spec.to_string(), args: Vec::new() });
+        }
+        let mut words = spec.split_whitespace().map(String::from);
+        let program = words.next().ok_or("--python needs an interpreter to run")?;
+        Ok(Interpreter { program, args: words.collect() })
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command
+    }
+}
 
-pub fn get_python_sys_path() -> Result<Vec<String>, CommandError> {
+impl std::fmt::Display for Interpreter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn get_python_sys_path(python: &Interpreter) -> Result<Vec<String>, InlinerError> {
     // Launch the Python subprocess
-    let output = Command::new("python3") // or "python" depending on your setup
+    let output = python.command()
         .arg("-c") // Use the -c option to run the following command
         .arg("import sys; print('\\n'.join(sys.path))") // Correctly escape the newline character
         .stdout(Stdio::piped()) // Capture standard output
@@ -23,7 +67,7 @@ pub fn get_python_sys_path() -> Result<Vec<String>, CommandError> {
         .output(); // Execute the command and capture the output
 
     if let Err(e) = output {
-        return Err(CommandError(format!("Command failed with error: {}", e)));
+        return Err(discovery_error(python, format!("couldn't be run: {}", e)));
     }
     // Check if the command was successful
     let output = output.unwrap();
@@ -36,10 +80,7 @@ pub fn get_python_sys_path() -> Result<Vec<String>, CommandError> {
         eprintln!("stdout: {}", stdout_str);
         eprintln!("stderr: {}", stderr_str);
 
-        return Err(CommandError(format!(
-            "Command failed with status: {}",
-            output.status
-        )));
+        return Err(discovery_error(python, format!("exited with {}", output.status)));
     }
 
     // Convert the output to a String
@@ -52,7 +93,95 @@ pub fn get_python_sys_path() -> Result<Vec<String>, CommandError> {
             Ok(sys_path)
         },
         Err(e) => {
-            return Err(CommandError(format!("Error converting output to string: {}", e)));
+            Err(discovery_error(python, format!("printed output that isn't UTF-8: {}", e)))
         }
     }
 }
+
+/// Query the interpreter for its (major, minor) version
+pub fn get_python_version(python: &Interpreter) -> Result<(u32, u32), InlinerError> {
+    let output = python.command()
+        .arg("-c")
+        .arg("import sys; print(sys.version_info[0], sys.version_info[1])")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| discovery_error(python, format!("couldn't be run: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(discovery_error(python, format!("exited with {}", output.status)));
+    }
+
+    let output_str = str::from_utf8(&output.stdout).map_err(|e| discovery_error(python, format!("printed output that isn't UTF-8: {}", e)))?;
+    let mut parts = output_str.split_whitespace().map(|part| part.parse::<u32>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => Ok((major, minor)),
+        _ => Err(discovery_error(python, format!("printed an unexpected version: {}", output_str.trim()))),
+    }
+}
+
+/// Query the path of the interpreter the command resolves to
+pub fn get_python_executable(python: &Interpreter) -> Result<String, InlinerError> {
+    let output = python.command()
+        .arg("-c")
+        .arg("import sys; print(sys.executable)")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| discovery_error(python, format!("couldn't be run: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(discovery_error(python, format!("exited with {}", output.status)));
+    }
+
+    let output_str = str::from_utf8(&output.stdout).map_err(|e| discovery_error(python, format!("printed output that isn't UTF-8: {}", e)))?;
+    Ok(output_str.trim().to_string())
+}
+
+/// Byte-compile a Python file with `-m py_compile`, returning the compiler's message (e.g. the
+/// SyntaxError and where it occurred) if it doesn't compile
+pub fn py_compile(python: &Interpreter, path: &Path) -> Result<(), InlinerError> {
+    let output = python.command()
+        .arg("-m")
+        .arg("py_compile")
+        .arg(path)
+        // Keep the .pyc out of the output directory
+        .env("PYTHONPYCACHEPREFIX", std::env::temp_dir().join("python-inliner-pycache"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| discovery_error(python, format!("couldn't be run: {}", e)))?;
+
+    if !output.status.success() {
+        let stderr_str = str::from_utf8(&output.stderr).unwrap_or("<invalid utf-8>");
+        return Err(InlinerError::Parse { file: path.to_path_buf(), reason: format!("does not compile:\n{}", stderr_str.trim_end()), site: None });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_interpreter() {
+        assert_eq!(Interpreter::select(None, None).unwrap(), Interpreter::default());
+        assert_eq!(Interpreter::select(None, Some(" ")).unwrap(), Interpreter::default());
+        let launcher = Interpreter::select(None, Some("py -3.11")).unwrap();
+        assert_eq!(launcher, Interpreter { program: "py".to_string(), args: vec!["-3.11".to_string()] });
+        assert_eq!(launcher.to_string(), "py -3.11");
+        // The option wins over the environment variable
+        let venv = Interpreter::select(Some("/venv/bin/python"), Some("py -3.11")).unwrap();
+        assert_eq!(venv, Interpreter { program: "/venv/bin/python".to_string(), args: Vec::new() });
+        assert!(Interpreter::select(Some(""), None).is_err());
+
+        // An existing file is run as is, spaces and all
+        let dir = std::env::temp_dir().join(format!("python-inliner-select-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("my python");
+        std::fs::write(&program, "").unwrap();
+        let spec = program.to_str().unwrap();
+        assert_eq!(Interpreter::select(Some(spec), None).unwrap(), Interpreter { program: spec.to_string(), args: Vec::new() });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+/// CRC-32 (IEEE 802.3 polynomial) as used by zip archives
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// A member to write into a zip archive. `data` must already be compressed with `method`.
+pub struct ZipMember<'a> {
+    pub name: &'a str,
+    pub method: u16,
+    pub data: &'a [u8],
+    pub crc: u32,
+    pub uncompressed_size: u32,
+}
+
+impl<'a> ZipMember<'a> {
+    /// An uncompressed member
+    pub fn stored(name: &'a str, contents: &'a [u8]) -> ZipMember<'a> {
+        ZipMember { name, method: 0, data: contents, crc: crc32(contents), uncompressed_size: contents.len() as u32 }
+    }
+}
+
+/// Build a zip archive holding the given members. Offsets are relative to the start of the
+/// returned bytes; readers such as zipimport cope with a prefix (e.g. a shebang) in front of it.
+pub fn build_zip(members: &[ZipMember]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+    for member in members {
+        // Fields shared by the local header and the central directory entry, from the version
+        // needed to extract up to the file name length
+        let mut common = Vec::new();
+        common.extend_from_slice(&20u16.to_le_bytes()); // version needed to extract
+        common.extend_from_slice(&0x0800u16.to_le_bytes()); // flags: UTF-8 names
+        common.extend_from_slice(&member.method.to_le_bytes());
+        common.extend_from_slice(&[0, 0, 0x21, 0]); // time 00:00, date 1980-01-01
+        common.extend_from_slice(&member.crc.to_le_bytes());
+        common.extend_from_slice(&(member.data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&member.uncompressed_size.to_le_bytes());
+        common.extend_from_slice(&(member.name.len() as u16).to_le_bytes());
+
+        let offset = archive.len() as u32;
+        archive.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        archive.extend_from_slice(&common);
+        archive.extend_from_slice(&[0, 0]); // extra field length
+        archive.extend_from_slice(member.name.as_bytes());
+        archive.extend_from_slice(member.data);
+
+        directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        directory.extend_from_slice(&0x0314u16.to_le_bytes()); // made by: Unix, version 2.0
+        directory.extend_from_slice(&common);
+        directory.extend_from_slice(&[0; 8]); // extra, comment, disk number, internal attributes
+        directory.extend_from_slice(&(0o100644u32 << 16).to_le_bytes()); // regular file, rw-r--r--
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(member.name.as_bytes());
+    }
+
+    let directory_offset = archive.len() as u32;
+    archive.extend_from_slice(&directory);
+    archive.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    archive.extend_from_slice(&[0; 4]); // disk numbers
+    archive.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&directory_offset.to_le_bytes());
+    archive.extend_from_slice(&[0, 0]); // comment length
+    archive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+}
@@ -0,0 +1,38 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use structopt::StructOpt;
+use structopt::clap;
+use crate::cli::print_error;
+use crate::modules::options::Opt;
+use crate::run_bundles;
+
+/// Bundle again whenever the entry script or a file inlined into it changes, polling their
+/// modification times. A failed run is reported and waited out like a successful one.
+pub fn watch(opt: Opt, matches: &clap::ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut watched = BTreeSet::new();
+    let mut first = Some(opt);
+    loop {
+        // Options of their own for each run, so that warnings and reports don't pile up
+        let opt = first.take().unwrap_or_else(|| Opt::from_clap(matches));
+        let inlined_files = opt.inlined_files.clone();
+        watched.extend(opt.input_file.iter().cloned());
+        watched.extend(opt.template.iter().cloned());
+        if let Err(e) = run_bundles(opt) {
+            print_error(&e);
+        }
+        watched.extend(inlined_files.borrow().iter().cloned());
+        println!("Watching {} files for changes", watched.len());
+        let before = modification_times(&watched);
+        while modification_times(&watched) == before {
+            std::thread::sleep(Duration::from_millis(500));
+        }
+    }
+}
+
+/// When each file was last modified, None for a file that can't be read
+fn modification_times(paths: &BTreeSet<PathBuf>) -> Vec<Option<SystemTime>> {
+    paths.iter().map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok()).collect()
+}
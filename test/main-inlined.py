@@ -2,6 +2,7 @@
 import json
 import sys
 class Class2:
+    import sys
     def __init__(self):
         self.name = "Class2"
 LONG_DESCRIPTION = """
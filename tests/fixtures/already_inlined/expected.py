@@ -0,0 +1,13 @@
+# ↓↓↓ inlined submodule: pkg.a
+A = 'a'
+
+# ↑↑↑ inlined submodule: pkg.a
+# ↓↓↓ inlined submodule: pkg.b
+# →→ .a ←← module already inlined
+
+B = A + 'b'
+
+# ↑↑↑ inlined submodule: pkg.b
+# →→ pkg.a ←← module already inlined
+
+print(A, B)
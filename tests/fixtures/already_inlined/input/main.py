@@ -0,0 +1,5 @@
+from pkg.a import A
+from pkg.b import B
+from pkg.a import A
+
+print(A, B)
@@ -0,0 +1,15 @@
+def show():
+    # ↓↓↓ inlined submodule: pkg.text
+    BANNER = '''first
+  second
+third'''
+
+
+    def banner():
+        return BANNER.upper()
+
+    # ↑↑↑ inlined submodule: pkg.text
+    print(BANNER)
+
+
+show()
@@ -0,0 +1,6 @@
+def show():
+    from pkg.text import BANNER
+    print(BANNER)
+
+
+show()
@@ -0,0 +1,7 @@
+BANNER = '''first
+  second
+third'''
+
+
+def banner():
+    return BANNER.upper()
@@ -0,0 +1,14 @@
+import os
+# ↓↓↓ inlined package: pkg
+"""Greetings."""
+# ↓↓↓ inlined submodule: .greeting
+def greet(name):
+    return f'hello {name}'
+
+# ↑↑↑ inlined submodule: .greeting
+
+VERSION = '1.0'
+
+# ↑↑↑ inlined package: pkg
+
+print(greet(os.sep), VERSION)
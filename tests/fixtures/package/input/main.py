@@ -0,0 +1,4 @@
+import os
+from pkg import greet, VERSION
+
+print(greet(os.sep), VERSION)
@@ -0,0 +1,4 @@
+"""Greetings."""
+from .greeting import greet
+
+VERSION = '1.0'
@@ -0,0 +1,21 @@
+# ↓↓↓ inlined submodule: pkg.sub.deep
+# ↓↓↓ inlined submodule: ..numbers
+VALUES = [1, 2, 3]
+
+# ↑↑↑ inlined submodule: ..numbers
+# ↓↓↓ inlined package: .
+# ↓↓↓ inlined package: ..
+
+# ↑↑↑ inlined package: ..
+# →→ ..numbers ←← module already inlined
+numbers = __import__("sys").modules[__name__]
+
+# ↑↑↑ inlined package: .
+
+
+def total():
+    return sum(VALUES) + len(numbers.VALUES)
+
+# ↑↑↑ inlined submodule: pkg.sub.deep
+
+print(total())
@@ -0,0 +1 @@
+VALUES = [1, 2, 3]
@@ -0,0 +1,6 @@
+from ..numbers import VALUES
+from . import numbers
+
+
+def total():
+    return sum(VALUES) + len(numbers.VALUES)
@@ -0,0 +1,10 @@
+#!/usr/bin/env python3
+import math
+import sys
+from os import path
+def area(radius):
+    return math.pi * radius ** 2
+def lazy():
+    import json
+    return json.dumps(path.sep)
+print(area(float(sys.argv[1]) if len(sys.argv) > 1 else 2))
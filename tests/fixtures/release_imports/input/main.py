@@ -0,0 +1,5 @@
+#!/usr/bin/env python3
+import sys
+from pkg import area
+
+print(area(float(sys.argv[1]) if len(sys.argv) > 1 else 2))
@@ -0,0 +1,3 @@
+"""Shapes."""
+import math
+from .circle import area
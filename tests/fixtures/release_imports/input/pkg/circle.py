@@ -0,0 +1,14 @@
+# Circles
+import math
+import sys
+from os import path
+
+
+def area(radius):
+    """The area."""
+    return math.pi * radius ** 2  # squared
+
+
+def lazy():
+    import json
+    return json.dumps(path.sep)
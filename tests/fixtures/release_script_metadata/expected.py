@@ -0,0 +1,8 @@
+#!/usr/bin/env python3
+# /// script
+# dependencies = ["requests"]
+# ///
+import json
+def dump(value):
+    return json.dumps(value, sort_keys=True)
+print(dump({'a': 1}))
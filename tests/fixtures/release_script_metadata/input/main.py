@@ -0,0 +1,8 @@
+#!/usr/bin/env python3
+# /// script
+# dependencies = ["requests"]
+# ///
+import json
+from pkg.util import dump
+
+print(dump({'a': 1}))
@@ -0,0 +1,6 @@
+import json
+
+
+def dump(value):
+    # Sorted, so the output is stable
+    return json.dumps(value, sort_keys=True)
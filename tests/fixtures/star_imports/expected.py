@@ -0,0 +1,9 @@
+# ↓↓↓ inlined submodule: pkg.constants
+__all__ = ['PUBLIC']
+
+PUBLIC = 'public'
+_pkg_constants__HIDDEN = 'hidden'
+
+# ↑↑↑ inlined submodule: pkg.constants
+
+print(PUBLIC, sorted(k for k in globals() if k.isupper()))
@@ -0,0 +1,3 @@
+from pkg.constants import *
+
+print(PUBLIC, sorted(k for k in globals() if k.isupper()))
@@ -0,0 +1,4 @@
+__all__ = ['PUBLIC']
+
+PUBLIC = 'public'
+HIDDEN = 'hidden'
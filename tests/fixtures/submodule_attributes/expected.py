@@ -0,0 +1,15 @@
+# ↓↓↓ inlined package: pkg
+
+# ↑↑↑ inlined package: pkg
+# ↓↓↓ inlined submodule: pkg.util
+def double(value):
+    return value * 2
+
+# ↑↑↑ inlined submodule: pkg.util
+pkg = vars().get("pkg") or __import__("types").SimpleNamespace()
+pkg.util = getattr(pkg, "util", None) or __import__("types").SimpleNamespace()
+vars(pkg.util).update(double=double)
+# →→ pkg.util ←← module already inlined
+util = __import__("types").SimpleNamespace(double=double)
+
+print(pkg.util.double(2), util.double(3))
@@ -0,0 +1,4 @@
+import pkg.util
+import pkg.util as util
+
+print(pkg.util.double(2), util.double(3))
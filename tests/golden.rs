@@ -0,0 +1,81 @@
+//! The bundles of the projects in `tests/fixtures`, the cases of tests/snapshots.rs, run with
+//! the system Python next to the scripts they came from: a bundle has to print what its script
+//! prints. Needs Python on the PATH (or in $PYTHON), so it's only built with the `python-tests`
+//! feature:
+//!
+//! ```bash
+//! cargo test --features python-tests --test golden
+//! ```
+#![cfg(feature = "python-tests")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A directory of its own under the system temp directory, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("python-inliner-golden-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn python() -> String {
+    std::env::var("PYTHON").unwrap_or_else(|_| "python3".to_string())
+}
+
+/// Run `script` with Python from `dir`, failing the test if it fails. No bytecode is written,
+/// so the fixture projects stay as they are.
+fn run_python(dir: &Path, script: &str) -> Output {
+    let output = Command::new(python()).arg(script).current_dir(dir).env_remove("PYTHONPATH").env("PYTHONDONTWRITEBYTECODE", "1").output()
+        .unwrap_or_else(|err| panic!("couldn't run {}: {}", python(), err));
+    assert!(output.status.success(), "{} failed in {:?}:\n{}", script, dir, String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+/// Bundle the case in `case_dir` as tests/snapshots.rs does, returning the stdout of its script
+/// and of its bundle. The bundle is run from a directory of its own, so that it can't import
+/// what it should have inlined.
+fn bundle_and_run(case_dir: &Path) -> (String, String) {
+    let input = case_dir.join("input");
+    let args = fs::read_to_string(case_dir.join("args")).unwrap_or_default();
+    let name = case_dir.file_name().unwrap().to_string_lossy();
+    let bundled = TempDir::new(&name);
+    let bundle = bundled.0.join("main.py");
+
+    let inliner = Command::new(env!("CARGO_BIN_EXE_python-inliner"))
+        .arg("main.py").arg(&bundle).args(args.split_whitespace())
+        .args(["--offline", "--pythonpath", "."])
+        .current_dir(&input).env_remove("PYTHONPATH")
+        .output().unwrap();
+    assert!(inliner.status.success(), "{}: the inliner failed:\n{}", name, String::from_utf8_lossy(&inliner.stderr));
+
+    let original = run_python(&input, "main.py");
+    let inlined = run_python(&bundled.0, "main.py");
+    (String::from_utf8_lossy(&original.stdout).into_owned(), String::from_utf8_lossy(&inlined.stdout).into_owned())
+}
+
+#[test]
+fn test_bundles_print_what_their_scripts_print() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut cases: Vec<PathBuf> = fs::read_dir(&fixtures).unwrap().map(|entry| entry.unwrap().path()).filter(|path| path.is_dir()).collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no cases in {:?}", fixtures);
+
+    for case in &cases {
+        let name = case.file_name().unwrap().to_string_lossy();
+        let (original, inlined) = bundle_and_run(case);
+        assert!(!original.is_empty(), "{}: the script prints nothing to compare", name);
+        assert_eq!(inlined, original, "{}: the bundle's output differs from the script's", name);
+    }
+}
@@ -0,0 +1,69 @@
+//! The bundles of the projects in `tests/fixtures`, compared against the bundles they made
+//! before. Each case is a directory holding:
+//!
+//! - `input/`, the project, bundled from `input/main.py` with `--offline --pythonpath input`
+//! - `args`, the rest of the command line: the modules to inline and any flags
+//! - `expected.py`, the bundle
+//!
+//! A change to what the inliner writes fails here until the snapshots are updated, which shows
+//! it in the diff of `expected.py`:
+//!
+//! ```bash
+//! UPDATE_SNAPSHOTS=1 cargo test --test snapshots
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The bundle of the case in `case_dir`
+fn bundle(case_dir: &Path) -> String {
+    let input = case_dir.join("input");
+    let args = fs::read_to_string(case_dir.join("args")).unwrap_or_default();
+    let name = case_dir.file_name().unwrap().to_string_lossy();
+    let output = std::env::temp_dir().join(format!("python-inliner-{}-snapshot-{}.py", std::process::id(), name));
+    let inliner = Command::new(env!("CARGO_BIN_EXE_python-inliner"))
+        .arg("main.py").arg(&output).args(args.split_whitespace())
+        .args(["--offline", "--pythonpath", "."])
+        .current_dir(&input).env_remove("PYTHONPATH")
+        .output().unwrap();
+    assert!(inliner.status.success(), "{}: the inliner failed:\n{}", name, String::from_utf8_lossy(&inliner.stderr));
+    let bundle = fs::read_to_string(&output).unwrap();
+    fs::remove_file(&output).unwrap();
+    bundle
+}
+
+/// The lines of `expected` and `actual` from the first that differs, as `-` and `+` lines
+fn difference(expected: &str, actual: &str) -> String {
+    let (expected, actual): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), actual.lines().collect());
+    let first = expected.iter().zip(&actual).take_while(|(a, b)| a == b).count();
+    let mut lines = vec![format!("from line {}:", first + 1)];
+    lines.extend(expected[first..].iter().take(10).map(|line| format!("-{}", line)));
+    lines.extend(actual[first..].iter().take(10).map(|line| format!("+{}", line)));
+    lines.join("\n")
+}
+
+#[test]
+fn test_snapshots() {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some_and(|value| !value.is_empty() && value != "0");
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut cases: Vec<PathBuf> = fs::read_dir(&fixtures).unwrap().map(|entry| entry.unwrap().path()).filter(|path| path.is_dir()).collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no cases in {:?}", fixtures);
+
+    let mut changed = Vec::new();
+    for case in &cases {
+        let actual = bundle(case);
+        let snapshot = case.join("expected.py");
+        let expected = fs::read_to_string(&snapshot).unwrap_or_default();
+        if actual == expected {
+            continue;
+        }
+        if update {
+            fs::write(&snapshot, &actual).unwrap();
+        } else {
+            changed.push(format!("{}\n{}", case.file_name().unwrap().to_string_lossy(), difference(&expected, &actual)));
+        }
+    }
+    assert!(changed.is_empty(), "bundles differ from their snapshots (UPDATE_SNAPSHOTS=1 to accept them):\n\n{}", changed.join("\n\n"));
+}